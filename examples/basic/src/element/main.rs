@@ -3,6 +3,7 @@ use topos::cosmic_text::{Attrs, Editor, Metrics};
 use topos::element::TextBoxEditorElement;
 use topos::math::Angle;
 use topos::util::layout::Manual;
+use topos::util::text::GlyphRenderMode;
 use topos::{
     accessibility::{AccessNodeBuilder, AccessRole},
     color::{ColorRgba, ColorSrgba, FromNSColor},
@@ -45,7 +46,9 @@ impl MainElement {
             "Hello world".into(),
             Attrs::new(),
             FlexBox::builder().width(50.),
-        );
+        )
+        // stays crisp while zooming, without rasterizing each scale
+        .with_glyph_mode(GlyphRenderMode::Sdf);
 
         Self {
             rects: vec![
@@ -1,15 +1,15 @@
 use crate::{
     math::{Pos, Rect},
+    repaint::RepaintHandle,
     scene::{
         framepacer::{
-            self, CADisplayLinkFramepacer, Framepacer, FramepacerInstant, InstantLike,
-            NoopFramepacer,
+            self, Framepacer, FramepacerInstant, InstantLike, NoopFramepacer,
         },
         layout::{self, ElementTree},
     },
     surface::{RenderTarget, RenderingContext},
     texture::TextureManagerRef,
-    util::{min, PhysicalUnit},
+    util::PhysicalUnit,
 };
 use core::panic;
 
@@ -21,10 +21,22 @@ use winit::{
 use crate::{
     element::RootConstructor,
     input::{input_state::InputState, winit::WinitState},
-    scene::{framepacer::ManagedFramepacer, scene::Scene},
+    scene::scene::Scene,
     surface::{RenderAttachment, WindowSurface},
 };
 
+/// Clock frames are paced with. Presentation timestamps are only reported by
+/// some backends, so other platforms estimate vsync from when frames were presented.
+#[cfg(target_os = "macos")]
+type FrameInstant = wgpu::PresentationTimestamp;
+#[cfg(not(target_os = "macos"))]
+type FrameInstant = crate::time::Instant;
+
+#[cfg(target_os = "macos")]
+type AppFramepacer = framepacer::ManagedFramepacer<FrameInstant>;
+#[cfg(not(target_os = "macos"))]
+type AppFramepacer = framepacer::EstimatedVsyncFramepacer;
+
 struct AppInner<Root: RootConstructor + 'static> {
     swap_chain: Option<(RenderAttachment, ElementTree)>,
 
@@ -41,12 +53,18 @@ struct AppInner<Root: RootConstructor + 'static> {
 
     last_presentation_time: Option<wgpu::PresentationTimestamp>,
 
-    framepacer: ManagedFramepacer,
+    framepacer: AppFramepacer,
+
+    repaint: RepaintHandle,
+    /// Whether the last frame asked to be followed by another right away
+    requested_immediate_repaint: bool,
 }
 
 pub enum ToposEvent<Root: RootConstructor + 'static> {
     Exit(i32),
     AccessKitActionRequest(accesskit_winit::WindowEvent),
+    /// Wakes up the event loop after a repaint was requested, see [`RepaintHandle`]
+    RepaintRequested,
     AppInnerCreated(PhantomData<AppInner<Root>>),
 }
 
@@ -70,9 +88,8 @@ impl<Root: RootConstructor + 'static> App<Root> {
     pub fn run() {
         let event_loop = EventLoop::with_user_event().build().expect("Failed to create event loop");
 
-          // ControlFlow::Poll continuously runs the event loop, even if the OS hasn't
-        // dispatched any events. This is ideal for games and similar applications.
-        event_loop.set_control_flow(ControlFlow::Poll);
+        // the event loop sleeps until input arrives or a repaint is requested
+        event_loop.set_control_flow(ControlFlow::Wait);
 
         let mut app = Self {
             event_loop_proxy: event_loop.create_proxy(),
@@ -125,8 +142,12 @@ impl<Root: RootConstructor + 'static> ApplicationHandler<ToposEvent<Root>> for A
         
         let main_proxy = &self.event_loop_proxy;
 
-        // TODO: use this information to determine whether to repaint, i guess
-        let _ = app_inner.winit_state.on_window_event(window, &event);
+        let response = app_inner.winit_state.on_window_event(window, &event);
+
+        // redraws which are drawn right away don't need another one
+        if response.repaint && event != WindowEvent::RedrawRequested {
+            app_inner.repaint.request_repaint();
+        }
 
         match event {
             WindowEvent::CloseRequested
@@ -164,7 +185,17 @@ impl<Root: RootConstructor + 'static> ApplicationHandler<ToposEvent<Root>> for A
             return;
         };
 
-        app_inner.window_surface.window().request_redraw();
+        let now = crate::time::Instant::now();
+
+        if app_inner.repaint.take_due(now) {
+            app_inner.window_surface.window().request_redraw();
+        }
+
+        // winit's instants differ from ours on the web, so only the delay is passed on
+        event_loop.set_control_flow(match app_inner.repaint.next_repaint() {
+            Some(repaint_at) => ControlFlow::wait_duration(repaint_at.saturating_duration_since(now)),
+            None => ControlFlow::Wait,
+        });
     }
 
     fn user_event(&mut self, event_loop: &ActiveEventLoop, event: ToposEvent<Root>) {
@@ -191,7 +222,7 @@ impl<Root: RootConstructor + 'static> ApplicationHandler<ToposEvent<Root>> for A
 
 impl<Root: RootConstructor + 'static> AppInner<Root> {
     fn draw(&mut self) {
-        type I = wgpu::PresentationTimestamp;
+        type I = FrameInstant;
 
         let dpi = self.window_surface.window().scale_factor();
         
@@ -206,22 +237,33 @@ impl<Root: RootConstructor + 'static> AppInner<Root> {
 
         let time_context = I::context_from(self.window_surface.surface().rendering_context());
 
+        // the frame is retried until it's rendered, a frame later if the surface
+        // had no output
         let (output, element_tree) = match self.swap_chain.take() {
             Some(output) => output,
-            None => return,
+            None => {
+                let frame_time = self.framepacer.desired_frame_time().unwrap_or_else(|| {
+                    crate::time::Duration::from_secs_f64(framepacer::DEFAULT_FRAME_TIME_SECS)
+                });
+
+                self.repaint.request_repaint_after(frame_time);
+                return;
+            }
         };
 
-        let (should_render, render_start_time) =
-            self.framepacer.should_render(I::now(time_context));
+        let now = <I as InstantLike>::now(time_context);
+        let (should_render, render_start_time) = self.framepacer.should_render(now);
 
+        // or once rendering would just finish by the deadline
         if !should_render {
             self.swap_chain = Some((output, element_tree));
+            self.repaint.request_repaint_after(self.framepacer.time_until_render(now).unwrap_or_default());
             return;
         }
 
         let raw_input = self.winit_state.take_egui_input(self.window_surface.window());
 
-        let input_state = std::mem::take(&mut self.input_state).begin_pass(raw_input, true, dpi as f32, &Default::default());
+        let input_state = std::mem::take(&mut self.input_state).begin_pass(raw_input, self.requested_immediate_repaint, dpi as f32, &Default::default());
 
         let (mut result_input, result_output, render_time, approx_present_time) =
             self.scene.render(
@@ -240,10 +282,20 @@ impl<Root: RootConstructor + 'static> AppInner<Root> {
 
         result_input.end_frame();
 
+        let repaint_after = result_input.wants_repaint_after();
+        self.requested_immediate_repaint = repaint_after == Some(Default::default());
+
+        if let Some(repaint_after) = repaint_after {
+            self.repaint.request_repaint_after(repaint_after);
+        }
+
         self.try_create_new_output(approx_present_time.into());
 
         self.framepacer.push_frametime(render_time);
 
+        log::trace!("frame timings: {:?}", self.scene.frame_timings());
+        log::trace!("glyph atlas stats: {:?}", self.scene.glyph_atlas_stats());
+
         self.input_state = result_input;
 
         self.winit_state
@@ -252,9 +304,9 @@ impl<Root: RootConstructor + 'static> AppInner<Root> {
 
     fn try_create_new_output(
         &mut self,
-        approx_presentation_start: Option<wgpu::PresentationTimestamp>,
+        approx_presentation_start: Option<FrameInstant>,
     ) {
-        type I = wgpu::PresentationTimestamp;
+        type I = FrameInstant;
         
         if self.swap_chain.is_some() {
             return;
@@ -315,7 +367,7 @@ impl<Root: RootConstructor + 'static> AppInner<Root> {
                 let last_presentation_time = I::query_presentation_statistics(
                     self.window_surface.surface().surface(),
                     &self.window_surface.window(),
-                    approx_presentation_start.unwrap_or(I::now(I::context_from(render_ctx))),
+                    approx_presentation_start.unwrap_or(<I as InstantLike>::now(I::context_from(render_ctx))),
                 );
 
                 self.framepacer.start_window(
@@ -323,7 +375,7 @@ impl<Root: RootConstructor + 'static> AppInner<Root> {
                     get_window_frame_time_nanos(self.window_surface.window()),
                 );
 
-                let layout_result = self.scene.do_layout(&self.window_surface.surface());
+                let layout_result = self.scene.do_layout(self.window_surface.surface());
 
                 self.swap_chain = (output, layout_result).into();
             }
@@ -452,22 +504,11 @@ impl<Root: RootConstructor + 'static> AppInner<Root> {
         let window_surface = WindowSurface::new(window, render_target).await;
         let rendering_context = window_surface.surface().clone_rendering_context();
 
-        let wgpu::Limits {
-            max_sampled_textures_per_shader_stage,
-            max_bindings_per_bind_group,
-            ..
-        } = rendering_context.device.limits();
-
-        let max_textures = min(
-            max_sampled_textures_per_shader_stage,
-            max_bindings_per_bind_group,
-        );
-
-        let texture_manager = TextureManagerRef::new(max_textures, &rendering_context);
+        let texture_manager = TextureManagerRef::from_device_limits(&rendering_context);
 
         let mut scene = Scene::new(
             rendering_context,
-            &window_surface.surface(),
+            window_surface.surface(),
             &texture_manager,
             scale_factor,
         );
@@ -490,6 +531,15 @@ impl<Root: RootConstructor + 'static> AppInner<Root> {
 
         let input_state = InputState::default().into();
 
+        let repaint = scene.repaint_handle();
+
+        repaint.set_waker(move || {
+            let _ = winit_state_proxy.send_event(ToposEvent::RepaintRequested);
+        });
+
+        // the first frame
+        repaint.request_repaint();
+
         Self {
             window_surface,
 
@@ -506,6 +556,9 @@ impl<Root: RootConstructor + 'static> AppInner<Root> {
             last_presentation_time: Default::default(),
 
             texture_manager,
+
+            repaint,
+            requested_immediate_repaint: false,
         }
     }
 
@@ -583,13 +636,18 @@ pub fn get_window_last_screen_draw_time(
 
 use std::{fmt::Debug, marker::PhantomData, os::raw::c_int, ptr::NonNull};
 
+#[cfg(target_os = "macos")]
 use icrate::Foundation::{NSCopying, NSObject, NSObjectProtocol, NSZone};
+#[cfg(target_os = "macos")]
 use objc2::declare::{Ivar, IvarBool, IvarDrop, IvarEncode};
+#[cfg(target_os = "macos")]
 use objc2::rc::Id;
+#[cfg(target_os = "macos")]
 use objc2::{
     declare_class, extern_protocol, msg_send, msg_send_id, mutability, ClassType, ProtocolType,
 };
 
+#[cfg(target_os = "macos")]
 declare_class!(
     struct CustomAppDelegate {
         pub should_render: IvarBool<"_should_render">,
@@ -1,12 +1,21 @@
 use crate::{
     color::{ColorRgb, ColorRgba},
+    gradient::PushGradients,
     graphics::PushVertices,
-    math::{PhysicalPos, PhysicalRect, PhysicalSize, Pos, Rect, ScaleFactor, Sides, Size},
+    math::{
+        PhysicalPos, PhysicalRect, PhysicalSize, PhysicalVector, Pos, Rect, ScaleFactor, Sides,
+        Size,
+    },
     shape::BoxShaderVertex,
-    texture::{TextureManagerError, TextureManagerRef, TextureRef, TextureWeakRef},
+    texture::{PushTextures, TextureManagerRef, TextureRef},
+    time::{Duration, Instant},
     util::{
         guard::{ReadLockable, WritableLock, WriteLockable},
-        text::{AtlasContentType, FontSystem, FontSystemRef, PlacedTextBox},
+        sdf::signed_distance_field,
+        text::{
+            AtlasContentType, AtlasGlyphKey, FontSystem, FontSystemRef, GlyphRenderMode,
+            PlacedTextBox,
+        },
     },
 };
 
@@ -42,6 +51,74 @@ type GlyphCacheKey = cosmic_text::CacheKey;
 
 const MAX_ATLAS_SIZE: u32 = 4096;
 
+/// Size of the first page of each content type, later pages double in size up
+/// to [`MAX_ATLAS_SIZE`]
+const INITIAL_ATLAS_SIZE: u32 = 1024;
+
+/// Limits of the glyph atlases, see [`FontManager::set_atlas_options`]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct GlyphAtlasOptions {
+    /// Glyphs which weren't drawn for more than this many frames are evicted
+    pub max_unused_frames: u64,
+    /// Pages per content type. Once they're full, the least recently used
+    /// glyphs are evicted to make space.
+    pub max_pages: usize,
+    /// Pages less occupied than this fraction have their glyphs moved into
+    /// other pages after each frame, so that they can be freed. Disabled if
+    /// `None`.
+    pub compaction_threshold: Option<f32>,
+}
+
+impl Default for GlyphAtlasOptions {
+    fn default() -> Self {
+        Self {
+            max_unused_frames: 120,
+            max_pages: 4,
+            compaction_threshold: None,
+        }
+    }
+}
+
+impl GlyphAtlasOptions {
+    #[inline]
+    pub fn with_max_unused_frames(mut self, max_unused_frames: u64) -> Self {
+        self.max_unused_frames = max_unused_frames;
+        self
+    }
+
+    #[inline]
+    pub fn with_max_pages(mut self, max_pages: usize) -> Self {
+        self.max_pages = max_pages;
+        self
+    }
+
+    #[inline]
+    pub fn with_compaction_threshold(
+        mut self,
+        compaction_threshold: impl Into<Option<f32>>,
+    ) -> Self {
+        self.compaction_threshold = compaction_threshold.into();
+        self
+    }
+}
+
+/// Usage of the glyph atlases after a frame, see [`FontManager::atlas_stats`]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct GlyphAtlasStats {
+    /// Pages of both mask and color glyphs
+    pub num_pages: usize,
+    pub num_glyphs: usize,
+    /// Fraction of the pages' area which is allocated
+    pub occupancy: f32,
+    /// Glyphs evicted during the frame, either because they went unused or to
+    /// make space
+    pub evictions: usize,
+    /// Glyphs moved into other pages by compaction
+    pub compacted_glyphs: usize,
+    /// Glyphs which didn't fit into any page, and weren't rendered
+    pub allocation_failures: usize,
+}
+
 pub struct GlyphToRender {
     size: PhysicalSize<u32>,
     draw_rect: Rect,
@@ -71,16 +148,16 @@ impl TextureAtlas {
         atlas_type: AtlasContentType,
         width: u32,
         height: u32,
-    ) -> Result<Self, TextureManagerError> {
+    ) -> Self {
         let RenderingContext { device, .. } = context;
 
-        let allocator = BucketedAtlasAllocator::new(etagere::size2(width as i32, height as i32));
-
         let max_texture_dimension_2d = device.limits().max_texture_dimension_2d;
 
         let width = width.min(max_texture_dimension_2d);
         let height = height.min(max_texture_dimension_2d);
 
+        let allocator = BucketedAtlasAllocator::new(etagere::size2(width as i32, height as i32));
+
         let texture = device.create_texture(&wgpu::TextureDescriptor {
             label: Some("FontAtlas"),
             size: wgpu::Extent3d {
@@ -95,20 +172,23 @@ impl TextureAtlas {
                 AtlasContentType::Mask => wgpu::TextureFormat::R8Unorm,
                 AtlasContentType::Color => wgpu::TextureFormat::Rgba8UnormSrgb,
             },
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            // glyphs are copied between pages by compaction
+            usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_DST
+                | wgpu::TextureUsages::COPY_SRC,
             view_formats: &[],
         });
 
-        let texture_ref = texture_manager.write().unwrap().register_texture(texture)?;
+        let texture_ref = texture_manager.write().unwrap().register_texture(texture);
 
-        Ok(Self {
+        Self {
             allocator,
             atlas_type,
             width: width as i32,
-            height: width as i32,
+            height: height as i32,
             texture_ref,
             num_glyphs: 0,
-        })
+        }
     }
 
     fn try_allocate_space(&mut self, space: &PhysicalSize<u32>) -> Option<EtagereAllocation> {
@@ -271,6 +351,20 @@ impl TextureAtlas {
     fn can_fit(&self, space: PhysicalSize<i32>) -> bool {
         return space.width <= self.width && space.height <= self.height;
     }
+
+    /// Allocated and total area of the page
+    fn area(&self) -> (i64, i64) {
+        (
+            self.allocator.allocated_space() as i64,
+            self.width as i64 * self.height as i64,
+        )
+    }
+
+    /// Fraction of the page which is allocated
+    fn occupancy(&self) -> f32 {
+        let (allocated, total) = self.area();
+        allocated as f32 / total.max(1) as f32
+    }
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
@@ -314,7 +408,7 @@ pub trait HasAtlasAllocationId {
     fn get_id(&self) -> AtlasAllocationId;
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct AtlasAllocationId {
     pub(crate) atlas_id: AtlasId,
     pub(crate) allocation: EtagereAllocation,
@@ -407,6 +501,23 @@ enum GlyphCacheEntry {
     Noop,
 }
 
+struct CachedGlyph {
+    entry: GlyphCacheEntry,
+    /// Frame the glyph was last drawn in
+    last_used_frame: u64,
+    /// Generation of the cache when the glyph was added or last moved
+    generation: u64,
+}
+
+impl CachedGlyph {
+    fn atlas_id(&self) -> Option<AtlasId> {
+        match &self.entry {
+            GlyphCacheEntry::GlyphAllocation(glyph) => Some(glyph.allocation.atlas_id()),
+            GlyphCacheEntry::Noop => None,
+        }
+    }
+}
+
 // TODO: use no hash hasher
 type FontAtlasCollection = HashMap<AtlasId, TextureAtlas>;
 
@@ -440,55 +551,103 @@ impl WriteLockable<TextureAtlasManager> for &TextureAtlasManagerRef {
 
 #[derive(Default)]
 struct GlyphCache {
-    glyphs: FxHashMap<GlyphCacheKey, GlyphCacheEntry>,
+    glyphs: FxHashMap<AtlasGlyphKey, CachedGlyph>,
 
     // TODO: btreeset is excessive, just use a list?
-    glyph_btreemap: FxHashMap<(fontdb::ID, u16), BTreeSet<GlyphCacheKey>>,
+    glyph_btreemap: FxHashMap<(fontdb::ID, u16, GlyphRenderMode), BTreeSet<AtlasGlyphKey>>,
+
+    /// Incremented whenever a glyph is added or moved
+    generation: u64,
 }
 
 impl GlyphCache {
-    fn get(&self, cache_key: &GlyphCacheKey) -> Option<&GlyphCacheEntry> {
-        self.glyphs.get(cache_key)
+    #[inline(always)]
+    fn btreemap_key(key: &AtlasGlyphKey) -> (fontdb::ID, u16, GlyphRenderMode) {
+        (key.cache_key.font_id, key.cache_key.glyph_id, key.mode)
+    }
+
+    fn get(&self, cache_key: &AtlasGlyphKey) -> Option<&GlyphCacheEntry> {
+        self.glyphs.get(cache_key).map(|glyph| &glyph.entry)
+    }
+
+    fn get_mut(&mut self, cache_key: &AtlasGlyphKey) -> Option<&mut GlyphCacheEntry> {
+        let glyph = self.glyphs.get_mut(cache_key)?;
+
+        // the entry may be moved
+        self.generation += 1;
+        glyph.generation = self.generation;
+
+        Some(&mut glyph.entry)
+    }
+
+    fn touch(&mut self, cache_key: &AtlasGlyphKey, frame: u64) {
+        if let Some(glyph) = self.glyphs.get_mut(cache_key) {
+            glyph.last_used_frame = frame;
+        }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = (&AtlasGlyphKey, &CachedGlyph)> {
+        self.glyphs.iter()
     }
 
-    fn contains_key(&self, key: &GlyphCacheKey) -> bool {
+    fn contains_key(&self, key: &AtlasGlyphKey) -> bool {
         self.glyphs.contains_key(key)
     }
 
-    fn insert(&mut self, cache_key: cosmic_text::CacheKey, noop: GlyphCacheEntry) {
-        self.glyphs.insert(cache_key, noop);
-        self.glyph_btreemap.entry((cache_key.font_id, cache_key.glyph_id)).or_default().insert(cache_key);
+    fn insert(&mut self, cache_key: AtlasGlyphKey, entry: GlyphCacheEntry, frame: u64) {
+        self.generation += 1;
+
+        let glyph = CachedGlyph {
+            entry,
+            last_used_frame: frame,
+            generation: self.generation,
+        };
+
+        self.glyphs.insert(cache_key, glyph);
+        self.glyph_btreemap
+            .entry(Self::btreemap_key(&cache_key))
+            .or_default()
+            .insert(cache_key);
     }
 
-    fn remove(&mut self, cache_key: &GlyphCacheKey) -> Option<GlyphCacheEntry> {
-        let entry = self.glyphs.remove(cache_key);
+    fn remove(&mut self, cache_key: &AtlasGlyphKey) -> Option<GlyphCacheEntry> {
+        let entry = self.glyphs.remove(cache_key).map(|glyph| glyph.entry);
 
         let mut destroy = false;
-        self.glyph_btreemap.entry((cache_key.font_id, cache_key.glyph_id)).and_modify(|set| {
-            set.remove(cache_key);
-            destroy = set.is_empty();
-        });
+        self.glyph_btreemap
+            .entry(Self::btreemap_key(cache_key))
+            .and_modify(|set| {
+                set.remove(cache_key);
+                destroy = set.is_empty();
+            });
 
         if destroy {
-            self.glyph_btreemap.remove(&(cache_key.font_id, cache_key.glyph_id));
+            self.glyph_btreemap.remove(&Self::btreemap_key(cache_key));
         }
 
         entry
     }
 
-    fn keys(&self) -> impl Iterator<Item = &GlyphCacheKey> {
-        self.glyphs.keys()
-    }
-
     fn clear(&mut self) {
         self.glyphs.clear();
         self.glyph_btreemap.clear();
     }
 
-    fn find_closest_key(&self, key: &GlyphCacheKey) -> Option<(&GlyphCacheKey, &GlyphCacheEntry)> {
-        self.glyph_btreemap.get(&(key.font_id, key.glyph_id))
+    /// Whether each of `keys` is cached, and wasn't added or moved after
+    /// `generation`
+    fn unchanged_since(&self, keys: &[AtlasGlyphKey], generation: u64) -> bool {
+        keys.iter().all(|key| {
+            self.glyphs
+                .get(key)
+                .is_some_and(|glyph| glyph.generation <= generation)
+        })
+    }
+
+    fn find_closest_key(&self, key: &AtlasGlyphKey) -> Option<(&AtlasGlyphKey, &GlyphCacheEntry)> {
+        self.glyph_btreemap
+            .get(&Self::btreemap_key(key))
             .and_then(|set| set.last())
-            .and_then(|key| self.glyphs.get(key).map(|entry| (key, entry)))
+            .and_then(|key| self.glyphs.get(key).map(|glyph| (key, &glyph.entry)))
     }
 }
 
@@ -497,13 +656,25 @@ pub struct TextureAtlasManager {
 
     glyphs: GlyphCache,
 
-    used_glyphs_this_frame: HashSet<GlyphCacheKey, FxBuildHasher>,
+    used_glyphs_this_frame: HashSet<AtlasGlyphKey, FxBuildHasher>,
 
     id: u32,
 
     rendering_context: Arc<RenderingContext>,
 
     deallocation_queue: DeallocationQueue,
+
+    /// Time spent rasterizing glyphs since it was last taken
+    glyph_rasterization_time: Duration,
+
+    options: GlyphAtlasOptions,
+
+    /// Frames since the manager was created
+    frame: u64,
+
+    /// Counters of the current frame
+    stats: GlyphAtlasStats,
+    last_stats: GlyphAtlasStats,
 }
 
 impl TextureAtlasManager {
@@ -519,13 +690,20 @@ impl TextureAtlasManager {
             rendering_context,
 
             deallocation_queue: Default::default(),
+
+            glyph_rasterization_time: Default::default(),
+
+            options: Default::default(),
+            frame: 0,
+            stats: Default::default(),
+            last_stats: Default::default(),
         };
     }
 
     pub(crate) fn prepare<'a>(
         &'a mut self,
         boxes: impl IntoIterator<Item = PlacedTextBox> + 'a,
-        output: &'a mut impl PushVertices<BoxShaderVertex>,
+        output: &'a mut (impl PushVertices<BoxShaderVertex> + PushGradients + PushTextures),
     ) -> impl Iterator<Item = GlyphCacheKey> + 'a {
         boxes
             .into_iter()
@@ -537,75 +715,138 @@ impl TextureAtlasManager {
                         text_box.pos,
                         text_box.scale_fac,
                         text_box.bounding_size,
+                        text_box.glyph_mode,
                     )
                 })
             })
-            .filter_map(|(g, clip_rect, pos, scale_fac, bounding_size)| {
-                let alloc = self.glyphs.get(&g.glyph.cache_key).map(|entry| (&g.glyph.cache_key, entry, false))
-                    .or_else(|| {
-                        self.glyphs.find_closest_key(&g.glyph.cache_key)
-                            .map(|(key, entry)| (key, entry, true))
-                    });
-
-                match alloc {
-                    Some((key, GlyphCacheEntry::GlyphAllocation(GlyphAllocation {
-                        size,
-                        allocation,
-                        placement,
-                        ..
-                    }), is_fallback)) => {
-                        self.used_glyphs_this_frame.insert(*key);
-
-                        if let Some(atlas) = self.get_atlas_by_id(&allocation.atlas_id()) {
-                            let color = g.color;
-
-                            let (adjusted_size, placement) = if is_fallback {
-                                let scale_fac = f32::from_bits(g.glyph.cache_key.font_size_bits) / f32::from_bits(key.font_size_bits);
-
-                                (
-                                    size.map(|x| (x as f32 * scale_fac).round() as u32), 
-                                    placement.map(|x| (x as f32 * scale_fac).round() as i32)
-                                )
-                            } else {
-                                (*size, *placement)
-                            };
-
-                            let draw_rect =
-                                g.to_draw_glyph(pos, adjusted_size, placement, scale_fac.inverse());
-
-                            if clip_rect
-                                .map(|clip_rect| clip_rect.inner.intersection(&draw_rect).is_none())
-                                .unwrap_or_default()
-                            {
-                                return None;
+            .filter_map(
+                |(g, clip_rect, pos, scale_fac, bounding_size, glyph_mode)| {
+                    let glyph_key = AtlasGlyphKey::new(g.glyph.cache_key, glyph_mode);
+
+                    let alloc = self
+                        .glyphs
+                        .get(&glyph_key)
+                        .map(|entry| (&glyph_key, entry, false))
+                        .or_else(|| {
+                            self.glyphs
+                                .find_closest_key(&glyph_key)
+                                .map(|(key, entry)| (key, entry, true))
+                        });
+
+                    match alloc {
+                        Some((
+                            key,
+                            GlyphCacheEntry::GlyphAllocation(GlyphAllocation {
+                                size,
+                                allocation,
+                                placement,
+                                ..
+                            }),
+                            is_fallback,
+                        )) => {
+                            self.used_glyphs_this_frame.insert(*key);
+
+                            if let Some(atlas) = self.get_atlas_by_id(&allocation.atlas_id()) {
+                                let color = g.color.clone();
+
+                                let glyph_scale = f32::from_bits(g.glyph.cache_key.font_size_bits)
+                                    / key.font_size();
+
+                                let (adjusted_size, placement) = match glyph_mode {
+                                    // distance fields are rasterized at a fixed size and without
+                                    // a subpixel offset, so both are applied here
+                                    GlyphRenderMode::Sdf => (
+                                        size.map(|x| x as f32 * glyph_scale),
+                                        placement.map(|x| x as f32 * glyph_scale)
+                                            + PhysicalVector::new(
+                                                g.glyph.cache_key.x_bin.as_float(),
+                                                g.glyph.cache_key.y_bin.as_float(),
+                                            ),
+                                    ),
+                                    GlyphRenderMode::Bitmap if is_fallback => (
+                                        size.map(|x| (x as f32 * glyph_scale).round()),
+                                        placement.map(|x| (x as f32 * glyph_scale).round()),
+                                    ),
+                                    GlyphRenderMode::Bitmap => {
+                                        (size.map(|x| x as f32), placement.map(|x| x as f32))
+                                    }
+                                };
+
+                                // distance in logical units over which the distance field goes from the
+                                // outline to fully covered
+                                let sdf_spread = (glyph_mode == GlyphRenderMode::Sdf
+                                    && atlas.atlas_type == AtlasContentType::Mask)
+                                    .then(|| {
+                                        GlyphRenderMode::SDF_SPREAD as f32 * glyph_scale
+                                            / scale_fac.get().into_inner()
+                                    });
+
+                                let draw_rect = g.to_draw_glyph(
+                                    pos,
+                                    adjusted_size,
+                                    placement,
+                                    scale_fac.inverse(),
+                                );
+
+                                if clip_rect
+                                    .map(|clip_rect| {
+                                        clip_rect.inner.intersection(&draw_rect).is_none()
+                                    })
+                                    .unwrap_or_default()
+                                {
+                                    return None;
+                                }
+
+                                let uv = allocation.draw_rect();
+
+                                let alloc_pos = Pos::new(uv.min.x as u32, uv.min.y as u32);
+                                let uv = PhysicalRect::new(alloc_pos, alloc_pos + *size);
+
+                                let (vertices, indices) = BoxShaderVertex::glyph_rect(
+                                    self as &TextureAtlasManager,
+                                    output,
+                                    draw_rect,
+                                    uv,
+                                    allocation.atlas_id().0,
+                                    color,
+                                    &atlas.texture_ref,
+                                    Rect::from_min_size(pos, bounding_size),
+                                    sdf_spread,
+                                );
+
+                                output.push_vertices(vertices, indices);
+
+                                return Some(g.glyph.cache_key);
                             }
+                        }
+                        None => log::warn!(
+                            "Glyph {} not cached, it will not be rendered this frame",
+                            g.glyph.cache_key.glyph_id
+                        ),
+                        Some((_, GlyphCacheEntry::Noop, _)) => {}
+                    };
 
-                            let uv = allocation.draw_rect();
-
-                            let alloc_pos = Pos::new(uv.min.x as u32, uv.min.y as u32);
-                            let uv = PhysicalRect::new(alloc_pos, alloc_pos + *size);
-
-                            let (vertices, indices) = BoxShaderVertex::glyph_rect(
-                                self as &TextureAtlasManager,
-                                draw_rect,
-                                uv,
-                                allocation.atlas_id().0,
-                                color,
-                                &atlas.texture_ref,
-                                Rect::from_min_size(pos, bounding_size),
-                            );
+                    None
+                },
+            )
+    }
 
-                            output.push_vertices(vertices, indices);
+    /// Incremented whenever a glyph is added or moved, see
+    /// [`Self::glyphs_unchanged_since`]
+    pub(crate) fn generation(&self) -> u64 {
+        self.glyphs.generation
+    }
 
-                            return Some(g.glyph.cache_key);
-                        }
-                    }
-                    None => log::warn!("Glyph {} not cached, it will not be rendered this frame", g.glyph.cache_key.glyph_id),
-                    Some((_, GlyphCacheEntry::Noop, _)) => {}
-                };
+    /// Whether vertices prepared for `glyphs` at `generation` are still valid,
+    /// which is the case while none of them were added, moved or removed since.
+    /// Glyphs elsewhere in the atlases don't matter.
+    pub(crate) fn glyphs_unchanged_since(&self, glyphs: &[AtlasGlyphKey], generation: u64) -> bool {
+        self.glyphs.unchanged_since(glyphs, generation)
+    }
 
-                None
-            })
+    /// Keeps glyphs drawn from earlier prepared vertices from being evicted
+    pub(crate) fn mark_glyphs_used(&mut self, glyphs: &[AtlasGlyphKey]) {
+        self.used_glyphs_this_frame.extend(glyphs);
     }
 
     pub(crate) fn get_atlas_mut(&mut self, id: &AtlasId) -> Option<&mut TextureAtlas> {
@@ -625,7 +866,7 @@ impl TextureAtlasManager {
         texture_manager: &TextureManagerRef,
         kind: AtlasContentType,
         size: u32,
-    ) -> Result<AtlasId, TextureManagerError> {
+    ) -> AtlasId {
         let size = u32::max(size, 512);
 
         log::trace!("Creating new atlas of size {size}");
@@ -633,14 +874,14 @@ impl TextureAtlasManager {
         let atlas_id = AtlasId(kind, self.id);
         self.id += 1;
 
-        let atlas = TextureAtlas::new(&self.rendering_context, texture_manager, kind, size, size)?;
+        let atlas = TextureAtlas::new(&self.rendering_context, texture_manager, kind, size, size);
 
         self.atlases.insert(atlas_id, atlas);
 
-        Ok(atlas_id)
+        atlas_id
     }
 
-    pub fn has_glyph(&self, key: &GlyphCacheKey) -> bool {
+    pub fn has_glyph(&self, key: &AtlasGlyphKey) -> bool {
         self.glyphs.contains_key(key)
     }
 
@@ -650,50 +891,145 @@ impl TextureAtlasManager {
         kind: AtlasContentType,
         size: PhysicalSize<u32>,
     ) -> Option<AtlasAllocation> {
-        self.atlases
-            .iter_mut()
-            .filter_map(|(id, atlas)| match id.0.eq(&kind) {
-                true => Some(atlas.try_allocate_space(&size).map(|res| (*id, res))),
-                false => None,
-            })
-            .flatten()
-            .next()
-            .or_else(|| {
-                // let new_dimensions = u32::max(size.width, size.height).next_power_of_2();
-                let atlas_id = self.create_atlas(texture_manager, kind, 4096).unwrap();
-
-                match self.get_atlas_mut(&atlas_id) {
-                    Some(atlas) => match atlas.try_allocate_space(&size) {
-                        Some(res) => Some((atlas_id, res)),
-                        None => {
-                            // log::error!(
-                            //     "Failed to allocate space for glyph {:x}",
-                            //     cache_key.hash_u64()
-                            // );
-
-                            None
-                        }
-                    },
-                    None => {
-                        debug_panic!("Failed to get atlas for glyph");
+        if let Some(allocation) = self.allocate_in_pages(kind, size, &[]) {
+            return Some(allocation);
+        }
 
-                        None
+        if self.num_pages(kind) < self.options.max_pages {
+            let atlas_id = self.create_atlas(texture_manager, kind, self.new_page_size(kind, size));
+
+            match self.get_atlas_mut(&atlas_id) {
+                Some(atlas) => {
+                    if let Some(alloc) = atlas.try_allocate_space(&size) {
+                        return Some(self.new_allocation(atlas_id, alloc, size));
                     }
                 }
+                None => {
+                    debug_panic!("Failed to get atlas for glyph");
+                }
+            }
+        }
+
+        let allocation = self.evict_for(kind, size);
+
+        if allocation.is_none() {
+            log::warn!(
+                "Failed to allocate {}x{} in the {kind:?} atlases",
+                size.width,
+                size.height
+            );
+            self.stats.allocation_failures += 1;
+        }
+
+        allocation
+    }
+
+    /// Allocates space in an existing page of `kind`, other than those in `exclude`
+    fn allocate_in_pages(
+        &mut self,
+        kind: AtlasContentType,
+        size: PhysicalSize<u32>,
+        exclude: &[AtlasId],
+    ) -> Option<AtlasAllocation> {
+        let (atlas_id, alloc) = self
+            .atlases
+            .iter_mut()
+            .filter(|(id, _)| id.0 == kind && !exclude.contains(id))
+            .find_map(|(id, atlas)| atlas.try_allocate_space(&size).map(|alloc| (*id, alloc)))?;
+
+        Some(self.new_allocation(atlas_id, alloc, size))
+    }
+
+    fn new_allocation(
+        &self,
+        atlas_id: AtlasId,
+        alloc: EtagereAllocation,
+        size: PhysicalSize<u32>,
+    ) -> AtlasAllocation {
+        AtlasAllocation::new(
+            atlas_id,
+            alloc,
+            // TODO: impl Into for euclid
+            Rect::from_min_size(
+                Pos::new(alloc.rectangle.min.x, alloc.rectangle.min.y),
+                size.map(|x| x as i32 + 2 * TextureAtlas::TEXTURE_PADDING as i32),
+            ),
+            self.deallocation_queue.get_ref(),
+            TextureAtlas::TEXTURE_PADDING,
+        )
+    }
+
+    fn num_pages(&self, kind: AtlasContentType) -> usize {
+        self.atlases.keys().filter(|id| id.0 == kind).count()
+    }
+
+    /// Size of the next page of `kind`, which is at least large enough for `size`
+    fn new_page_size(&self, kind: AtlasContentType, size: PhysicalSize<u32>) -> u32 {
+        let padded_size = size.width.max(size.height) + 2 * TextureAtlas::TEXTURE_PADDING;
+
+        let page_size = self
+            .atlases
+            .iter()
+            .filter(|(id, _)| id.0 == kind)
+            .map(|(_, atlas)| atlas.width as u32)
+            .max()
+            .map_or(INITIAL_ATLAS_SIZE, |largest| {
+                (largest * 2).min(MAX_ATLAS_SIZE)
+            });
+
+        page_size.max(padded_size.next_power_of_two())
+    }
+
+    /// Evicts glyphs of `kind` which weren't used this frame, least recently used
+    /// first, until `size` can be allocated
+    fn evict_for(
+        &mut self,
+        kind: AtlasContentType,
+        size: PhysicalSize<u32>,
+    ) -> Option<AtlasAllocation> {
+        let padding = 2 * TextureAtlas::TEXTURE_PADDING as i64;
+        let required_area = (size.width as i64 + padding) * (size.height as i64 + padding);
+
+        let mut candidates = self
+            .glyphs
+            .iter()
+            .filter(|(key, glyph)| {
+                glyph.atlas_id().is_some_and(|id| id.0 == kind)
+                    && !self.used_glyphs_this_frame.contains(*key)
             })
-            .map(|(atlas_id, alloc)| {
-                AtlasAllocation::new(
-                    atlas_id,
-                    alloc,
-                    // TODO: impl Into for euclid
-                    Rect::from_min_size(
-                        Pos::new(alloc.rectangle.min.x, alloc.rectangle.min.y),
-                        size.map(|x| x as i32 + 2 * TextureAtlas::TEXTURE_PADDING as i32),
-                    ),
-                    self.deallocation_queue.get_ref(),
-                    TextureAtlas::TEXTURE_PADDING,
-                )
-            })
+            .map(|(key, glyph)| (glyph.last_used_frame, *key))
+            .collect_vec();
+
+        candidates.sort_unstable();
+
+        let mut freed_area = 0;
+
+        for (_, key) in candidates {
+            if let Some(GlyphCacheEntry::GlyphAllocation(glyph)) = self.glyphs.remove(&key) {
+                freed_area += glyph.allocation.alloc_id.atlas_rect.area() as i64;
+                self.stats.evictions += 1;
+            }
+
+            // freed space may be fragmented, so this can take a few attempts
+            if freed_area >= required_area {
+                self.process_deallocations();
+
+                if let Some(allocation) = self.allocate_in_pages(kind, size, &[]) {
+                    return Some(allocation);
+                }
+            }
+        }
+
+        self.process_deallocations();
+        self.allocate_in_pages(kind, size, &[])
+    }
+
+    fn process_deallocations(&mut self) {
+        for (atlas_id, alloc_id) in self.deallocation_queue.drain() {
+            if let Some(atlas) = self.get_atlas_mut(&atlas_id) {
+                atlas.deallocate_glyph(alloc_id);
+            }
+        }
     }
 
     pub(crate) fn allocate_glyph(
@@ -701,13 +1037,14 @@ impl TextureAtlasManager {
         texture_manager: &TextureManagerRef,
         kind: AtlasContentType,
         image: cosmic_text::SwashImage,
-        cache_key: GlyphCacheKey,
+        cache_key: AtlasGlyphKey,
     ) {
         let glyph_size = PhysicalSize::<u32>::new(image.placement.width, image.placement.height);
         let glyph_placement = PhysicalPos::<i32>::new(image.placement.left, image.placement.top);
 
         if glyph_size.is_empty() {
-            self.glyphs.insert(cache_key, GlyphCacheEntry::Noop);
+            self.glyphs
+                .insert(cache_key, GlyphCacheEntry::Noop, self.frame);
             return;
         }
 
@@ -735,8 +1072,11 @@ impl TextureAtlasManager {
             Some(alloc) => {
                 let alloc_id = alloc.allocation.atlas_id();
 
-                self.glyphs
-                    .insert(cache_key, GlyphCacheEntry::GlyphAllocation(alloc));
+                self.glyphs.insert(
+                    cache_key,
+                    GlyphCacheEntry::GlyphAllocation(alloc),
+                    self.frame,
+                );
 
                 let _atlas = self.get_atlas_mut(&alloc_id).debug_assert();
             }
@@ -744,28 +1084,163 @@ impl TextureAtlasManager {
         }
     }
 
-    fn collect_garbage(&mut self) {
-        let mut glyphs_to_remove: HashSet<_> = self.glyphs.keys().copied().collect();
-        glyphs_to_remove.retain(|key| !self.used_glyphs_this_frame.contains(key));
+    /// Moves the glyphs of pages less occupied than `threshold` into other pages
+    /// of the same kind, so that the emptied pages are freed
+    fn compact(&mut self, threshold: f32) {
+        let mut sparse_pages = self
+            .atlases
+            .iter()
+            .map(|(id, atlas)| (atlas.occupancy(), *id))
+            .filter(|(occupancy, _)| *occupancy < threshold)
+            .collect_vec();
+
+        // emptiest first, since they're the cheapest to move
+        sparse_pages.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut emptied_pages = Vec::new();
+        let mut encoder = None;
+
+        for (_, page_id) in sparse_pages {
+            let glyphs = self
+                .glyphs
+                .iter()
+                .filter_map(|(key, glyph)| match &glyph.entry {
+                    GlyphCacheEntry::GlyphAllocation(alloc)
+                        if alloc.allocation.atlas_id() == page_id =>
+                    {
+                        Some((*key, alloc.size, alloc.allocation.alloc_id.atlas_rect))
+                    }
+                    _ => None,
+                })
+                .collect_vec();
 
-        for key in glyphs_to_remove { self.glyphs.remove(&key); }
+            // pages can't be emptied into themselves, or into pages which were emptied
+            let mut exclude = emptied_pages.clone();
+            exclude.push(page_id);
 
-        self.used_glyphs_this_frame.clear();
+            let mut moves = Vec::with_capacity(glyphs.len());
+            let mut moved_all = true;
 
-        self.atlases.retain(|_, atlas| atlas.num_glyphs > 0);
+            for (key, size, atlas_rect) in glyphs {
+                match self.allocate_in_pages(page_id.0, size, &exclude) {
+                    Some(allocation) => moves.push((key, atlas_rect, allocation)),
+                    // the allocations made so far are dropped, and deallocated
+                    None => {
+                        moved_all = false;
+                        break;
+                    }
+                }
+            }
 
-        for (atlas_id, alloc_id) in self.deallocation_queue.drain() {
-            if let Some(atlas) = self.get_atlas_mut(&atlas_id) {
-                atlas.deallocate_glyph(alloc_id);
+            if !moved_all {
+                continue;
             }
+
+            let encoder = encoder.get_or_insert_with(|| {
+                self.rendering_context.device.create_command_encoder(
+                    &wgpu::CommandEncoderDescriptor {
+                        label: Some("glyph atlas compaction"),
+                    },
+                )
+            });
+
+            for (key, src_rect, allocation) in moves {
+                let dst_rect = allocation.alloc_id.atlas_rect;
+
+                // the padding is copied too, since it's already cleared
+                encoder.copy_texture_to_texture(
+                    wgpu::TexelCopyTextureInfo {
+                        texture: &self.atlases[&page_id].texture_ref.texture,
+                        mip_level: 0,
+                        origin: wgpu::Origin3d {
+                            x: src_rect.min.x as u32,
+                            y: src_rect.min.y as u32,
+                            z: 0,
+                        },
+                        aspect: wgpu::TextureAspect::All,
+                    },
+                    wgpu::TexelCopyTextureInfo {
+                        texture: &self.atlases[&allocation.atlas_id()].texture_ref.texture,
+                        mip_level: 0,
+                        origin: wgpu::Origin3d {
+                            x: dst_rect.min.x as u32,
+                            y: dst_rect.min.y as u32,
+                            z: 0,
+                        },
+                        aspect: wgpu::TextureAspect::All,
+                    },
+                    wgpu::Extent3d {
+                        width: src_rect.width() as u32,
+                        height: src_rect.height() as u32,
+                        depth_or_array_layers: 1,
+                    },
+                );
+
+                // the previous allocation is deallocated when it's dropped
+                if let Some(GlyphCacheEntry::GlyphAllocation(glyph)) = self.glyphs.get_mut(&key) {
+                    glyph.allocation = allocation;
+                }
+
+                self.stats.compacted_glyphs += 1;
+            }
+
+            emptied_pages.push(page_id);
+        }
+
+        if let Some(encoder) = encoder {
+            self.rendering_context.queue.submit([encoder.finish()]);
+        }
+    }
+
+    fn collect_garbage(&mut self) {
+        for key in self.used_glyphs_this_frame.drain() {
+            self.glyphs.touch(&key, self.frame);
+        }
+
+        let unused_glyphs = self
+            .glyphs
+            .iter()
+            .filter(|(_, glyph)| {
+                self.frame.saturating_sub(glyph.last_used_frame) > self.options.max_unused_frames
+            })
+            .map(|(key, _)| *key)
+            .collect_vec();
+
+        self.stats.evictions += unused_glyphs.len();
+
+        for key in unused_glyphs {
+            self.glyphs.remove(&key);
+        }
+
+        self.process_deallocations();
+
+        if let Some(threshold) = self.options.compaction_threshold {
+            self.compact(threshold);
+            self.process_deallocations();
         }
 
-        // log::debug!(
-        //     "Atlas GC result: total number of atlases: {}, allocated glyphs: {}, total glyphs: {}",
-        //     self.atlases.len(),
-        //     self.glyphs.len(),
-        //     self.atlases.values().map(|x| x.num_glyphs).sum::<usize>()
-        // )
+        self.atlases.retain(|_, atlas| atlas.num_glyphs > 0);
+
+        let (allocated_area, total_area) = self
+            .atlases
+            .values()
+            .map(TextureAtlas::area)
+            .fold((0, 0), |(a, t), (allocated, total)| {
+                (a + allocated, t + total)
+            });
+
+        self.last_stats = GlyphAtlasStats {
+            num_pages: self.atlases.len(),
+            num_glyphs: self
+                .glyphs
+                .iter()
+                .filter(|(_, glyph)| glyph.atlas_id().is_some())
+                .count(),
+            occupancy: allocated_area as f32 / total_area.max(1) as f32,
+            ..std::mem::take(&mut self.stats)
+        };
+
+        self.frame += 1;
     }
 }
 
@@ -792,8 +1267,7 @@ impl FontManager {
     }
 
     pub fn process_glyphs(&mut self, text_box: &PlacedTextBox) {
-        let glyph_cache_keys: HashSet<_> =
-            text_box.glyphs.iter().map(|g| g.glyph.cache_key).collect();
+        let glyph_cache_keys: HashSet<_> = text_box.atlas_glyph_keys().collect();
 
         self.atlas_manager
             .write()
@@ -807,7 +1281,7 @@ impl FontManager {
     pub fn prepare<'a>(
         &mut self,
         text_box: PlacedTextBox,
-        output: &mut impl PushVertices<BoxShaderVertex>,
+        output: &mut (impl PushVertices<BoxShaderVertex> + PushGradients + PushTextures),
     ) {
         // if (text_box.clip_rect.map(|x| x.is_empty()).unwrap_or_default()) {
         //     return;
@@ -835,6 +1309,26 @@ impl FontManager {
         self.atlas_manager.write().unwrap().collect_garbage();
     }
 
+    /// Usage of the glyph atlases after the last frame
+    pub fn atlas_stats(&self) -> GlyphAtlasStats {
+        self.atlas_manager.read().unwrap().last_stats
+    }
+
+    pub fn atlas_options(&self) -> GlyphAtlasOptions {
+        self.atlas_manager.read().unwrap().options
+    }
+
+    pub fn set_atlas_options(&mut self, options: GlyphAtlasOptions) {
+        self.atlas_manager.write().unwrap().options = options;
+    }
+
+    /// Time spent rasterizing glyphs since this was last called. Glyphs are
+    /// rasterized on worker threads, so this includes work started in earlier
+    /// frames.
+    pub fn take_glyph_rasterization_time(&mut self) -> Duration {
+        std::mem::take(&mut self.atlas_manager.write().unwrap().glyph_rasterization_time)
+    }
+
     // pub fn render<'a, 'b, 'c>(
     //     &self,
     //     render_pass: &'a mut wgpu::RenderPass<'b>,
@@ -846,11 +1340,13 @@ impl FontManager {
     // }
 
     fn generate_textures_worker(
-        mut glyphs: HashSet<GlyphCacheKey>,
+        mut glyphs: HashSet<AtlasGlyphKey>,
         mut atlas_manager: impl WriteLockable<TextureAtlasManager> + Sync,
         font_system: FontSystemRef,
         texture_manager: TextureManagerRef,
     ) {
+        let start = Instant::now();
+
         #[cfg(not(target_arch = "wasm32"))]
         let drain_iter = glyphs.par_drain();
 
@@ -858,19 +1354,29 @@ impl FontManager {
         #[cfg(target_arch = "wasm32")]
         let drain_iter = glyphs.drain();
 
-        let results: Vec<(GlyphCacheKey, cosmic_text::SwashImage)> = drain_iter
+        let results: Vec<(AtlasGlyphKey, cosmic_text::SwashImage)> = drain_iter
             .map(|cache_key| {
                 if atlas_manager.read_lock().has_glyph(&cache_key) {
                     return None;
                 }
 
-                match rasterize_glyph(&cache_key, font_system.as_ref()) {
+                // distance fields are scaled, so they aren't hinted to the pixel grid
+                let hint = cache_key.mode == GlyphRenderMode::Bitmap;
+
+                match rasterize_glyph(&cache_key.cache_key, hint, font_system.as_ref()) {
+                    Some(image) if cache_key.mode == GlyphRenderMode::Sdf => {
+                        log::trace!(
+                            "rasterized distance field for glyph {:?}",
+                            cache_key.cache_key.glyph_id
+                        );
+                        Some((cache_key, distance_field_image(image)))
+                    }
                     Some(image) => {
-                        log::trace!("rasterized glyph {:?}", cache_key.glyph_id);
+                        log::trace!("rasterized glyph {:?}", cache_key.cache_key.glyph_id);
                         Some((cache_key, image))
                     }
                     None => {
-                        log::error!("failed to render glyph {}!", cache_key.glyph_id);
+                        log::error!("failed to render glyph {}!", cache_key.cache_key.glyph_id);
 
                         None
                     }
@@ -893,9 +1399,11 @@ impl FontManager {
                     .allocate_glyph(&texture_manager, kind, image, cache_key);
             }
         }
+
+        atlas_manager.write_lock().glyph_rasterization_time += start.elapsed();
     }
 
-    pub fn generate_textures<'a>(&mut self, glyphs: HashSet<GlyphCacheKey>) {
+    pub fn generate_textures<'a>(&mut self, glyphs: HashSet<AtlasGlyphKey>) {
         let atlas_manager = self.atlas_manager.clone();
         let font_system = self.font_system.clone();
         let texture_manager = self.texture_manager.clone();
@@ -928,8 +1436,9 @@ thread_local! {
     static SCALE_CONTEXT: RefCell<ScaleContext> = RefCell::new(ScaleContext::new())
 }
 
-fn rasterize_glyph(
+pub(crate) fn rasterize_glyph(
     cache_key: &GlyphCacheKey,
+    hint: bool,
     font_system: &Mutex<FontSystem>,
 ) -> Option<cosmic_text::SwashImage> {
     log::trace!("Rasterizing glyph {:x}", cache_key.hash_u64());
@@ -977,9 +1486,232 @@ fn rasterize_glyph(
         let mut scaler = context
             .builder(font.as_swash())
             .size(f32::from_bits(cache_key.font_size_bits))
-            .hint(true)
+            .hint(hint)
             .build();
 
         render.render(&mut scaler, cache_key.glyph_id)
     })
 }
+
+/// Converts a coverage mask into a distance field, padded by
+/// [`GlyphRenderMode::SDF_SPREAD`] so the field falls off outside the outline.
+/// Color glyphs are left as they are.
+pub(crate) fn distance_field_image(mut image: cosmic_text::SwashImage) -> cosmic_text::SwashImage {
+    let (width, height) = (
+        image.placement.width as usize,
+        image.placement.height as usize,
+    );
+
+    if !matches!(image.content, cosmic_text::SwashContent::Mask) || width == 0 || height == 0 {
+        return image;
+    }
+
+    let spread = GlyphRenderMode::SDF_SPREAD;
+    let padding = spread as usize;
+    let padded_width = width + 2 * padding;
+    let padded_height = height + 2 * padding;
+
+    let mut padded = vec![0; padded_width * padded_height];
+
+    for (y, row) in image.data.chunks_exact(width).enumerate() {
+        let start = (y + padding) * padded_width + padding;
+        padded[start..start + width].copy_from_slice(row);
+    }
+
+    image.data = signed_distance_field(&padded, padded_width, padded_height, spread);
+
+    image.placement.left -= spread as i32;
+    image.placement.top += spread as i32;
+    image.placement.width = padded_width as u32;
+    image.placement.height = padded_height as u32;
+
+    image
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::surface::{OffscreenSurface, SurfaceLike};
+
+    fn glyph_key(glyph_id: u16) -> AtlasGlyphKey {
+        let (cache_key, _, _) = cosmic_text::CacheKey::new(
+            fontdb::ID::dummy(),
+            glyph_id,
+            16.,
+            (0., 0.),
+            cosmic_text::CacheKeyFlags::empty(),
+        );
+
+        AtlasGlyphKey::new(cache_key, GlyphRenderMode::Bitmap)
+    }
+
+    #[test]
+    fn glyphs_are_unchanged_until_moved_or_removed() {
+        let mut cache = GlyphCache::default();
+        let (a, b, c) = (glyph_key(1), glyph_key(2), glyph_key(3));
+
+        cache.insert(a, GlyphCacheEntry::Noop, 0);
+        cache.insert(b, GlyphCacheEntry::Noop, 0);
+
+        let generation = cache.generation;
+
+        // glyphs added elsewhere don't matter
+        cache.insert(c, GlyphCacheEntry::Noop, 0);
+        assert!(cache.unchanged_since(&[a, b], generation));
+        assert!(!cache.unchanged_since(&[a, c], generation));
+
+        cache.get_mut(&a);
+        assert!(!cache.unchanged_since(&[a], generation));
+        assert!(cache.unchanged_since(&[b], generation));
+
+        cache.remove(&b);
+        assert!(!cache.unchanged_since(&[b], generation));
+    }
+
+    fn test_manager(
+        options: GlyphAtlasOptions,
+    ) -> Option<(TextureAtlasManager, TextureManagerRef)> {
+        let rendering_context = OffscreenSurface::for_tests(1, 1)?.clone_rendering_context();
+        let texture_manager = TextureManagerRef::from_device_limits(&rendering_context);
+
+        let mut manager = TextureAtlasManager::new(rendering_context);
+        manager.options = options;
+
+        Some((manager, texture_manager))
+    }
+
+    fn cache_glyph(
+        manager: &mut TextureAtlasManager,
+        key: AtlasGlyphKey,
+        allocation: AtlasAllocation,
+        size: PhysicalSize<u32>,
+        frame: u64,
+    ) {
+        let glyph = GlyphAllocation {
+            allocation,
+            size,
+            placement: PhysicalPos::zero(),
+        };
+
+        manager
+            .glyphs
+            .insert(key, GlyphCacheEntry::GlyphAllocation(glyph), frame);
+    }
+
+    fn page_of(manager: &TextureAtlasManager, key: &AtlasGlyphKey) -> Option<AtlasId> {
+        match manager.glyphs.get(key)? {
+            GlyphCacheEntry::GlyphAllocation(glyph) => Some(glyph.allocation.atlas_id()),
+            GlyphCacheEntry::Noop => None,
+        }
+    }
+
+    #[test]
+    fn least_recently_used_glyphs_are_evicted_first() {
+        let Some((mut manager, texture_manager)) =
+            test_manager(GlyphAtlasOptions::default().with_max_pages(1))
+        else {
+            return;
+        };
+
+        // two of these fit into the first page, but not three
+        let size = PhysicalSize::new(600, 400);
+        let (a, b, c) = (glyph_key(1), glyph_key(2), glyph_key(3));
+
+        for (key, frame) in [(b, 1), (a, 0)] {
+            let allocation = manager
+                .allocate(&texture_manager, AtlasContentType::Mask, size)
+                .unwrap();
+            cache_glyph(&mut manager, key, allocation, size, frame);
+        }
+
+        let allocation = manager
+            .allocate(&texture_manager, AtlasContentType::Mask, size)
+            .unwrap();
+        cache_glyph(&mut manager, c, allocation, size, 2);
+
+        assert!(!manager.has_glyph(&a));
+        assert!(manager.has_glyph(&b));
+        assert!(manager.has_glyph(&c));
+        assert_eq!(manager.stats.evictions, 1);
+    }
+
+    #[test]
+    fn pages_which_cant_be_emptied_are_left_intact() {
+        let Some((mut manager, texture_manager)) = test_manager(Default::default()) else {
+            return;
+        };
+
+        // fills the first page
+        let large = PhysicalSize::new(1000, 1000);
+        let (full, large_key, small_key) = (glyph_key(1), glyph_key(2), glyph_key(3));
+
+        let allocation = manager
+            .allocate(&texture_manager, AtlasContentType::Mask, large)
+            .unwrap();
+        let full_page = allocation.atlas_id();
+        cache_glyph(&mut manager, full, allocation, large, 0);
+
+        let allocation = manager
+            .allocate(&texture_manager, AtlasContentType::Mask, large)
+            .unwrap();
+        let sparse_page = allocation.atlas_id();
+        cache_glyph(&mut manager, large_key, allocation, large, 0);
+
+        assert_ne!(full_page, sparse_page);
+
+        // the large glyph doesn't fit into the full page, so neither is moved
+        let small = PhysicalSize::new(10, 10);
+        let allocation = manager
+            .allocate_in_pages(AtlasContentType::Mask, small, &[full_page])
+            .unwrap();
+        cache_glyph(&mut manager, small_key, allocation, small, 0);
+
+        manager.compact(0.5);
+        manager.process_deallocations();
+
+        assert_eq!(page_of(&manager, &large_key), Some(sparse_page));
+        assert_eq!(page_of(&manager, &small_key), Some(sparse_page));
+        assert_eq!(manager.atlases[&full_page].num_glyphs, 1);
+        assert_eq!(manager.atlases[&sparse_page].num_glyphs, 2);
+        assert_eq!(manager.stats.compacted_glyphs, 0);
+    }
+
+    #[test]
+    fn stats_count_glyphs_pages_and_failures() {
+        let Some((mut manager, texture_manager)) =
+            test_manager(GlyphAtlasOptions::default().with_max_pages(1))
+        else {
+            return;
+        };
+
+        let size = PhysicalSize::new(1000, 1000);
+        let (used, unused) = (glyph_key(1), glyph_key(2));
+
+        let allocation = manager
+            .allocate(&texture_manager, AtlasContentType::Mask, size)
+            .unwrap();
+        cache_glyph(&mut manager, used, allocation, size, 0);
+
+        manager.mark_glyphs_used(&[used]);
+
+        // the page is full, and its only glyph was used this frame
+        assert!(manager
+            .allocate(&texture_manager, AtlasContentType::Mask, size)
+            .is_none());
+
+        manager.glyphs.insert(unused, GlyphCacheEntry::Noop, 0);
+        manager.collect_garbage();
+
+        let stats = manager.last_stats;
+
+        assert_eq!(stats.num_pages, 1);
+        assert_eq!(stats.num_glyphs, 1);
+        assert_eq!(stats.evictions, 0);
+        assert_eq!(stats.allocation_failures, 1);
+        assert_eq!(stats.compacted_glyphs, 0);
+        assert!(stats.occupancy > 0.9);
+
+        // the next frame starts counting again
+        assert_eq!(manager.stats, GlyphAtlasStats::default());
+    }
+}
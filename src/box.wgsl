@@ -4,11 +4,21 @@ const FEATHERING = 1.;
 const shapeRect = 0;
 /// Triangle mesh
 const shapeMesh = 1;
+/// Glyph with a signed distance field mask, whose spread is the stroke width
+const shapeSdfGlyph = 2;
 
 const fillModeColor = 0;
 const fillModeTexture = 1;
 const fillModeTextureMaskColor = 2;
-const fillModeTextureMaskTexture = 2;
+const fillModeTextureMaskTexture = 3;
+const fillModeGradient = 4;
+const fillModeTextureMaskGradient = 5;
+
+const gradientLinear = 0;
+const gradientRadial = 1;
+const gradientConic = 2;
+
+const MAX_GRADIENT_STOPS = 8;
 
 struct Params {
     screen_resolution: vec2<u32>,
@@ -20,6 +30,22 @@ struct ClipRect {
     half_size: vec2<f32>,
     rounding: f32,
     transformation_idx: u32,
+    // 0 if this clip rect isn't nested in another
+    parent_idx: u32,
+    _padding: u32,
+};
+
+struct Gradient {
+    kind: u32,
+    /// 0 for linear, 1 for sRGB
+    interpolation: u32,
+    num_stops: u32,
+    /// linear: start, radial/conic: center
+    p0: vec2<f32>,
+    /// linear: end, radial: (radius, _), conic: (angle, _)
+    p1: vec2<f32>,
+    colors: array<vec4<f32>, MAX_GRADIENT_STOPS>,
+    offsets: array<f32, MAX_GRADIENT_STOPS>,
 };
 
 struct VertexInput {
@@ -51,6 +77,31 @@ struct VertexInput {
     @location(14) uv_alt: vec2<f32>,
 }
 
+/// A rectangle, drawn as an instance of a unit quad
+struct RectInstanceInput {
+    /// Corner of the unit quad
+    @location(0) corner: vec2<f32>,
+
+    @location(1) fillMode: u32,
+
+    /// min and max corners
+    @location(2) rect: vec4<f32>,
+
+    @location(3) uv: vec4<f32>,
+    @location(4) uv_alt: vec4<f32>,
+    @location(5) atlas_idx: u32,
+
+    @location(6) color: vec4<f32>,
+
+    @location(7) rounding: f32,
+    @location(8) stroke_width: f32,
+    @location(9) blur_radius: f32,
+
+    @location(10) clip_rect_idx: u32,
+
+    @location(11) transformation_idx: u32,
+}
+
 struct VertexOutput {
     @invariant @builtin(position) position: vec4<f32>,
 
@@ -215,6 +266,9 @@ var<storage, read> transformations: array<mat3x2<f32>>;
 @group(0) @binding(3)
 var<storage, read> transformation_inversions: array<mat3x2<f32>>;
 
+@group(0) @binding(4)
+var<storage, read> gradients: array<Gradient>;
+
 {{#times num_atlas_textures}}
 @group(1) @binding({{index}})
 var atlas_texture_{{index}}: texture_2d<f32>;
@@ -227,6 +281,43 @@ var atlas_sampler: sampler;
 fn vs_main(
     vertex_in: VertexInput
 ) -> VertexOutput {
+    return shape_vertex(vertex_in);
+}
+
+@vertex
+fn vs_rect(
+    instance: RectInstanceInput
+) -> VertexOutput {
+    var vertex_in: VertexInput;
+
+    let rect_min = instance.rect.xy;
+    let rect_max = instance.rect.zw;
+
+    vertex_in.shapeType = u32(shapeRect);
+    vertex_in.fillMode = instance.fillMode;
+
+    vertex_in.pos = mix(rect_min, rect_max, instance.corner);
+
+    vertex_in.dims = (rect_max - rect_min) / 2.;
+    vertex_in.origin = (rect_min + rect_max) / 2.;
+
+    vertex_in.uv = mix(instance.uv.xy, instance.uv.zw, instance.corner);
+    vertex_in.uv_alt = mix(instance.uv_alt.xy, instance.uv_alt.zw, instance.corner);
+    vertex_in.atlas_idx = instance.atlas_idx;
+
+    vertex_in.color = instance.color;
+
+    vertex_in.rounding = instance.rounding;
+    vertex_in.stroke_width = instance.stroke_width;
+    vertex_in.blur_radius = instance.blur_radius;
+
+    vertex_in.clip_rect_idx = instance.clip_rect_idx;
+    vertex_in.transformation_idx = instance.transformation_idx;
+
+    return shape_vertex(vertex_in);
+}
+
+fn shape_vertex(vertex_in: VertexInput) -> VertexOutput {
     var vertex_out: VertexOutput;
 
     var transformation_cols = transpose(transformations[vertex_in.transformation_idx]);
@@ -337,6 +428,75 @@ fn sdSharpBox(p: vec2<f32>, b: vec2<f32>) -> f32 {
     return max(qm.x, qm.y) + min(max(q.x, q.y), 0.0);
 }
 
+// Coverage of a pixel from a signed distance field sample, where 0.5 is the
+// outline and `spread` is the distance in local units from there to 0 or 1
+fn sdf_coverage(value: f32, spread: f32, scale_fac: f32) -> f32 {
+    // physical pixels per local unit
+    let pixels_per_unit = sqrt(abs(scale_fac) / params.scale_factor);
+    let dist = (value - 0.5) * 2. * spread * pixels_per_unit;
+
+    return clamp(dist + 0.5, 0., 1.);
+}
+
+fn premultiply(col: vec4<f32>) -> vec4<f32> {
+    return vec4<f32>(col.rgb * col.a, col.a);
+}
+
+// Evaluates a gradient at a point in the shape's local coordinates
+fn gradient_color(gradient_idx: u32, p: vec2<f32>) -> vec4<f32> {
+    var g = gradients[gradient_idx];
+
+    if g.num_stops == 0u {
+        return vec4<f32>(0.);
+    }
+
+    var t = 0.;
+
+    switch (g.kind) {
+        case 0u: { // gradientLinear
+            let d = g.p1 - g.p0;
+            t = dot(p - g.p0, d) / max(dot(d, d), 1e-6);
+        }
+
+        case 1u: { // gradientRadial
+            t = length(p - g.p0) / max(g.p1.x, 1e-6);
+        }
+
+        case 2u: { // gradientConic
+            let v = p - g.p0;
+            t = fract((atan2(v.y, v.x) - g.p1.x) / (2. * pi));
+        }
+
+        default: { }
+    }
+
+    t = clamp(t, 0., 1.);
+
+    // stops are interpolated premultiplied, to avoid dark fringes around transparent stops
+    var col = premultiply(g.colors[0]);
+
+    for (var i = 1u; i < g.num_stops; i += 1u) {
+        let a = g.offsets[i - 1u];
+
+        if t > a {
+            let s = clamp((t - a) / max(g.offsets[i] - a, 1e-6), 0., 1.);
+            col = mix(premultiply(g.colors[i - 1u]), premultiply(g.colors[i]), s);
+        }
+    }
+
+    if col.a <= 0. {
+        return vec4<f32>(0.);
+    }
+
+    var rgb = col.rgb / col.a;
+
+    if g.interpolation == 1u {
+        rgb = vec3<f32>(srgb_to_linear(rgb.r), srgb_to_linear(rgb.g), srgb_to_linear(rgb.b));
+    }
+
+    return vec4<f32>(rgb, col.a);
+}
+
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
     var col = in.color;
@@ -370,6 +530,15 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
             color_uv = in.uv_alt;
         }
 
+        case 4u: { // fillModeGradient
+            col *= gradient_color(in.atlas_idx_alt, in.pos);
+        }
+
+        case 5u: { // fillModeTextureMaskGradient
+            col *= gradient_color(in.atlas_idx_alt, in.pos);
+            do_mask_texture = true;
+        }
+
         default: { }
     }
 
@@ -384,7 +553,7 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
                 var sampled_hsv = rgb_to_hsv(sampled_col.rgb);
                 var col_fixed = hsv_to_rgb(vec3<f32>(sampled_hsv.x, sampled_hsv.y, srgb_to_linear(sampled_hsv.z)));
 
-                col = vec4<f32>(col_fixed, sampled_col.a);
+                col *= vec4<f32>(col_fixed, sampled_col.a);
             }
             {{/times}}
 
@@ -397,6 +566,11 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
             {{#times num_atlas_textures}}
             case {{index}}u: {
                 var alpha = textureSampleLevel(atlas_texture_{{index}}, atlas_sampler, mask_uv, 0.0).x;
+
+                if in.shapeType == 2u { // shapeSdfGlyph
+                    alpha = sdf_coverage(alpha, in.stroke_width, in.scale_factor);
+                }
+
                 col = vec4<f32>(col.rgb, col.a * alpha);
             }
             {{/times}}
@@ -409,22 +583,28 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
 
     if alpha == 0. { discard; }
 
-    if (in.clip_rect_idx != 0u) {
-        let clip_rect = clip_rects[in.clip_rect_idx];
+    // nested clip rects intersect, so walk up to the outermost one
+    var clip_rect_idx = in.clip_rect_idx;
+
+    while (clip_rect_idx != 0u) {
+        let clip_rect = clip_rects[clip_rect_idx];
 
-        // TODO: pass clip rect instead of clip_rect_idx
         var clip_transform_cols = transpose(transformation_inversions[clip_rect.transformation_idx]);
         var clip_transform = transpose(mat3x3<f32>(
             clip_transform_cols[0], 
             clip_transform_cols[1], 
             vec3<f32>(0.0, 0.0, 1.0),
-        )) * (1. / params.scale_factor);
+        ));
 
-        var clip_pos = (clip_transform * vec3<f32>(in.original_pos, 1.)).xy;
+        // positions are in physical pixels, so they're scaled back before the
+        // inverse is applied, rather than scaling its translation too
+        var clip_pos = (clip_transform * vec3<f32>(in.original_pos / params.scale_factor, 1.)).xy;
 
         var clip_dist = sdRoundBox(clip_pos - clip_rect.origin, clip_rect.half_size, clip_rect.rounding);
 
         alpha *= sdSmoothStep(clip_dist);
+
+        clip_rect_idx = clip_rect.parent_idx;
     }
 
     if alpha == 0. { discard; }
@@ -472,7 +652,7 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
 
     var res = vec4<f32>(col.rgb, alpha);
 
-    return res;
+    return premultiply(res);
 }
 
 // 24 x 16
\ No newline at end of file
@@ -0,0 +1,319 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::mpsc,
+};
+
+use crate::{
+    image::ImageData,
+    math::{DeviceScaleFactor, PhysicalPos, PhysicalRect, PhysicalSize, Rect},
+    surface::{read_texture_rgba8, RenderingContext},
+};
+
+#[derive(Debug)]
+pub enum CaptureError {
+    Io(io::Error),
+    Png(png::EncodingError),
+    /// Only 8 bit RGBA and BGRA frames can be read back
+    UnsupportedFormat(wgpu::TextureFormat),
+    /// The frame's texture wasn't created with `COPY_SRC`, which some surfaces
+    /// don't support
+    NotCopyable,
+    /// The requested region doesn't overlap the frame
+    EmptyRegion,
+}
+
+impl From<io::Error> for CaptureError {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl From<png::EncodingError> for CaptureError {
+    fn from(value: png::EncodingError) -> Self {
+        Self::Png(value)
+    }
+}
+
+pub type CaptureResult<T> = Result<T, CaptureError>;
+
+/// The pixels of a rendered frame, or of a region of it
+#[derive(Clone, Debug)]
+pub struct FrameCapture {
+    image: ImageData,
+    scale_factor: DeviceScaleFactor,
+}
+
+impl FrameCapture {
+    /// Reads back the physical pixels covering a logical `region` of `texture`
+    pub(crate) fn read(
+        rendering_context: &RenderingContext,
+        texture: &wgpu::Texture,
+        region: Option<Rect>,
+        scale_factor: DeviceScaleFactor,
+    ) -> CaptureResult<Self> {
+        use wgpu::TextureFormat;
+
+        let is_bgra = match texture.format() {
+            TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb => false,
+            TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb => true,
+            format => return Err(CaptureError::UnsupportedFormat(format)),
+        };
+
+        if !texture.usage().contains(wgpu::TextureUsages::COPY_SRC) {
+            return Err(CaptureError::NotCopyable);
+        }
+
+        let size = PhysicalSize::new(texture.width(), texture.height());
+
+        let region =
+            physical_region(region, scale_factor, size).ok_or(CaptureError::EmptyRegion)?;
+
+        let mut pixels = read_texture_rgba8(
+            rendering_context,
+            texture,
+            wgpu::Origin3d {
+                x: region.min.x,
+                y: region.min.y,
+                z: 0,
+            },
+            region.width(),
+            region.height(),
+        );
+
+        if is_bgra {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+
+        let image =
+            ImageData::from_rgba8(PhysicalSize::new(region.width(), region.height()), pixels)
+                .expect("readback should match the region's size");
+
+        Ok(Self {
+            image,
+            scale_factor,
+        })
+    }
+
+    /// Tightly packed RGBA8 pixels, which are sRGB encoded for sRGB surfaces
+    #[inline(always)]
+    pub fn image(&self) -> &ImageData {
+        &self.image
+    }
+
+    #[inline(always)]
+    pub fn into_image(self) -> ImageData {
+        self.image
+    }
+
+    #[inline(always)]
+    pub fn size(&self) -> PhysicalSize<u32> {
+        self.image.size()
+    }
+
+    /// The scale factor the frame was rendered at
+    #[inline(always)]
+    pub fn scale_factor(&self) -> DeviceScaleFactor {
+        self.scale_factor
+    }
+
+    pub fn encode_png(&self, writer: impl io::Write) -> CaptureResult<()> {
+        let PhysicalSize { width, height, .. } = self.size();
+
+        let mut encoder = png::Encoder::new(writer, width, height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(self.image.pixels())?;
+        writer.finish()?;
+
+        Ok(())
+    }
+
+    pub fn to_png(&self) -> CaptureResult<Vec<u8>> {
+        let mut bytes = Vec::new();
+        self.encode_png(&mut bytes)?;
+
+        Ok(bytes)
+    }
+
+    pub fn save_png(&self, path: impl AsRef<Path>) -> CaptureResult<()> {
+        let file = std::fs::File::create(path)?;
+        self.encode_png(io::BufWriter::new(file))
+    }
+}
+
+/// Where captured frames are delivered
+#[derive(Debug)]
+pub enum CaptureOutput {
+    Channel(mpsc::Sender<FrameCapture>),
+    Png(PathBuf),
+    /// Numbered PNGs (`00000.png`, `00001.png`, ...) in an existing directory,
+    /// one for each of the next `num_frames` frames
+    Sequence {
+        directory: PathBuf,
+        num_frames: u32,
+    },
+}
+
+/// Captures the next rendered frame. See
+/// [`crate::scene::ctx::SceneContext::request_capture`].
+#[derive(Debug)]
+pub struct CaptureRequest {
+    /// Logical region of the frame, or the whole frame if `None`
+    pub region: Option<Rect>,
+    pub output: CaptureOutput,
+    /// Frames captured so far
+    frame_idx: u32,
+}
+
+impl CaptureRequest {
+    pub fn new(output: CaptureOutput) -> Self {
+        Self {
+            region: None,
+            output,
+            frame_idx: 0,
+        }
+    }
+
+    /// Sends the frame to the returned receiver
+    pub fn channel() -> (Self, mpsc::Receiver<FrameCapture>) {
+        let (sender, receiver) = mpsc::channel();
+        (Self::new(CaptureOutput::Channel(sender)), receiver)
+    }
+
+    pub fn png(path: impl Into<PathBuf>) -> Self {
+        Self::new(CaptureOutput::Png(path.into()))
+    }
+
+    pub fn sequence(directory: impl Into<PathBuf>, num_frames: u32) -> Self {
+        Self::new(CaptureOutput::Sequence {
+            directory: directory.into(),
+            num_frames,
+        })
+    }
+
+    #[inline]
+    pub fn with_region(mut self, region: impl Into<Rect>) -> Self {
+        self.region = Some(region.into());
+        self
+    }
+
+    fn capture(
+        &self,
+        rendering_context: &RenderingContext,
+        texture: &wgpu::Texture,
+        scale_factor: DeviceScaleFactor,
+    ) -> CaptureResult<()> {
+        let frame = FrameCapture::read(rendering_context, texture, self.region, scale_factor)?;
+
+        match &self.output {
+            // the receiver may have been dropped, which is fine
+            CaptureOutput::Channel(sender) => drop(sender.send(frame)),
+            CaptureOutput::Png(path) => frame.save_png(path)?,
+            CaptureOutput::Sequence { directory, .. } => {
+                frame.save_png(directory.join(format!("{:05}.png", self.frame_idx)))?
+            }
+        }
+
+        Ok(())
+    }
+
+    #[inline(always)]
+    fn is_finished(&self) -> bool {
+        match self.output {
+            CaptureOutput::Sequence { num_frames, .. } => self.frame_idx >= num_frames,
+            _ => self.frame_idx > 0,
+        }
+    }
+}
+
+/// Captures a frame rendered into `texture` for each request, returning the
+/// sequences which have frames left
+pub(crate) fn capture_texture(
+    rendering_context: &RenderingContext,
+    texture: &wgpu::Texture,
+    scale_factor: DeviceScaleFactor,
+    requests: Vec<CaptureRequest>,
+) -> Vec<CaptureRequest> {
+    requests
+        .into_iter()
+        .filter_map(|mut request| {
+            if let Err(err) = request.capture(rendering_context, texture, scale_factor) {
+                log::error!("failed to capture frame: {err:?}");
+                return None;
+            }
+
+            request.frame_idx += 1;
+
+            (!request.is_finished()).then_some(request)
+        })
+        .collect()
+}
+
+/// The pixels covered by a logical `region` of a frame, clipped to the frame
+fn physical_region(
+    region: Option<Rect>,
+    scale_factor: DeviceScaleFactor,
+    size: PhysicalSize<u32>,
+) -> Option<PhysicalRect<u32>> {
+    let bounds = PhysicalRect::from_min_size(PhysicalPos::zero(), size.map(|x| x as f32));
+
+    let region = match region {
+        // partially covered pixels are included
+        Some(region) => {
+            let region: PhysicalRect = (region * scale_factor.as_float()).cast_unit();
+            PhysicalRect::new(region.min.map(f32::floor), region.max.map(f32::ceil))
+        }
+        None => bounds,
+    };
+
+    region
+        .intersection(&bounds)
+        .map(|region| region.map(|x| x as u32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::Pos;
+
+    #[test]
+    fn region_is_scaled_and_clipped() {
+        let scale_factor = DeviceScaleFactor::from_float(2.);
+        let size = PhysicalSize::new(8, 8);
+
+        assert_eq!(
+            physical_region(None, scale_factor, size),
+            Some(PhysicalRect::new(
+                PhysicalPos::zero(),
+                PhysicalPos::new(8, 8)
+            ))
+        );
+
+        assert_eq!(
+            physical_region(
+                Some(Rect::new(Pos::new(1.25, 0.), Pos::new(2., 10.))),
+                scale_factor,
+                size
+            ),
+            Some(PhysicalRect::new(
+                PhysicalPos::new(2, 0),
+                PhysicalPos::new(4, 8)
+            ))
+        );
+
+        assert_eq!(
+            physical_region(
+                Some(Rect::new(Pos::new(5., 5.), Pos::new(6., 6.))),
+                scale_factor,
+                size
+            ),
+            None
+        );
+    }
+}
@@ -1,6 +1,5 @@
 use std::ptr::NonNull;
 
-use icrate::Foundation::CGFloat;
 use palette::*;
 
 pub type ColorRgba = LinSrgba;
@@ -0,0 +1,32 @@
+struct LayerParams {
+    opacity: f32,
+};
+
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var layer_texture: texture_2d<f32>;
+
+@group(0) @binding(1)
+var<uniform> layer_params: LayerParams;
+
+// A single triangle covering the whole target
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_idx: u32) -> VertexOutput {
+    var out: VertexOutput;
+
+    let uv = vec2<f32>(f32((vertex_idx << 1u) & 2u), f32(vertex_idx & 2u));
+    out.position = vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // layer textures are the same size as the target, and hold premultiplied color
+    let col = textureLoad(layer_texture, vec2<i32>(in.position.xy), 0);
+
+    return col * layer_params.opacity;
+}
@@ -0,0 +1,144 @@
+use std::sync::{Arc, Mutex};
+
+use crate::{
+    math::PhysicalSize,
+    surface::RenderingContext,
+    texture::{TextureManagerRef, TextureRef},
+};
+
+/// Everything a [`RenderCallback`] needs to draw into its texture
+pub struct CustomRenderContext<'a> {
+    pub device: &'a wgpu::Device,
+    pub queue: &'a wgpu::Queue,
+    /// Commands recorded here are submitted before the frame's main render pass
+    pub encoder: &'a mut wgpu::CommandEncoder,
+    pub texture: &'a TextureRef,
+}
+
+impl CustomRenderContext<'_> {
+    #[inline(always)]
+    pub fn size(&self) -> PhysicalSize<u32> {
+        PhysicalSize::new(self.texture.texture.width(), self.texture.texture.height())
+    }
+
+    #[inline(always)]
+    pub fn format(&self) -> wgpu::TextureFormat {
+        self.texture.texture.format()
+    }
+}
+
+/// Draws custom GPU content (e.g. with its own render pipelines) into the texture
+/// of a [`CustomRenderTarget`]
+pub trait RenderCallback: Send {
+    fn render(&mut self, ctx: CustomRenderContext<'_>);
+}
+
+impl<F: FnMut(CustomRenderContext<'_>) + Send> RenderCallback for F {
+    #[inline(always)]
+    fn render(&mut self, ctx: CustomRenderContext<'_>) {
+        self(ctx)
+    }
+}
+
+/// A texture owned by an element, which is drawn into by a [`RenderCallback`]
+/// before each frame it's queued in.
+///
+/// See [`crate::scene::ctx::SceneContext::render_custom`].
+pub struct CustomRenderTarget {
+    callback: Arc<Mutex<dyn RenderCallback>>,
+    format: wgpu::TextureFormat,
+    texture: Option<TextureRef>,
+}
+
+impl CustomRenderTarget {
+    pub fn new(callback: impl RenderCallback + 'static) -> Self {
+        Self {
+            callback: Arc::new(Mutex::new(callback)),
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            texture: None,
+        }
+    }
+
+    pub fn with_format(mut self, format: wgpu::TextureFormat) -> Self {
+        self.format = format;
+        self.texture = None;
+        self
+    }
+
+    /// The texture from the last time this target was queued, if any
+    #[inline(always)]
+    pub fn texture(&self) -> Option<&TextureRef> {
+        self.texture.as_ref()
+    }
+
+    /// (Re)creates the texture if it doesn't match `size`
+    pub(crate) fn prepare(
+        &mut self,
+        rendering_context: &RenderingContext,
+        texture_manager: &TextureManagerRef,
+        size: PhysicalSize<u32>,
+    ) -> &TextureRef {
+        let size = size.max(PhysicalSize::new(1, 1));
+
+        let matches = self.texture.as_ref().is_some_and(|texture| {
+            texture.texture.width() == size.width && texture.texture.height() == size.height
+        });
+
+        if !matches {
+            // free the old texture first
+            self.texture = None;
+
+            let texture = rendering_context
+                .device
+                .create_texture(&wgpu::TextureDescriptor {
+                    label: Some("custom render target"),
+                    size: wgpu::Extent3d {
+                        width: size.width,
+                        height: size.height,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: self.format,
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                        | wgpu::TextureUsages::TEXTURE_BINDING
+                        | wgpu::TextureUsages::COPY_SRC
+                        | wgpu::TextureUsages::COPY_DST,
+                    view_formats: &[],
+                });
+
+            self.texture = Some(texture_manager.write().unwrap().register_texture(texture));
+        }
+
+        self.texture.as_ref().unwrap()
+    }
+
+    pub(crate) fn queued_render(&self) -> Option<QueuedCustomRender> {
+        Some(QueuedCustomRender {
+            callback: self.callback.clone(),
+            texture: self.texture.clone()?,
+        })
+    }
+}
+
+/// A [`RenderCallback`] to run before a frame's main render pass
+pub struct QueuedCustomRender {
+    callback: Arc<Mutex<dyn RenderCallback>>,
+    texture: TextureRef,
+}
+
+impl QueuedCustomRender {
+    pub(crate) fn render(
+        &self,
+        RenderingContext { device, queue, .. }: &RenderingContext,
+        encoder: &mut wgpu::CommandEncoder,
+    ) {
+        self.callback.lock().unwrap().render(CustomRenderContext {
+            device,
+            queue,
+            encoder,
+            texture: &self.texture,
+        });
+    }
+}
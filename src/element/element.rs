@@ -5,6 +5,7 @@ use uuid::Uuid;
 use crate::accessibility::{AccessNodeBuilder, AccessNodeId};
 use crate::debug::HashU64;
 use crate::input::input_state::InputState;
+use crate::layer::{Layer, ZIndex};
 use crate::math::CoordinateTransform;
 use crate::refbox::{self, coerce_weak, RefBox};
 
@@ -81,6 +82,33 @@ pub trait Element {
     fn clip_rect(&self) -> Option<ClipRect> {
         None
     }
+
+    /// Draws this element and its children into an offscreen layer, which is
+    /// composited as a group (e.g. for group opacity)
+    fn layer(&self) -> Option<Layer> {
+        None
+    }
+
+    /// Paints this element and its children above elements with a lower
+    /// z-index wherever they are in the tree, and gives them input first (e.g.
+    /// for menus). Elements which don't set one inherit their parent's.
+    ///
+    /// An element whose z-index differs from its parent's isn't clipped by its
+    /// ancestors' clip rects, nor drawn into their layers.
+    fn z_index(&self) -> Option<ZIndex> {
+        None
+    }
+
+    /// Whether `ui` and `ui_post` have to run. This is asked before they run,
+    /// and again after, to find out whether what they painted can be reused.
+    ///
+    /// Elements which don't need to repaint are drawn with the shapes they
+    /// painted last, without tessellating or uploading them again, as long as
+    /// they weren't moved, resized, clipped or scaled since. Elements which set
+    /// the cursor or render custom content in `ui` should keep repainting.
+    fn needs_repaint(&self) -> bool {
+        true
+    }
 }
 
 pub struct ElementRef<T: Element + ?Sized> {
@@ -15,4 +15,7 @@ mod text;
 pub use text::*;
 
 mod text_edit;
-pub use text_edit::*;
\ No newline at end of file
+pub use text_edit::*;
+
+mod svg;
+pub use self::svg::*;
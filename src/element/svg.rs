@@ -0,0 +1,113 @@
+use std::sync::Arc;
+
+use crate::{
+    accessibility::{AccessNodeBuilder, AccessRole},
+    color::ColorRgba,
+    element::Element,
+    math::{CompleteScaleFactor, Rect},
+    scene::{
+        ctx::SceneContext,
+        layout::{LayoutPass, LayoutPassResult},
+        scene::SceneResources,
+    },
+    shape::PaintMesh,
+    svg_document::SvgDocument,
+    util::layout::{LayoutStyle, TaffyNodeContext},
+};
+
+/// Paints an [`SvgDocument`], which is sized by the layout engine according to
+/// the document's intrinsic size
+pub struct SvgElement {
+    document: Arc<SvgDocument>,
+    color: ColorRgba,
+    layout_node: LayoutPassResult,
+
+    /// Tessellated paths from the last frame, which are reused while the rect
+    /// and scale factor don't change
+    meshes: Option<(Rect, CompleteScaleFactor, Vec<PaintMesh>)>,
+}
+
+impl SvgElement {
+    pub fn new(
+        scene_resources: &mut SceneResources,
+        document: impl Into<Arc<SvgDocument>>,
+        layout: impl Into<LayoutStyle>,
+    ) -> Self {
+        let document = document.into();
+
+        let layout_node = scene_resources
+            .layout_engine()
+            .new_leaf_with_context(layout, TaffyNodeContext::IntrinsicSize(document.size()))
+            .unwrap();
+
+        Self {
+            document,
+            color: ColorRgba::new(0., 0., 0., 1.),
+            layout_node,
+            meshes: None,
+        }
+    }
+
+    /// Sets the color of `currentColor` fills and strokes, e.g. to tint icons
+    pub fn with_color(mut self, color: impl Into<ColorRgba>) -> Self {
+        self.set_color(color);
+        self
+    }
+
+    pub fn set_color(&mut self, color: impl Into<ColorRgba>) {
+        self.color = color.into();
+        self.meshes = None;
+    }
+
+    pub fn set_document(&mut self, document: impl Into<Arc<SvgDocument>>) {
+        self.document = document.into();
+        self.meshes = None;
+    }
+
+    pub fn set_layout(&mut self, layout: impl Into<LayoutStyle>) {
+        self.layout_node.set_style(layout);
+    }
+
+    #[inline(always)]
+    pub fn document(&self) -> &Arc<SvgDocument> {
+        &self.document
+    }
+}
+
+impl Element for SvgElement {
+    fn layout(&mut self, _layout_pass: &mut LayoutPass) -> LayoutPassResult {
+        self.layout_node.clone()
+    }
+
+    fn ui(&mut self, ctx: &mut SceneContext, rect: Rect) {
+        let scale_factor = ctx.scale_factor();
+
+        let meshes = match self.meshes.take() {
+            Some((cached_rect, cached_scale_factor, meshes))
+                if cached_rect == rect && cached_scale_factor == scale_factor =>
+            {
+                meshes
+            }
+            _ => self
+                .document
+                .paths(rect, self.color)
+                .flat_map(|path| path.tessellate(scale_factor).collect::<Vec<_>>())
+                .collect(),
+        };
+
+        for mesh in meshes.iter() {
+            ctx.add_shape(mesh.clone());
+        }
+
+        self.meshes = Some((rect, scale_factor, meshes));
+    }
+
+    fn node(&self) -> AccessNodeBuilder {
+        AccessNodeBuilder::new(AccessRole::Image)
+    }
+
+    fn needs_repaint(&self) -> bool {
+        // cleared when the document or color change
+        self.meshes.is_none()
+    }
+}
@@ -15,7 +15,7 @@ use crate::{
     scene::layout::{AvailableSpace, FlexBox, LayoutPassResult},
     shape::{PaintFill, PaintRectangle},
     util::{
-        guard::ReadLockable, layout::{LayoutStyle, TaffyNodeContext}, text::{AtlasContentType, CachedFloat, FontSystemRef, HasBuffer, TextBox, TextBoxLike, TextCacheBuffer}, DeviceUnit, LogicalUnit, PhysicalUnit
+        guard::ReadLockable, layout::{LayoutStyle, TaffyNodeContext}, text::{AtlasContentType, CachedFloat, FontSystemRef, GlyphRenderMode, HasBuffer, TextBox, TextBoxLike, TextCacheBuffer}, DeviceUnit, LogicalUnit, PhysicalUnit
     },
 };
 
@@ -85,6 +85,15 @@ impl<Buffer: HasBuffer + 'static> TextBoxElement<Buffer> {
     pub fn set_layout(&mut self, layout: impl Into<LayoutStyle>) {
         self.layout_node.set_style(layout);
     }
+
+    pub fn with_glyph_mode(mut self, glyph_mode: GlyphRenderMode) -> Self {
+        self.set_glyph_mode(glyph_mode);
+        self
+    }
+
+    pub fn set_glyph_mode(&mut self, glyph_mode: GlyphRenderMode) {
+        self.buffer.lock().unwrap().buffer.glyph_mode = glyph_mode;
+    }
 }
 
 impl<Buffer: HasBuffer + 'static> Element for TextBoxElement<Buffer> {
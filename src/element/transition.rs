@@ -27,6 +27,18 @@ impl Transition {
             true => self.fac = (self.fac + fac).min(1.),
             false => self.fac = (self.fac - fac).max(0.),
         }
+
+        if self.is_animating() {
+            ctx.input().request_repaint();
+        }
+    }
+
+    /// Whether the transition hasn't reached its state yet
+    pub fn is_animating(&self) -> bool {
+        match self.state {
+            true => self.fac < 1.,
+            false => self.fac > 0.,
+        }
     }
 
     pub fn set_state(&mut self, state: bool) {
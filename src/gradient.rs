@@ -0,0 +1,346 @@
+use crate::{
+    color::{ColorRgba, ColorSrgba},
+    debug_panic,
+    math::{Angle, Pos},
+};
+
+use palette::{FromColor, WithAlpha};
+
+pub const MAX_GRADIENT_STOPS: usize = 8;
+
+/// The geometry of a gradient, in the local coordinates of the shape it fills
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum GradientKind {
+    Linear {
+        start: Pos,
+        end: Pos,
+    },
+    Radial {
+        center: Pos,
+        radius: f32,
+    },
+    /// Sweeps clockwise around `center`, starting at `angle` from the x axis
+    Conic {
+        center: Pos,
+        angle: Angle,
+    },
+}
+
+/// The color space in which stops are interpolated
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum GradientInterpolation {
+    #[default]
+    Linear,
+    Srgb,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct GradientStop {
+    pub offset: f32,
+    pub color: ColorRgba,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PaintGradient {
+    kind: GradientKind,
+    interpolation: GradientInterpolation,
+    stops: [GradientStop; MAX_GRADIENT_STOPS],
+    num_stops: u8,
+}
+
+impl PaintGradient {
+    pub fn new(kind: GradientKind) -> Self {
+        Self {
+            kind,
+            interpolation: Default::default(),
+            stops: Default::default(),
+            num_stops: 0,
+        }
+    }
+
+    #[inline(always)]
+    pub fn linear(start: impl Into<Pos>, end: impl Into<Pos>) -> Self {
+        Self::new(GradientKind::Linear {
+            start: start.into(),
+            end: end.into(),
+        })
+    }
+
+    #[inline(always)]
+    pub fn radial(center: impl Into<Pos>, radius: f32) -> Self {
+        Self::new(GradientKind::Radial {
+            center: center.into(),
+            radius,
+        })
+    }
+
+    #[inline(always)]
+    pub fn conic(center: impl Into<Pos>, angle: impl Into<Angle>) -> Self {
+        Self::new(GradientKind::Conic {
+            center: center.into(),
+            angle: angle.into(),
+        })
+    }
+
+    /// Adds a color stop at `offset`, which is clamped to `0..=1`
+    pub fn with_stop(mut self, offset: f32, color: impl Into<ColorRgba>) -> Self {
+        if self.num_stops as usize >= MAX_GRADIENT_STOPS {
+            debug_panic!("Gradients support at most {MAX_GRADIENT_STOPS} stops");
+            return self;
+        }
+
+        let stop = GradientStop {
+            offset: offset.clamp(0., 1.),
+            color: color.into(),
+        };
+
+        let num_stops = self.num_stops as usize;
+        let idx = self.stops[..num_stops].partition_point(|s| s.offset <= stop.offset);
+
+        self.stops.copy_within(idx..num_stops, idx + 1);
+        self.stops[idx] = stop;
+        self.num_stops += 1;
+
+        self
+    }
+
+    #[inline]
+    pub fn with_stops(self, stops: impl IntoIterator<Item = (f32, impl Into<ColorRgba>)>) -> Self {
+        stops
+            .into_iter()
+            .fold(self, |g, (offset, color)| g.with_stop(offset, color))
+    }
+
+    #[inline]
+    pub fn with_interpolation(mut self, interpolation: GradientInterpolation) -> Self {
+        self.interpolation = interpolation;
+        self
+    }
+
+    #[inline(always)]
+    pub fn kind(&self) -> GradientKind {
+        self.kind
+    }
+
+    #[inline(always)]
+    pub fn interpolation(&self) -> GradientInterpolation {
+        self.interpolation
+    }
+
+    #[inline(always)]
+    pub fn stops(&self) -> &[GradientStop] {
+        &self.stops[..self.num_stops as usize]
+    }
+
+    /// Position of `pos` along the gradient, in `0..=1`
+    pub fn offset_at(&self, pos: Pos) -> f32 {
+        let t = match self.kind {
+            GradientKind::Linear { start, end } => {
+                let d = end - start;
+                (pos - start).dot(d) / d.square_length().max(1e-6)
+            }
+            GradientKind::Radial { center, radius } => (pos - center).length() / radius.max(1e-6),
+            GradientKind::Conic { center, angle } => {
+                let v = pos - center;
+                let tau = std::f32::consts::TAU;
+                ((v.y.atan2(v.x) - angle.radians) / tau).rem_euclid(1.)
+            }
+        };
+
+        t.clamp(0., 1.)
+    }
+
+    /// Evaluates the gradient at `pos`; this mirrors `gradient_color` in `box.wgsl`
+    pub fn color_at(&self, pos: Pos) -> ColorRgba {
+        let stops = self.stops();
+
+        let Some(first) = stops.first() else {
+            return ColorRgba::new(0., 0., 0., 0.);
+        };
+
+        let t = self.offset_at(pos);
+
+        let to_space = |c: ColorRgba| -> [f32; 4] {
+            let c = match self.interpolation {
+                GradientInterpolation::Linear => c,
+                GradientInterpolation::Srgb => {
+                    let s = ColorSrgba::from_color(c);
+                    ColorRgba::new(s.red, s.green, s.blue, s.alpha)
+                }
+            };
+
+            premultiply(c.into())
+        };
+
+        let mut col = to_space(first.color);
+
+        for w in stops.windows(2) {
+            let (a, b) = (&w[0], &w[1]);
+
+            if t > a.offset {
+                let s = ((t - a.offset) / (b.offset - a.offset).max(1e-6)).clamp(0., 1.);
+                let (ca, cb) = (to_space(a.color), to_space(b.color));
+                col = std::array::from_fn(|i| ca[i] + (cb[i] - ca[i]) * s);
+            }
+        }
+
+        let [r, g, b, a] = unpremultiply(col);
+
+        match self.interpolation {
+            GradientInterpolation::Linear => ColorRgba::new(r, g, b, a),
+            GradientInterpolation::Srgb => {
+                ColorRgba::from_color(ColorSrgba::new(r, g, b, 1.)).with_alpha(a)
+            }
+        }
+    }
+
+    pub(crate) fn to_shader_gradient(&self) -> ShaderGradient {
+        let mut res = ShaderGradient {
+            num_stops: self.num_stops as u32,
+            interpolation: self.interpolation as u32,
+            ..Default::default()
+        };
+
+        match self.kind {
+            GradientKind::Linear { start, end } => {
+                res.kind = 0;
+                res.p0 = start.into();
+                res.p1 = end.into();
+            }
+            GradientKind::Radial { center, radius } => {
+                res.kind = 1;
+                res.p0 = center.into();
+                res.p1 = [radius, 0.];
+            }
+            GradientKind::Conic { center, angle } => {
+                res.kind = 2;
+                res.p0 = center.into();
+                res.p1 = [angle.radians, 0.];
+            }
+        }
+
+        for (i, stop) in self.stops().iter().enumerate() {
+            res.offsets[i] = stop.offset;
+
+            res.colors[i] = match self.interpolation {
+                GradientInterpolation::Linear => stop.color.into(),
+                GradientInterpolation::Srgb => ColorSrgba::from_color(stop.color).into(),
+            };
+        }
+
+        res
+    }
+}
+
+#[inline(always)]
+fn premultiply([r, g, b, a]: [f32; 4]) -> [f32; 4] {
+    [r * a, g * a, b * a, a]
+}
+
+#[inline(always)]
+fn unpremultiply([r, g, b, a]: [f32; 4]) -> [f32; 4] {
+    if a <= 0. {
+        return [0.; 4];
+    }
+
+    [r / a, g / a, b / a, a]
+}
+
+/// A gradient as laid out in the `gradients` storage buffer of `box.wgsl`
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ShaderGradient {
+    kind: u32,
+    interpolation: u32,
+    num_stops: u32,
+    _padding: u32,
+    p0: [f32; 2],
+    p1: [f32; 2],
+    /// Stop colors, already converted to the interpolation color space
+    colors: [[f32; 4]; MAX_GRADIENT_STOPS],
+    offsets: [f32; MAX_GRADIENT_STOPS],
+}
+
+/// A sink for the gradients referenced by shader vertices
+pub trait PushGradients {
+    /// Stores `gradient`, returning its index into the gradient buffer
+    fn push_gradient(&mut self, gradient: &PaintGradient) -> u16;
+}
+
+impl PushGradients for Vec<ShaderGradient> {
+    fn push_gradient(&mut self, gradient: &PaintGradient) -> u16 {
+        let gradient = gradient.to_shader_gradient();
+
+        // consecutive shapes (e.g. glyphs in a text box) usually share a gradient
+        if self.last() != Some(&gradient) {
+            // vertices index gradients with a u16, so further gradients reuse the last one
+            if self.len() > u16::MAX as usize {
+                debug_panic!("Frames support at most {} gradients", u16::MAX as usize + 1);
+                return u16::MAX;
+            }
+
+            self.push(gradient);
+        }
+
+        (self.len() - 1) as u16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn approx_eq(a: ColorRgba, b: ColorRgba) -> bool {
+        let (a, b): ([f32; 4], [f32; 4]) = (a.into(), b.into());
+        a.iter().zip(b).all(|(x, y)| (x - y).abs() < 1e-4)
+    }
+
+    #[test]
+    fn stops_are_sorted() {
+        let g = PaintGradient::linear(Pos::new(0., 0.), Pos::new(1., 0.))
+            .with_stop(1., ColorRgba::new(0., 0., 1., 1.))
+            .with_stop(0., ColorRgba::new(1., 0., 0., 1.))
+            .with_stop(0.5, ColorRgba::new(0., 1., 0., 1.));
+
+        let offsets: Vec<_> = g.stops().iter().map(|s| s.offset).collect();
+        assert_eq!(offsets, [0., 0.5, 1.]);
+    }
+
+    #[test]
+    fn linear_interpolation() {
+        let red = ColorRgba::new(1., 0., 0., 1.);
+        let blue = ColorRgba::new(0., 0., 1., 1.);
+
+        let g = PaintGradient::linear(Pos::new(0., 0.), Pos::new(10., 0.))
+            .with_stop(0., red)
+            .with_stop(1., blue);
+
+        assert!(approx_eq(g.color_at(Pos::new(-5., 3.)), red));
+        assert!(approx_eq(g.color_at(Pos::new(20., 3.)), blue));
+        assert!(approx_eq(
+            g.color_at(Pos::new(5., 0.)),
+            ColorRgba::new(0.5, 0., 0.5, 1.)
+        ));
+    }
+
+    #[test]
+    fn radial_and_conic_offsets() {
+        let radial = PaintGradient::radial(Pos::new(0., 0.), 10.);
+        assert!((radial.offset_at(Pos::new(0., 5.)) - 0.5).abs() < 1e-5);
+
+        let conic = PaintGradient::conic(Pos::new(0., 0.), Angle::radians(0.));
+        assert!((conic.offset_at(Pos::new(0., 1.)) - 0.25).abs() < 1e-5);
+        assert!((conic.offset_at(Pos::new(-1., 0.)) - 0.5).abs() < 1e-5);
+    }
+
+    #[test]
+    fn srgb_interpolation() {
+        let g = PaintGradient::linear(Pos::new(0., 0.), Pos::new(1., 0.))
+            .with_stop(0., ColorRgba::new(0., 0., 0., 1.))
+            .with_stop(1., ColorRgba::new(1., 1., 1., 1.))
+            .with_interpolation(GradientInterpolation::Srgb);
+
+        let mid = ColorRgba::from_color(ColorSrgba::new(0.5, 0.5, 0.5, 1.));
+        assert!(approx_eq(g.color_at(Pos::new(0.5, 0.)), mid));
+    }
+}
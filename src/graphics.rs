@@ -103,6 +103,31 @@ impl<T: Sized + Pod> DynamicGPUBuffer<T> {
         reallocated
     }
 
+    /// Grows the buffer to fit `num_items`, returning whether it was reallocated,
+    /// which discards its contents
+    pub fn reserve(&mut self, device: &wgpu::Device, num_items: u64) -> bool {
+        let new_size = num_items * (std::mem::size_of::<T>() as u64);
+
+        self.size = new_size;
+        self.reallocate_self(device, new_size)
+    }
+
+    /// Writes `items` starting at the `offset`th item, which must have been
+    /// reserved
+    pub fn write_at(&self, queue: &wgpu::Queue, offset: u64, items: &[T]) {
+        debug_assert!(
+            (offset + items.len() as u64) * (std::mem::size_of::<T>() as u64) <= self.buffer.size()
+        );
+
+        if !items.is_empty() {
+            queue.write_buffer(
+                &self.buffer,
+                offset * (std::mem::size_of::<T>() as u64),
+                bytemuck::cast_slice(items),
+            );
+        }
+    }
+
     pub const fn count(&self) -> u64 {
         self.size / (std::mem::size_of::<T>() as u64)
     }
@@ -112,6 +137,78 @@ impl<T: Sized + Pod> DynamicGPUBuffer<T> {
     }
 }
 
+/// Hands out ranges of a buffer's items which stay in place until they're
+/// freed, so that what they hold doesn't have to be uploaded again
+#[derive(Debug, Default)]
+pub struct RangeAllocator {
+    /// Unallocated ranges before `end`, sorted and never adjacent
+    free: Vec<Range<u32>>,
+    end: u32,
+}
+
+impl RangeAllocator {
+    /// Allocates `len` items in the first free range they fit into, or after
+    /// the last allocated range
+    pub fn allocate(&mut self, len: u32) -> Range<u32> {
+        if len == 0 {
+            return 0..0;
+        }
+
+        if let Some(idx) = self.free.iter().position(|range| range.len() as u32 >= len) {
+            let start = self.free[idx].start;
+            self.free[idx].start += len;
+
+            if self.free[idx].is_empty() {
+                self.free.remove(idx);
+            }
+
+            return start..start + len;
+        }
+
+        self.end += len;
+        self.end - len..self.end
+    }
+
+    pub fn free(&mut self, range: Range<u32>) {
+        if range.is_empty() {
+            return;
+        }
+
+        let idx = self.free.partition_point(|free| free.end <= range.start);
+
+        let joins_previous = idx > 0 && self.free[idx - 1].end == range.start;
+        let joins_next = self.free.get(idx).is_some_and(|next| next.start == range.end);
+
+        match (joins_previous, joins_next) {
+            (true, true) => {
+                self.free[idx - 1].end = self.free.remove(idx).end;
+            }
+            (true, false) => self.free[idx - 1].end = range.end,
+            (false, true) => self.free[idx].start = range.start,
+            (false, false) => self.free.insert(idx, range),
+        }
+
+        if self.free.last().is_some_and(|last| last.end == self.end) {
+            self.end = self.free.pop().unwrap().start;
+        }
+    }
+
+    /// The end of the last allocated range, which the buffer has to fit
+    pub fn end(&self) -> u32 {
+        self.end
+    }
+
+    /// Number of unallocated items before [`Self::end`]
+    pub fn num_free(&self) -> u32 {
+        self.free.iter().map(|range| range.len() as u32).sum()
+    }
+
+    pub fn clear(&mut self) {
+        self.free.clear();
+        self.end = 0;
+    }
+}
+
 pub struct DynamicGPUMeshTriBuffer<T: Sized + Pod + Debug> {
     vertex_buffer: DynamicGPUBuffer<T>,
     index_buffer: DynamicGPUBuffer<u16>,
@@ -145,14 +242,30 @@ impl<T: Sized + Pod + Debug> DynamicGPUMeshTriBuffer<T> {
     }
 
     pub fn render<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, instances: Range<u32>) {
+        self.bind(render_pass);
+        self.draw(render_pass, 0..self.index_buffer.count() as u32, 0, instances);
+    }
+
+    pub fn bind(&self, render_pass: &mut wgpu::RenderPass<'_>) {
         render_pass.set_vertex_buffer(0, self.vertex_buffer.buffer.slice(..));
 
         render_pass.set_index_buffer(
             self.index_buffer.buffer.slice(..),
             wgpu::IndexFormat::Uint16,
         );
+    }
 
-        render_pass.draw_indexed(0..self.index_buffer.count() as u32, 0, instances);
+    /// Draws a range of the index buffer, whose indices are offset by
+    /// `base_vertex`; [`Self::bind`] must be called first
+    #[inline(always)]
+    pub fn draw(
+        &self,
+        render_pass: &mut wgpu::RenderPass<'_>,
+        indices: Range<u32>,
+        base_vertex: i32,
+        instances: Range<u32>,
+    ) {
+        render_pass.draw_indexed(indices, base_vertex, instances);
     }
 
     pub fn write_all(
@@ -173,6 +286,118 @@ impl<T: Sized + Pod + Debug> DynamicGPUMeshTriBuffer<T> {
 
         self.index_buffer.write(device, queue, &buffers.indices, num_indices as u64);
     }
+
+    /// Grows the buffers to fit the given numbers of vertices and indices,
+    /// returning whether either was reallocated, which discards its contents
+    pub fn reserve(&mut self, device: &wgpu::Device, num_vertices: u64, num_indices: u64) -> bool {
+        let vertices_reallocated = self.vertex_buffer.reserve(device, num_vertices);
+        let indices_reallocated = self.index_buffer.reserve(device, num_indices);
+
+        vertices_reallocated || indices_reallocated
+    }
+
+    /// Writes vertices and indices at the given offsets, which must have been
+    /// reserved. Indices are written in pairs, to stay 4-byte aligned, so
+    /// `index_offset` must be even.
+    pub fn write_at(
+        &self,
+        queue: &wgpu::Queue,
+        vertex_offset: u64,
+        vertices: &[T],
+        index_offset: u64,
+        indices: &[u16],
+    ) {
+        debug_assert!(index_offset.is_multiple_of(2));
+
+        self.vertex_buffer.write_at(queue, vertex_offset, vertices);
+
+        if indices.len().is_multiple_of(2) {
+            self.index_buffer.write_at(queue, index_offset, indices);
+        } else {
+            let padded = indices.iter().copied().chain([0]).collect::<Vec<_>>();
+            self.index_buffer.write_at(queue, index_offset, &padded);
+        }
+    }
+}
+
+/// Instances drawn over a shared unit quad, whose corners are given to the
+/// vertex shader at location 0
+pub struct DynamicGPUQuadInstanceBuffer<T: Sized + Pod + Debug> {
+    quad_vertex_buffer: wgpu::Buffer,
+    quad_index_buffer: wgpu::Buffer,
+    instance_buffer: DynamicGPUBuffer<T>,
+}
+
+impl<T: Sized + Pod + Debug> DynamicGPUQuadInstanceBuffer<T> {
+    const MIN_CAP_INSTANCES: u64 = 16;
+
+    const QUAD_CORNERS: [[f32; 2]; 4] = [[0., 0.], [1., 0.], [0., 1.], [1., 1.]];
+    const QUAD_ATTRIBS: [wgpu::VertexAttribute; 1] = wgpu::vertex_attr_array![0 => Float32x2];
+
+    pub fn new(device: &wgpu::Device) -> Self {
+        use wgpu::util::DeviceExt;
+
+        let quad_vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("unit quad vertex buffer"),
+            contents: bytemuck::cast_slice(&Self::QUAD_CORNERS),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        let quad_index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("unit quad index buffer"),
+            contents: bytemuck::cast_slice(&QUAD_VERT_ORDER),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        let instance_buffer = DynamicGPUBuffer::new(
+            device,
+            Self::MIN_CAP_INSTANCES,
+            wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        );
+
+        Self {
+            quad_vertex_buffer,
+            quad_index_buffer,
+            instance_buffer,
+        }
+    }
+
+    /// Layout of the unit quad's vertex buffer, which comes before the instance buffer
+    pub fn quad_desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<[f32; 2]>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::QUAD_ATTRIBS,
+        }
+    }
+
+    pub fn bind(&self, render_pass: &mut wgpu::RenderPass<'_>) {
+        render_pass.set_vertex_buffer(0, self.quad_vertex_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, self.instance_buffer.buffer.slice(..));
+
+        render_pass.set_index_buffer(self.quad_index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+    }
+
+    /// Draws a range of instances; [`Self::bind`] must be called first
+    #[inline(always)]
+    pub fn draw(&self, render_pass: &mut wgpu::RenderPass<'_>, instances: Range<u32>) {
+        render_pass.draw_indexed(0..QUAD_VERT_ORDER.len() as u32, 0, instances);
+    }
+
+    pub fn write_all(&mut self, queue: &wgpu::Queue, device: &wgpu::Device, instances: Vec<T>) {
+        self.instance_buffer.write(device, queue, &instances, instances.len() as u64);
+    }
+
+    /// Grows the instance buffer to fit `num_instances`, returning whether it was
+    /// reallocated, which discards its contents
+    pub fn reserve(&mut self, device: &wgpu::Device, num_instances: u64) -> bool {
+        self.instance_buffer.reserve(device, num_instances)
+    }
+
+    /// Writes instances starting at `offset`, which must have been reserved
+    pub fn write_at(&self, queue: &wgpu::Queue, offset: u64, instances: &[T]) {
+        self.instance_buffer.write_at(queue, offset, instances);
+    }
 }
 
 pub trait PushVertices<T> {
@@ -205,3 +430,47 @@ impl<V> PushVertices<V> for VertexBuffers<V> {
 }
 
 pub type VertexBuffers<V> = lyon::tessellation::VertexBuffers<V, u16>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn freed_ranges_are_reused_and_merged() {
+        let mut allocator = RangeAllocator::default();
+
+        let a = allocator.allocate(4);
+        let b = allocator.allocate(2);
+        let c = allocator.allocate(4);
+        assert_eq!((a.clone(), b.clone(), c.clone()), (0..4, 4..6, 6..10));
+
+        allocator.free(a);
+        assert_eq!(allocator.allocate(3), 0..3);
+        assert_eq!(allocator.num_free(), 1);
+
+        // merges with the free item at 3, but doesn't fit 5 items
+        allocator.free(b);
+        assert_eq!(allocator.allocate(5), 10..15);
+        assert_eq!(allocator.allocate(3), 3..6);
+        assert_eq!(allocator.num_free(), 0);
+    }
+
+    #[test]
+    fn freeing_the_last_range_shrinks_the_end() {
+        let mut allocator = RangeAllocator::default();
+
+        let a = allocator.allocate(4);
+        let b = allocator.allocate(4);
+        let c = allocator.allocate(4);
+
+        allocator.free(b);
+        assert_eq!(allocator.end(), 12);
+
+        allocator.free(c);
+        assert_eq!(allocator.end(), 4);
+        assert_eq!(allocator.num_free(), 0);
+
+        allocator.free(a);
+        assert_eq!(allocator.end(), 0);
+    }
+}
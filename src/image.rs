@@ -0,0 +1,308 @@
+use crate::{
+    math::PhysicalSize,
+    surface::RenderingContext,
+    texture::{TextureManagerRef, TextureRef},
+};
+
+/// How the color channels of an image are encoded
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ImageColorSpace {
+    /// sRGB encoded, which is the case for most images (e.g. PNG, JPEG)
+    #[default]
+    Srgb,
+    /// Linear, e.g. for normal maps or other non-color data
+    Linear,
+}
+
+impl ImageColorSpace {
+    #[inline(always)]
+    fn texture_format(self) -> wgpu::TextureFormat {
+        match self {
+            Self::Srgb => wgpu::TextureFormat::Rgba8UnormSrgb,
+            Self::Linear => wgpu::TextureFormat::Rgba8Unorm,
+        }
+    }
+}
+
+/// Whether the color channels of an image are multiplied by its alpha channel
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ImageAlphaMode {
+    #[default]
+    Straight,
+    Premultiplied,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ImageOptions {
+    pub color_space: ImageColorSpace,
+    /// The alpha mode of the source pixels. Textures are sampled with straight
+    /// alpha, so premultiplied pixels are converted on upload.
+    pub alpha_mode: ImageAlphaMode,
+}
+
+impl ImageOptions {
+    #[inline]
+    pub fn with_color_space(mut self, color_space: ImageColorSpace) -> Self {
+        self.color_space = color_space;
+        self
+    }
+
+    #[inline]
+    pub fn with_alpha_mode(mut self, alpha_mode: ImageAlphaMode) -> Self {
+        self.alpha_mode = alpha_mode;
+        self
+    }
+}
+
+#[derive(Debug)]
+pub enum ImageError {
+    /// The image is empty, or the pixel buffer doesn't match its size
+    InvalidSize {
+        size: PhysicalSize<u32>,
+        num_bytes: usize,
+    },
+    /// The image is larger than the device's maximum texture size
+    TooLarge {
+        size: PhysicalSize<u32>,
+        max_dimension: u32,
+    },
+    /// The bytes are neither a PNG nor a JPEG image
+    UnsupportedFormat,
+    Png(png::DecodingError),
+    Jpeg(jpeg_decoder::Error),
+}
+
+impl From<png::DecodingError> for ImageError {
+    fn from(value: png::DecodingError) -> Self {
+        Self::Png(value)
+    }
+}
+
+impl From<jpeg_decoder::Error> for ImageError {
+    fn from(value: jpeg_decoder::Error) -> Self {
+        Self::Jpeg(value)
+    }
+}
+
+pub type ImageResult<T> = Result<T, ImageError>;
+
+/// Decoded, tightly packed RGBA8 pixels
+#[derive(Clone, Debug)]
+pub struct ImageData {
+    size: PhysicalSize<u32>,
+    pixels: Vec<u8>,
+}
+
+impl ImageData {
+    pub fn from_rgba8(size: impl Into<PhysicalSize<u32>>, pixels: Vec<u8>) -> ImageResult<Self> {
+        let size = size.into();
+
+        let num_bytes = size.width as u64 * size.height as u64 * 4;
+
+        if size.is_empty() || pixels.len() as u64 != num_bytes {
+            return Err(ImageError::InvalidSize {
+                size,
+                num_bytes: pixels.len(),
+            });
+        }
+
+        Ok(Self { size, pixels })
+    }
+
+    /// Decodes a PNG or JPEG image
+    pub fn decode(bytes: &[u8]) -> ImageResult<Self> {
+        if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Self::decode_png(bytes)
+        } else if bytes.starts_with(&[0xFF, 0xD8]) {
+            Self::decode_jpeg(bytes)
+        } else {
+            Err(ImageError::UnsupportedFormat)
+        }
+    }
+
+    fn decode_png(bytes: &[u8]) -> ImageResult<Self> {
+        let mut decoder = png::Decoder::new(bytes);
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+
+        let mut reader = decoder.read_info()?;
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf)?;
+        buf.truncate(info.buffer_size());
+
+        let pixels = match info.color_type {
+            png::ColorType::Rgba => buf,
+            png::ColorType::Rgb => buf
+                .chunks_exact(3)
+                .flat_map(|c| [c[0], c[1], c[2], 255])
+                .collect(),
+            png::ColorType::GrayscaleAlpha => buf
+                .chunks_exact(2)
+                .flat_map(|c| [c[0], c[0], c[0], c[1]])
+                .collect(),
+            png::ColorType::Grayscale => buf.iter().flat_map(|&l| [l, l, l, 255]).collect(),
+            // expanded by `normalize_to_color8`
+            png::ColorType::Indexed => unreachable!(),
+        };
+
+        Self::from_rgba8(PhysicalSize::new(info.width, info.height), pixels)
+    }
+
+    fn decode_jpeg(bytes: &[u8]) -> ImageResult<Self> {
+        let mut decoder = jpeg_decoder::Decoder::new(bytes);
+        let buf = decoder.decode()?;
+
+        let info = decoder.info().ok_or(ImageError::UnsupportedFormat)?;
+
+        let pixels = match info.pixel_format {
+            jpeg_decoder::PixelFormat::RGB24 => buf
+                .chunks_exact(3)
+                .flat_map(|c| [c[0], c[1], c[2], 255])
+                .collect(),
+            jpeg_decoder::PixelFormat::L8 => buf.iter().flat_map(|&l| [l, l, l, 255]).collect(),
+            jpeg_decoder::PixelFormat::L16 => buf
+                .chunks_exact(2)
+                .flat_map(|c| {
+                    let l = (u16::from_ne_bytes([c[0], c[1]]) >> 8) as u8;
+                    [l, l, l, 255]
+                })
+                .collect(),
+            jpeg_decoder::PixelFormat::CMYK32 => {
+                buf.chunks_exact(4).flat_map(cmyk_to_rgba8).collect()
+            }
+        };
+
+        Self::from_rgba8(
+            PhysicalSize::new(info.width as u32, info.height as u32),
+            pixels,
+        )
+    }
+
+    #[inline(always)]
+    pub fn size(&self) -> PhysicalSize<u32> {
+        self.size
+    }
+
+    #[inline(always)]
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    /// Uploads the pixels into a new texture, which can be painted with
+    /// [`crate::shape::PaintFill::from_entire_texture`]
+    pub fn upload(
+        &self,
+        rendering_context: &RenderingContext,
+        texture_manager: &TextureManagerRef,
+        options: ImageOptions,
+    ) -> ImageResult<TextureRef> {
+        let PhysicalSize { width, height, .. } = self.size;
+
+        let max_dimension = rendering_context.device.limits().max_texture_dimension_2d;
+
+        if width > max_dimension || height > max_dimension {
+            return Err(ImageError::TooLarge {
+                size: self.size,
+                max_dimension,
+            });
+        }
+
+        let unpremultiplied;
+
+        let pixels = match options.alpha_mode {
+            ImageAlphaMode::Straight => &self.pixels,
+            ImageAlphaMode::Premultiplied => {
+                unpremultiplied = unpremultiply_rgba8(&self.pixels);
+                &unpremultiplied
+            }
+        };
+
+        let texture = rendering_context
+            .device
+            .create_texture(&wgpu::TextureDescriptor {
+                label: Some("image texture"),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: options.color_space.texture_format(),
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            });
+
+        rendering_context.queue.write_texture(
+            wgpu::TexelCopyTextureInfo {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            pixels,
+            wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(width * 4),
+                rows_per_image: None,
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+
+        Ok(texture_manager.write().unwrap().register_texture(texture))
+    }
+}
+
+fn cmyk_to_rgba8(cmyk: &[u8]) -> [u8; 4] {
+    let k = 255 - cmyk[3] as u32;
+    let channel = |x: u8| ((255 - x as u32) * k / 255) as u8;
+
+    [channel(cmyk[0]), channel(cmyk[1]), channel(cmyk[2]), 255]
+}
+
+pub(crate) fn unpremultiply_rgba8(pixels: &[u8]) -> Vec<u8> {
+    pixels
+        .chunks_exact(4)
+        .flat_map(|c| {
+            let a = c[3] as u32;
+
+            if a == 0 {
+                return [0; 4];
+            }
+
+            let channel = |x: u8| ((x as u32 * 255 + a / 2) / a).min(255) as u8;
+            [channel(c[0]), channel(c[1]), channel(c[2]), c[3]]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unpremultiply() {
+        assert_eq!(
+            unpremultiply_rgba8(&[64, 0, 128, 128, 10, 10, 10, 0]),
+            [128, 0, 255, 128, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn cmyk() {
+        assert_eq!(cmyk_to_rgba8(&[0, 0, 0, 0]), [255, 255, 255, 255]);
+        assert_eq!(cmyk_to_rgba8(&[0, 0, 0, 255]), [0, 0, 0, 255]);
+        assert_eq!(cmyk_to_rgba8(&[255, 0, 51, 51]), [0, 204, 163, 255]);
+    }
+
+    #[test]
+    fn rejects_mismatched_sizes() {
+        assert!(ImageData::from_rgba8(PhysicalSize::new(2, 2), vec![0; 12]).is_err());
+        assert!(ImageData::from_rgba8(PhysicalSize::new(2, 2), vec![0; 16]).is_ok());
+        assert!(ImageData::from_rgba8(PhysicalSize::new(1 << 16, 1 << 14), vec![0; 16]).is_err());
+    }
+}
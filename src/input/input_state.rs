@@ -208,6 +208,9 @@ pub struct InputState {
     // accesskit_actions: Rc<Vec<accesskit::ActionRequest>>,
 
     pub(crate) editing_text: bool,
+
+    /// Repaint requested by elements during this frame's input pass
+    repaint_after: Option<Duration>,
 }
 
 impl Default for InputState {
@@ -243,6 +246,8 @@ impl Default for InputState {
             focus_state: Default::default(),
 
             editing_text: false,
+
+            repaint_after: None,
         }
     }
 }
@@ -453,6 +458,8 @@ impl InputState {
             focus_state: self.focus_state,
 
             editing_text: false,
+
+            repaint_after: None,
         }
     }
 
@@ -508,6 +515,11 @@ impl InputState {
             .unwrap_or(false)
     }
 
+    /// The element which currently has focus, if any
+    pub(crate) fn focused_element(&self) -> Option<ElementId> {
+        self.focus_state.focused()
+    }
+
     pub fn just_focused(&self) -> bool {
         self.current_element
             .map(|id| self.focus_state.just_focused(id))
@@ -583,7 +595,10 @@ impl InputState {
     ///
     /// Returns how long to wait for a repaint.
     pub fn wants_repaint_after(&self) -> Option<Duration> {
-        if self.pointer.wants_repaint()
+        let requested = self.repaint_after;
+
+        if requested == Some(Duration::ZERO)
+            || self.pointer.wants_repaint()
             || self.unprocessed_scroll_delta.abs().max_elem() > 0.2
             || self.unprocessed_scroll_delta_for_zoom.abs() > 0.2
             || !self.events.is_empty()
@@ -600,12 +615,27 @@ impl InputState {
                     && press_duration < self.input_options.max_click_duration
                 {
                     let secs_until_menu = self.input_options.max_click_duration - press_duration;
-                    return Some(Duration::from_secs_f64(secs_until_menu));
+                    let delay = Duration::from_secs_f64(secs_until_menu);
+                    return Some(requested.map_or(delay, |requested| requested.min(delay)));
                 }
             }
         }
 
-        None
+        requested
+    }
+
+    /// Requests another frame as soon as possible, e.g. while animating
+    #[inline(always)]
+    pub fn request_repaint(&mut self) {
+        self.request_repaint_after(Duration::ZERO)
+    }
+
+    /// Requests another frame once `delay` has passed
+    pub fn request_repaint_after(&mut self, delay: Duration) {
+        self.repaint_after = Some(match self.repaint_after {
+            Some(repaint_after) => repaint_after.min(delay),
+            None => delay,
+        });
     }
 
     /// Count presses of a key. If non-zero, the presses are consumed, so that this will only return non-zero once.
@@ -849,13 +879,14 @@ impl InputState {
         self.pointer.active_transformation_idx = transformation_idx;
     }
 
-    pub(crate) fn set_active_clip_rect(
+    /// Sets the clip rects (each with its transformation) which the pointer must
+    /// be inside of to hover the current element
+    pub(crate) fn set_active_clip_rects(
         &mut self,
-        clip_rect: Option<ClipRect>,
-        transformation_idx: Option<usize>,
+        clip_rects: impl IntoIterator<Item = (ClipRect, Option<usize>)>,
     ) {
-        self.pointer.active_clip_rect = clip_rect;
-        self.pointer.active_clip_rect_transformation_idx = transformation_idx;
+        self.pointer.active_clip_rects.clear();
+        self.pointer.active_clip_rects.extend(clip_rects);
     }
 }
 
@@ -1160,8 +1191,8 @@ pub struct PointerState<const NUM_POINTER_BUTTONS: usize = 5> {
 
     active_transformation_idx: Option<usize>,
 
-    active_clip_rect: Option<ClipRect>,
-    active_clip_rect_transformation_idx: Option<usize>,
+    /// Nested clip rects, which intersect
+    active_clip_rects: Vec<(ClipRect, Option<usize>)>,
 
     transformable_pointer_cache: TransformablePointerCache,
 }
@@ -1192,8 +1223,7 @@ impl Default for PointerState {
 
             active_transformation_idx: Default::default(),
 
-            active_clip_rect: Default::default(),
-            active_clip_rect_transformation_idx: Default::default(),
+            active_clip_rects: Default::default(),
 
             transformable_pointer_cache: Default::default(),
         }
@@ -1413,12 +1443,12 @@ impl PointerState {
             return None;
         }
 
-        if let Some(clip_rect) = self.active_clip_rect {
-            let mut pos = self.latest_pos_raw();
+        for (clip_rect, transformation_idx) in &self.active_clip_rects {
+            let mut pos = self.latest_pos;
 
             if let Some(transformed_pos) = self
                 .transformable_pointer_cache
-                .get_latest_pos_at(self.active_clip_rect_transformation_idx, self.latest_pos)
+                .get_latest_pos_at(*transformation_idx, self.latest_pos)
             {
                 pos = transformed_pos.into();
             }
@@ -0,0 +1,512 @@
+use std::{num::NonZeroU64, ops::Range};
+
+use wgpu::ShaderModuleDescriptor;
+
+use crate::{math::PhysicalSize, surface::RenderingContext};
+
+/// How a source is combined with what has already been drawn beneath it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum BlendMode {
+    #[default]
+    Normal,
+    Multiply,
+    Screen,
+    Additive,
+    /// Erases the destination wherever the source is opaque
+    DestinationOut,
+}
+
+impl BlendMode {
+    pub const ALL: [BlendMode; 5] = [
+        BlendMode::Normal,
+        BlendMode::Multiply,
+        BlendMode::Screen,
+        BlendMode::Additive,
+        BlendMode::DestinationOut,
+    ];
+
+    /// Blend state for a source with premultiplied alpha. Multiply is only exact
+    /// over an opaque destination.
+    pub(crate) fn premultiplied_blend_state(self) -> wgpu::BlendState {
+        use wgpu::{BlendComponent, BlendFactor, BlendOperation};
+
+        let component = |src_factor, dst_factor| BlendComponent {
+            src_factor,
+            dst_factor,
+            operation: BlendOperation::Add,
+        };
+
+        let over = component(BlendFactor::One, BlendFactor::OneMinusSrcAlpha);
+
+        let (color, alpha) = match self {
+            BlendMode::Normal => (over, over),
+            BlendMode::Multiply => (
+                component(BlendFactor::Dst, BlendFactor::OneMinusSrcAlpha),
+                over,
+            ),
+            BlendMode::Screen => (component(BlendFactor::One, BlendFactor::OneMinusSrc), over),
+            BlendMode::Additive => {
+                let add = component(BlendFactor::One, BlendFactor::One);
+                (add, add)
+            }
+            BlendMode::DestinationOut => {
+                let out = component(BlendFactor::Zero, BlendFactor::OneMinusSrcAlpha);
+                (out, out)
+            }
+        };
+
+        wgpu::BlendState { color, alpha }
+    }
+
+    /// CPU equivalent of [`Self::premultiplied_blend_state`]
+    pub(crate) fn blend_premultiplied(self, dst: [f32; 4], src: [f32; 4]) -> [f32; 4] {
+        let sa = src[3];
+        let over = |i: usize| src[i] + dst[i] * (1. - sa);
+
+        let res: [f32; 4] = std::array::from_fn(|i| match self {
+            BlendMode::Multiply | BlendMode::Screen if i == 3 => over(i),
+            BlendMode::Normal => over(i),
+            BlendMode::Multiply => src[i] * dst[i] + dst[i] * (1. - sa),
+            BlendMode::Screen => src[i] + dst[i] * (1. - src[i]),
+            BlendMode::Additive => src[i] + dst[i],
+            BlendMode::DestinationOut => dst[i] * (1. - sa),
+        });
+
+        // unorm render targets saturate
+        res.map(|c| c.clamp(0., 1.))
+    }
+}
+
+/// Draws an element's subtree into an offscreen texture, which is then
+/// composited back as a group. See [`crate::element::Element::layer`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Layer {
+    pub opacity: f32,
+    pub blend_mode: BlendMode,
+}
+
+impl Default for Layer {
+    fn default() -> Self {
+        Self {
+            opacity: 1.,
+            blend_mode: Default::default(),
+        }
+    }
+}
+
+impl Layer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[inline]
+    pub fn with_opacity(mut self, opacity: impl Into<f32>) -> Self {
+        self.opacity = opacity.into();
+        self
+    }
+
+    #[inline]
+    pub fn with_blend_mode(mut self, blend_mode: BlendMode) -> Self {
+        self.blend_mode = blend_mode;
+        self
+    }
+}
+
+/// Stacking order of an element and its subtree, see
+/// [`crate::element::Element::z_index`]. Higher indices are painted above lower
+/// ones, and receive input before them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ZIndex(pub i32);
+
+impl ZIndex {
+    /// The z-index of elements which neither set one nor have an ancestor that does
+    pub const BASE: Self = Self(0);
+    /// Popups, menus and dropdowns
+    pub const OVERLAY: Self = Self(100);
+    /// Tooltips, which may be shown for elements in overlays
+    pub const TOOLTIP: Self = Self(200);
+    /// Drag previews, above everything else
+    pub const DRAG: Self = Self(300);
+}
+
+impl From<i32> for ZIndex {
+    #[inline(always)]
+    fn from(value: i32) -> Self {
+        Self(value)
+    }
+}
+
+/// A layer pushed during a frame's ui pass. Layers are pushed in paint order, so
+/// a layer's parent always has a lower index.
+#[derive(Clone, Copy, Debug)]
+pub struct FrameLayer {
+    pub layer: Layer,
+    pub parent_idx: Option<u32>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum LayerCommand {
+    /// Draw a range of the frame's shape draws
+    Draw(Range<u32>, BlendMode),
+    /// Composite a child layer
+    Composite(u32),
+}
+
+/// Draw commands for the root target and for each layer
+#[derive(Debug)]
+pub(crate) struct LayerCommands {
+    commands: Vec<Vec<LayerCommand>>,
+    composited: Vec<bool>,
+}
+
+impl LayerCommands {
+    pub fn new(num_layers: usize) -> Self {
+        Self {
+            commands: (0..=num_layers).map(|_| Vec::new()).collect(),
+            composited: vec![false; num_layers],
+        }
+    }
+
+    #[inline(always)]
+    fn list_idx(layer_idx: Option<u32>) -> usize {
+        layer_idx.map(|idx| idx as usize + 1).unwrap_or_default()
+    }
+
+    pub fn push_draw(
+        &mut self,
+        layers: &[FrameLayer],
+        layer_idx: Option<u32>,
+        draws: Range<u32>,
+        blend_mode: BlendMode,
+    ) {
+        if draws.is_empty() {
+            return;
+        }
+
+        self.push_composite(layers, layer_idx);
+
+        let commands = &mut self.commands[Self::list_idx(layer_idx)];
+
+        match commands.last_mut() {
+            Some(LayerCommand::Draw(range, mode))
+                if range.end == draws.start && *mode == blend_mode =>
+            {
+                range.end = draws.end
+            }
+            _ => commands.push(LayerCommand::Draw(draws, blend_mode)),
+        }
+    }
+
+    /// Composites a layer into its parent the first time it's drawn to
+    fn push_composite(&mut self, layers: &[FrameLayer], layer_idx: Option<u32>) {
+        let Some(idx) = layer_idx else {
+            return;
+        };
+
+        if std::mem::replace(&mut self.composited[idx as usize], true) {
+            return;
+        }
+
+        let parent_idx = layers[idx as usize].parent_idx;
+
+        self.push_composite(layers, parent_idx);
+        self.commands[Self::list_idx(parent_idx)].push(LayerCommand::Composite(idx));
+    }
+
+    pub fn get(&self, layer_idx: Option<u32>) -> &[LayerCommand] {
+        &self.commands[Self::list_idx(layer_idx)]
+    }
+
+    /// Layers which have anything to draw, with children before their parents
+    pub fn layers_to_render(&self) -> impl Iterator<Item = u32> + '_ {
+        (0..self.composited.len())
+            .rev()
+            .filter(|&idx| self.composited[idx])
+            .map(|idx| idx as u32)
+    }
+}
+
+struct LayerTarget {
+    size: PhysicalSize<u32>,
+    view: wgpu::TextureView,
+    msaa_view: Option<wgpu::TextureView>,
+    params_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+/// Owns the offscreen textures layers are drawn into, and composites them
+pub(crate) struct LayerCompositor {
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipelines: Vec<(BlendMode, wgpu::RenderPipeline)>,
+    targets: Vec<LayerTarget>,
+}
+
+impl LayerCompositor {
+    pub fn new(
+        RenderingContext {
+            device,
+            texture_format,
+            texture_info,
+            ..
+        }: &RenderingContext,
+    ) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("layer composite bind group"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    count: None,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    count: None,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: NonZeroU64::new(std::mem::size_of::<[f32; 4]>() as u64),
+                    },
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                },
+            ],
+        });
+
+        let shader_module = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("layer composite shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("composite.wgsl").into()),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("layer composite pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipelines = BlendMode::ALL
+            .into_iter()
+            .map(|blend_mode| {
+                let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                    label: Some("layer composite pipeline"),
+                    layout: Some(&pipeline_layout),
+                    vertex: wgpu::VertexState {
+                        module: &shader_module,
+                        entry_point: "vs_main".into(),
+                        buffers: &[],
+                        compilation_options: Default::default(),
+                    },
+                    fragment: Some(wgpu::FragmentState {
+                        module: &shader_module,
+                        entry_point: "fs_main".into(),
+                        targets: &[Some(wgpu::ColorTargetState {
+                            format: *texture_format,
+                            blend: Some(blend_mode.premultiplied_blend_state()),
+                            write_mask: wgpu::ColorWrites::ALL,
+                        })],
+                        compilation_options: Default::default(),
+                    }),
+                    primitive: Default::default(),
+                    depth_stencil: None,
+                    multisample: texture_info.default_multisample_state(),
+                    multiview: None,
+                    cache: None,
+                });
+
+                (blend_mode, pipeline)
+            })
+            .collect();
+
+        Self {
+            bind_group_layout,
+            pipelines,
+            targets: Default::default(),
+        }
+    }
+
+    /// Makes sure there is a target of `size` for every layer in `layers`
+    pub fn prepare(
+        &mut self,
+        rendering_context: &RenderingContext,
+        size: PhysicalSize<u32>,
+        layers: &[FrameLayer],
+    ) {
+        self.targets.truncate(layers.len());
+        self.targets.retain(|target| target.size == size);
+
+        while self.targets.len() < layers.len() {
+            let target = self.create_target(rendering_context, size);
+            self.targets.push(target);
+        }
+
+        for (target, FrameLayer { layer, .. }) in self.targets.iter().zip(layers) {
+            rendering_context.queue.write_buffer(
+                &target.params_buffer,
+                0,
+                bytemuck::cast_slice(&[layer.opacity, 0., 0., 0.]),
+            );
+        }
+    }
+
+    fn create_target(
+        &self,
+        RenderingContext {
+            device,
+            texture_format,
+            texture_info,
+            ..
+        }: &RenderingContext,
+        size: PhysicalSize<u32>,
+    ) -> LayerTarget {
+        let num_samples = texture_info.get_num_samples();
+
+        let create_texture = |sample_count, usage| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                size: wgpu::Extent3d {
+                    width: size.width,
+                    height: size.height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count,
+                dimension: wgpu::TextureDimension::D2,
+                format: *texture_format,
+                usage,
+                label: Some("layer texture"),
+                view_formats: &[],
+            })
+        };
+
+        let view = create_texture(
+            1,
+            wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        )
+        .create_view(&Default::default());
+
+        let msaa_view = (num_samples > 1).then(|| {
+            create_texture(num_samples, wgpu::TextureUsages::RENDER_ATTACHMENT)
+                .create_view(&Default::default())
+        });
+
+        let params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("layer params buffer"),
+            size: std::mem::size_of::<[f32; 4]>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("layer composite bind group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: params_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        LayerTarget {
+            size,
+            view,
+            msaa_view,
+            params_buffer,
+            bind_group,
+        }
+    }
+
+    /// The view to draw a layer into, and its multisampled view if any
+    pub fn target_views(&self, layer_idx: u32) -> (&wgpu::TextureView, Option<&wgpu::TextureView>) {
+        let target = &self.targets[layer_idx as usize];
+        (&target.view, target.msaa_view.as_ref())
+    }
+
+    pub fn composite(
+        &self,
+        render_pass: &mut wgpu::RenderPass<'_>,
+        layer_idx: u32,
+        blend_mode: BlendMode,
+    ) {
+        let (_, pipeline) = self
+            .pipelines
+            .iter()
+            .find(|(mode, _)| *mode == blend_mode)
+            .unwrap();
+
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, &self.targets[layer_idx as usize].bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blend_modes_over_opaque_destination() {
+        let (dst, src) = ([0.5, 0.5, 0.5, 1.], [0.5, 0.5, 0.5, 1.]);
+
+        let blend = |blend_mode: BlendMode| blend_mode.blend_premultiplied(dst, src);
+
+        assert_eq!(blend(BlendMode::Normal), src);
+        assert_eq!(blend(BlendMode::Multiply), [0.25, 0.25, 0.25, 1.]);
+        assert_eq!(blend(BlendMode::Screen), [0.75, 0.75, 0.75, 1.]);
+        assert_eq!(blend(BlendMode::Additive), [1.; 4]);
+        assert_eq!(blend(BlendMode::DestinationOut), [0.; 4]);
+    }
+
+    #[test]
+    fn translucent_sources_are_premultiplied() {
+        let (dst, src) = ([0., 0., 1., 1.], [0.25, 0., 0., 0.5]);
+
+        assert_eq!(
+            BlendMode::Normal.blend_premultiplied(dst, src),
+            [0.25, 0., 0.5, 1.]
+        );
+        assert_eq!(
+            BlendMode::DestinationOut.blend_premultiplied(dst, src),
+            [0., 0., 0.5, 0.5]
+        );
+    }
+
+    #[test]
+    fn layers_are_composited_into_their_parents() {
+        let layer = |parent_idx| FrameLayer {
+            layer: Layer::default(),
+            parent_idx,
+        };
+
+        let layers = [layer(None), layer(Some(0))];
+        let mut commands = LayerCommands::new(layers.len());
+
+        commands.push_draw(&layers, None, 0..2, BlendMode::Normal);
+        commands.push_draw(&layers, Some(1), 2..3, BlendMode::Normal);
+        commands.push_draw(&layers, Some(1), 3..4, BlendMode::Normal);
+        commands.push_draw(&layers, None, 4..5, BlendMode::Multiply);
+        commands.push_draw(&layers, None, 5..5, BlendMode::Normal);
+
+        use LayerCommand::*;
+
+        assert_eq!(
+            commands.get(None),
+            [
+                Draw(0..2, BlendMode::Normal),
+                Composite(0),
+                Draw(4..5, BlendMode::Multiply),
+            ]
+        );
+        assert_eq!(commands.get(Some(0)), [Composite(1)]);
+        assert_eq!(commands.get(Some(1)), [Draw(2..4, BlendMode::Normal)]);
+
+        // children are rendered before the layers they're composited into
+        assert_eq!(commands.layers_to_render().collect::<Vec<_>>(), [1, 0]);
+    }
+}
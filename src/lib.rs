@@ -11,17 +11,25 @@ pub mod accessibility;
 pub mod app;
 pub mod atlas;
 pub mod buffer;
+pub mod capture;
 pub mod color;
+pub mod custom_render;
 pub mod debug;
 pub mod element;
+pub mod gradient;
 pub mod graphics;
 pub mod hash;
 pub mod history;
+pub mod image;
 pub mod input;
+pub mod layer;
 pub mod num;
+pub mod path;
+pub mod repaint;
 pub mod scene;
 pub mod shape;
 pub mod surface;
+pub mod svg_document;
 pub mod text;
 pub mod texture;
 pub mod time;
@@ -0,0 +1,454 @@
+use std::f32::consts::SQRT_2;
+
+use lyon::{
+    algorithms::{
+        aabb::bounding_box,
+        measure::{PathMeasurements, SampleType},
+    },
+    math::point,
+    path::{
+        builder::WithSvg, path::BuilderImpl, traits::SvgPathBuilder, ArcFlags, Path, PathEvent,
+    },
+    tessellation::{
+        BuffersBuilder, FillOptions, FillTessellator, StrokeOptions, StrokeTessellator,
+    },
+};
+
+use crate::{
+    color::ColorRgba,
+    math::{Angle, CompleteScaleFactor, CoordinateTransform, Pos, Rect, Vector},
+    shape::{PaintFill, PaintMesh},
+    util::svg::{PosVertexBuffers, PosVertexCtor},
+};
+
+pub use lyon::tessellation::{FillRule, LineCap, LineJoin};
+
+/// Maximum distance, in physical pixels, between a curve and its tessellation
+const TOLERANCE: f32 = 0.1;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct StrokeStyle {
+    pub width: f32,
+    pub join: LineJoin,
+    pub cap: LineCap,
+    pub miter_limit: f32,
+    /// Alternating lengths of dashes and gaps, repeated along the path
+    pub dashes: Vec<f32>,
+    pub dash_offset: f32,
+}
+
+impl Default for StrokeStyle {
+    fn default() -> Self {
+        Self {
+            width: 1.,
+            join: LineJoin::Miter,
+            cap: LineCap::Butt,
+            miter_limit: StrokeOptions::DEFAULT_MITER_LIMIT,
+            dashes: Default::default(),
+            dash_offset: 0.,
+        }
+    }
+}
+
+impl StrokeStyle {
+    pub fn new(width: f32) -> Self {
+        Self {
+            width,
+            ..Default::default()
+        }
+    }
+
+    #[inline]
+    pub fn with_width(mut self, width: f32) -> Self {
+        self.width = width;
+        self
+    }
+
+    #[inline]
+    pub fn with_join(mut self, join: LineJoin) -> Self {
+        self.join = join;
+        self
+    }
+
+    #[inline]
+    pub fn with_cap(mut self, cap: LineCap) -> Self {
+        self.cap = cap;
+        self
+    }
+
+    #[inline]
+    pub fn with_miter_limit(mut self, miter_limit: f32) -> Self {
+        self.miter_limit = miter_limit;
+        self
+    }
+
+    #[inline]
+    pub fn with_dashes(mut self, dashes: impl IntoIterator<Item = f32>) -> Self {
+        self.dashes = dashes.into_iter().collect();
+        self
+    }
+
+    #[inline]
+    pub fn with_dash_offset(mut self, dash_offset: f32) -> Self {
+        self.dash_offset = dash_offset;
+        self
+    }
+
+    /// Scales the width and dashes, e.g. when the path is transformed
+    pub(crate) fn scaled(mut self, factor: f32) -> Self {
+        self.width *= factor;
+        self.dash_offset *= factor;
+
+        for dash in self.dashes.iter_mut() {
+            *dash *= factor;
+        }
+
+        self
+    }
+
+    /// The dash pattern with an even number of entries (odd patterns are repeated,
+    /// as in SVG), or `None` if the path shouldn't be dashed
+    fn dash_pattern(&self) -> Option<Vec<f32>> {
+        if self.dashes.iter().any(|d| *d < 0.) || self.dashes.iter().sum::<f32>() <= 0. {
+            return None;
+        }
+
+        let mut dashes = self.dashes.clone();
+
+        if dashes.len() % 2 == 1 {
+            dashes.extend_from_within(..);
+        }
+
+        Some(dashes)
+    }
+}
+
+/// A vector path, which is filled and/or stroked
+#[derive(Clone, Debug)]
+pub struct PaintPath {
+    path: Path,
+    fill: Option<PaintFill>,
+    fill_rule: FillRule,
+    stroke: Option<(PaintFill, StrokeStyle)>,
+}
+
+impl From<Path> for PaintPath {
+    #[inline(always)]
+    fn from(value: Path) -> Self {
+        Self::new(value)
+    }
+}
+
+impl PaintPath {
+    pub fn new(path: Path) -> Self {
+        Self {
+            path,
+            fill: None,
+            fill_rule: FillRule::NonZero,
+            stroke: None,
+        }
+    }
+
+    #[inline(always)]
+    pub fn builder() -> PaintPathBuilder {
+        PaintPathBuilder::new()
+    }
+
+    #[inline]
+    pub fn with_fill(mut self, fill: impl Into<PaintFill>) -> Self {
+        self.fill = Some(fill.into());
+        self
+    }
+
+    #[inline]
+    pub fn with_fill_rule(mut self, fill_rule: FillRule) -> Self {
+        self.fill_rule = fill_rule;
+        self
+    }
+
+    #[inline]
+    pub fn with_stroke(mut self, fill: impl Into<PaintFill>, style: StrokeStyle) -> Self {
+        self.stroke = Some((fill.into(), style));
+        self
+    }
+
+    #[inline(always)]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Bounds of the path's fill and stroke
+    pub fn get_bounding_box(&self) -> Rect {
+        let bounds = bounding_box(self.path.iter());
+
+        // miter joins reach up to `miter_limit` half widths out, and square caps
+        // up to sqrt(2) half widths
+        let padding = self
+            .stroke
+            .as_ref()
+            .map(|(_, style)| style.width / 2. * style.miter_limit.max(SQRT_2))
+            .unwrap_or_default();
+
+        Rect::new(
+            Pos::new(bounds.min.x, bounds.min.y),
+            Pos::new(bounds.max.x, bounds.max.y),
+        )
+        .inflate(padding, padding)
+    }
+
+    /// Applies `transform` to the path. Stroke widths are scaled by the average
+    /// scale of `transform`, so non-uniform scales don't distort strokes.
+    pub fn transformed(mut self, transform: &CoordinateTransform) -> Self {
+        self.path = self.path.transformed(&lyon_transform(transform));
+
+        let scale = transform.determinant().abs().sqrt();
+
+        self.stroke = self.stroke.map(|(fill, style)| (fill, style.scaled(scale)));
+
+        self
+    }
+
+    /// Tessellates the fill and the stroke (in that order) into meshes, with a
+    /// tolerance fine enough for `scale_factor`
+    pub(crate) fn tessellate(
+        &self,
+        scale_factor: CompleteScaleFactor,
+    ) -> impl Iterator<Item = PaintMesh> {
+        let tolerance = TOLERANCE / scale_factor.as_float().get().max(1e-3);
+
+        let fill = self.fill.clone().and_then(|fill| {
+            let mut buffers = PosVertexBuffers::new();
+
+            FillTessellator::new()
+                .tessellate_path(
+                    &self.path,
+                    &FillOptions::tolerance(tolerance).with_fill_rule(self.fill_rule),
+                    &mut BuffersBuilder::new(&mut buffers, PosVertexCtor),
+                )
+                .map_err(|err| log::warn!("Failed to tessellate path fill: {err:?}"))
+                .ok()?;
+
+            Some(Self::mesh(&buffers, fill))
+        });
+
+        let stroke = self.stroke.clone().and_then(|(fill, style)| {
+            let dashed_path = style
+                .dash_pattern()
+                .map(|dashes| dash_path(&self.path, &dashes, style.dash_offset, tolerance));
+
+            let mut buffers = PosVertexBuffers::new();
+
+            StrokeTessellator::new()
+                .tessellate_path(
+                    dashed_path.as_ref().unwrap_or(&self.path),
+                    &StrokeOptions::tolerance(tolerance)
+                        .with_line_width(style.width)
+                        .with_line_join(style.join)
+                        .with_line_cap(style.cap)
+                        .with_miter_limit(
+                            style.miter_limit.max(StrokeOptions::MINIMUM_MITER_LIMIT),
+                        ),
+                    &mut BuffersBuilder::new(&mut buffers, PosVertexCtor),
+                )
+                .map_err(|err| log::warn!("Failed to tessellate path stroke: {err:?}"))
+                .ok()?;
+
+            Some(Self::mesh(&buffers, fill))
+        });
+
+        fill.into_iter().chain(stroke)
+    }
+
+    fn mesh(buffers: &PosVertexBuffers, fill: PaintFill) -> PaintMesh {
+        PaintMesh::from_pos_vertex_buffers(buffers, ColorRgba::new(1., 1., 1., 1.), Pos::zero())
+            .with_fill(fill)
+    }
+}
+
+#[inline(always)]
+pub(crate) fn lyon_transform(transform: &CoordinateTransform) -> lyon::math::Transform {
+    let [m11, m12, m21, m22, m31, m32] = transform.to_array();
+    lyon::math::Transform::new(m11, m12, m21, m22, m31, m32)
+}
+
+/// Splits `path` into the dashes of `dashes`, which restarts at each sub-path
+fn dash_path(path: &Path, dashes: &[f32], dash_offset: f32, tolerance: f32) -> Path {
+    let pattern_length = dashes.iter().sum::<f32>();
+
+    let mut output = Path::builder();
+    let mut subpath = Path::builder();
+
+    for event in path.iter() {
+        subpath.path_event(event);
+
+        if !matches!(event, PathEvent::End { .. }) {
+            continue;
+        }
+
+        let subpath = std::mem::replace(&mut subpath, Path::builder()).build();
+
+        let measurements = PathMeasurements::from_path(&subpath, tolerance);
+        let mut sampler = measurements.create_sampler(&subpath, SampleType::Distance);
+        let length = sampler.length();
+
+        if length <= 0. {
+            continue;
+        }
+
+        let mut dist = -dash_offset.rem_euclid(pattern_length);
+
+        'pattern: loop {
+            for (i, dash) in dashes.iter().enumerate() {
+                if dist >= length {
+                    break 'pattern;
+                }
+
+                let end = dist + dash;
+
+                if i % 2 == 0 && end > 0. {
+                    sampler.split_range(dist.max(0.)..end.min(length), &mut output);
+                }
+
+                dist = end;
+            }
+        }
+    }
+
+    output.build()
+}
+
+/// Builds a [`PaintPath`] out of lines and curves, in local coordinates
+pub struct PaintPathBuilder {
+    inner: WithSvg<BuilderImpl>,
+}
+
+impl Default for PaintPathBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PaintPathBuilder {
+    pub fn new() -> Self {
+        Self {
+            inner: Path::svg_builder(),
+        }
+    }
+
+    #[inline(always)]
+    fn point(pos: impl Into<Pos>) -> lyon::math::Point {
+        let pos = pos.into();
+        point(pos.x, pos.y)
+    }
+
+    /// Starts a new sub-path at `to`
+    pub fn move_to(&mut self, to: impl Into<Pos>) -> &mut Self {
+        self.inner.move_to(Self::point(to));
+        self
+    }
+
+    pub fn line_to(&mut self, to: impl Into<Pos>) -> &mut Self {
+        self.inner.line_to(Self::point(to));
+        self
+    }
+
+    pub fn quad_to(&mut self, ctrl: impl Into<Pos>, to: impl Into<Pos>) -> &mut Self {
+        self.inner
+            .quadratic_bezier_to(Self::point(ctrl), Self::point(to));
+        self
+    }
+
+    pub fn cubic_to(
+        &mut self,
+        ctrl1: impl Into<Pos>,
+        ctrl2: impl Into<Pos>,
+        to: impl Into<Pos>,
+    ) -> &mut Self {
+        self.inner
+            .cubic_bezier_to(Self::point(ctrl1), Self::point(ctrl2), Self::point(to));
+        self
+    }
+
+    /// Sweeps an elliptic arc around `center`, starting at the current position
+    pub fn arc(
+        &mut self,
+        center: impl Into<Pos>,
+        radii: impl Into<Vector>,
+        sweep_angle: impl Into<Angle>,
+        x_rotation: impl Into<Angle>,
+    ) -> &mut Self {
+        let radii = radii.into();
+
+        self.inner.arc(
+            Self::point(center),
+            lyon::math::vector(radii.x, radii.y),
+            lyon::math::Angle::radians(sweep_angle.into().radians),
+            lyon::math::Angle::radians(x_rotation.into().radians),
+        );
+        self
+    }
+
+    /// An elliptic arc to `to`, as in the SVG `A` command
+    pub fn arc_to(
+        &mut self,
+        radii: impl Into<Vector>,
+        x_rotation: impl Into<Angle>,
+        large_arc: bool,
+        sweep: bool,
+        to: impl Into<Pos>,
+    ) -> &mut Self {
+        let radii = radii.into();
+
+        self.inner.arc_to(
+            lyon::math::vector(radii.x, radii.y),
+            lyon::math::Angle::radians(x_rotation.into().radians),
+            ArcFlags { large_arc, sweep },
+            Self::point(to),
+        );
+        self
+    }
+
+    /// Closes the current sub-path
+    pub fn close(&mut self) -> &mut Self {
+        self.inner.close();
+        self
+    }
+
+    pub fn build(self) -> PaintPath {
+        PaintPath::new(self.inner.build())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn num_dashes(dashes: &[f32], dash_offset: f32) -> usize {
+        let mut builder = Path::builder();
+        builder.begin(point(0., 0.));
+        builder.line_to(point(10., 0.));
+        builder.end(false);
+
+        dash_path(&builder.build(), dashes, dash_offset, TOLERANCE)
+            .iter()
+            .filter(|event| matches!(event, PathEvent::Begin { .. }))
+            .count()
+    }
+
+    #[test]
+    fn dashes() {
+        assert_eq!(num_dashes(&[2., 3.], 0.), 2);
+        assert_eq!(num_dashes(&[2., 3.], 1.), 3);
+    }
+
+    #[test]
+    fn odd_dash_patterns_repeat() {
+        let style = StrokeStyle::new(1.).with_dashes([1., 2., 3.]);
+        assert_eq!(style.dash_pattern(), Some(vec![1., 2., 3., 1., 2., 3.]));
+
+        assert_eq!(
+            StrokeStyle::new(1.).with_dashes([0., 0.]).dash_pattern(),
+            None
+        );
+    }
+}
@@ -0,0 +1,96 @@
+use std::sync::{Arc, Mutex, OnceLock};
+
+use crate::time::{Duration, Instant};
+
+type Waker = Box<dyn Fn() + Send + Sync>;
+
+#[derive(Default)]
+struct RepaintState {
+    /// Earliest time a repaint was requested for
+    requested_at: Mutex<Option<Instant>>,
+    /// Wakes up the event loop when a repaint is requested
+    waker: OnceLock<Waker>,
+}
+
+/// Requests repaints of a scene. Handles are cheap to clone, and can be sent to
+/// background threads which need to update the ui.
+///
+/// Apps only redraw when input arrives or a repaint is requested, so elements
+/// which animate should keep requesting repaints until they're done.
+#[derive(Clone, Default)]
+pub struct RepaintHandle(Arc<RepaintState>);
+
+impl RepaintHandle {
+    /// Repaints as soon as possible
+    #[inline(always)]
+    pub fn request_repaint(&self) {
+        self.request_repaint_after(Duration::ZERO)
+    }
+
+    /// Repaints once `delay` has passed, unless a repaint was already requested
+    /// for earlier
+    pub fn request_repaint_after(&self, delay: Duration) {
+        let at = Instant::now() + delay;
+
+        {
+            let mut requested_at = self.0.requested_at.lock().unwrap();
+
+            if requested_at.is_some_and(|requested_at| requested_at <= at) {
+                return;
+            }
+
+            *requested_at = Some(at);
+        }
+
+        if let Some(waker) = self.0.waker.get() {
+            waker();
+        }
+    }
+
+    /// Time of the earliest pending repaint
+    pub fn next_repaint(&self) -> Option<Instant> {
+        *self.0.requested_at.lock().unwrap()
+    }
+
+    /// Clears the pending repaint if it's due at `now`, and returns whether it was
+    pub(crate) fn take_due(&self, now: Instant) -> bool {
+        let mut requested_at = self.0.requested_at.lock().unwrap();
+
+        match *requested_at {
+            Some(at) if at <= now => {
+                *requested_at = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Sets the callback which wakes up the event loop. Only the first waker is
+    /// kept.
+    pub(crate) fn set_waker(&self, waker: impl Fn() + Send + Sync + 'static) {
+        if self.0.waker.set(Box::new(waker)).is_err() {
+            log::warn!("repaint waker was already set");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn earliest_request_wins() {
+        let handle = RepaintHandle::default();
+        let now = Instant::now();
+
+        handle.request_repaint_after(Duration::from_secs(60));
+        assert!(!handle.take_due(now));
+
+        handle.clone().request_repaint();
+        handle.request_repaint_after(Duration::from_secs(60));
+        assert!(handle.next_repaint().unwrap() < now + Duration::from_secs(1));
+
+        assert!(handle.take_due(Instant::now()));
+        assert_eq!(handle.next_repaint(), None);
+    }
+}
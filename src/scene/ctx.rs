@@ -1,21 +1,76 @@
-use std::borrow::Cow;
+use std::{borrow::Cow, collections::HashMap, ops::Range};
 
 use crate::{
     atlas::TextureAtlasManagerRef,
+    capture::CaptureRequest,
+    custom_render::{CustomRenderTarget, QueuedCustomRender},
+    element::ElementId,
+    layer::{BlendMode, FrameLayer, Layer, ZIndex},
     input::output::{CursorIcon, PlatformOutput},
     math::{
         CompleteScaleFactor, CoordinateTransform, DeviceScaleFactor, Pos, Rect, Size,
         TransformationList, TransformationScaleFactor,
     },
     shape::{ClipRect, ClipRectList, ComputedPaintShape, PaintShape, ShaderClipRect}, util::os::OperatingSystem,
+    texture::TextureRef,
+    time::Duration,
 };
 
 use super::scene::SceneResources;
 
-pub(super) struct PaintShapeWithContext {
+#[derive(Clone)]
+pub struct PaintShapeWithContext {
     pub shape: ComputedPaintShape,
     pub clip_rect_idx: Option<u32>,
     pub transformation_idx: Option<u32>,
+    /// The element which painted this shape
+    pub element_id: Option<ElementId>,
+    /// Index into [`super::renderer::FramePaint::layers`]
+    pub layer_idx: Option<u32>,
+    pub blend_mode: BlendMode,
+    pub z_index: ZIndex,
+}
+
+/// What an element is painted for. Shapes painted for the same key are culled,
+/// clipped and scaled the same way.
+///
+/// The clip rect and transformation are compared by value, since their indices
+/// shift whenever an earlier element adds or removes one. Reused shapes are
+/// pointed to the current indices instead, see [`ElementPaint::remap`].
+#[derive(Clone, PartialEq)]
+pub(super) struct ElementPaintKey {
+    rect: Rect,
+    transformation: Option<CoordinateTransform>,
+    clip_rect: Option<ClipRect>,
+    visible_rect: Option<Rect>,
+    scale_factor: CompleteScaleFactor,
+    z_index: ZIndex,
+}
+
+/// Shapes an element painted, which are reused while it doesn't need to repaint
+pub(super) struct ElementPaint {
+    key: ElementPaintKey,
+    /// See [`super::renderer::FramePaint::element_generations`]
+    pub(super) generation: u64,
+    pub(super) ui_shapes: Vec<PaintShapeWithContext>,
+    pub(super) ui_post_shapes: Vec<PaintShapeWithContext>,
+}
+
+impl ElementPaint {
+    /// Points the shapes to the clip rect, transformation and layer the element
+    /// is painted with this frame
+    fn remap(
+        &mut self,
+        clip_rect_idx: Option<u32>,
+        transformation_idx: Option<u32>,
+        layer_idx: Option<u32>,
+    ) {
+        for shape in self.ui_shapes.iter_mut().chain(&mut self.ui_post_shapes) {
+            shape.clip_rect_idx = clip_rect_idx;
+            shape.transformation_idx = transformation_idx;
+            shape.layer_idx = layer_idx;
+        }
+    }
 }
 
 pub struct SceneContext<'a> {
@@ -28,6 +83,31 @@ pub struct SceneContext<'a> {
     pub(super) clip_rects: ClipRectList,
     pub(super) active_clip_rect_idx: Option<usize>,
 
+    pub(super) active_element_id: Option<ElementId>,
+
+    pub(super) layers: Vec<FrameLayer>,
+    pub(super) active_layer_idx: Option<usize>,
+
+    pub(super) active_blend_mode: BlendMode,
+
+    pub(super) active_z_index: ZIndex,
+
+    pub(super) custom_renders: Vec<QueuedCustomRender>,
+
+    pub(super) capture_requests: Vec<CaptureRequest>,
+
+    /// What elements painted in the last frame, taken as they're reused
+    last_element_paints: HashMap<ElementId, ElementPaint>,
+    pub(super) element_paints: HashMap<ElementId, ElementPaint>,
+    /// Generation of the shapes painted this frame
+    paint_generation: u64,
+
+    /// Logical bounds of the frame
+    viewport: Rect,
+    /// The last visible rect, and the clip rect and transformation it was
+    /// computed for
+    visible_rect_cache: Option<((Option<usize>, Option<usize>), Option<Rect>)>,
+
     scene_resources: SceneResources<'a>,
 
     scale_factor: DeviceScaleFactor,
@@ -39,7 +119,10 @@ impl<'a> SceneContext<'a> {
         scale_factor: DeviceScaleFactor,
         transformations: TransformationList,
         clip_rects: ClipRectList,
+        viewport: Rect,
         scene_resources: SceneResources<'a>,
+        last_element_paints: HashMap<ElementId, ElementPaint>,
+        paint_generation: u64,
     ) -> Self {
         Self {
             shapes: Default::default(),
@@ -49,6 +132,18 @@ impl<'a> SceneContext<'a> {
             output: Default::default(),
             clip_rects,
             active_clip_rect_idx: Default::default(),
+            active_element_id: Default::default(),
+            layers: Default::default(),
+            active_layer_idx: Default::default(),
+            active_blend_mode: Default::default(),
+            active_z_index: Default::default(),
+            custom_renders: Default::default(),
+            capture_requests: Default::default(),
+            last_element_paints,
+            element_paints: Default::default(),
+            paint_generation,
+            viewport,
+            visible_rect_cache: Default::default(),
             scene_resources,
             os: OperatingSystem::default(),
         }
@@ -60,6 +155,10 @@ impl<'a> SceneContext<'a> {
     }
 
     pub fn add_shape<'b, T: Into<PaintShape<'b>>>(&mut self, shape: T) {
+        let Some(visible_rect) = self.visible_rect() else {
+            return;
+        };
+
         let scale_fac = self.scale_factor
             * self
                 .active_transformation_idx
@@ -68,14 +167,158 @@ impl<'a> SceneContext<'a> {
                 .map(|(sx, sy)| sx.max(sy))
                 .unwrap_or_default();
 
-        let shape = Into::<PaintShape<'b>>::into(shape)
-            .compute_paint_shape(self.current_clip_rect(), scale_fac);
+        let clip_rect = self.current_clip_rect();
+
+        let clip_rect_idx = self.active_clip_rect_idx.map(|x| x as u32);
+        let transformation_idx = self.active_transformation_idx.map(|x| x as u32);
+        let element_id = self.active_element_id;
+        let layer_idx = self.active_layer_idx.map(|x| x as u32);
+        let blend_mode = self.active_blend_mode;
+        let z_index = self.active_z_index;
+
+        Into::<PaintShape<'b>>::into(shape).compute_paint_shapes(
+            clip_rect,
+            visible_rect,
+            scale_fac,
+            |shape| {
+                self.shapes.push(PaintShapeWithContext {
+                    shape,
+                    clip_rect_idx,
+                    transformation_idx,
+                    element_id,
+                    layer_idx,
+                    blend_mode,
+                    z_index,
+                })
+            },
+        )
+    }
+
+    /// The part of the current coordinate space which can be seen through the
+    /// current clip rect and the window, or `None` if nothing can be seen.
+    /// Shapes outside of it are culled by [`Self::add_shape`].
+    pub fn visible_rect(&mut self) -> Option<Rect> {
+        let key = (self.active_clip_rect_idx, self.active_transformation_idx);
+
+        match self.visible_rect_cache {
+            Some((cached_key, visible_rect)) if cached_key == key => visible_rect,
+            _ => {
+                let visible_rect = self.clip_rects.visible_rect(
+                    self.active_clip_rect_idx,
+                    &self.transformations,
+                    self.active_transformation_idx,
+                    self.viewport,
+                );
+
+                self.visible_rect_cache = Some((key, visible_rect));
 
-        self.shapes.push(PaintShapeWithContext {
-            shape,
-            clip_rect_idx: self.active_clip_rect_idx.map(|x| x as u32),
-            transformation_idx: self.active_transformation_idx.map(|x| x as u32),
-        })
+                visible_rect
+            }
+        }
+    }
+
+    /// Sets how shapes added after this are blended with what's beneath them,
+    /// until the end of the current `ui` or `ui_post` call
+    #[inline(always)]
+    pub fn set_blend_mode(&mut self, blend_mode: BlendMode) {
+        self.active_blend_mode = blend_mode;
+    }
+
+    #[inline(always)]
+    pub fn blend_mode(&self) -> BlendMode {
+        self.active_blend_mode
+    }
+
+    /// Sizes the texture of `target` to `rect` and queues its callback to run
+    /// before this frame's main render pass. The returned texture can be painted
+    /// with [`crate::shape::PaintFill::from_entire_texture`].
+    pub fn render_custom<'t>(
+        &mut self,
+        target: &'t mut CustomRenderTarget,
+        rect: Rect,
+    ) -> &'t TextureRef {
+        let size = (rect.size() * self.scale_factor().as_float()).map(|x| x.ceil() as u32);
+
+        let rendering_context = self.scene_resources.rendering_context_ref();
+
+        target.prepare(
+            &rendering_context,
+            self.scene_resources.texture_manager(),
+            size,
+        );
+
+        self.custom_renders.extend(target.queued_render());
+
+        target.texture().unwrap()
+    }
+
+    /// Captures this frame once it's rendered
+    pub fn request_capture(&mut self, request: CaptureRequest) {
+        self.capture_requests.push(request);
+    }
+
+    /// Identifies what the active element paints for
+    pub(super) fn element_paint_key(&mut self, rect: Rect) -> ElementPaintKey {
+        ElementPaintKey {
+            rect,
+            transformation: self
+                .active_transformation_idx
+                .map(|idx| *self.transformations.get(idx)),
+            clip_rect: self.current_clip_rect(),
+            visible_rect: self.visible_rect(),
+            scale_factor: self.scale_factor(),
+            z_index: self.active_z_index,
+        }
+    }
+
+    /// Takes what an element painted in the last frame, if it was painted for `key`
+    pub(super) fn take_element_paint(
+        &mut self,
+        element_id: ElementId,
+        key: &ElementPaintKey,
+    ) -> Option<ElementPaint> {
+        let mut paint = self
+            .last_element_paints
+            .remove(&element_id)
+            .filter(|paint| paint.key == *key)?;
+
+        paint.remap(
+            self.active_clip_rect_idx.map(|x| x as u32),
+            self.active_transformation_idx.map(|x| x as u32),
+            self.active_layer_idx.map(|x| x as u32),
+        );
+
+        Some(paint)
+    }
+
+    /// Keeps the shapes an element painted this frame, in `ui_shapes` and
+    /// `ui_post_shapes`, so they can be reused
+    pub(super) fn keep_element_paint(
+        &mut self,
+        element_id: ElementId,
+        key: ElementPaintKey,
+        ui_shapes: Range<usize>,
+        ui_post_shapes: Range<usize>,
+    ) {
+        let paint = ElementPaint {
+            key,
+            generation: self.paint_generation,
+            ui_shapes: self.shapes[ui_shapes].to_vec(),
+            ui_post_shapes: self.shapes[ui_post_shapes].to_vec(),
+        };
+
+        self.element_paints.insert(element_id, paint);
+    }
+
+    pub(super) fn push_layer(&mut self, layer: Layer, parent_idx: Option<usize>) -> usize {
+        let idx = self.layers.len();
+
+        self.layers.push(FrameLayer {
+            layer,
+            parent_idx: parent_idx.map(|x| x as u32),
+        });
+
+        idx
     }
 
     // pub fn push_clip_rect(&mut self, rect: impl Into<ClipRect>) {
@@ -116,6 +359,18 @@ impl<'a> SceneContext<'a> {
         self.output.open_url(url)
     }
 
+    /// Requests another frame as soon as possible, e.g. while animating
+    #[inline(always)]
+    pub fn request_repaint(&mut self) {
+        self.scene_resources.repaint.request_repaint()
+    }
+
+    /// Requests another frame once `delay` has passed
+    #[inline(always)]
+    pub fn request_repaint_after(&mut self, delay: Duration) {
+        self.scene_resources.repaint.request_repaint_after(delay)
+    }
+
     pub fn resources(&mut self) -> &mut SceneResources<'a> {
         &mut self.scene_resources
     }
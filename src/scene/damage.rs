@@ -0,0 +1,426 @@
+use std::collections::VecDeque;
+
+use crate::{
+    color::ColorRgba,
+    layer::{BlendMode, FrameLayer, ZIndex},
+    math::{CoordinateTransform, DeviceScaleFactor, PhysicalRect, PhysicalSize, Pos, Rect},
+    shape::{ClipRect, ComputedPaintShape, PaintRectangle, ShaderClipRect},
+};
+
+use super::{ctx::PaintShapeWithContext, renderer::FramePaint};
+
+/// Frames of damage kept for targets whose contents are older than the last
+/// frame, see [`DamageTracker::track`]
+const MAX_BUFFER_AGE: usize = 4;
+
+/// The pixels of a frame which differ from an earlier frame
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Damage {
+    /// Nothing changed
+    Empty,
+    /// Only pixels within the rect changed
+    Rect(PhysicalRect<u32>),
+    /// Everything has to be redrawn
+    #[default]
+    Full,
+}
+
+impl Damage {
+    pub fn union(self, other: Self) -> Self {
+        match (self, other) {
+            (Damage::Full, _) | (_, Damage::Full) => Damage::Full,
+            (Damage::Empty, damage) | (damage, Damage::Empty) => damage,
+            (Damage::Rect(a), Damage::Rect(b)) => Damage::Rect(a.union(&b)),
+        }
+    }
+
+    /// Rounds `rect` out to whole pixels within `size`
+    fn from_rect(rect: PhysicalRect, size: PhysicalSize<u32>) -> Self {
+        let clamp = |x: f32, max: u32| (x.max(0.) as u32).min(max);
+
+        let rect = PhysicalRect::new(
+            Pos::new(
+                clamp(rect.min.x.floor(), size.width),
+                clamp(rect.min.y.floor(), size.height),
+            ),
+            Pos::new(
+                clamp(rect.max.x.ceil(), size.width),
+                clamp(rect.max.y.ceil(), size.height),
+            ),
+        );
+
+        match rect.min.x < rect.max.x && rect.min.y < rect.max.y {
+            true if rect == PhysicalRect::new(Pos::zero(), Pos::new(size.width, size.height)) => {
+                Damage::Full
+            }
+            true => Damage::Rect(rect),
+            false => Damage::Empty,
+        }
+    }
+}
+
+/// A frame which later frames are compared against
+struct TrackedFrame {
+    shapes: Vec<PaintShapeWithContext>,
+    /// Pixels covered by each shape, `None` if it can't be seen
+    bounds: Vec<Option<PhysicalRect>>,
+    clip_rects: Vec<ShaderClipRect>,
+    transformations: Vec<CoordinateTransform>,
+    layers: Vec<FrameLayer>,
+    size: PhysicalSize<u32>,
+    scale_factor: DeviceScaleFactor,
+}
+
+impl TrackedFrame {
+    fn new(frame: &FramePaint, size: PhysicalSize<u32>, scale_factor: DeviceScaleFactor) -> Self {
+        let viewport = Rect::new(Pos::zero(), Pos::new(size.width as f32, size.height as f32));
+
+        let mut tracked = Self {
+            shapes: frame.shapes.clone(),
+            bounds: Default::default(),
+            clip_rects: frame.clip_rects.clone(),
+            transformations: frame.transformations.clone(),
+            layers: frame.layers.clone(),
+            size,
+            scale_factor,
+        };
+
+        tracked.bounds = tracked
+            .shapes
+            .iter()
+            .map(|shape| tracked.shape_bounds(shape, viewport))
+            .collect();
+
+        tracked
+    }
+
+    #[inline(always)]
+    fn transformation(&self, idx: Option<u32>) -> &CoordinateTransform {
+        &self.transformations[idx.unwrap_or_default() as usize]
+    }
+
+    /// The clip rect at `idx`, followed by every clip rect it is nested in
+    fn clip_chain(
+        &self,
+        idx: Option<u32>,
+    ) -> impl Iterator<Item = (ClipRect, &CoordinateTransform)> + '_ {
+        std::iter::successors(idx.filter(|idx| *idx != 0), |idx| {
+            Some(self.clip_rects[*idx as usize].parent_idx).filter(|idx| *idx != 0)
+        })
+        .map(|idx| {
+            let clip_rect = self.clip_rects[idx as usize];
+            (
+                clip_rect.into(),
+                self.transformation(Some(clip_rect.transformation_idx)),
+            )
+        })
+    }
+
+    /// The layer at `idx`, followed by every layer it is nested in
+    fn layer_chain(&self, idx: Option<u32>) -> impl Iterator<Item = &FrameLayer> + '_ {
+        std::iter::successors(idx, |idx| self.layers[*idx as usize].parent_idx)
+            .map(|idx| &self.layers[idx as usize])
+    }
+
+    /// Physical pixels which `shape` may cover, padded by a pixel for
+    /// anti-aliasing
+    fn shape_bounds(
+        &self,
+        shape: &PaintShapeWithContext,
+        viewport: PhysicalRect,
+    ) -> Option<PhysicalRect> {
+        let bounds = match &shape.shape {
+            ComputedPaintShape::Rectangle(rect) => rect.get_bounding_box(),
+            ComputedPaintShape::Mesh(mesh) if mesh.vertices.is_empty() => return None,
+            ComputedPaintShape::Mesh(mesh) => mesh.get_bounding_box(),
+            ComputedPaintShape::Text(text_box) => {
+                let bounds = text_box.glyph_bounds()?;
+
+                match text_box.clip_rect {
+                    Some(clip_rect) => bounds.intersection(&clip_rect.inner)?,
+                    None => bounds,
+                }
+            }
+        };
+
+        let bounds = self
+            .transformation(shape.transformation_idx)
+            .outer_transformed_rect(&bounds);
+
+        let bounds = self.clip_chain(shape.clip_rect_idx).try_fold(
+            bounds,
+            |bounds, (clip_rect, transformation)| {
+                bounds.intersection(&transformation.outer_transformed_rect(&clip_rect.inner))
+            },
+        )?;
+
+        let scale_factor = self.scale_factor.as_float().get();
+
+        bounds
+            .map_points(|p| Pos::new(p.x * scale_factor, p.y * scale_factor))
+            .inflate(1., 1.)
+            .intersection(&viewport)
+    }
+
+    /// Whether the shapes at `idx` and `other_idx` paint the same pixels
+    fn same_paint(&self, idx: usize, other: &TrackedFrame, other_idx: usize) -> bool {
+        let (a, b) = (&self.shapes[idx], &other.shapes[other_idx]);
+
+        self.bounds[idx] == other.bounds[other_idx]
+            && a.blend_mode == b.blend_mode
+            && self.transformation(a.transformation_idx)
+                == other.transformation(b.transformation_idx)
+            && self
+                .clip_chain(a.clip_rect_idx)
+                .eq(other.clip_chain(b.clip_rect_idx))
+            && self
+                .layer_chain(a.layer_idx)
+                .map(|layer| layer.layer)
+                .eq(other.layer_chain(b.layer_idx).map(|layer| layer.layer))
+            && a.shape == b.shape
+    }
+
+    /// Pixels which differ between `self` and `other`, a later frame
+    fn damage(&self, other: &TrackedFrame) -> Damage {
+        if self.size != other.size || self.scale_factor != other.scale_factor {
+            return Damage::Full;
+        }
+
+        let (len, other_len) = (self.shapes.len(), other.shapes.len());
+
+        // shapes before and after the changed ones paint the same pixels, as
+        // long as they stay in the same order
+        let prefix = (0..len.min(other_len))
+            .take_while(|&idx| self.same_paint(idx, other, idx))
+            .count();
+
+        let suffix = (1..=len.min(other_len) - prefix)
+            .take_while(|&n| self.same_paint(len - n, other, other_len - n))
+            .count();
+
+        self.bounds[prefix..len - suffix]
+            .iter()
+            .chain(&other.bounds[prefix..other_len - suffix])
+            .flatten()
+            .map(|bounds| Damage::from_rect(*bounds, self.size))
+            .fold(Damage::Empty, Damage::union)
+    }
+}
+
+/// Compares each frame with the one before, so that targets which still hold an
+/// earlier frame only need to redraw what changed
+#[derive(Default)]
+pub struct DamageTracker {
+    last_frame: Option<TrackedFrame>,
+    /// Damage of the most recent frames, the latest first
+    history: VecDeque<Damage>,
+}
+
+impl DamageTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Tracks `frame`, and returns the pixels which have to be redrawn in a
+    /// target that holds the frame drawn `buffer_age` frames ago.
+    ///
+    /// A buffer age of 0 means the target's contents are undefined, so it's
+    /// redrawn fully. If nothing changed since the last frame, [`Damage::Empty`]
+    /// is returned regardless, and the last frame can be kept on screen instead.
+    pub fn track(
+        &mut self,
+        frame: &FramePaint,
+        size: PhysicalSize<u32>,
+        scale_factor: DeviceScaleFactor,
+        buffer_age: u32,
+    ) -> Damage {
+        let tracked = TrackedFrame::new(frame, size, scale_factor);
+
+        // custom renders may draw anything into their textures
+        let damage = match &self.last_frame {
+            Some(last_frame) if frame.custom_renders.is_empty() => last_frame.damage(&tracked),
+            _ => Damage::Full,
+        };
+
+        self.last_frame = Some(tracked);
+
+        self.history.push_front(damage);
+        self.history.truncate(MAX_BUFFER_AGE);
+
+        match buffer_age as usize {
+            0 if damage == Damage::Empty => Damage::Empty,
+            age if age == 0 || age > self.history.len() => Damage::Full,
+            age => self
+                .history
+                .iter()
+                .take(age)
+                .fold(Damage::Empty, |acc, damage| acc.union(*damage)),
+        }
+    }
+}
+
+/// An opaque rectangle which erases `viewport` before the damaged region of a
+/// target is redrawn
+pub(crate) fn erase_shape(viewport: Rect) -> PaintShapeWithContext {
+    PaintShapeWithContext {
+        shape: ComputedPaintShape::Rectangle(
+            PaintRectangle::from_rect(viewport.inflate(2., 2.))
+                .with_fill(ColorRgba::new(1., 1., 1., 1.)),
+        ),
+        clip_rect_idx: None,
+        transformation_idx: None,
+        element_id: None,
+        layer_idx: None,
+        blend_mode: BlendMode::DestinationOut,
+        z_index: ZIndex::BASE,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: PhysicalSize<u32> = PhysicalSize::new(100, 100);
+
+    fn scale_factor() -> DeviceScaleFactor {
+        DeviceScaleFactor::from_float(2.)
+    }
+
+    /// A 10 by 10 rect at `x`
+    fn rect(x: f32, color: ColorRgba, transformation_idx: Option<u32>) -> PaintShapeWithContext {
+        let rect = Rect::new(Pos::new(x, 0.), Pos::new(x + 10., 10.));
+
+        PaintShapeWithContext {
+            shape: ComputedPaintShape::Rectangle(PaintRectangle::from_rect(rect).with_fill(color)),
+            clip_rect_idx: None,
+            transformation_idx,
+            element_id: None,
+            layer_idx: None,
+            blend_mode: BlendMode::Normal,
+            z_index: ZIndex::BASE,
+        }
+    }
+
+    fn frame(
+        shapes: Vec<PaintShapeWithContext>,
+        transformations: Vec<CoordinateTransform>,
+    ) -> FramePaint {
+        FramePaint {
+            shapes,
+            transformation_inverses: transformations
+                .iter()
+                .map(|t| t.inverse().unwrap())
+                .collect(),
+            transformations,
+            clip_rects: vec![Default::default()],
+            layers: Default::default(),
+            custom_renders: Default::default(),
+            element_generations: Default::default(),
+        }
+    }
+
+    fn rects(xs: &[f32]) -> FramePaint {
+        let red = ColorRgba::new(1., 0., 0., 1.);
+        let shapes = xs.iter().map(|x| rect(*x, red, None)).collect();
+        frame(shapes, vec![CoordinateTransform::identity()])
+    }
+
+    fn damage(previous: &FramePaint, next: &FramePaint) -> Damage {
+        TrackedFrame::new(previous, SIZE, scale_factor()).damage(&TrackedFrame::new(
+            next,
+            SIZE,
+            scale_factor(),
+        ))
+    }
+
+    /// Physical bounds of the rect at logical `x`, padded by a logical unit of
+    /// feathering and a pixel
+    fn rect_damage(x: u32) -> Damage {
+        Damage::Rect(PhysicalRect::new(
+            Pos::new((2 * x).saturating_sub(3), 0),
+            Pos::new((2 * x + 23).min(100), 23),
+        ))
+    }
+
+    #[test]
+    fn only_changed_shapes_are_damaged() {
+        let (red, blue) = (
+            ColorRgba::new(1., 0., 0., 1.),
+            ColorRgba::new(0., 0., 1., 1.),
+        );
+
+        let shapes = |colors: [ColorRgba; 3]| {
+            let shapes = (0..3).map(|i| rect(i as f32 * 10., colors[i], None));
+            frame(shapes.collect(), vec![CoordinateTransform::identity()])
+        };
+
+        assert_eq!(
+            damage(&shapes([red, red, red]), &shapes([red, red, red])),
+            Damage::Empty
+        );
+        assert_eq!(
+            damage(&shapes([red, red, red]), &shapes([red, blue, red])),
+            rect_damage(10)
+        );
+    }
+
+    #[test]
+    fn moved_added_and_removed_shapes() {
+        assert_eq!(
+            damage(&rects(&[10.]), &rects(&[30.])),
+            rect_damage(10).union(rect_damage(30))
+        );
+        assert_eq!(damage(&rects(&[10.]), &rects(&[10., 30.])), rect_damage(30));
+        assert_eq!(damage(&rects(&[10., 30.]), &rects(&[30.])), rect_damage(10));
+    }
+
+    #[test]
+    fn changed_transformations() {
+        let translated = |x: f32| {
+            frame(
+                vec![rect(10., ColorRgba::new(1., 0., 0., 1.), Some(1))],
+                vec![
+                    CoordinateTransform::identity(),
+                    CoordinateTransform::translation(x, 0.),
+                ],
+            )
+        };
+
+        assert_eq!(damage(&translated(0.), &translated(0.)), Damage::Empty);
+        assert_eq!(
+            damage(&translated(0.), &translated(20.)),
+            rect_damage(10).union(rect_damage(30))
+        );
+    }
+
+    #[test]
+    fn damage_accumulates_over_buffer_ages() {
+        let mut tracker = DamageTracker::new();
+        let mut track =
+            |xs: &[f32], buffer_age| tracker.track(&rects(xs), SIZE, scale_factor(), buffer_age);
+
+        let (first, second) = (
+            rect_damage(0).union(rect_damage(20)),
+            rect_damage(20).union(rect_damage(40)),
+        );
+
+        assert_eq!(track(&[0.], 1), Damage::Full);
+        assert_eq!(track(&[20.], 1), first);
+        assert_eq!(track(&[40.], 2), first.union(second));
+
+        // the first frame was drawn fully, and older frames aren't tracked
+        assert_eq!(track(&[40.], 4), Damage::Full);
+        assert_eq!(track(&[40.], 4), first.union(second));
+        assert_eq!(track(&[40.], 5), Damage::Full);
+    }
+
+    #[test]
+    fn undefined_targets_are_redrawn_unless_unchanged() {
+        let mut tracker = DamageTracker::new();
+        let mut track = |xs: &[f32]| tracker.track(&rects(xs), SIZE, scale_factor(), 0);
+
+        assert_eq!(track(&[0.]), Damage::Full);
+        assert_eq!(track(&[0.]), Damage::Empty);
+        assert_eq!(track(&[20.]), Damage::Full);
+    }
+}
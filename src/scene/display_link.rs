@@ -0,0 +1,292 @@
+//! Frame pacing with `CADisplayLink`, which reports when the next frame is
+//! displayed on macOS
+
+use icrate::{
+    AppKit::NSScreen,
+    CoreAnimation::{CADisplayLink, CAFrameRateRangeMake, CFTimeInterval},
+    Foundation::NSProcessInfo,
+};
+use objc2::rc::Id;
+use std::os::raw::c_int;
+
+use icrate::Foundation::{
+    NSCopying, NSObject, NSObjectProtocol, NSRunLoop, NSRunLoopCommonModes, NSZone,
+};
+use objc2::declare::{Ivar, IvarBool, IvarDrop, IvarEncode};
+use objc2::{
+    declare_class, extern_protocol, msg_send, msg_send_id, mutability, sel, ClassType, ProtocolType,
+};
+
+use crate::{app::get_window_last_screen_draw_time, surface::RenderingContext};
+
+use super::framepacer::{Framepacer, InstantLike, ManagedFramepacer};
+
+/// Seconds since boot, the timebase of `CADisplayLink`
+impl InstantLike for std::time::Duration {
+    type Context = ();
+
+    fn now(_: &Self::Context) -> Self {
+        Self::from_secs_f64(unsafe { NSProcessInfo::processInfo().systemUptime() })
+    }
+
+    fn context_from(render_ctx: &RenderingContext) -> &Self::Context
+    where
+        Self: Sized,
+    {
+        &()
+    }
+
+    fn duration_since(&self, earlier: &Self) -> std::time::Duration {
+        *self - *earlier
+    }
+
+    fn add_duration(self, duration: std::time::Duration) -> Self {
+        self + duration
+    }
+
+    fn query_presentation_statistics(
+        _surface: &wgpu::Surface,
+        window: &winit::window::Window,
+        fallback: Self,
+    ) -> Self
+    where
+        Self: Sized,
+    {
+        get_window_last_screen_draw_time(window).unwrap()
+    }
+}
+
+declare_class!(
+    struct CADisplayLinkPollable {
+        target_timestamp: IvarEncode<CFTimeInterval, "_target_timestamp">,
+        timestamp: IvarEncode<CFTimeInterval, "_timestamp">,
+        is_ready: IvarBool<"_is_ready">,
+        ca_display_link: IvarDrop<Id<CADisplayLink>, "_ca_display_link">,
+    }
+
+    mod ivar;
+
+    unsafe impl ClassType for CADisplayLinkPollable {
+        type Super = NSObject;
+        type Mutability = mutability::Mutable;
+        const NAME: &'static str = "CADisplayLinkPollable";
+    }
+
+    unsafe impl CADisplayLinkPollable {
+        #[method(init)]
+        fn init(this: &mut Self) -> Option<&mut Self> {
+            let this: Option<&mut Self> = unsafe { msg_send![super(this), init] };
+
+            this.map(|this| {
+                let ca_display_link =
+                    unsafe { CADisplayLink::displayLinkWithTarget_selector(this, sel!(step:)) };
+
+                unsafe {
+                    ca_display_link
+                        .addToRunLoop_forMode(&NSRunLoop::currentRunLoop(), NSRunLoopCommonModes);
+                }
+
+                Ivar::write(&mut this.ca_display_link, ca_display_link);
+                *this.is_ready = false;
+
+                // All the instance variables have been initialized; our
+                // initializer is sound
+                this
+            })
+        }
+
+        #[method(step:)]
+        fn step(this: &mut Self, ca_display_link: &mut CADisplayLink) {
+            *this.target_timestamp = unsafe { ca_display_link.targetTimestamp() };
+            *this.timestamp = unsafe { ca_display_link.timestamp() };
+
+            *this.is_ready = true;
+        }
+
+        #[method(isReady)]
+        fn __get_is_ready(&self) -> bool {
+            *self.is_ready
+        }
+
+        #[method(targetTimestamp)]
+        fn __get_target_timestamp(&mut self) -> CFTimeInterval {
+            *self.is_ready = false;
+            *self.target_timestamp
+        }
+
+        #[method(timestamp)]
+        fn __get_timestamp(&mut self) -> CFTimeInterval {
+            *self.timestamp
+        }
+
+        #[method_id(caDisplayLink)]
+        fn __get_ca_display_link(&self) -> Id<CADisplayLink> {
+            self.ca_display_link.clone()
+        }
+    }
+);
+
+impl CADisplayLinkPollable {
+    pub fn new() -> Id<Self> {
+        unsafe { msg_send_id![Self::alloc(), init] }
+    }
+
+    pub fn get_is_ready(&self) -> bool {
+        unsafe { msg_send![self, isReady] }
+    }
+
+    pub fn get_target_timestamp(&self) -> CFTimeInterval {
+        unsafe { msg_send![self, targetTimestamp] }
+    }
+
+    pub fn get_timestamp(&self) -> CFTimeInterval {
+        unsafe { msg_send![self, timestamp] }
+    }
+
+    pub fn get_ca_display_link(&self) -> Id<CADisplayLink> {
+        unsafe { msg_send_id![self, caDisplayLink] }
+    }
+}
+
+pub struct CADisplayLinkFramepacer {
+    ca_display_link_pollable: Id<CADisplayLinkPollable>,
+    managed_framepacer: ManagedFramepacer<std::time::Duration>,
+
+    next_deadline: Option<std::time::Duration>,
+    last_deadline: Option<std::time::Duration>,
+
+    desired_frametime: Option<std::time::Duration>,
+
+    needs_kickstart: bool,
+}
+
+impl CADisplayLinkFramepacer {
+    pub fn new() -> Self {
+        Self {
+            ca_display_link_pollable: CADisplayLinkPollable::new(),
+            managed_framepacer: Default::default(),
+
+            next_deadline: Default::default(),
+            last_deadline: Default::default(),
+
+            desired_frametime: Default::default(),
+
+            needs_kickstart: true,
+        }
+    }
+}
+
+impl Framepacer<std::time::Duration> for CADisplayLinkFramepacer {
+    fn start_window(
+        &mut self,
+        presentation_start: std::time::Duration,
+        screen_refresh_time: Option<std::time::Duration>,
+    ) {
+        self.managed_framepacer
+            .start_window(presentation_start, screen_refresh_time);
+    }
+
+    fn check_missed_deadline(
+        &mut self,
+        now: std::time::Duration,
+        render_time: Option<std::time::Duration>,
+    ) -> bool {
+        self.managed_framepacer
+            .check_missed_deadline(now, render_time)
+    }
+
+    fn get_deadline(&self) -> Option<std::time::Duration> {
+        self.next_deadline
+    }
+
+    fn should_render(&mut self, start_time: std::time::Duration) -> (bool, std::time::Duration) {
+        let new_deadline = if self.ca_display_link_pollable.get_is_ready() {
+            Some(std::time::Duration::from_secs_f64(
+                self.ca_display_link_pollable.get_target_timestamp(),
+            ))
+        } else {
+            None
+        };
+
+        if let Some(deadline) = new_deadline {
+            // if self.next_deadline.is_some() {
+            //     log::warn!("deadline override!");
+            // }
+
+            let old_deadline = self.managed_framepacer.deadline.replace(deadline);
+
+            // if let Some(old_deadline) = old_deadline {
+            //     println!(
+            //         "managed deadline mismatch: {:?}",
+            //         duration_dist(old_deadline, deadline)
+            //     )
+            // }
+
+            // self.last_deadline = self.next_deadline.take();
+
+            self.next_deadline = deadline.into();
+        }
+
+        if let Some(next_deadline) = self.next_deadline {
+            let result = self.managed_framepacer.should_render(start_time);
+
+            if result.0 {
+                self.desired_frametime = (next_deadline
+                    - std::time::Duration::from_secs_f64(
+                        self.ca_display_link_pollable.get_timestamp(),
+                    ))
+                .into();
+
+                // if let Some(last_deadline) = self.last_deadline {
+                //     self.desired_frametime = (next_deadline - last_deadline).into();
+                // }
+
+                self.next_deadline = None;
+            }
+
+            result
+        } else {
+            (false, start_time)
+        }
+    }
+
+    fn time_until_render(&self, now: std::time::Duration) -> Option<std::time::Duration> {
+        self.managed_framepacer.time_until_render(now)
+    }
+
+    fn push_frametime(&mut self, duration: crate::time::Duration) {
+        self.managed_framepacer.push_frametime(duration)
+    }
+
+    fn desired_frame_time(&self) -> Option<std::time::Duration> {
+        self.desired_frametime
+
+        // self.desired_frametime.and_then(|duration| {
+        //     if let Some(screen_refresh) = self.managed_framepacer.desired_frame_time {
+        //         if duration > screen_refresh + std::time::Duration::from_micros(500) {
+        //             None
+        //         } else {
+        //             Some(duration)
+        //         }
+        //     } else {
+        //         None
+        //     }
+        // })
+    }
+
+    fn desired_frame_instant(&self) -> Option<wgpu::PresentationTimestamp>
+    where
+        std::time::Duration: Copy,
+    {
+        self.get_deadline()
+            .map(|deadline| wgpu::PresentationTimestamp(deadline.as_nanos()))
+    }
+
+    fn sync_to_fps(&self, fps: f32) {
+        unsafe {
+            self.ca_display_link_pollable
+                .get_ca_display_link()
+                .setPreferredFrameRateRange(CAFrameRateRangeMake(fps, fps, fps))
+        }
+    }
+}
@@ -2,7 +2,13 @@ use ringbuffer::{ConstGenericRingBuffer, RingBuffer};
 
 use num_traits::Pow;
 
-use crate::{app::get_window_last_screen_draw_time, surface::RenderingContext};
+use crate::{
+    surface::RenderingContext,
+    time::{Duration, Instant},
+};
+
+#[cfg(target_os = "macos")]
+pub use super::display_link::CADisplayLinkFramepacer;
 
 const FRAMEPACER_NUM_SAMPLES: usize = 120;
 
@@ -98,7 +104,7 @@ impl InstantLike for FramepacerInstant {
 
 static STATIC_NON_CONTEXT: () = ();
 
-impl InstantLike for std::time::Instant {
+impl InstantLike for Instant {
     type Context = ();
 
     fn now(_: &Self::Context) -> Self {
@@ -119,40 +125,18 @@ impl InstantLike for std::time::Instant {
     fn add_duration(self, duration: std::time::Duration) -> Self {
         self + duration
     }
-}
-
-impl InstantLike for std::time::Duration {
-    type Context = ();
-
-    fn now(_: &Self::Context) -> Self {
-        // TODO: non-osx impl
-        Self::from_secs_f64(unsafe { NSProcessInfo::processInfo().systemUptime() })
-    }
-
-    fn context_from(render_ctx: &RenderingContext) -> &Self::Context
-    where
-        Self: Sized,
-    {
-        &STATIC_NON_CONTEXT
-    }
-
-    fn duration_since(&self, earlier: &Self) -> std::time::Duration {
-        *self - *earlier
-    }
-
-    fn add_duration(self, duration: std::time::Duration) -> Self {
-        self + duration
-    }
 
     fn query_presentation_statistics(
         _surface: &wgpu::Surface,
-        window: &winit::window::Window,
-        fallback: Self,
+        _window: &winit::window::Window,
+        _fallback: Self,
     ) -> Self
     where
         Self: Sized,
     {
-        get_window_last_screen_draw_time(window).unwrap()
+        // acquiring a frame waits for an earlier one to be presented, so this is
+        // as close to the last vsync as can be measured
+        Self::now()
     }
 }
 
@@ -163,7 +147,7 @@ pub struct ManagedFramepacer<I: InstantLike = FramepacerInstant> {
 
     worst_frametime_secs: f64,
 
-    deadline: Option<I>,
+    pub(super) deadline: Option<I>,
 
     desired_frame_time: Option<std::time::Duration>,
 
@@ -185,7 +169,7 @@ impl<I: InstantLike> Default for ManagedFramepacer<I> {
     }
 }
 
-const DEFAULT_FRAME_TIME_SECS: f64 = 1. / 60.;
+pub(crate) const DEFAULT_FRAME_TIME_SECS: f64 = 1. / 60.;
 
 const DEVIATION_BUFFER_MICROS: u64 = 30;
 
@@ -209,6 +193,9 @@ pub trait Framepacer<I: InstantLike = FramepacerInstant> {
 
     fn should_render(&mut self, start_time: I) -> (bool, I);
 
+    /// Time from `now` until frames should start rendering to meet the deadline
+    fn time_until_render(&self, now: I) -> Option<std::time::Duration>;
+
     fn push_frametime(&mut self, duration: crate::time::Duration);
 
     fn desired_frame_time(&self) -> Option<std::time::Duration>;
@@ -223,253 +210,6 @@ pub trait Framepacer<I: InstantLike = FramepacerInstant> {
     fn sync_to_fps(&self, fps: f32) {}
 }
 
-use icrate::{
-    AppKit::NSScreen,
-    CoreAnimation::{CADisplayLink, CAFrameRateRangeMake, CFTimeInterval},
-    Foundation::NSProcessInfo,
-};
-use objc2::rc::Id;
-use std::os::raw::c_int;
-
-use icrate::Foundation::{
-    NSCopying, NSObject, NSObjectProtocol, NSRunLoop, NSRunLoopCommonModes, NSZone,
-};
-use objc2::declare::{Ivar, IvarBool, IvarDrop, IvarEncode};
-use objc2::{
-    declare_class, extern_protocol, msg_send, msg_send_id, mutability, sel, ClassType, ProtocolType,
-};
-
-declare_class!(
-    struct CADisplayLinkPollable {
-        target_timestamp: IvarEncode<CFTimeInterval, "_target_timestamp">,
-        timestamp: IvarEncode<CFTimeInterval, "_timestamp">,
-        is_ready: IvarBool<"_is_ready">,
-        ca_display_link: IvarDrop<Id<CADisplayLink>, "_ca_display_link">,
-    }
-
-    mod ivar;
-
-    unsafe impl ClassType for CADisplayLinkPollable {
-        type Super = NSObject;
-        type Mutability = mutability::Mutable;
-        const NAME: &'static str = "CADisplayLinkPollable";
-    }
-
-    unsafe impl CADisplayLinkPollable {
-        #[method(init)]
-        fn init(this: &mut Self) -> Option<&mut Self> {
-            let this: Option<&mut Self> = unsafe { msg_send![super(this), init] };
-
-            this.map(|this| {
-                let ca_display_link =
-                    unsafe { CADisplayLink::displayLinkWithTarget_selector(this, sel!(step:)) };
-
-                unsafe {
-                    ca_display_link
-                        .addToRunLoop_forMode(&NSRunLoop::currentRunLoop(), NSRunLoopCommonModes);
-                }
-
-                Ivar::write(&mut this.ca_display_link, ca_display_link);
-                *this.is_ready = false;
-
-                // All the instance variables have been initialized; our
-                // initializer is sound
-                this
-            })
-        }
-
-        #[method(step:)]
-        fn step(this: &mut Self, ca_display_link: &mut CADisplayLink) {
-            *this.target_timestamp = unsafe { ca_display_link.targetTimestamp() };
-            *this.timestamp = unsafe { ca_display_link.timestamp() };
-
-            *this.is_ready = true;
-        }
-
-        #[method(isReady)]
-        fn __get_is_ready(&self) -> bool {
-            *self.is_ready
-        }
-
-        #[method(targetTimestamp)]
-        fn __get_target_timestamp(&mut self) -> CFTimeInterval {
-            *self.is_ready = false;
-            *self.target_timestamp
-        }
-
-        #[method(timestamp)]
-        fn __get_timestamp(&mut self) -> CFTimeInterval {
-            *self.timestamp
-        }
-
-        #[method_id(caDisplayLink)]
-        fn __get_ca_display_link(&self) -> Id<CADisplayLink> {
-            self.ca_display_link.clone()
-        }
-    }
-);
-
-impl CADisplayLinkPollable {
-    pub fn new() -> Id<Self> {
-        unsafe { msg_send_id![Self::alloc(), init] }
-    }
-
-    pub fn get_is_ready(&self) -> bool {
-        unsafe { msg_send![self, isReady] }
-    }
-
-    pub fn get_target_timestamp(&self) -> CFTimeInterval {
-        unsafe { msg_send![self, targetTimestamp] }
-    }
-
-    pub fn get_timestamp(&self) -> CFTimeInterval {
-        unsafe { msg_send![self, timestamp] }
-    }
-
-    pub fn get_ca_display_link(&self) -> Id<CADisplayLink> {
-        unsafe { msg_send_id![self, caDisplayLink] }
-    }
-}
-
-pub struct CADisplayLinkFramepacer {
-    ca_display_link_pollable: Id<CADisplayLinkPollable>,
-    managed_framepacer: ManagedFramepacer<std::time::Duration>,
-
-    next_deadline: Option<std::time::Duration>,
-    last_deadline: Option<std::time::Duration>,
-
-    desired_frametime: Option<std::time::Duration>,
-
-    needs_kickstart: bool,
-}
-
-impl CADisplayLinkFramepacer {
-    pub fn new() -> Self {
-        Self {
-            ca_display_link_pollable: CADisplayLinkPollable::new(),
-            managed_framepacer: Default::default(),
-
-            next_deadline: Default::default(),
-            last_deadline: Default::default(),
-
-            desired_frametime: Default::default(),
-
-            needs_kickstart: true,
-        }
-    }
-}
-
-impl Framepacer<std::time::Duration> for CADisplayLinkFramepacer {
-    fn start_window(
-        &mut self,
-        presentation_start: std::time::Duration,
-        screen_refresh_time: Option<std::time::Duration>,
-    ) {
-        self.managed_framepacer
-            .start_window(presentation_start, screen_refresh_time);
-    }
-
-    fn check_missed_deadline(
-        &mut self,
-        now: std::time::Duration,
-        render_time: Option<std::time::Duration>,
-    ) -> bool {
-        self.managed_framepacer
-            .check_missed_deadline(now, render_time)
-    }
-
-    fn get_deadline(&self) -> Option<std::time::Duration> {
-        self.next_deadline
-    }
-
-    fn should_render(&mut self, start_time: std::time::Duration) -> (bool, std::time::Duration) {
-        let new_deadline = if self.ca_display_link_pollable.get_is_ready() {
-            Some(std::time::Duration::from_secs_f64(
-                self.ca_display_link_pollable.get_target_timestamp(),
-            ))
-        } else {
-            None
-        };
-
-        if let Some(deadline) = new_deadline {
-            // if self.next_deadline.is_some() {
-            //     log::warn!("deadline override!");
-            // }
-
-            let old_deadline = self.managed_framepacer.deadline.replace(deadline);
-
-            // if let Some(old_deadline) = old_deadline {
-            //     println!(
-            //         "managed deadline mismatch: {:?}",
-            //         duration_dist(old_deadline, deadline)
-            //     )
-            // }
-
-            // self.last_deadline = self.next_deadline.take();
-
-            self.next_deadline = deadline.into();
-        }
-
-        if let Some(next_deadline) = self.next_deadline {
-            let result = self.managed_framepacer.should_render(start_time);
-
-            if result.0 {
-                self.desired_frametime = (next_deadline
-                    - std::time::Duration::from_secs_f64(
-                        self.ca_display_link_pollable.get_timestamp(),
-                    ))
-                .into();
-
-                // if let Some(last_deadline) = self.last_deadline {
-                //     self.desired_frametime = (next_deadline - last_deadline).into();
-                // }
-
-                self.next_deadline = None;
-            }
-
-            result
-        } else {
-            (false, start_time)
-        }
-    }
-
-    fn push_frametime(&mut self, duration: crate::time::Duration) {
-        self.managed_framepacer.push_frametime(duration)
-    }
-
-    fn desired_frame_time(&self) -> Option<std::time::Duration> {
-        self.desired_frametime
-
-        // self.desired_frametime.and_then(|duration| {
-        //     if let Some(screen_refresh) = self.managed_framepacer.desired_frame_time {
-        //         if duration > screen_refresh + std::time::Duration::from_micros(500) {
-        //             None
-        //         } else {
-        //             Some(duration)
-        //         }
-        //     } else {
-        //         None
-        //     }
-        // })
-    }
-
-    fn desired_frame_instant(&self) -> Option<wgpu::PresentationTimestamp>
-    where
-        std::time::Duration: Copy,
-    {
-        self.get_deadline()
-            .map(|deadline| wgpu::PresentationTimestamp(deadline.as_nanos()))
-    }
-
-    fn sync_to_fps(&self, fps: f32) {
-        unsafe {
-            self.ca_display_link_pollable
-                .get_ca_display_link()
-                .setPreferredFrameRateRange(CAFrameRateRangeMake(fps, fps, fps))
-        }
-    }
-}
-
 pub struct NoopFramepacer {
     screen_refresh_time: Option<std::time::Duration>,
 }
@@ -503,6 +243,10 @@ impl<I: InstantLike> Framepacer<I> for NoopFramepacer {
         (true, start_time)
     }
 
+    fn time_until_render(&self, _now: I) -> Option<std::time::Duration> {
+        None
+    }
+
     fn push_frametime(&mut self, duration: crate::time::Duration) {}
 
     fn desired_frame_time(&self) -> Option<std::time::Duration> {
@@ -510,6 +254,14 @@ impl<I: InstantLike> Framepacer<I> for NoopFramepacer {
     }
 }
 
+impl<I: InstantLike> ManagedFramepacer<I> {
+    #[inline(always)]
+    fn predicted_frametime(&self) -> std::time::Duration {
+        crate::time::Duration::from_secs_f64(self.worst_frametime_secs)
+            + crate::time::Duration::from_micros(DEVIATION_BUFFER_MICROS)
+    }
+}
+
 impl<I: InstantLike + Copy + std::fmt::Debug> Framepacer<I> for ManagedFramepacer<I> {
     fn new() -> Self {
         Default::default()
@@ -614,10 +366,7 @@ impl<I: InstantLike + Copy + std::fmt::Debug> Framepacer<I> for ManagedFramepace
     fn should_render(&mut self, start_time: I) -> (bool, I) {
         let should_render = match self.deadline {
             Some(deadline) => {
-                let predicted_finish_time = start_time.add_duration(
-                    crate::time::Duration::from_secs_f64(self.worst_frametime_secs)
-                        + crate::time::Duration::from_micros(DEVIATION_BUFFER_MICROS),
-                );
+                let predicted_finish_time = start_time.add_duration(self.predicted_frametime());
 
                 // TODO: add buffer here for input/parsing time...
                 predicted_finish_time >= deadline
@@ -629,6 +378,20 @@ impl<I: InstantLike + Copy + std::fmt::Debug> Framepacer<I> for ManagedFramepace
         (should_render, start_time)
     }
 
+    fn time_until_render(&self, now: I) -> Option<std::time::Duration> {
+        let deadline = self.deadline?;
+
+        if deadline <= now {
+            return Some(Default::default());
+        }
+
+        Some(
+            deadline
+                .duration_since(&now)
+                .saturating_sub(self.predicted_frametime()),
+        )
+    }
+
     //  fn next_deadline(&mut self, from: crate::time::Instant) -> crate::time::Instant {}
 
     fn push_frametime(&mut self, duration: crate::time::Duration) {
@@ -674,6 +437,131 @@ impl<I: InstantLike + Copy + std::fmt::Debug> Framepacer<I> for ManagedFramepace
     }
 }
 
+/// Number of present intervals vsync is estimated from
+const VSYNC_NUM_SAMPLES: usize = 32;
+
+/// Present intervals outside of this range are gaps between frames, or stalls
+const VSYNC_INTERVAL_RANGE_SECS: (f64, f64) = (1. / 500., 1. / 20.);
+
+/// Paces frames on any platform, by estimating the display's refresh interval
+/// from the times frames were presented at.
+///
+/// Presenting blocks until vsync with [`wgpu::PresentMode::Fifo`], so the
+/// intervals between presents of consecutive frames are multiples of the
+/// refresh interval. Their median is used once enough have been seen, and the
+/// monitor's reported refresh rate before that.
+#[derive(Default)]
+pub struct EstimatedVsyncFramepacer {
+    managed_framepacer: ManagedFramepacer<Instant>,
+
+    /// Recent intervals between presents, in seconds
+    present_intervals: ConstGenericRingBuffer<f64, VSYNC_NUM_SAMPLES>,
+    last_present: Option<Instant>,
+}
+
+impl EstimatedVsyncFramepacer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The estimated refresh interval, if enough frames have been presented
+    pub fn estimated_vsync_interval(&self) -> Option<Duration> {
+        if self.present_intervals.len() < VSYNC_NUM_SAMPLES / 4 {
+            return None;
+        }
+
+        let mut intervals = self.present_intervals.to_vec();
+        intervals.sort_by(f64::total_cmp);
+
+        Some(Duration::from_secs_f64(intervals[intervals.len() / 2]))
+    }
+}
+
+impl Framepacer<Instant> for EstimatedVsyncFramepacer {
+    fn start_window(&mut self, presentation_start: Instant, screen_refresh_time: Option<Duration>) {
+        if let Some(last_present) = self.last_present {
+            let interval = presentation_start
+                .saturating_duration_since(last_present)
+                .as_secs_f64();
+
+            let (min, max) = VSYNC_INTERVAL_RANGE_SECS;
+
+            if (min..max).contains(&interval) {
+                self.present_intervals.push(interval);
+            }
+        }
+
+        self.last_present = Some(presentation_start);
+
+        self.managed_framepacer.start_window(
+            presentation_start,
+            self.estimated_vsync_interval().or(screen_refresh_time),
+        );
+    }
+
+    fn check_missed_deadline(&mut self, now: Instant, render_time: Option<Duration>) -> bool {
+        self.managed_framepacer
+            .check_missed_deadline(now, render_time)
+    }
+
+    fn get_deadline(&self) -> Option<Instant> {
+        self.managed_framepacer.get_deadline()
+    }
+
+    fn should_render(&mut self, start_time: Instant) -> (bool, Instant) {
+        self.managed_framepacer.should_render(start_time)
+    }
+
+    fn time_until_render(&self, now: Instant) -> Option<Duration> {
+        self.managed_framepacer.time_until_render(now)
+    }
+
+    fn push_frametime(&mut self, duration: Duration) {
+        self.managed_framepacer.push_frametime(duration)
+    }
+
+    fn desired_frame_time(&self) -> Option<Duration> {
+        self.managed_framepacer.desired_frame_time()
+    }
+}
+
 fn duration_dist(d1: std::time::Duration, d2: std::time::Duration) -> std::time::Duration {
     d1.saturating_sub(d2).max(d2.saturating_sub(d1))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vsync_interval_from_presents() {
+        let mut framepacer = EstimatedVsyncFramepacer::new();
+        let refresh_interval = Duration::from_micros(6944);
+
+        let start = Instant::now();
+        let mut present = start;
+
+        // the monitor's refresh rate is used until enough frames were presented
+        framepacer.start_window(present, Some(Duration::from_micros(16667)));
+        assert_eq!(
+            framepacer.desired_frame_time(),
+            Some(Duration::from_micros(16667))
+        );
+
+        for i in 0..VSYNC_NUM_SAMPLES as u32 {
+            present += match i % 8 {
+                // a missed vsync, and a gap between frames
+                3 => refresh_interval * 2,
+                5 => Duration::from_secs(1),
+                _ => refresh_interval,
+            } + Duration::from_micros((i % 3) as u64 * 20);
+
+            framepacer.start_window(present, Some(Duration::from_micros(16667)));
+        }
+
+        let estimate = framepacer.desired_frame_time().unwrap();
+        assert!(duration_dist(estimate, refresh_interval) < Duration::from_micros(50));
+
+        assert_eq!(framepacer.get_deadline(), Some(present + estimate));
+    }
+}
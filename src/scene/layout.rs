@@ -1,11 +1,15 @@
-use std::ops::DerefMut;
+use std::{
+    collections::HashMap,
+    ops::{DerefMut, Range},
+};
 
 use bytemuck::Zeroable;
 use winit::window;
 
 use crate::{
-    element::{Element, ElementRef, ElementWeakref},
+    element::{Element, ElementId, ElementRef, ElementWeakref},
     input::input_state::InputState,
+    layer::ZIndex,
     math::{DeviceScaleFactor, Pos, Rect, Size, TransformationList},
     shape::{ClipRect, ClipRectList, ShaderClipRect},
     util::text::{FontSystem, FontSystemRef, TextBox},
@@ -31,76 +35,170 @@ pub struct ElementTreeNode {
     children: Vec<ElementTreeNode>,
     layout_node: LayoutPassResult,
     transformation_idx: Option<usize>,
+    /// Includes the ancestors' clip rects, unless the element is stacked apart
+    /// from its parent
+    clip_rect_idx: Option<usize>,
+    z_index: ZIndex,
+}
+
+/// An element to give input to, along with the state it sees
+struct InputTarget<'a> {
+    element: &'a mut ElementWeakref<dyn Element>,
+    rect: Rect,
+    z_index: ZIndex,
+    transformation_idx: Option<usize>,
     clip_rect_idx: Option<usize>,
+    /// Positions of the element's descendants and the element itself in the
+    /// order they were collected, which is contiguous
+    subtree: Range<usize>,
 }
 
 impl ElementTreeNode {
+    /// Gives input to elements in the reverse of their paint order, so that the
+    /// topmost elements see it first
     pub(super) fn do_input_pass(
         &mut self,
         input: &mut InputState,
         parent_transformation_idx: Option<usize>,
         clip_rects: &mut ClipRectList,
-        parent_clip_rect_idx: Option<usize>,
         resources: &mut SceneResources,
-    ) -> bool {
-        let element_id = self.element.id();
-        let mut focus_within = input.is_focused();
+    ) {
+        let mut targets = Vec::new();
+        self.collect_input_targets(parent_transformation_idx, &mut targets);
 
-        let transform_idx = self.transformation_idx.or(parent_transformation_idx);
-        let clip_rect_idx = self.clip_rect_idx.or(parent_clip_rect_idx);
+        let positions: HashMap<ElementId, usize> = targets
+            .iter()
+            .enumerate()
+            .map(|(position, target)| (target.element.id(), position))
+            .collect();
 
-        if let Some(mut element) = self.element.try_get() {
-            for child in self.children.iter_mut().rev() {
-                focus_within |=
-                    child.do_input_pass(input, transform_idx, clip_rects, parent_clip_rect_idx, resources);
-            }
+        // the sort is stable, so children still come before their parents
+        targets.sort_by_key(|target| std::cmp::Reverse(target.z_index));
+
+        for target in targets {
+            let element_id = target.element.id();
+
+            let Some(mut element) = target.element.try_get() else {
+                continue;
+            };
+
+            input.set_current_element(element_id);
 
-            input.set_active_transformation(transform_idx);
+            input.set_active_transformation(target.transformation_idx);
 
-            let (clip_rect, clip_rect_transformation_idx) = clip_rect_idx
-                .map(|idx| {
-                    let (r, idx) = *clip_rects.get(idx);
-                    (Some(r), idx)
-                })
-                .unwrap_or((None, None));
+            input.set_active_clip_rects(clip_rects.ancestors(target.clip_rect_idx));
 
-            input.set_active_clip_rect(clip_rect, clip_rect_transformation_idx);
+            // handlers which ran before may have moved the focus
+            let focus_within = input
+                .focused_element()
+                .and_then(|focused| positions.get(&focused))
+                .is_some_and(|position| target.subtree.contains(position));
 
             input.set_focused_within(focus_within);
 
-            input.set_current_element(element_id.into());
+            element.input(input, target.rect);
+            element.input_with_resources(input, resources, target.rect);
+        }
+    }
+
+    /// Collects this element and its descendants, children before their
+    /// parents
+    fn collect_input_targets<'a>(
+        &'a mut self,
+        parent_transformation_idx: Option<usize>,
+        targets: &mut Vec<InputTarget<'a>>,
+    ) {
+        if self.element.try_get().is_none() {
+            return;
+        }
+
+        let transform_idx = self.transformation_idx.or(parent_transformation_idx);
+        let subtree_start = targets.len();
 
-            element.input(input, self.rect);
-            element.input_with_resources(input, resources, self.rect);
+        for child in self.children.iter_mut().rev() {
+            child.collect_input_targets(transform_idx, targets);
         }
 
-        focus_within
+        targets.push(InputTarget {
+            element: &mut self.element,
+            rect: self.rect,
+            z_index: self.z_index,
+            transformation_idx: transform_idx,
+            clip_rect_idx: self.clip_rect_idx,
+            subtree: subtree_start..targets.len() + 1,
+        });
     }
 
     pub(super) fn do_ui_pass(
         &mut self,
         ctx: &mut SceneContext,
         parent_transformation_idx: Option<usize>,
-        parent_clip_rect_idx: Option<usize>,
+        parent_layer_idx: Option<usize>,
+        parent_z_index: ZIndex,
     ) {
         let element_id = self.element.id();
 
         if let Some(mut element) = self.element.try_get() {
             let transform_idx = self.transformation_idx.or(parent_transformation_idx);
-            let clip_rect_idx = self.clip_rect_idx.or(parent_clip_rect_idx);
+            let clip_rect_idx = self.clip_rect_idx;
+            // elements stacked apart from their parent are composited on their own
+            let parent_layer_idx = parent_layer_idx.filter(|_| self.z_index == parent_z_index);
+            let layer_idx = element
+                .layer()
+                .map(|layer| ctx.push_layer(layer, parent_layer_idx))
+                .or(parent_layer_idx);
+
             ctx.active_transformation_idx = transform_idx;
             ctx.active_clip_rect_idx = clip_rect_idx;
+            ctx.active_element_id = Some(element_id);
+            ctx.active_layer_idx = layer_idx;
+            ctx.active_blend_mode = Default::default();
+            ctx.active_z_index = self.z_index;
+
+            let paint_key = ctx.element_paint_key(self.rect);
+            let retained_paint = (!element.needs_repaint())
+                .then(|| ctx.take_element_paint(element_id, &paint_key))
+                .flatten();
+
+            let ui_start = ctx.shapes.len();
+
+            match &retained_paint {
+                Some(paint) => ctx.shapes.extend_from_slice(&paint.ui_shapes),
+                None => element.ui(ctx, self.rect),
+            }
 
-            element.ui(ctx, self.rect);
+            let ui_shapes = ui_start..ctx.shapes.len();
 
             let mut children_access_nodes = Vec::new();
 
             for child in self.children.iter_mut() {
-                child.do_ui_pass(ctx, transform_idx, clip_rect_idx);
+                child.do_ui_pass(ctx, transform_idx, layer_idx, self.z_index);
                 children_access_nodes.push(child.element.id().as_access_id())
             }
 
-            element.ui_post(ctx, self.rect);
+            ctx.active_transformation_idx = transform_idx;
+            ctx.active_clip_rect_idx = clip_rect_idx;
+            ctx.active_element_id = Some(element_id);
+            ctx.active_layer_idx = layer_idx;
+            ctx.active_blend_mode = Default::default();
+            ctx.active_z_index = self.z_index;
+
+            let ui_post_start = ctx.shapes.len();
+
+            match retained_paint {
+                Some(paint) => {
+                    ctx.shapes.extend_from_slice(&paint.ui_post_shapes);
+                    ctx.element_paints.insert(element_id, paint);
+                }
+                None => {
+                    element.ui_post(ctx, self.rect);
+
+                    if !element.needs_repaint() {
+                        let ui_post_shapes = ui_post_start..ctx.shapes.len();
+                        ctx.keep_element_paint(element_id, paint_key, ui_shapes, ui_post_shapes);
+                    }
+                }
+            }
 
             let mut access_node_builder = element.node();
             access_node_builder.set_children(children_access_nodes);
@@ -120,17 +218,17 @@ impl ElementTreeNode {
     pub(super) fn do_layout_post_pass(
         &mut self,
         resources: &mut SceneResources,
+        viewport: Rect,
         transformations: &TransformationList,
         parent_transformation_idx: Option<usize>,
         clip_rects: &ClipRectList,
-        parent_clip_rect_idx: Option<usize>,
     ) {
         if let Some(mut element) = self.element.try_get() {
             let transform_idx = self.transformation_idx.or(parent_transformation_idx);
-            let clip_rect_idx = self.clip_rect_idx.or(parent_clip_rect_idx);
+            let clip_rect_idx = self.clip_rect_idx;
 
-            // TODO: include clip_rect (need to do transformation on rect...)
-            // resources.clip_rect = clip_rect_idx.map(|idx| clip_rects.get(idx));
+            resources.element_visible_rect =
+                clip_rects.visible_rect(clip_rect_idx, transformations, transform_idx, viewport);
 
             resources.element_transformation_scale_factor = transform_idx
                 .map(|idx| transformations.get_scale_factor(idx))
@@ -141,10 +239,10 @@ impl ElementTreeNode {
             for child in self.children.iter_mut() {
                 child.do_layout_post_pass(
                     resources,
+                    viewport,
                     transformations,
-                    parent_transformation_idx,
+                    transform_idx,
                     clip_rects,
-                    parent_clip_rect_idx,
                 );
             }
         }
@@ -237,12 +335,20 @@ impl<'a, 'b: 'a> LayoutPass<'a, 'b> {
             None,
             &mut clip_rects,
             None,
+            ZIndex::BASE,
         );
 
-        root.do_layout_post_pass(resources, &transformations, None, &clip_rects, None);
+        root.do_layout_post_pass(
+            resources,
+            Rect::from_min_size(Pos::zero(), screen_size),
+            &transformations,
+            None,
+            &clip_rects,
+        );
 
         resources.element_clip_rect = None;
         resources.element_transformation_scale_factor = None;
+        resources.element_visible_rect = None;
 
         ElementTree {
             root,
@@ -277,11 +383,21 @@ impl LayoutNode {
         parent_transformation_idx: Option<usize>,
         clip_rects: &mut ClipRectList,
         parent_clip_rect_idx: Option<usize>,
+        parent_z_index: ZIndex,
     ) -> ElementTreeNode {
         let mut transformation_idx = parent_transformation_idx;
         let mut clip_rect_idx = parent_clip_rect_idx;
+        let mut z_index = parent_z_index;
 
         if let Some(el) = self.element.try_get() {
+            if let Some(new_z_index) = el.z_index() {
+                if new_z_index != parent_z_index {
+                    clip_rect_idx = None;
+                }
+
+                z_index = new_z_index;
+            }
+
             if let Some(new_transform) = el.coordinate_transform() {
                 transformation_idx = transformations
                     .push_transform(
@@ -293,9 +409,8 @@ impl LayoutNode {
             }
 
             if let Some(new_clip_rect) = el.clip_rect() {
-                // TODO: cascading intersections
                 clip_rect_idx = clip_rects
-                    .push_clip_rect(new_clip_rect, transformation_idx)
+                    .push_clip_rect(new_clip_rect, transformation_idx, clip_rect_idx)
                     .into();
             }
         }
@@ -309,6 +424,7 @@ impl LayoutNode {
             layout_node: self.result,
             transformation_idx,
             clip_rect_idx,
+            z_index,
         };
 
         for child in self.children.into_iter() {
@@ -319,6 +435,7 @@ impl LayoutNode {
                 transformation_idx,
                 clip_rects,
                 clip_rect_idx,
+                z_index,
             ));
         }
 
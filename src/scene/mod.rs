@@ -2,8 +2,14 @@ mod paint;
 
 pub use paint::*;
 pub mod ctx;
+pub mod damage;
+#[cfg(target_os = "macos")]
+pub mod display_link;
 pub mod framepacer;
 pub mod input;
 pub mod layout;
+pub mod raster;
+pub mod renderer;
 pub mod scene;
+pub mod timings;
 pub mod update;
@@ -0,0 +1,1349 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    atlas::{distance_field_image, rasterize_glyph},
+    color::ColorRgba,
+    gradient::PaintGradient,
+    image::{unpremultiply_rgba8, ImageAlphaMode, ImageColorSpace, ImageData, ImageOptions},
+    layer::{BlendMode, FrameLayer, Layer},
+    math::{CoordinateTransform, PhysicalPos, PhysicalSize, PhysicalVector, Pos, Rect},
+    shape::{
+        ComputedPaintShape, PaintBlur, PaintFill, PaintMesh, PaintMeshVertex, PaintRectangle,
+        ShaderClipRect, TextureFill,
+    },
+    surface::MultisampleMode,
+    texture::TextureRef,
+    util::text::{AtlasGlyphKey, FontSystemRef, GlyphRenderMode, PlacedTextBox},
+};
+
+use super::{
+    ctx::PaintShapeWithContext,
+    renderer::{FramePaint, RenderResources, Renderer},
+};
+
+/// Mirrors `FEATHERING` in `box.wgsl`
+const FEATHERING: f32 = 1.;
+
+/// A pure-CPU implementation of `box.wgsl`, rasterizing the shapes painted by a
+/// [`super::ctx::SceneContext`] into an RGBA buffer.
+///
+/// This is meant as a ground truth for the wgpu renderer, so it mirrors the
+/// shader (padding, sdf feathering, blur approximation, multisampling) rather
+/// than trying to be fast.
+///
+/// Textures live on the GPU, so `PaintFill::Texture` is only rendered for
+/// textures whose pixels were given to [`Self::insert_texture`]. Atlas fills
+/// (`PaintFill::TextureAtlas`) and custom renders are not rendered. Glyphs are
+/// rasterized from the same glyph keys as the atlas, as bitmaps or distance
+/// fields depending on the text box's [`GlyphRenderMode`].
+pub struct CpuRasterizer {
+    size: PhysicalSize<u32>,
+    scale_factor: f32,
+    multisample_mode: MultisampleMode,
+
+    /// Premultiplied linear color for every sample of every pixel, composited
+    /// with [`BlendMode::blend_premultiplied`] under each shape's blend mode.
+    samples: Vec<[f32; 4]>,
+
+    /// The frame's clip rects, each with the inverse of its transformation
+    clip_rects: Vec<(ShaderClipRect, CoordinateTransform)>,
+
+    glyphs: HashMap<AtlasGlyphKey, Option<RasterImage>>,
+    used_glyphs_this_frame: HashSet<AtlasGlyphKey>,
+
+    /// Pixels of the textures given to [`Self::insert_texture`]
+    textures: RasterTextures,
+}
+
+/// Few textures are usually drawn, so they're looked up linearly
+#[derive(Default)]
+struct RasterTextures(Vec<(TextureRef, RasterImage)>);
+
+impl RasterTextures {
+    fn get(&self, texture: &TextureRef) -> Option<&RasterImage> {
+        self.0
+            .iter()
+            .find(|(t, _)| t == texture)
+            .map(|(_, image)| image)
+    }
+
+    fn insert(&mut self, texture: &TextureRef, image: RasterImage) {
+        self.remove(texture);
+        self.0.push((texture.clone(), image));
+    }
+
+    fn remove(&mut self, texture: &TextureRef) {
+        self.0.retain(|(t, _)| t != texture);
+    }
+}
+
+#[derive(Clone, Copy)]
+enum RasterImageFormat {
+    /// Single channel coverage mask (`R8Unorm` in the atlas)
+    Mask,
+    /// sRGB encoded RGBA (`Rgba8UnormSrgb` in the atlas)
+    Color,
+    /// Linear RGBA (`Rgba8Unorm`)
+    LinearColor,
+}
+
+struct RasterImage {
+    width: u32,
+    height: u32,
+    placement: PhysicalPos<i32>,
+    format: RasterImageFormat,
+    /// Whether samples outside the image repeat its edge, like the texture
+    /// sampler, rather than being transparent
+    clamp_to_edge: bool,
+    data: Vec<u8>,
+}
+
+impl RasterImage {
+    fn texel(&self, x: i32, y: i32) -> [f32; 4] {
+        let (x, y) = match self.clamp_to_edge {
+            true => (
+                x.clamp(0, self.width as i32 - 1),
+                y.clamp(0, self.height as i32 - 1),
+            ),
+            false => (x, y),
+        };
+
+        // the atlas pads each allocation with transparent texels
+        if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {
+            return [0.; 4];
+        }
+
+        let idx = (y as u32 * self.width + x as u32) as usize;
+
+        match self.format {
+            RasterImageFormat::Mask => {
+                let v = self.data[idx] as f32 / 255.;
+                [v, 0., 0., 1.]
+            }
+            RasterImageFormat::Color => {
+                let px = &self.data[idx * 4..idx * 4 + 4];
+                [
+                    srgb_to_linear(px[0] as f32 / 255.),
+                    srgb_to_linear(px[1] as f32 / 255.),
+                    srgb_to_linear(px[2] as f32 / 255.),
+                    px[3] as f32 / 255.,
+                ]
+            }
+            RasterImageFormat::LinearColor => {
+                let px = &self.data[idx * 4..idx * 4 + 4];
+                std::array::from_fn(|i| px[i] as f32 / 255.)
+            }
+        }
+    }
+
+    /// Bilinear sample, with `uv` in texels
+    fn sample(&self, uv: [f32; 2]) -> [f32; 4] {
+        let x = uv[0] - 0.5;
+        let y = uv[1] - 0.5;
+
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i32, y0 as i32);
+
+        let t00 = self.texel(x0, y0);
+        let t10 = self.texel(x0 + 1, y0);
+        let t01 = self.texel(x0, y0 + 1);
+        let t11 = self.texel(x0 + 1, y0 + 1);
+
+        std::array::from_fn(|i| {
+            let top = t00[i] * (1. - fx) + t10[i] * fx;
+            let bottom = t01[i] * (1. - fx) + t11[i] * fx;
+            top * (1. - fy) + bottom * fy
+        })
+    }
+}
+
+#[derive(Clone, Copy)]
+enum RasterShape {
+    Rect {
+        origin: [f32; 2],
+        dims: [f32; 2],
+        rounding: f32,
+        stroke_width: f32,
+        blur_radius: f32,
+    },
+    /// A glyph whose mask is a distance field, see `sdf_coverage`
+    SdfGlyph {
+        spread: f32,
+    },
+    Mesh,
+}
+
+#[derive(Clone, Copy)]
+enum RasterFill<'a> {
+    Color,
+    Texture(&'a RasterImage),
+    TextureMaskColor(&'a RasterImage),
+    Gradient(&'a PaintGradient),
+    TextureMaskGradient(&'a RasterImage, &'a PaintGradient),
+}
+
+#[derive(Clone, Copy, Default)]
+struct RasterVertex {
+    pos: [f32; 2],
+    uv: [f32; 2],
+    color: [f32; 4],
+}
+
+#[derive(Clone, Copy)]
+struct ShapeContext {
+    transformation: CoordinateTransform,
+    /// Mirrors `determinant(transformation)` in the vertex shader, which includes
+    /// the device scale factor
+    shader_scale_factor: f32,
+    /// Index into [`CpuRasterizer::clip_rects`], or 0 if unclipped
+    clip_rect_idx: u32,
+    blend_mode: BlendMode,
+}
+
+impl ShapeContext {
+    #[inline(always)]
+    fn draw<'a>(self, shape: RasterShape, fill: RasterFill<'a>) -> DrawContext<'a> {
+        DrawContext {
+            shape_ctx: self,
+            shape,
+            fill,
+        }
+    }
+}
+
+struct DrawContext<'a> {
+    shape_ctx: ShapeContext,
+    shape: RasterShape,
+    fill: RasterFill<'a>,
+}
+
+impl CpuRasterizer {
+    pub fn new(size: impl Into<PhysicalSize<u32>>, scale_factor: f64) -> Self {
+        let mut rasterizer = Self {
+            size: PhysicalSize::zero(),
+            scale_factor: scale_factor as f32,
+            multisample_mode: Default::default(),
+            samples: Default::default(),
+            clip_rects: Default::default(),
+            glyphs: Default::default(),
+            used_glyphs_this_frame: Default::default(),
+            textures: Default::default(),
+        };
+
+        rasterizer.resize(size, None);
+
+        rasterizer
+    }
+
+    pub fn with_multisample_mode(mut self, multisample_mode: MultisampleMode) -> Self {
+        self.multisample_mode = multisample_mode;
+        self.resize(self.size, None);
+        self
+    }
+
+    pub fn resize(&mut self, size: impl Into<PhysicalSize<u32>>, scale_factor: Option<f64>) {
+        self.size = size.into();
+
+        if let Some(scale_factor) = scale_factor {
+            self.scale_factor = scale_factor as f32;
+        }
+
+        self.samples = vec![[0.; 4]; self.num_samples_total()];
+    }
+
+    pub fn get_size(&self) -> PhysicalSize<u32> {
+        self.size
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.fill([0.; 4]);
+    }
+
+    /// Gives the rasterizer the pixels of `texture`, so that texture fills
+    /// sampling it can be rendered. `options` should match the ones the image
+    /// was uploaded with.
+    pub fn insert_texture(
+        &mut self,
+        texture: &TextureRef,
+        image: &ImageData,
+        options: ImageOptions,
+    ) {
+        let data = match options.alpha_mode {
+            ImageAlphaMode::Straight => image.pixels().to_vec(),
+            ImageAlphaMode::Premultiplied => unpremultiply_rgba8(image.pixels()),
+        };
+
+        let format = match options.color_space {
+            ImageColorSpace::Srgb => RasterImageFormat::Color,
+            ImageColorSpace::Linear => RasterImageFormat::LinearColor,
+        };
+
+        self.textures.insert(
+            texture,
+            RasterImage {
+                width: image.size().width,
+                height: image.size().height,
+                placement: PhysicalPos::zero(),
+                format,
+                clamp_to_edge: true,
+                data,
+            },
+        );
+    }
+
+    pub fn remove_texture(&mut self, texture: &TextureRef) {
+        self.textures.remove(texture);
+    }
+
+    #[inline(always)]
+    fn num_samples_total(&self) -> usize {
+        (self.size.width * self.size.height * self.multisample_mode.num_samples()) as usize
+    }
+
+    /// Resolves the multisampled buffer into tightly packed, row-major RGBA8
+    /// (sRGB-encoded) pixels, in the same layout as
+    /// [`crate::surface::OffscreenSurface::read_pixels`].
+    pub fn read_pixels(&self) -> Vec<u8> {
+        let num_samples = self.multisample_mode.num_samples() as usize;
+
+        self.samples
+            .chunks(num_samples)
+            .flat_map(|samples| {
+                let resolved: [f32; 4] = std::array::from_fn(|i| {
+                    samples.iter().map(|s| s[i]).sum::<f32>() / num_samples as f32
+                });
+
+                [
+                    linear_to_srgb(resolved[0]),
+                    linear_to_srgb(resolved[1]),
+                    linear_to_srgb(resolved[2]),
+                    resolved[3],
+                ]
+                .map(|c| (c.clamp(0., 1.) * 255.).round() as u8)
+            })
+            .collect()
+    }
+
+    /// Clears the buffer and draws the frame's shapes in order.
+    pub fn rasterize(
+        &mut self,
+        font_system: &FontSystemRef,
+        FramePaint {
+            shapes,
+            clip_rects,
+            transformations,
+            transformation_inverses,
+            layers,
+            ..
+        }: FramePaint,
+    ) {
+        self.clear();
+
+        self.clip_rects = clip_rects
+            .into_iter()
+            .map(|clip_rect| {
+                (
+                    clip_rect,
+                    transformation_inverses[clip_rect.transformation_idx as usize],
+                )
+            })
+            .collect();
+
+        let mut glyphs = std::mem::take(&mut self.glyphs);
+        let textures = std::mem::take(&mut self.textures);
+
+        // open layers, each with the samples of its parent
+        let mut layer_stack = Vec::<(u32, Vec<[f32; 4]>)>::new();
+
+        for PaintShapeWithContext {
+            shape,
+            clip_rect_idx,
+            transformation_idx,
+            layer_idx,
+            blend_mode,
+            ..
+        } in shapes
+        {
+            self.enter_layer(&layers, &mut layer_stack, layer_idx);
+
+            let transformation = transformations[transformation_idx.unwrap_or_default() as usize];
+
+            let shader_scale_factor = self.scale_factor.powi(3) * transformation.determinant();
+
+            let ctx = ShapeContext {
+                transformation,
+                shader_scale_factor,
+                clip_rect_idx: clip_rect_idx.unwrap_or_default(),
+                blend_mode,
+            };
+
+            match shape {
+                ComputedPaintShape::Rectangle(paint_rect) => {
+                    self.draw_paint_rect(&textures, paint_rect, ctx);
+                }
+
+                ComputedPaintShape::Text(text_box) => {
+                    self.draw_text_box(font_system, &mut glyphs, text_box, ctx);
+                }
+
+                ComputedPaintShape::Mesh(PaintMesh {
+                    vertices,
+                    indices,
+                    fill,
+                    ..
+                }) => {
+                    let (fill, fill_color, uv_rect) = match &fill {
+                        None => (RasterFill::Color, [1.; 4], Default::default()),
+                        Some(PaintFill::Color(color)) => {
+                            (RasterFill::Color, (*color).into(), Default::default())
+                        }
+                        Some(PaintFill::Gradient(gradient)) => {
+                            (RasterFill::Gradient(gradient), [1.; 4], Default::default())
+                        }
+                        Some(PaintFill::Texture(TextureFill { texture, uv })) => {
+                            match textures.get(texture) {
+                                Some(image) => (RasterFill::Texture(image), [1.; 4], *uv),
+                                None => {
+                                    log::warn!("texture fill with a texture the cpu rasterizer wasn't given");
+                                    continue;
+                                }
+                            }
+                        }
+                        Some(PaintFill::TextureAtlas(..)) => {
+                            log::warn!("atlas fills are not supported by the cpu rasterizer");
+                            continue;
+                        }
+                    };
+
+                    // mesh uvs are relative to the fill's uv rect, see `BoxShaderVertex::filled_mesh_tri`
+                    let vertices = vertices
+                        .into_iter()
+                        .map(|PaintMeshVertex { pos, color, uv }| {
+                            let color: [f32; 4] = color.into();
+
+                            RasterVertex {
+                                pos: [pos.x, pos.y],
+                                uv: [
+                                    uv_rect.min.x + uv.x * uv_rect.width(),
+                                    uv_rect.min.y + uv.y * uv_rect.height(),
+                                ],
+                                color: mul_color(color, fill_color),
+                            }
+                        })
+                        .collect::<Vec<_>>();
+
+                    let ctx = ctx.draw(RasterShape::Mesh, fill);
+
+                    for tri in indices.chunks_exact(3) {
+                        self.draw_triangle(
+                            &ctx,
+                            [tri[0], tri[1], tri[2]].map(|i| vertices[i as usize]),
+                        );
+                    }
+                }
+            }
+        }
+
+        self.enter_layer(&layers, &mut layer_stack, None);
+
+        glyphs.retain(|key, _| self.used_glyphs_this_frame.contains(key));
+        self.used_glyphs_this_frame.clear();
+
+        self.glyphs = glyphs;
+        self.textures = textures;
+    }
+
+    /// Composites open layers into their parents until `layer_idx` is on top of
+    /// the stack, opening any layers on the way there
+    fn enter_layer(
+        &mut self,
+        layers: &[FrameLayer],
+        layer_stack: &mut Vec<(u32, Vec<[f32; 4]>)>,
+        layer_idx: Option<u32>,
+    ) {
+        let mut path = std::iter::successors(layer_idx, |idx| layers[*idx as usize].parent_idx)
+            .collect::<Vec<_>>();
+        path.reverse();
+
+        let common = layer_stack
+            .iter()
+            .zip(&path)
+            .take_while(|((open_idx, _), idx)| open_idx == *idx)
+            .count();
+
+        while layer_stack.len() > common {
+            let (idx, parent_samples) = layer_stack.pop().unwrap();
+            let Layer {
+                opacity,
+                blend_mode,
+            } = layers[idx as usize].layer;
+
+            let layer_samples = std::mem::replace(&mut self.samples, parent_samples);
+
+            for (dst, src) in self.samples.iter_mut().zip(layer_samples) {
+                *dst = blend_mode.blend_premultiplied(*dst, src.map(|c| c * opacity));
+            }
+        }
+
+        for &idx in &path[common..] {
+            let layer_samples = vec![[0.; 4]; self.num_samples_total()];
+            layer_stack.push((idx, std::mem::replace(&mut self.samples, layer_samples)));
+        }
+    }
+
+    fn draw_paint_rect(
+        &mut self,
+        textures: &RasterTextures,
+        paint_rect: PaintRectangle,
+        ctx: ShapeContext,
+    ) {
+        let PaintRectangle {
+            rounded_rect,
+            fill,
+            stroke_color,
+            stroke_width,
+            blur,
+        } = paint_rect;
+
+        // same order as `BoxShaderRectInstance::from_paint_rect`
+        let rects = [
+            blur.map(
+                |PaintBlur {
+                     blur_radius, color, ..
+                 }| (color, 0., blur_radius),
+            ),
+            fill.map(|fill| (fill, 0., 0.)),
+            stroke_color
+                .zip(stroke_width)
+                .map(|(color, width)| (color, width, 0.)),
+        ];
+
+        let white = ColorRgba::new(1., 1., 1., 1.);
+
+        for (fill, stroke_width, blur_radius) in rects.into_iter().flatten() {
+            let (raster_fill, color, uv) = match &fill {
+                PaintFill::Color(color) => (RasterFill::Color, *color, None),
+                PaintFill::Gradient(gradient) => (RasterFill::Gradient(gradient), white, None),
+                PaintFill::Texture(TextureFill { texture, uv }) => match textures.get(texture) {
+                    Some(image) => (RasterFill::Texture(image), white, Some(uv.cast_unit())),
+                    None => {
+                        log::warn!("texture fill with a texture the cpu rasterizer wasn't given");
+                        continue;
+                    }
+                },
+                PaintFill::TextureAtlas(..) => {
+                    log::warn!("atlas fills are not supported by the cpu rasterizer");
+                    continue;
+                }
+            };
+
+            let rect = rounded_rect.inner;
+            let origin = rect.center();
+            let dims = rect.max - origin;
+
+            let shape = RasterShape::Rect {
+                origin: origin.into(),
+                dims: [dims.x, dims.y],
+                rounding: rounded_rect.radius.unwrap_or(0.),
+                stroke_width,
+                blur_radius,
+            };
+
+            // vs_main pads rectangles to make room for feathering, strokes and blur
+            let padding = FEATHERING / ctx.shader_scale_factor + stroke_width + blur_radius;
+
+            let ctx = ctx.draw(shape, raster_fill);
+
+            // like `vs_rect`, the uv rect is stretched over the padding
+            self.draw_quad(&ctx, rect.inflate(padding, padding), uv, color);
+        }
+    }
+
+    fn draw_text_box(
+        &mut self,
+        font_system: &FontSystemRef,
+        glyphs: &mut HashMap<AtlasGlyphKey, Option<RasterImage>>,
+        text_box: PlacedTextBox,
+        ctx: ShapeContext,
+    ) {
+        for g in text_box.glyphs.iter() {
+            let key = AtlasGlyphKey::new(g.glyph.cache_key, text_box.glyph_mode);
+
+            self.used_glyphs_this_frame.insert(key);
+
+            glyphs
+                .entry(key)
+                .or_insert_with(|| Self::rasterize_glyph(font_system, &key));
+        }
+
+        let glyphs = &*glyphs;
+
+        for g in text_box.glyphs.iter() {
+            let key = AtlasGlyphKey::new(g.glyph.cache_key, text_box.glyph_mode);
+
+            let Some(Some(image)) = glyphs.get(&key) else {
+                continue;
+            };
+
+            let glyph_scale = f32::from_bits(g.glyph.cache_key.font_size_bits) / key.font_size();
+
+            let size = PhysicalSize::new(image.width as f32, image.height as f32);
+            let placement = image.placement.map(|x| x as f32);
+
+            // see `TextureAtlasManager::prepare`: distance fields are rasterized at a
+            // fixed size and without a subpixel offset, so both are applied here
+            let (size, placement) = match text_box.glyph_mode {
+                GlyphRenderMode::Sdf => (
+                    size.map(|x| x * glyph_scale),
+                    placement.map(|x| x * glyph_scale)
+                        + PhysicalVector::new(
+                            g.glyph.cache_key.x_bin.as_float(),
+                            g.glyph.cache_key.y_bin.as_float(),
+                        ),
+                ),
+                GlyphRenderMode::Bitmap => (size, placement),
+            };
+
+            let draw_rect =
+                g.to_draw_glyph(text_box.pos, size, placement, text_box.scale_fac.inverse());
+
+            if text_box
+                .clip_rect
+                .map(|clip_rect| clip_rect.inner.intersection(&draw_rect).is_none())
+                .unwrap_or_default()
+            {
+                continue;
+            }
+
+            let white = ColorRgba::new(1., 1., 1., 1.);
+
+            let (fill, color) = match (image.format, &g.color) {
+                (RasterImageFormat::Mask, PaintFill::Color(color)) => {
+                    (RasterFill::TextureMaskColor(image), *color)
+                }
+                (RasterImageFormat::Mask, PaintFill::Gradient(gradient)) => {
+                    (RasterFill::TextureMaskGradient(image, gradient), white)
+                }
+                // color glyphs aren't tinted
+                (RasterImageFormat::Color, PaintFill::Color(_) | PaintFill::Gradient(_)) => {
+                    (RasterFill::Texture(image), white)
+                }
+                _ => {
+                    log::warn!("texture fills on text are not supported by the cpu rasterizer");
+                    continue;
+                }
+            };
+
+            let shape = match (text_box.glyph_mode, image.format) {
+                (GlyphRenderMode::Sdf, RasterImageFormat::Mask) => RasterShape::SdfGlyph {
+                    spread: GlyphRenderMode::SDF_SPREAD as f32 * glyph_scale
+                        / text_box.scale_fac.get().into_inner(),
+                },
+                _ => RasterShape::Mesh,
+            };
+
+            self.draw_quad(
+                &ctx.draw(shape, fill),
+                draw_rect,
+                Some(Rect::new(
+                    Pos::zero(),
+                    Pos::new(image.width as f32, image.height as f32),
+                )),
+                color,
+            );
+        }
+    }
+
+    fn rasterize_glyph(font_system: &FontSystemRef, key: &AtlasGlyphKey) -> Option<RasterImage> {
+        // distance fields are scaled, so they aren't hinted to the pixel grid
+        let hint = key.mode == GlyphRenderMode::Bitmap;
+
+        let image = rasterize_glyph(&key.cache_key, hint, font_system.as_ref())?;
+
+        let image = match key.mode {
+            GlyphRenderMode::Sdf => distance_field_image(image),
+            GlyphRenderMode::Bitmap => image,
+        };
+
+        let format = match image.content {
+            cosmic_text::SwashContent::Mask => RasterImageFormat::Mask,
+            cosmic_text::SwashContent::Color => RasterImageFormat::Color,
+            cosmic_text::SwashContent::SubpixelMask => return None,
+        };
+
+        if image.placement.width == 0 || image.placement.height == 0 {
+            return None;
+        }
+
+        Some(RasterImage {
+            width: image.placement.width,
+            height: image.placement.height,
+            placement: PhysicalPos::new(image.placement.left, image.placement.top),
+            format,
+            clamp_to_edge: false,
+            data: image.data,
+        })
+    }
+
+    fn draw_quad(&mut self, ctx: &DrawContext, rect: Rect, uv: Option<Rect>, color: ColorRgba) {
+        let uv = uv.unwrap_or_default();
+        let color: [f32; 4] = color.into();
+
+        let vertex = |pos: Pos, uv: Pos| RasterVertex {
+            pos: [pos.x, pos.y],
+            uv: [uv.x, uv.y],
+            color,
+        };
+
+        let corners = [
+            vertex(rect.min, uv.min),
+            vertex(
+                Pos::new(rect.max.x, rect.min.y),
+                Pos::new(uv.max.x, uv.min.y),
+            ),
+            vertex(
+                Pos::new(rect.min.x, rect.max.y),
+                Pos::new(uv.min.x, uv.max.y),
+            ),
+            vertex(rect.max, uv.max),
+        ];
+
+        self.draw_triangle(ctx, [corners[0], corners[1], corners[2]]);
+        self.draw_triangle(ctx, [corners[1], corners[2], corners[3]]);
+    }
+
+    fn draw_triangle(&mut self, ctx: &DrawContext, vertices: [RasterVertex; 3]) {
+        let to_screen = |v: &RasterVertex| {
+            let p = ctx
+                .shape_ctx
+                .transformation
+                .transform_point(Pos::new(v.pos[0], v.pos[1]));
+            [p.x * self.scale_factor, p.y * self.scale_factor]
+        };
+
+        let mut vertices = vertices;
+        let mut screen = vertices.each_ref().map(to_screen);
+
+        let area = edge(screen[0], screen[1], screen[2]);
+
+        if area == 0. || !area.is_finite() {
+            return;
+        }
+
+        if area < 0. {
+            vertices.swap(1, 2);
+            screen.swap(1, 2);
+        }
+
+        let area = area.abs();
+
+        let (width, height) = (self.size.width as i32, self.size.height as i32);
+
+        let min_x = screen
+            .iter()
+            .map(|p| p[0])
+            .fold(f32::MAX, f32::min)
+            .floor()
+            .max(0.) as i32;
+        let min_y = screen
+            .iter()
+            .map(|p| p[1])
+            .fold(f32::MAX, f32::min)
+            .floor()
+            .max(0.) as i32;
+        let max_x = (screen.iter().map(|p| p[0]).fold(f32::MIN, f32::max).ceil() as i32).min(width);
+        let max_y =
+            (screen.iter().map(|p| p[1]).fold(f32::MIN, f32::max).ceil() as i32).min(height);
+
+        let edges = [(1, 2), (2, 0), (0, 1)];
+        let top_left = edges.map(|(a, b)| is_top_left(screen[a], screen[b]));
+
+        let sample_positions = sample_positions(self.multisample_mode);
+        let num_samples = sample_positions.len();
+
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                let mut coverage = 0u32;
+
+                for (i, offset) in sample_positions.iter().enumerate() {
+                    let p = [x as f32 + 0.5 + offset[0], y as f32 + 0.5 + offset[1]];
+
+                    let inside = edges.iter().zip(top_left).all(|(&(a, b), top_left)| {
+                        let w = edge(screen[a], screen[b], p);
+                        w > 0. || (w == 0. && top_left)
+                    });
+
+                    if inside {
+                        coverage |= 1 << i;
+                    }
+                }
+
+                if coverage == 0 {
+                    continue;
+                }
+
+                // fragments are shaded once per pixel, at the pixel center
+                let center = [x as f32 + 0.5, y as f32 + 0.5];
+                let bary = edges.map(|(a, b)| edge(screen[a], screen[b], center) / area);
+
+                let Some(color) = self.shade(ctx, &vertices, bary, center) else {
+                    continue;
+                };
+
+                let color = premultiply(color);
+                let pixel_idx = (y * width + x) as usize * num_samples;
+
+                for i in 0..num_samples {
+                    if coverage & (1 << i) != 0 {
+                        let sample = &mut self.samples[pixel_idx + i];
+                        *sample = ctx.shape_ctx.blend_mode.blend_premultiplied(*sample, color);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Mirrors `fs_main` in `box.wgsl`
+    fn shade(
+        &self,
+        ctx: &DrawContext,
+        vertices: &[RasterVertex; 3],
+        bary: [f32; 3],
+        screen_pos: [f32; 2],
+    ) -> Option<[f32; 4]> {
+        let interpolate =
+            |f: fn(&RasterVertex) -> f32| (0..3).map(|i| f(&vertices[i]) * bary[i]).sum::<f32>();
+
+        let mut col: [f32; 4] =
+            std::array::from_fn(|c| (0..3).map(|i| vertices[i].color[c] * bary[i]).sum::<f32>());
+        let uv = [interpolate(|v| v.uv[0]), interpolate(|v| v.uv[1])];
+        let pos = Pos::new(interpolate(|v| v.pos[0]), interpolate(|v| v.pos[1]));
+
+        match ctx.fill {
+            RasterFill::Color => {}
+
+            RasterFill::Texture(image) => {
+                let sampled_col = image.sample(uv);
+
+                // see `fs_main`: the value channel needs an extra linearization
+                let hsv = rgb_to_hsv([sampled_col[0], sampled_col[1], sampled_col[2]]);
+                let col_fixed = hsv_to_rgb([hsv[0], hsv[1], srgb_to_linear(hsv[2])]);
+
+                col = mul_color(
+                    col,
+                    [col_fixed[0], col_fixed[1], col_fixed[2], sampled_col[3]],
+                );
+            }
+
+            RasterFill::TextureMaskColor(image) => {
+                col[3] *= self.mask_coverage(ctx, image.sample(uv)[0]);
+            }
+
+            RasterFill::Gradient(gradient) => {
+                col = mul_color(col, gradient.color_at(pos).into());
+            }
+
+            RasterFill::TextureMaskGradient(image, gradient) => {
+                col = mul_color(col, gradient.color_at(pos).into());
+                col[3] *= self.mask_coverage(ctx, image.sample(uv)[0]);
+            }
+        }
+
+        let mut alpha = col[3];
+
+        if alpha == 0. {
+            return None;
+        }
+
+        // nested clip rects intersect, so walk up to the outermost one
+        let mut clip_rect_idx = ctx.shape_ctx.clip_rect_idx;
+
+        while clip_rect_idx != 0 {
+            let (clip_rect, inverse) = self.clip_rects[clip_rect_idx as usize];
+
+            let clip_pos = inverse.transform_point(Pos::new(
+                screen_pos[0] / self.scale_factor,
+                screen_pos[1] / self.scale_factor,
+            ));
+
+            let clip_dist = sd_round_box(
+                [
+                    clip_pos.x - clip_rect.origin[0],
+                    clip_pos.y - clip_rect.origin[1],
+                ],
+                clip_rect.half_size,
+                clip_rect.rounding,
+            );
+
+            alpha *= smoothstep(0., 1., -clip_dist + 0.5);
+
+            clip_rect_idx = clip_rect.parent_idx;
+        }
+
+        if alpha == 0. {
+            return None;
+        }
+
+        if let RasterShape::Rect {
+            origin,
+            dims,
+            rounding,
+            stroke_width,
+            blur_radius,
+        } = ctx.shape
+        {
+            let pos = [interpolate(|v| v.pos[0]), interpolate(|v| v.pos[1])];
+            let rel_pos = [pos[0] - origin[0], pos[1] - origin[1]];
+
+            // the vertex shader divides the blur radius by 3
+            let blur_radius = blur_radius / 3.;
+
+            if blur_radius > 0. {
+                alpha *= rounded_box_shadow(dims, rel_pos, blur_radius, rounding);
+            } else if rounding > 0. || stroke_width > 0. {
+                let dist = if rounding <= 0. {
+                    sd_sharp_box(rel_pos, dims)
+                } else {
+                    sd_round_box(rel_pos, dims, rounding)
+                };
+
+                if stroke_width <= 0. {
+                    alpha *= smoothstep(0., 1., -dist + 0.5);
+                } else {
+                    alpha *= 1.
+                        - (smoothstep(
+                            0.,
+                            0.5 / ctx.shape_ctx.shader_scale_factor,
+                            dist.abs() - stroke_width / 2.,
+                        ) * 2.);
+                }
+            }
+        }
+
+        Some([col[0], col[1], col[2], alpha])
+    }
+
+    /// Mirrors `sdf_coverage` in `box.wgsl` for distance field glyphs
+    fn mask_coverage(&self, ctx: &DrawContext, value: f32) -> f32 {
+        let RasterShape::SdfGlyph { spread } = ctx.shape else {
+            return value;
+        };
+
+        // physical pixels per local unit
+        let pixels_per_unit = (ctx.shape_ctx.shader_scale_factor.abs() / self.scale_factor).sqrt();
+        let dist = (value - 0.5) * 2. * spread * pixels_per_unit;
+
+        (dist + 0.5).clamp(0., 1.)
+    }
+}
+
+impl Renderer for CpuRasterizer {
+    type Target<'a> = ();
+
+    fn render_frame(&mut self, resources: &mut RenderResources, frame: FramePaint, _target: ()) {
+        self.rasterize(&resources.font_manager.get_font_system_ref(), frame);
+    }
+}
+
+#[inline(always)]
+fn edge(a: [f32; 2], b: [f32; 2], p: [f32; 2]) -> f32 {
+    (b[0] - a[0]) * (p[1] - a[1]) - (b[1] - a[1]) * (p[0] - a[0])
+}
+
+/// Top-left fill rule, so that shared edges aren't blended twice
+#[inline(always)]
+fn is_top_left(a: [f32; 2], b: [f32; 2]) -> bool {
+    let (dx, dy) = (b[0] - a[0], b[1] - a[1]);
+    (dy == 0. && dx > 0.) || dy < 0.
+}
+
+/// Standard sample positions (relative to the pixel center) for each sample count
+fn sample_positions(multisample_mode: MultisampleMode) -> Vec<[f32; 2]> {
+    let positions: &[[i32; 2]] = match multisample_mode {
+        MultisampleMode::None => &[[0, 0]],
+        MultisampleMode::MSAA2x => &[[4, 4], [-4, -4]],
+        MultisampleMode::MSAA4x => &[[-2, -6], [6, -2], [-6, 2], [2, 6]],
+        MultisampleMode::MSAA8x => &[
+            [1, -3],
+            [-1, 3],
+            [5, 1],
+            [-3, -5],
+            [-5, 5],
+            [-7, -1],
+            [3, 7],
+            [7, -7],
+        ],
+        MultisampleMode::MSAA16x => {
+            return (0..16)
+                .map(|i| {
+                    [
+                        ((i % 4) as f32 + 0.5) / 4. - 0.5,
+                        ((i / 4) as f32 + 0.5) / 4. - 0.5,
+                    ]
+                })
+                .collect()
+        }
+    };
+
+    positions
+        .iter()
+        .map(|[x, y]| [*x as f32 / 16., *y as f32 / 16.])
+        .collect()
+}
+
+#[inline(always)]
+fn premultiply([r, g, b, a]: [f32; 4]) -> [f32; 4] {
+    [r * a, g * a, b * a, a]
+}
+
+#[inline(always)]
+fn mul_color(a: [f32; 4], b: [f32; 4]) -> [f32; 4] {
+    std::array::from_fn(|i| a[i] * b[i])
+}
+
+#[inline(always)]
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0., 1.);
+    t * t * (3. - 2. * t)
+}
+
+fn sd_round_box(p: [f32; 2], b: [f32; 2], r: f32) -> f32 {
+    let q = [p[0].abs() - (b[0] - r), p[1].abs() - (b[1] - r)];
+    let outside = (q[0].max(0.).powi(2) + q[1].max(0.).powi(2)).sqrt();
+    outside + q[0].max(q[1]).min(0.) - r
+}
+
+fn sd_sharp_box(p: [f32; 2], b: [f32; 2]) -> f32 {
+    let q = [p[0].abs() - b[0], p[1].abs() - b[1]];
+    q[0].max(0.).max(q[1].max(0.)) + q[0].max(q[1]).min(0.)
+}
+
+fn gaussian(x: f32, sigma: f32) -> f32 {
+    (-(x * x) / (2. * sigma * sigma)).exp() / ((2. * std::f32::consts::PI).sqrt() * sigma)
+}
+
+fn erf(x: f32) -> f32 {
+    let s = x.signum();
+    let a = x.abs();
+    let mut x = 1. + (0.278393 + (0.230389 + 0.078108 * (a * a)) * a) * a;
+    x *= x;
+    s - s / (x * x)
+}
+
+fn rounded_box_shadow_x(x: f32, y: f32, sigma: f32, corner: f32, half_size: [f32; 2]) -> f32 {
+    let delta = (half_size[1] - corner - y.abs()).min(0.);
+    let curved = half_size[0] - corner + (corner * corner - delta * delta).max(0.).sqrt();
+    let fac = 0.5f32.sqrt() / sigma;
+    let low = 0.5 + 0.5 * erf((x - curved) * fac);
+    let high = 0.5 + 0.5 * erf((x + curved) * fac);
+    high - low
+}
+
+fn rounded_box_shadow(half_size: [f32; 2], pt: [f32; 2], sigma: f32, corner: f32) -> f32 {
+    let low = pt[1] - half_size[1];
+    let high = pt[1] + half_size[1];
+    let start = (-3. * sigma).clamp(low, high);
+    let end = (3. * sigma).clamp(low, high);
+
+    let step = (end - start) / 4.;
+    let mut y = start + step * 0.5;
+    let mut value = 0.;
+
+    for _ in 0..4 {
+        value += rounded_box_shadow_x(pt[0], pt[1] - y, sigma, corner, half_size)
+            * gaussian(y, sigma)
+            * step;
+        y += step;
+    }
+
+    value
+}
+
+fn srgb_to_linear(channel: f32) -> f32 {
+    if channel <= 0.04045 {
+        channel / 12.92
+    } else {
+        ((channel + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(channel: f32) -> f32 {
+    if channel <= 0.0031308 {
+        channel * 12.92
+    } else {
+        1.055 * channel.powf(1. / 2.4) - 0.055
+    }
+}
+
+fn rgb_to_hsv(rgb: [f32; 3]) -> [f32; 3] {
+    const EPSILON: f32 = 1e-10;
+
+    let [r, g, b] = rgb;
+
+    let p = if g < b {
+        [b, g, -1., 2. / 3.]
+    } else {
+        [g, b, 0., -1. / 3.]
+    };
+
+    let q = if r < p[0] {
+        [p[0], p[1], p[3], r]
+    } else {
+        [r, p[1], p[2], p[0]]
+    };
+
+    let c = q[0] - q[3].min(q[1]);
+    let h = ((q[3] - q[1]) / (6. * c + EPSILON) + q[2]).abs();
+    let s = c / (q[0] + EPSILON);
+
+    [h, s, q[0]]
+}
+
+fn hsv_to_rgb(hsv: [f32; 3]) -> [f32; 3] {
+    let [h, s, v] = hsv;
+
+    let rgb = [
+        (h * 6. - 3.).abs() - 1.,
+        2. - (h * 6. - 2.).abs(),
+        2. - (h * 6. - 4.).abs(),
+    ]
+    .map(|c| c.clamp(0., 1.));
+
+    rgb.map(|c| ((c - 1.) * s + 1.) * v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Draws untransformed and unclipped, at a scale factor of 1
+    fn identity_ctx() -> ShapeContext {
+        ShapeContext {
+            transformation: CoordinateTransform::identity(),
+            shader_scale_factor: 1.,
+            clip_rect_idx: 0,
+            blend_mode: Default::default(),
+        }
+    }
+
+    #[test]
+    fn layer_opacity_applies_to_group() {
+        let mut rasterizer = CpuRasterizer::new(PhysicalSize::new(4, 4), 1.)
+            .with_multisample_mode(MultisampleMode::None);
+
+        let ctx = identity_ctx();
+
+        let layers = [FrameLayer {
+            layer: Layer::default().with_opacity(0.5),
+            parent_idx: None,
+        }];
+
+        let mut layer_stack = Vec::new();
+        rasterizer.enter_layer(&layers, &mut layer_stack, Some(0));
+
+        // overlapping opaque shapes shouldn't show through each other
+        for _ in 0..2 {
+            rasterizer.draw_quad(
+                &ctx.draw(RasterShape::Mesh, RasterFill::Color),
+                Rect::new(Pos::zero(), Pos::new(4., 4.)),
+                None,
+                ColorRgba::new(1., 1., 1., 1.),
+            );
+        }
+
+        rasterizer.enter_layer(&layers, &mut layer_stack, None);
+
+        assert!(layer_stack.is_empty());
+
+        for sample in &rasterizer.samples {
+            assert_eq!(*sample, [0.5; 4]);
+        }
+    }
+
+    #[test]
+    fn shared_edges_blend_once() {
+        let mut rasterizer = CpuRasterizer::new(PhysicalSize::new(4, 4), 1.)
+            .with_multisample_mode(MultisampleMode::None);
+
+        let ctx = identity_ctx();
+
+        rasterizer.draw_quad(
+            &ctx.draw(RasterShape::Mesh, RasterFill::Color),
+            Rect::new(Pos::zero(), Pos::new(4., 4.)),
+            None,
+            ColorRgba::new(1., 1., 1., 0.5),
+        );
+
+        for sample in &rasterizer.samples {
+            assert_eq!(sample[3], 0.5);
+        }
+    }
+
+    #[test]
+    fn vertex_colors_interpolate() {
+        let mut rasterizer = CpuRasterizer::new(PhysicalSize::new(4, 4), 1.)
+            .with_multisample_mode(MultisampleMode::None);
+
+        let ctx = identity_ctx();
+
+        let vertex = |pos: [f32; 2], color: [f32; 4]| RasterVertex {
+            pos,
+            color,
+            ..Default::default()
+        };
+
+        let (red, blue) = ([1., 0., 0., 1.], [0., 0., 1., 1.]);
+
+        rasterizer.draw_triangle(
+            &ctx.draw(RasterShape::Mesh, RasterFill::Color),
+            [
+                vertex([0., 0.], red),
+                vertex([8., 0.], blue),
+                vertex([0., 8.], red),
+            ],
+        );
+
+        // 1/16 of the way to the blue vertex at the first pixel center, and 5/16 at the third
+        let (left, right) = (rasterizer.samples[0], rasterizer.samples[2]);
+
+        assert!((left[2] - 1. / 16.).abs() < 1e-4);
+        assert!((right[2] - 5. / 16.).abs() < 1e-4);
+        assert_eq!(left[3], 1.);
+    }
+
+    #[test]
+    fn texture_samples_are_tinted() {
+        let mut rasterizer = CpuRasterizer::new(PhysicalSize::new(4, 1), 1.)
+            .with_multisample_mode(MultisampleMode::None);
+
+        let ctx = identity_ctx();
+
+        let image = RasterImage {
+            width: 2,
+            height: 1,
+            placement: PhysicalPos::zero(),
+            format: RasterImageFormat::Color,
+            clamp_to_edge: true,
+            data: vec![255, 0, 0, 255, 0, 0, 255, 255],
+        };
+
+        rasterizer.draw_quad(
+            &ctx.draw(RasterShape::Mesh, RasterFill::Texture(&image)),
+            Rect::new(Pos::zero(), Pos::new(4., 1.)),
+            Some(Rect::new(Pos::zero(), Pos::new(2., 1.))),
+            ColorRgba::new(1., 1., 1., 0.5),
+        );
+
+        assert_eq!(rasterizer.samples[0], [0.5, 0., 0., 0.5]);
+        assert_eq!(rasterizer.samples[3], [0., 0., 0.5, 0.5]);
+    }
+
+    #[test]
+    fn sdf_glyphs_are_thresholded() {
+        let mut rasterizer = CpuRasterizer::new(PhysicalSize::new(4, 1), 1.)
+            .with_multisample_mode(MultisampleMode::None);
+
+        let ctx = identity_ctx();
+
+        let image = RasterImage {
+            width: 4,
+            height: 1,
+            placement: PhysicalPos::zero(),
+            format: RasterImageFormat::Mask,
+            clamp_to_edge: true,
+            data: vec![0, 102, 153, 255],
+        };
+
+        rasterizer.draw_quad(
+            &ctx.draw(
+                RasterShape::SdfGlyph { spread: 2. },
+                RasterFill::TextureMaskColor(&image),
+            ),
+            Rect::new(Pos::zero(), Pos::new(4., 1.)),
+            Some(Rect::new(Pos::zero(), Pos::new(4., 1.))),
+            ColorRgba::new(1., 1., 1., 1.),
+        );
+
+        let alpha = rasterizer.samples.iter().map(|s| s[3]).collect::<Vec<_>>();
+
+        for (alpha, expected) in alpha.into_iter().zip([0., 0.1, 0.9, 1.]) {
+            assert!((alpha - expected).abs() < 1e-4, "{alpha} != {expected}");
+        }
+    }
+
+    #[test]
+    fn nested_clip_rects_intersect() {
+        let mut rasterizer = CpuRasterizer::new(PhysicalSize::new(4, 4), 1.)
+            .with_multisample_mode(MultisampleMode::None);
+
+        let identity = CoordinateTransform::identity();
+
+        rasterizer.clip_rects = vec![
+            Default::default(),
+            ShaderClipRect::new(Rect::new(Pos::zero(), Pos::new(2., 4.)), 0., 0),
+            ShaderClipRect::new(Rect::new(Pos::zero(), Pos::new(4., 2.)), 0., 0)
+                .with_parent_idx(Some(1)),
+        ]
+        .into_iter()
+        .map(|clip_rect| (clip_rect, identity))
+        .collect();
+
+        let ctx = ShapeContext {
+            clip_rect_idx: 2,
+            ..identity_ctx()
+        };
+
+        rasterizer.draw_quad(
+            &ctx.draw(RasterShape::Mesh, RasterFill::Color),
+            Rect::new(Pos::zero(), Pos::new(4., 4.)),
+            None,
+            ColorRgba::new(1., 1., 1., 1.),
+        );
+
+        for (i, sample) in rasterizer.samples.iter().enumerate() {
+            let (x, y) = (i % 4, i / 4);
+            assert_eq!(sample[3] > 0., x < 2 && y < 2, "pixel ({x}, {y})");
+        }
+    }
+
+    #[test]
+    fn multiply_blends_with_destination() {
+        let mut rasterizer = CpuRasterizer::new(PhysicalSize::new(4, 4), 1.)
+            .with_multisample_mode(MultisampleMode::None);
+
+        let mut ctx = identity_ctx();
+
+        for blend_mode in [BlendMode::Normal, BlendMode::Multiply] {
+            ctx.blend_mode = blend_mode;
+
+            rasterizer.draw_quad(
+                &ctx.draw(RasterShape::Mesh, RasterFill::Color),
+                Rect::new(Pos::zero(), Pos::new(4., 4.)),
+                None,
+                ColorRgba::new(0.5, 0.5, 0.5, 1.),
+            );
+        }
+
+        for sample in &rasterizer.samples {
+            assert_eq!(*sample, [0.25, 0.25, 0.25, 1.]);
+        }
+    }
+
+    #[test]
+    fn box_sdf() {
+        assert_eq!(sd_sharp_box([0., 0.], [1., 1.]), -1.);
+        assert_eq!(sd_sharp_box([2., 0.], [1., 1.]), 1.);
+        assert_eq!(sd_round_box([2., 0.], [1., 1.], 0.5), 1.);
+    }
+
+    #[test]
+    fn hsv_roundtrip() {
+        let rgb = [0.2, 0.6, 0.4];
+        let back = hsv_to_rgb(rgb_to_hsv(rgb));
+
+        for i in 0..3 {
+            assert!((rgb[i] - back[i]).abs() < 1e-4);
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+
+use crate::{
+    atlas::{FontManager, TextureAtlasManagerRef},
+    custom_render::QueuedCustomRender,
+    element::ElementId,
+    layer::FrameLayer,
+    math::CoordinateTransform,
+    shape::ShaderClipRect,
+    surface::RenderingContext,
+    texture::TextureManagerRef,
+};
+
+use super::ctx::PaintShapeWithContext;
+
+/// Everything painted during a frame's ui pass
+pub struct FramePaint {
+    pub shapes: Vec<PaintShapeWithContext>,
+    pub clip_rects: Vec<ShaderClipRect>,
+    pub transformations: Vec<CoordinateTransform>,
+    pub transformation_inverses: Vec<CoordinateTransform>,
+    pub layers: Vec<FrameLayer>,
+    /// Custom render callbacks, which run before the main render pass
+    pub custom_renders: Vec<QueuedCustomRender>,
+    /// Generations of the shapes of elements which don't need to repaint. An
+    /// element's generation only changes when it repaints, so what's built from
+    /// its shapes can be reused while it stays the same.
+    pub element_generations: HashMap<ElementId, u64>,
+}
+
+/// Resources owned by the [`super::scene::Scene`] which a [`Renderer`] may need
+pub struct RenderResources<'a> {
+    pub rendering_context: &'a RenderingContext,
+    pub font_manager: &'a mut FontManager,
+    pub atlas_manager: &'a TextureAtlasManagerRef,
+    pub texture_manager: &'a TextureManagerRef,
+}
+
+/// A backend which draws the shapes painted during a frame.
+///
+/// [`crate::shape::ShapeRenderer`] is the wgpu implementation; see also
+/// [`super::raster::CpuRasterizer`] and [`RecordingRenderer`].
+pub trait Renderer {
+    /// Where a frame is drawn to, e.g. a texture view
+    type Target<'a>;
+
+    fn render_frame(
+        &mut self,
+        resources: &mut RenderResources,
+        frame: FramePaint,
+        target: Self::Target<'_>,
+    );
+}
+
+/// A renderer which keeps every frame it is given, for inspecting paint output
+/// in tests.
+#[derive(Default)]
+pub struct RecordingRenderer {
+    frames: Vec<FramePaint>,
+}
+
+impl RecordingRenderer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn frames(&self) -> &[FramePaint] {
+        &self.frames
+    }
+
+    pub fn last_frame(&self) -> Option<&FramePaint> {
+        self.frames.last()
+    }
+
+    pub fn take_frames(&mut self) -> Vec<FramePaint> {
+        std::mem::take(&mut self.frames)
+    }
+}
+
+impl Renderer for RecordingRenderer {
+    type Target<'a> = ();
+
+    fn render_frame(&mut self, _resources: &mut RenderResources, frame: FramePaint, _target: ()) {
+        self.frames.push(frame);
+    }
+}
@@ -1,4 +1,5 @@
 use std::{
+    collections::HashMap,
     ops::DerefMut,
     sync::{Arc, RwLock},
 };
@@ -8,19 +9,20 @@ use itertools::Itertools;
 
 use crate::{
     accessibility::AccessNode,
-    atlas::{self, FontManager, TextureAtlasManager, TextureAtlasManagerRef},
+    atlas::{self, FontManager, GlyphAtlasOptions, GlyphAtlasStats, TextureAtlasManager, TextureAtlasManagerRef},
+    capture::{self, CaptureRequest},
     element::{Element, ElementId, ElementRef, RootConstructor},
-    graphics::{DynamicGPUMeshTriBuffer, PushVertices, VertexBuffers},
+    image::{ImageData, ImageOptions, ImageResult},
     input::{input_state::InputState, output::PlatformOutput},
+    layer::ZIndex,
+    repaint::RepaintHandle,
     math::{
         CompleteScaleFactor, DeviceScaleFactor, PhysicalSize, Pos, Rect, TransformationScaleFactor,
     },
-    shape::{
-        self, BoxShaderVertex, ClipRect, ComputedPaintShape, PaintMeshVertex, PaintShape,
-        ShaderClipRect, ShapeBufferWithContext,
-    },
-    surface::{RenderAttachment, RenderSurface, RenderingContext},
-    texture::TextureManagerRef,
+    shape::{self, ClipRect, WgpuRenderTarget},
+    surface::{OffscreenSurface, RenderAttachment, RenderingContext, SurfaceLike},
+    texture::{TextureManagerRef, TextureRef},
+    time::Instant,
     util::{
         guard::ReadLockable,
         text::{FontSystem, FontSystemRef, HasBuffer, TextBox, TextBoxLike},
@@ -28,9 +30,12 @@ use crate::{
 };
 
 use super::{
-    ctx::{PaintShapeWithContext, SceneContext},
+    ctx::{ElementPaint, SceneContext},
+    damage::{self, Damage, DamageTracker},
     framepacer::{Framepacer, InstantLike, ManagedFramepacer},
     layout::{ElementTree, LayoutEngine, LayoutPass},
+    renderer::{FramePaint, RenderResources, Renderer},
+    timings::FrameTimings,
 };
 
 pub struct SceneResources<'a> {
@@ -41,9 +46,13 @@ pub struct SceneResources<'a> {
     layout_engine: &'a mut LayoutEngine,
     font_manager: &'a mut FontManager,
     device_scale_factor: DeviceScaleFactor,
+    pub(super) repaint: RepaintHandle,
 
     pub(crate) element_clip_rect: Option<ClipRect>,
     pub(crate) element_transformation_scale_factor: Option<TransformationScaleFactor>,
+    /// What can be seen of the element in the layout post pass, see
+    /// [`super::ctx::SceneContext::visible_rect`]
+    pub(crate) element_visible_rect: Option<Rect>,
 }
 
 impl<'a> SceneResources<'a> {
@@ -55,6 +64,7 @@ impl<'a> SceneResources<'a> {
         device_scale_factor: DeviceScaleFactor,
         layout_engine: &'a mut LayoutEngine,
         font_manager: &'a mut FontManager,
+        repaint: RepaintHandle,
     ) -> Self {
         Self {
             texture_atlas_manager,
@@ -64,9 +74,11 @@ impl<'a> SceneResources<'a> {
             device_scale_factor,
             layout_engine,
             font_manager,
+            repaint,
 
             element_clip_rect: Default::default(),
             element_transformation_scale_factor: Default::default(),
+            element_visible_rect: Default::default(),
         }
     }
 
@@ -86,6 +98,11 @@ impl<'a> SceneResources<'a> {
         self.rendering_context.clone()
     }
 
+    /// Handle for requesting repaints of the scene, e.g. from a background thread
+    pub fn repaint_handle(&self) -> RepaintHandle {
+        self.repaint.clone()
+    }
+
     pub fn device_scale_factor(&self) -> DeviceScaleFactor {
         self.device_scale_factor
     }
@@ -106,10 +123,41 @@ impl<'a> SceneResources<'a> {
         &self.texture_manager
     }
 
+    /// Uploads decoded pixels into a new texture, which can be painted with
+    /// [`crate::shape::PaintFill::from_entire_texture`]
+    pub fn upload_image(
+        &self,
+        image: &ImageData,
+        options: ImageOptions,
+    ) -> ImageResult<TextureRef> {
+        image.upload(&self.rendering_context, &self.texture_manager, options)
+    }
+
+    /// Uploads tightly packed, row-major RGBA8 pixels into a new texture
+    pub fn upload_image_rgba8(
+        &self,
+        size: impl Into<PhysicalSize<u32>>,
+        pixels: Vec<u8>,
+        options: ImageOptions,
+    ) -> ImageResult<TextureRef> {
+        self.upload_image(&ImageData::from_rgba8(size, pixels)?, options)
+    }
+
+    /// Decodes a PNG or JPEG image into a new texture
+    pub fn load_image(&self, bytes: &[u8], options: ImageOptions) -> ImageResult<TextureRef> {
+        self.upload_image(&ImageData::decode(bytes)?, options)
+    }
+
     pub(crate) fn prepare_text<Buffer: HasBuffer + 'static>(&mut self, text: &TextBox<Buffer>) {
-        self.font_manager.process_glyphs(
-            &text.calculate_placed_text_box(self.element_clip_rect, self.scale_factor()),
-        );
+        let Some(visible_rect) = self.element_visible_rect else {
+            return;
+        };
+
+        self.font_manager.process_glyphs(&text.calculate_placed_text_box(
+            self.element_clip_rect,
+            Some(visible_rect),
+            self.scale_factor(),
+        ));
     }
 }
 
@@ -124,12 +172,28 @@ pub struct Scene<Root: RootConstructor + 'static> {
     layout_engine: LayoutEngine,
 
     layout_result: Option<ElementTree>,
+
+    /// Captures of the next rendered frame
+    capture_requests: Vec<CaptureRequest>,
+
+    repaint: RepaintHandle,
+
+    damage_tracker: DamageTracker,
+
+    /// Shapes of elements which don't need to repaint
+    element_paints: HashMap<ElementId, ElementPaint>,
+    /// Incremented every frame, see [`FramePaint::element_generations`]
+    paint_generation: u64,
+
+    /// Timings of the frame in progress
+    timings: FrameTimings,
+    last_frame_timings: FrameTimings,
 }
 
 impl<Root: RootConstructor + 'static> Scene<Root> {
     pub fn new(
         rendering_context: Arc<RenderingContext>,
-        render_surface: &RenderSurface,
+        render_surface: &(impl SurfaceLike + ?Sized),
         texture_manager: &TextureManagerRef,
         scale_fac: f64,
     ) -> Self {
@@ -153,12 +217,15 @@ impl<Root: RootConstructor + 'static> Scene<Root> {
         let atlas_manager = font_manager.atlas_manager_ref();
         let texture_manager = texture_manager.clone();
 
+        let repaint = RepaintHandle::default();
+
         let mut scene_resources = Self::get_scene_resources(
             &atlas_manager,
             &texture_manager,
             &mut font_manager,
             render_surface,
             &mut layout_engine,
+            &repaint,
         );
 
         let root = Root::new(&mut scene_resources).into();
@@ -171,15 +238,36 @@ impl<Root: RootConstructor + 'static> Scene<Root> {
             layout_engine,
             texture_manager,
             layout_result: None,
+            capture_requests: Default::default(),
+            repaint,
+            damage_tracker: Default::default(),
+            element_paints: Default::default(),
+            paint_generation: 0,
+            timings: Default::default(),
+            last_frame_timings: Default::default(),
         }
     }
 
+    /// Creates a scene rendering into `offscreen_surface`, with its own texture manager.
+    pub fn new_offscreen(offscreen_surface: &OffscreenSurface) -> Self {
+        let rendering_context = offscreen_surface.clone_rendering_context();
+        let texture_manager = TextureManagerRef::from_device_limits(&rendering_context);
+
+        Self::new(
+            rendering_context,
+            offscreen_surface,
+            &texture_manager,
+            offscreen_surface.device_scale_factor().as_float().get() as f64,
+        )
+    }
+
     fn get_scene_resources<'a>(
         atlas_manager: &TextureAtlasManagerRef,
         texture_manager: &TextureManagerRef,
         font_manager: &'a mut FontManager,
-        render_surface: &RenderSurface,
+        render_surface: &(impl SurfaceLike + ?Sized),
         layout_engine: &'a mut LayoutEngine,
+        repaint: &RepaintHandle,
     ) -> SceneResources<'a> {
         SceneResources::new(
             atlas_manager.clone(),
@@ -189,10 +277,13 @@ impl<Root: RootConstructor + 'static> Scene<Root> {
             render_surface.device_scale_factor(),
             layout_engine,
             font_manager,
+            repaint.clone(),
         )
     }
 
-    pub fn do_layout(&mut self, render_surface: &RenderSurface) -> ElementTree {
+    pub fn do_layout(&mut self, render_surface: &(impl SurfaceLike + ?Sized)) -> ElementTree {
+        let start = Instant::now();
+
         let scale_fac = render_surface.device_scale_factor();
 
         let physical_screen_size: PhysicalSize<u32> = render_surface.get_size().into();
@@ -206,49 +297,242 @@ impl<Root: RootConstructor + 'static> Scene<Root> {
             &mut self.font_manager,
             render_surface,
             &mut self.layout_engine,
+            &self.repaint,
         );
 
         let layout_pass = LayoutPass::new(&mut self.root, &mut scene_resources);
 
-        layout_pass.do_layout_pass(screen_size, &mut self.root)
+        let element_tree = layout_pass.do_layout_pass(screen_size, &mut self.root);
+
+        self.timings.layout = start.elapsed();
+
+        element_tree
+    }
+
+    /// How long each phase of the last rendered frame took
+    #[inline(always)]
+    pub fn frame_timings(&self) -> &FrameTimings {
+        &self.last_frame_timings
+    }
+
+    /// Usage of the glyph atlases after the last rendered frame
+    #[inline(always)]
+    pub fn glyph_atlas_stats(&self) -> GlyphAtlasStats {
+        self.font_manager.atlas_stats()
+    }
+
+    /// Handle for requesting repaints of the scene, e.g. from a background thread
+    pub fn repaint_handle(&self) -> RepaintHandle {
+        self.repaint.clone()
+    }
+
+    pub fn set_glyph_atlas_options(&mut self, options: GlyphAtlasOptions) {
+        self.font_manager.set_atlas_options(options);
+    }
+
+    fn finish_frame_timings(&mut self) {
+        self.timings.glyph_rasterization = self.font_manager.take_glyph_rasterization_time();
+        self.last_frame_timings = std::mem::take(&mut self.timings);
     }
 
     pub fn render<I: InstantLike + Copy + std::fmt::Debug>(
         &mut self,
-        render_surface: &RenderSurface,
+        render_surface: &(impl SurfaceLike + ?Sized),
         RenderAttachment {
             window_texture,
             msaa_view,
+            back_buffer,
+            buffer_age,
             ..
         }: RenderAttachment,
-        ElementTree {
-            root: mut scene_layout,
-            transformations,
-            mut clip_rects,
-        }: ElementTree,
-        mut input: InputState,
+        element_tree: ElementTree,
+        input: InputState,
         start_time: I,
         fp: &mut (impl Framepacer<I> + ?Sized),
         time_context: &I::Context,
     ) -> (InputState, PlatformOutput, std::time::Duration, I) {
-        let render_ctx = render_surface.rendering_context();
+        let (input, platform_output, damage) = match &back_buffer {
+            Some(back_buffer) => self.render_through_back_buffer(
+                render_surface,
+                back_buffer,
+                buffer_age,
+                &window_texture.texture,
+                element_tree,
+                input,
+            ),
+
+            // wgpu doesn't expose the age of surface textures, so they're
+            // redrawn fully
+            None => {
+                let target_texture = &window_texture.texture;
+                let target_view =
+                    target_texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+                self.render_wgpu(
+                    render_surface,
+                    WgpuRenderTarget {
+                        view: &target_view,
+                        msaa_view: msaa_view.as_ref(),
+                        size: PhysicalSize::new(target_texture.width(), target_texture.height()),
+                        damage: Damage::Full,
+                        copy_to: None,
+                    },
+                    0,
+                    element_tree,
+                    input,
+                )
+            }
+        };
 
-        let RenderingContext {
-            device,
-            queue,
-            adapter,
-            ..
-        } = render_ctx;
+        self.capture_frame(
+            render_surface,
+            back_buffer.as_ref().unwrap_or(&window_texture.texture),
+        );
 
-        let window_view = window_texture
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
+        fp.check_missed_deadline(
+            I::now(time_context),
+            start_time.elapsed(time_context).into(),
+        );
 
-        let scale_fac = render_surface.device_scale_factor();
+        // window_texture.present(&wgpu::PresentationDescriptor {
+        //     presentation_delay: wgpu::PresentationDelay::ScheduleTime(
+        //         fp.get_deadline().expect("Deadline has not been set!"),
+        //     ),
+        // });
+
+        let approx_present_time = I::now(&time_context);
 
-        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
-            label: Some("Render Encoder"),
-        });
+        match (fp.desired_frame_time(), fp.desired_frame_instant()) {
+            // the window keeps showing the last frame, which is the same
+            _ if damage == Damage::Empty => drop(window_texture),
+
+            (Some(desired_frame_time), _) => {
+                // window_texture.present(&wgpu::PresentationDescriptor {
+                //     presentation_delay: wgpu::PresentationDelay::ScheduleMinimumDuration(
+                //         desired_frame_time,
+                //     ),
+                // });
+                window_texture.present();
+            }
+
+            (_, Some(desired_instant)) => {
+                // window_texture.present(&wgpu::PresentationDescriptor {
+                //     presentation_delay: wgpu::PresentationDelay::ScheduleTime(desired_instant),
+                // });
+                window_texture.present();
+            }
+
+            _ => {
+                // window_texture.present(&Default::default());
+                window_texture.present();
+            }
+        }
+
+        self.font_manager.collect_garbage();
+        self.finish_frame_timings();
+
+        let render_time = start_time.elapsed(time_context);
+
+        (input, platform_output, render_time, approx_present_time)
+    }
+
+    /// Draws the damaged region of a frame into `back_buffer`, which holds the
+    /// frame drawn `buffer_age` frames ago, and copies the result to
+    /// `surface_texture` in the same submission. Nothing is drawn or copied if
+    /// the frame didn't change.
+    fn render_through_back_buffer(
+        &mut self,
+        render_surface: &(impl SurfaceLike + ?Sized),
+        back_buffer: &wgpu::Texture,
+        buffer_age: u32,
+        surface_texture: &wgpu::Texture,
+        element_tree: ElementTree,
+        input: InputState,
+    ) -> (InputState, PlatformOutput, Damage) {
+        let view = back_buffer.create_view(&wgpu::TextureViewDescriptor::default());
+
+        self.render_wgpu(
+            render_surface,
+            WgpuRenderTarget {
+                view: &view,
+                msaa_view: None,
+                size: PhysicalSize::new(back_buffer.width(), back_buffer.height()),
+                damage: Damage::Full,
+                copy_to: Some((back_buffer, surface_texture)),
+            },
+            buffer_age,
+            element_tree,
+            input,
+        )
+    }
+
+    /// Renders a frame into an [`OffscreenSurface`] instead of a window. Use
+    /// [`OffscreenSurface::read_pixels`] afterwards to read back the result.
+    pub fn render_offscreen(
+        &mut self,
+        offscreen_surface: &OffscreenSurface,
+        element_tree: ElementTree,
+        input: InputState,
+    ) -> (InputState, PlatformOutput) {
+        let (target_view, msaa_view) = offscreen_surface.get_views();
+
+        let (input, platform_output, _) = self.render_wgpu(
+            offscreen_surface,
+            WgpuRenderTarget {
+                view: &target_view,
+                msaa_view: msaa_view.as_ref(),
+                size: offscreen_surface.get_size().into(),
+                damage: Damage::Full,
+                copy_to: None,
+            },
+            offscreen_surface.next_buffer_age(),
+            element_tree,
+            input,
+        );
+
+        self.capture_frame(offscreen_surface, offscreen_surface.texture());
+
+        self.font_manager.collect_garbage();
+        self.finish_frame_timings();
+
+        (input, platform_output)
+    }
+
+    /// Captures the next frame rendered by [`Self::render`] or
+    /// [`Self::render_offscreen`]
+    pub fn request_capture(&mut self, request: CaptureRequest) {
+        self.capture_requests.push(request);
+    }
+
+    fn capture_frame(
+        &mut self,
+        render_surface: &(impl SurfaceLike + ?Sized),
+        texture: &wgpu::Texture,
+    ) {
+        if self.capture_requests.is_empty() {
+            return;
+        }
+
+        self.capture_requests = capture::capture_texture(
+            render_surface.rendering_context(),
+            texture,
+            render_surface.device_scale_factor(),
+            std::mem::take(&mut self.capture_requests),
+        );
+    }
+
+    /// Runs the input and ui passes, collecting the shapes painted this frame.
+    fn paint_frame(
+        &mut self,
+        render_surface: &(impl SurfaceLike + ?Sized),
+        ElementTree {
+            root: mut scene_layout,
+            transformations,
+            mut clip_rects,
+        }: ElementTree,
+        mut input: InputState,
+    ) -> (InputState, PlatformOutput, FramePaint) {
+        let scale_fac = render_surface.device_scale_factor();
 
         // layout pass
         let mut scene_resources = Self::get_scene_resources(
@@ -257,15 +541,39 @@ impl<Root: RootConstructor + 'static> Scene<Root> {
             &mut self.font_manager,
             render_surface,
             &mut self.layout_engine,
+            &self.repaint,
         );
 
+        let input_start = Instant::now();
+
         input.insert_transformations(transformations);
-        scene_layout.do_input_pass(&mut input, None, &mut clip_rects, None, &mut scene_resources);
+        scene_layout.do_input_pass(&mut input, None, &mut clip_rects, &mut scene_resources);
         let transformations = input.take_transformations().unwrap();
 
-        let mut scene_context =
-            SceneContext::new(scale_fac, transformations, clip_rects, scene_resources);
-        scene_layout.do_ui_pass(&mut scene_context, None, None);
+        let ui_start = Instant::now();
+        self.timings.input_pass = ui_start - input_start;
+
+        let physical_screen_size: PhysicalSize<u32> = render_surface.get_size().into();
+
+        let viewport = Rect::from_min_size(
+            Pos::zero(),
+            physical_screen_size.cast_unit().map(|x| x as f32) * scale_fac.inverse().as_float(),
+        );
+
+        self.paint_generation += 1;
+
+        let mut scene_context = SceneContext::new(
+            scale_fac,
+            transformations,
+            clip_rects,
+            viewport,
+            scene_resources,
+            std::mem::take(&mut self.element_paints),
+            self.paint_generation,
+        );
+        scene_layout.do_ui_pass(&mut scene_context, None, None, ZIndex::BASE);
+
+        let ui_pass = ui_start.elapsed();
 
         {
             let root_id = self.root.id().as_access_id();
@@ -274,168 +582,374 @@ impl<Root: RootConstructor + 'static> Scene<Root> {
                 Some(accesskit::Tree::new(root_id));
         }
 
-        // render pass
         let SceneContext {
-            shapes,
-            clip_rects: scene_clip_rects,
-            transformations: scene_transformations,
+            mut shapes,
+            clip_rects,
+            transformations,
+            layers,
+            custom_renders,
+            capture_requests,
+            element_paints,
             output: platform_output,
             ..
         } = scene_context;
 
-        let mut shape_buffer_local = ShapeBufferWithContext::new();
+        let element_generations = element_paints
+            .iter()
+            .map(|(element_id, paint)| (*element_id, paint.generation))
+            .collect();
+
+        self.element_paints = element_paints;
+        self.capture_requests.extend(capture_requests);
+        self.timings.ui_pass = ui_pass;
+
+        // the sort is stable, so shapes with the same z-index keep their tree order
+        shapes.sort_by_key(|shape| shape.z_index);
+
+        (
+            input,
+            platform_output,
+            FramePaint {
+                shapes,
+                clip_rects: clip_rects.finish().collect_vec(),
+                transformations: transformations.transformations,
+                transformation_inverses: transformations.transformation_inverses,
+                layers,
+                custom_renders,
+                element_generations,
+            },
+        )
+    }
+
+    /// Runs the input and ui passes, and draws the result with `renderer`.
+    ///
+    /// This allows plugging in other backends, like a
+    /// [`super::raster::CpuRasterizer`] or a [`super::renderer::RecordingRenderer`].
+    pub fn render_with<R: Renderer + ?Sized>(
+        &mut self,
+        render_surface: &(impl SurfaceLike + ?Sized),
+        element_tree: ElementTree,
+        input: InputState,
+        renderer: &mut R,
+        target: R::Target<'_>,
+    ) -> (InputState, PlatformOutput) {
+        let (input, platform_output, frame) =
+            self.paint_frame(render_surface, element_tree, input);
+
+        renderer.render_frame(
+            &mut Self::get_render_resources(
+                render_surface.rendering_context(),
+                &mut self.font_manager,
+                &self.atlas_manager,
+                &self.texture_manager,
+            ),
+            frame,
+            target,
+        );
+
+        self.font_manager.collect_garbage();
+        self.finish_frame_timings();
+
+        (input, platform_output)
+    }
+
+    /// Draws a frame with the scene's own wgpu renderer. Only the pixels which
+    /// changed since the frame held by `target`, `buffer_age` frames ago, are
+    /// redrawn, and nothing is drawn if the frame didn't change. Returns the
+    /// damage which was drawn.
+    fn render_wgpu(
+        &mut self,
+        render_surface: &(impl SurfaceLike + ?Sized),
+        mut target: WgpuRenderTarget,
+        buffer_age: u32,
+        element_tree: ElementTree,
+        input: InputState,
+    ) -> (InputState, PlatformOutput, Damage) {
+        let (input, platform_output, mut frame) =
+            self.paint_frame(render_surface, element_tree, input);
+
+        let scale_fac = render_surface.device_scale_factor();
+
+        target.damage = self
+            .damage_tracker
+            .track(&frame, target.size, scale_fac, buffer_age);
+
+        // captures read the target, so it can't be left undefined
+        if buffer_age == 0 && !self.capture_requests.is_empty() {
+            target.damage = Damage::Full;
+        }
 
-        let clip_rects = scene_clip_rects.finish().collect_vec();
+        let damage = target.damage;
 
-        self.shape_renderer
-            .write_all_clip_rects(render_ctx, &clip_rects);
+        // the damaged region is cleared before it's drawn over
+        if let Damage::Rect(_) = target.damage {
+            let viewport = Rect::from_min_size(
+                Pos::zero(),
+                target.size.cast_unit().map(|x| x as f32) * scale_fac.inverse().as_float(),
+            );
 
-        self.shape_renderer.write_all_transformations(
-            render_ctx,
-            &scene_transformations.transformations,
-            &scene_transformations.transformation_inverses,
+            frame.shapes.insert(0, damage::erase_shape(viewport));
+        }
+
+        self.shape_renderer.render_frame(
+            &mut Self::get_render_resources(
+                render_surface.rendering_context(),
+                &mut self.font_manager,
+                &self.atlas_manager,
+                &self.texture_manager,
+            ),
+            frame,
+            target,
         );
 
-        let mut texture_manager_lock = self.texture_manager.write().unwrap();
+        let FrameTimings {
+            tessellation,
+            buffer_upload,
+            gpu,
+            ..
+        } = self.shape_renderer.frame_timings();
 
-        let (texture_bind_group, sampler_bind_group) = {
-            (
-                // TODO: store these things
-                texture_manager_lock.generate_texture_bind_group(device),
-                texture_manager_lock.generate_sampler_bind_group(device),
-            )
-        };
+        self.timings.tessellation = tessellation;
+        self.timings.buffer_upload = buffer_upload;
+        self.timings.gpu = gpu;
 
-        for PaintShapeWithContext {
-            shape,
-            clip_rect_idx,
-            transformation_idx,
-        } in shapes
-        {
-            shape_buffer_local.clip_rect_idx = clip_rect_idx.unwrap_or_default();
-            shape_buffer_local.transformation_idx = transformation_idx.unwrap_or_default();
-
-            match shape {
-                ComputedPaintShape::Rectangle(paint_rect) => {
-                    shape_buffer_local.push_quads(
-                        BoxShaderVertex::from_paint_rect(&self.atlas_manager, paint_rect).0,
-                    );
-                }
-
-                ComputedPaintShape::Text(text_box) => {
-                    self.font_manager.prepare(text_box, &mut shape_buffer_local);
-                }
-
-                ComputedPaintShape::Mesh(mesh) => shape_buffer_local.push_vertices(
-                    mesh.vertices
-                        .into_iter()
-                        .map(|PaintMeshVertex { color, pos }| {
-                            BoxShaderVertex::mesh_tri(pos, color)
-                        }),
-                    mesh.indices,
-                ),
-            }
+        (input, platform_output, damage)
+    }
+
+    fn get_render_resources<'a>(
+        rendering_context: &'a RenderingContext,
+        font_manager: &'a mut FontManager,
+        atlas_manager: &'a TextureAtlasManagerRef,
+        texture_manager: &'a TextureManagerRef,
+    ) -> RenderResources<'a> {
+        RenderResources {
+            rendering_context,
+            font_manager,
+            atlas_manager,
+            texture_manager,
         }
+    }
 
-        self.shape_renderer
-            .write_all_shapes(queue, device, shape_buffer_local.vertex_buffers);
+    pub fn root_id(&self) -> ElementId {
+        self.root.id()
+    }
 
-        {
-            let load_op = wgpu::LoadOp::Clear(wgpu::Color {
-                r: 0.0,
-                g: 0.0,
-                b: 0.0,
-                a: 0.0,
-            });
-
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[Some(match &msaa_view {
-                    None => wgpu::RenderPassColorAttachment {
-                        view: &window_view,
-                        resolve_target: None,
-                        ops: wgpu::Operations {
-                            load: load_op,
-                            store: wgpu::StoreOp::Store,
-                        },
-                    },
-                    Some(msaa_view) => wgpu::RenderPassColorAttachment {
-                        view: msaa_view,
-                        resolve_target: Some(&window_view),
-                        ops: wgpu::Operations {
-                            load: load_op,
-                            store: wgpu::StoreOp::Discard,
-                        },
-                    },
-                })],
-                depth_stencil_attachment: None,
-                timestamp_writes: Default::default(),
-                occlusion_query_set: Default::default(),
-            });
+    pub fn root_access_node(&mut self) -> AccessNode {
+        self.root.get().node().build()
+    }
+}
 
-            render_pass.set_pipeline(&self.shape_renderer.shape_render_pipeline);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        accessibility::{AccessNodeBuilder, AccessRole},
+        color::ColorRgba,
+        scene::layout::{FlexBox, LayoutPassResult, Percent},
+        shape::PaintRectangle,
+        surface::read_texture_rgba8,
+    };
+
+    /// Fills the screen, and paints an opaque red rectangle which tests move
+    /// around, and a green one which stays put
+    struct TestRoot {
+        rect: Rect,
+    }
 
-            render_pass.set_bind_group(0, &self.shape_renderer.shape_bind_group, &[]);
-            render_pass.set_bind_group(1, &texture_bind_group, &[]);
-            render_pass.set_bind_group(2, &sampler_bind_group, &[]);
+    impl RootConstructor for TestRoot {
+        fn new(_resources: &mut SceneResources) -> Self {
+            Self {
+                rect: Rect::new(Pos::new(2., 2.), Pos::new(6., 6.)),
+            }
+        }
+    }
 
-            self.shape_renderer.render(&mut render_pass, 0..1);
+    impl Element for TestRoot {
+        fn layout(&mut self, layout_pass: &mut LayoutPass) -> LayoutPassResult {
+            layout_pass
+                .engine()
+                .new_leaf(FlexBox::builder().width(Percent(1.)).height(Percent(1.)))
+                .unwrap()
         }
 
-        drop(texture_manager_lock);
+        fn ui(&mut self, ctx: &mut SceneContext, _rect: Rect) {
+            ctx.add_shape(
+                PaintRectangle::from_rect(self.rect).with_fill(ColorRgba::new(1., 0., 0., 1.)),
+            );
 
-        // TODO: for multiple render passes, submit multiple encoders as
-        // iterator (??? might work, test performance)
-        queue.submit(std::iter::once(encoder.finish()));
+            ctx.add_shape(
+                PaintRectangle::from_rect(Rect::new(Pos::new(10., 10.), Pos::new(14., 14.)))
+                    .with_fill(ColorRgba::new(0., 1., 0., 1.)),
+            );
+        }
 
-        fp.check_missed_deadline(
-            I::now(time_context),
-            start_time.elapsed(time_context).into(),
-        );
+        fn node(&self) -> AccessNodeBuilder {
+            AccessNodeBuilder::new(AccessRole::GenericContainer)
+        }
+    }
 
-        // window_texture.present(&wgpu::PresentationDescriptor {
-        //     presentation_delay: wgpu::PresentationDelay::ScheduleTime(
-        //         fp.get_deadline().expect("Deadline has not been set!"),
-        //     ),
-        // });
+    /// Requests focus whenever it gets input
+    struct FocusedChild;
 
-        let approx_present_time = I::now(&time_context);
+    impl Element for FocusedChild {
+        fn layout(&mut self, layout_pass: &mut LayoutPass) -> LayoutPassResult {
+            layout_pass.engine().new_leaf(FlexBox::builder()).unwrap()
+        }
 
-        match (fp.desired_frame_time(), fp.desired_frame_instant()) {
-            (Some(desired_frame_time), _) => {
-                // window_texture.present(&wgpu::PresentationDescriptor {
-                //     presentation_delay: wgpu::PresentationDelay::ScheduleMinimumDuration(
-                //         desired_frame_time,
-                //     ),
-                // });
-                window_texture.present();
-            }
+        fn input(&mut self, input: &mut InputState, _rect: Rect) {
+            input.request_focus();
+        }
 
-            (_, Some(desired_instant)) => {
-                // window_texture.present(&wgpu::PresentationDescriptor {
-                //     presentation_delay: wgpu::PresentationDelay::ScheduleTime(desired_instant),
-                // });
-                window_texture.present();
-            }
+        fn ui(&mut self, _ctx: &mut SceneContext, _rect: Rect) {}
 
-            _ => {
-                // window_texture.present(&Default::default());
-                window_texture.present();
+        fn node(&self) -> AccessNodeBuilder {
+            AccessNodeBuilder::new(AccessRole::GenericContainer)
+        }
+    }
+
+    struct FocusRoot {
+        child: ElementRef<FocusedChild>,
+        focused_within: bool,
+    }
+
+    impl RootConstructor for FocusRoot {
+        fn new(_resources: &mut SceneResources) -> Self {
+            Self {
+                child: FocusedChild.into(),
+                focused_within: false,
             }
         }
+    }
 
-        self.font_manager.collect_garbage();
+    impl Element for FocusRoot {
+        fn layout(&mut self, layout_pass: &mut LayoutPass) -> LayoutPassResult {
+            layout_pass.layout_child(&mut self.child);
 
-        let render_time = start_time.elapsed(time_context);
+            layout_pass
+                .engine()
+                .new_leaf(FlexBox::builder().width(Percent(1.)).height(Percent(1.)))
+                .unwrap()
+        }
 
-        (input, platform_output, render_time, approx_present_time)
+        fn input(&mut self, input: &mut InputState, _rect: Rect) {
+            self.focused_within = input.focused_within();
+        }
+
+        fn ui(&mut self, _ctx: &mut SceneContext, _rect: Rect) {}
+
+        fn node(&self) -> AccessNodeBuilder {
+            AccessNodeBuilder::new(AccessRole::GenericContainer)
+        }
     }
 
-    pub fn root_id(&self) -> ElementId {
-        self.root.id()
+    /// The RGBA8 pixel at `x`, `y` of tightly packed rows
+    fn pixel(pixels: &[u8], width: u32, x: u32, y: u32) -> [u8; 4] {
+        let idx = ((y * width + x) * 4) as usize;
+        pixels[idx..idx + 4].try_into().unwrap()
     }
 
-    pub fn root_access_node(&mut self) -> AccessNode {
-        self.root.get().node().build()
+    #[test]
+    fn offscreen_frames_are_read_back() {
+        let Some(surface) = OffscreenSurface::for_tests(8, 8) else {
+            return;
+        };
+
+        let mut scene = Scene::<TestRoot>::new_offscreen(&surface);
+
+        let element_tree = scene.do_layout(&surface);
+        scene.render_offscreen(&surface, element_tree, Default::default());
+
+        let pixels = surface.read_pixels();
+
+        assert_eq!(pixels.len(), 8 * 8 * 4);
+        assert_eq!(pixel(&pixels, 8, 4, 4), [255, 0, 0, 255]);
+        assert_eq!(pixel(&pixels, 8, 0, 0), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn focus_within_includes_focus_moved_by_earlier_handlers() {
+        let Some(surface) = OffscreenSurface::for_tests(8, 8) else {
+            return;
+        };
+
+        let mut scene = Scene::<FocusRoot>::new_offscreen(&surface);
+
+        // the child gets input before its parent, and is focused in the same frame
+        let element_tree = scene.do_layout(&surface);
+        scene.render_offscreen(&surface, element_tree, Default::default());
+
+        assert!(scene.root.get().focused_within);
+    }
+
+    #[test]
+    fn window_frames_are_redrawn_through_the_back_buffer() {
+        let Some(surface) = OffscreenSurface::for_tests(16, 16) else {
+            return;
+        };
+
+        // stand-ins for the window's back buffer and surface texture
+        let create_texture = |usage| {
+            surface
+                .rendering_context()
+                .device
+                .create_texture(&wgpu::TextureDescriptor {
+                    label: None,
+                    size: wgpu::Extent3d {
+                        width: 16,
+                        height: 16,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: OffscreenSurface::TEXTURE_FORMAT,
+                    usage,
+                    view_formats: &[],
+                })
+        };
+
+        let back_buffer =
+            create_texture(wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC);
+        let surface_texture =
+            create_texture(wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::COPY_SRC);
+
+        let mut scene = Scene::<TestRoot>::new_offscreen(&surface);
+
+        let render = |scene: &mut Scene<TestRoot>, buffer_age| {
+            let element_tree = scene.do_layout(&surface);
+
+            let (_, _, damage) = scene.render_through_back_buffer(
+                &surface,
+                &back_buffer,
+                buffer_age,
+                &surface_texture,
+                element_tree,
+                Default::default(),
+            );
+
+            damage
+        };
+
+        assert_eq!(render(&mut scene, 0), Damage::Full);
+
+        scene.root.get().rect = Rect::new(Pos::new(2., 8.), Pos::new(6., 12.));
+        assert!(matches!(render(&mut scene, 1), Damage::Rect(_)));
+
+        let pixels = read_texture_rgba8(
+            surface.rendering_context(),
+            &surface_texture,
+            wgpu::Origin3d::ZERO,
+            16,
+            16,
+        );
+
+        // the rect moved, and was erased where it was before
+        assert_eq!(pixel(&pixels, 16, 4, 10), [255, 0, 0, 255]);
+        assert_eq!(pixel(&pixels, 16, 4, 4), [0, 0, 0, 0]);
+        // outside of the damage, the last frame was kept
+        assert_eq!(pixel(&pixels, 16, 12, 12), [0, 255, 0, 255]);
+
+        // nothing changed, so nothing is drawn or copied
+        assert_eq!(render(&mut scene, 1), Damage::Empty);
     }
 }
@@ -0,0 +1,181 @@
+use std::sync::{Arc, Mutex};
+
+use crate::{surface::RenderingContext, time::Duration};
+
+/// How long each phase of a frame took, see
+/// [`super::scene::Scene::frame_timings`]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FrameTimings {
+    pub layout: Duration,
+    pub input_pass: Duration,
+    pub ui_pass: Duration,
+    /// Building vertices for the painted shapes, including text
+    pub tessellation: Duration,
+    /// Rasterizing glyphs which aren't cached yet, on worker threads started
+    /// during the ui pass and tessellation
+    pub glyph_rasterization: Duration,
+    /// Writing vertices, clip rects, transformations and gradients to the GPU
+    pub buffer_upload: Duration,
+    /// GPU execution of the render passes, which lags a frame or two behind the
+    /// other timings. `None` if the adapter doesn't support timestamp queries.
+    pub gpu: Option<Duration>,
+}
+
+impl FrameTimings {
+    /// Sum of the CPU phases on the main thread
+    pub fn cpu_total(&self) -> Duration {
+        self.layout + self.input_pass + self.ui_pass + self.tessellation + self.buffer_upload
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ReadbackState {
+    Idle,
+    /// Timestamps were written this frame, and will be copied to the readback buffer
+    Writing,
+    Mapping,
+    Mapped,
+}
+
+/// Measures the GPU time from the start of a frame's first render pass to the
+/// end of its last one, with timestamp queries
+pub(crate) struct GpuTimer {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    state: Arc<Mutex<ReadbackState>>,
+    /// Nanoseconds per timestamp tick
+    period: f32,
+    last_duration: Option<Duration>,
+}
+
+impl GpuTimer {
+    const BUFFER_SIZE: u64 = 2 * std::mem::size_of::<u64>() as u64;
+
+    /// `None` if the device wasn't created with [`wgpu::Features::TIMESTAMP_QUERY`]
+    pub fn new(RenderingContext { device, queue, .. }: &RenderingContext) -> Option<Self> {
+        if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            log::debug!("timestamp queries are not supported, gpu timings are disabled");
+            return None;
+        }
+
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("frame timestamps"),
+            ty: wgpu::QueryType::Timestamp,
+            count: 2,
+        });
+
+        let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("frame timestamps resolve buffer"),
+            size: Self::BUFFER_SIZE,
+            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("frame timestamps readback buffer"),
+            size: Self::BUFFER_SIZE,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        Some(Self {
+            query_set,
+            resolve_buffer,
+            readback_buffer,
+            state: Arc::new(Mutex::new(ReadbackState::Idle)),
+            period: queue.get_timestamp_period(),
+            last_duration: None,
+        })
+    }
+
+    /// Reads back the timestamps of a previous frame if they're ready, and
+    /// prepares to write this frame's unless the readback buffer is still busy
+    pub fn begin_frame(&mut self, device: &wgpu::Device) {
+        let _ = device.poll(wgpu::PollType::Poll);
+
+        let mut state = self.state.lock().unwrap();
+
+        if *state == ReadbackState::Mapped {
+            let timestamps: [u64; 2] = {
+                let data = self.readback_buffer.slice(..).get_mapped_range();
+                bytemuck::pod_read_unaligned(&data)
+            };
+
+            self.readback_buffer.unmap();
+
+            let ticks = timestamps[1].saturating_sub(timestamps[0]);
+            self.last_duration = Some(Duration::from_nanos(
+                (ticks as f64 * self.period as f64) as u64,
+            ));
+
+            *state = ReadbackState::Idle;
+        }
+
+        if *state == ReadbackState::Idle {
+            *state = ReadbackState::Writing;
+        }
+    }
+
+    /// Timestamp writes for a render pass, if this frame is being measured
+    pub fn timestamp_writes(
+        &self,
+        is_first_pass: bool,
+        is_last_pass: bool,
+    ) -> Option<wgpu::RenderPassTimestampWrites<'_>> {
+        (*self.state.lock().unwrap() == ReadbackState::Writing).then(|| {
+            wgpu::RenderPassTimestampWrites {
+                query_set: &self.query_set,
+                beginning_of_pass_write_index: is_first_pass.then_some(0),
+                end_of_pass_write_index: is_last_pass.then_some(1),
+            }
+        })
+    }
+
+    /// Copies this frame's timestamps into the readback buffer, after the last
+    /// render pass
+    pub fn resolve(&self, encoder: &mut wgpu::CommandEncoder) {
+        if *self.state.lock().unwrap() != ReadbackState::Writing {
+            return;
+        }
+
+        encoder.resolve_query_set(&self.query_set, 0..2, &self.resolve_buffer, 0);
+        encoder.copy_buffer_to_buffer(
+            &self.resolve_buffer,
+            0,
+            &self.readback_buffer,
+            0,
+            Self::BUFFER_SIZE,
+        );
+    }
+
+    /// Starts mapping the readback buffer, after the frame was submitted
+    pub fn end_frame(&self) {
+        {
+            let mut state = self.state.lock().unwrap();
+
+            if *state != ReadbackState::Writing {
+                return;
+            }
+
+            *state = ReadbackState::Mapping;
+        }
+
+        let callback_state = self.state.clone();
+
+        self.readback_buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                *callback_state.lock().unwrap() = match result {
+                    Ok(()) => ReadbackState::Mapped,
+                    Err(_) => ReadbackState::Idle,
+                };
+            });
+    }
+
+    /// The GPU time of the most recent frame which has been read back
+    #[inline(always)]
+    pub fn last_duration(&self) -> Option<Duration> {
+        self.last_duration
+    }
+}
@@ -2,28 +2,44 @@ use serde::Serialize;
 
 use crate::{
     atlas::{
-        AtlasAllocation, AtlasAllocationId, HasAtlasAllocationId, TextureAtlas,
+        AtlasAllocation, AtlasAllocationId, FontManager, HasAtlasAllocationId, TextureAtlas,
         TextureAtlasManager, TextureAtlasManagerRef,
     },
     color::{ColorRgba, ColorSrgba},
-    graphics::{DynamicGPUBuffer, DynamicGPUMeshTriBuffer, Mesh, PushVertices, VertexBuffers},
+    debug_panic,
+    element::ElementId,
+    gradient::{PaintGradient, PushGradients, ShaderGradient},
+    graphics::{
+        DynamicGPUBuffer, DynamicGPUMeshTriBuffer, DynamicGPUQuadInstanceBuffer, Mesh,
+        PushVertices, RangeAllocator, VertexBuffers,
+    },
+    layer::{BlendMode, FrameLayer, LayerCommand, LayerCommands, LayerCompositor},
     math::{
         CompleteScaleFactor, CoordinateTransform, PhysicalPos, PhysicalRect, PhysicalSize, Pos,
-        RoundedRect, ScaleFactor, Size, Vector,
+        RoundedRect, ScaleFactor, Size, TransformationList, Vector,
+    },
+    path::PaintPath,
+    scene::{
+        ctx::PaintShapeWithContext,
+        damage::Damage,
+        renderer::{FramePaint, RenderResources, Renderer},
+        timings::{FrameTimings, GpuTimer},
     },
     surface::ParamsBuffer,
-    texture::{TextureManagerRef, TextureRef},
+    texture::{PushTextures, TextureManagerRef, TextureRef},
+    time::{Duration, Instant},
     util::{
         guard::ReadLockable,
         svg::PosVertexBuffers,
         template::{HandlebarsTemplater, Templater},
-        text::{AtlasContentType, PlacedTextBox, TextBox, TextBoxLike},
+        text::{AtlasContentType, AtlasGlyphKey, PlacedTextBox, TextBox, TextBoxLike},
         PhysicalUnit,
     },
 };
 
 use std::{
     borrow::Borrow,
+    collections::HashMap,
     fmt::Debug,
     marker::PhantomData,
     num::NonZeroU64,
@@ -31,6 +47,7 @@ use std::{
     sync::{atomic::Ordering, Arc, RwLock},
 };
 
+use itertools::Itertools;
 use num_traits::{Float, Num};
 use wgpu::{BufferUsages, ShaderModuleDescriptor};
 
@@ -42,19 +59,46 @@ use crate::{
 
 pub struct ShapeRenderer {
     pub shape_bind_group_layout: wgpu::BindGroupLayout,
-    pub shape_render_pipeline: wgpu::RenderPipeline,
+    /// One pipeline for each [`BlendMode`]
+    pub shape_render_pipelines: Vec<(BlendMode, wgpu::RenderPipeline)>,
+    /// Like `shape_render_pipelines`, for instanced rectangles
+    pub rect_render_pipelines: Vec<(BlendMode, wgpu::RenderPipeline)>,
     pub shape_bind_group: wgpu::BindGroup,
 
     // shader storage
     clip_rects: DynamicGPUBuffer<ShaderClipRect>,
     transformations: DynamicGPUBuffer<CoordinateTransform>,
     transformation_inversions: DynamicGPUBuffer<CoordinateTransform>,
+    gradients: DynamicGPUBuffer<ShaderGradient>,
 
     // vertex buffers
     shape_buffer: DynamicGPUMeshTriBuffer<BoxShaderVertex>,
+    rect_buffer: DynamicGPUQuadInstanceBuffer<BoxShaderRectInstance>,
+
+    /// What was built from each run of shapes, at the same ranges of the
+    /// buffers across frames
+    segments: HashMap<SegmentKey, RetainedSegment>,
+    segment_allocators: SegmentAllocators,
+    bound_textures: BoundTextures,
+    /// Whether the buffers hold every segment at its ranges, which they don't
+    /// after a frame which sampled too many textures to be drawn from them
+    segments_uploaded: bool,
+
+    layer_compositor: LayerCompositor,
+
+    gpu_timer: Option<GpuTimer>,
+    tessellation_time: Duration,
+    buffer_upload_time: Duration,
 }
 
 impl ShapeRenderer {
+    const CLEAR_OP: wgpu::LoadOp<wgpu::Color> = wgpu::LoadOp::Clear(wgpu::Color {
+        r: 0.0,
+        g: 0.0,
+        b: 0.0,
+        a: 0.0,
+    });
+
     pub fn new(rendering_context: &RenderingContext, texture_manager: &TextureManagerRef) -> Self {
         let RenderingContext {
             params_buffer,
@@ -111,6 +155,16 @@ impl ShapeRenderer {
                         },
                         visibility: wgpu::ShaderStages::FRAGMENT,
                     },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 4,
+                        count: None,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: true },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                    },
                 ],
             });
 
@@ -147,43 +201,73 @@ impl ShapeRenderer {
             })
         };
 
-        let shape_render_pipeline =
-            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                label: Some("box render pipeline"),
-                layout: Some(&shape_render_pipeline_layout),
-                vertex: wgpu::VertexState {
-                    module: &shape_shader_module,
-                    entry_point: "vs_main".into(),
-                    buffers: &[BoxShaderVertex::desc()],
-                    compilation_options: Default::default(),
-                },
-                fragment: Some(wgpu::FragmentState {
-                    module: &shape_shader_module,
-                    entry_point: "fs_main".into(),
-                    targets: &[Some(wgpu::ColorTargetState {
-                        format: *texture_format,
-                        blend: Some(wgpu::BlendState::ALPHA_BLENDING),
-                        write_mask: wgpu::ColorWrites::ALL,
-                    })],
-                    compilation_options: Default::default(),
-                }),
-                primitive: wgpu::PrimitiveState {
-                    topology: wgpu::PrimitiveTopology::TriangleList,
-                    strip_index_format: None,
-                    front_face: wgpu::FrontFace::Ccw,
-                    polygon_mode: wgpu::PolygonMode::Fill,
-                    unclipped_depth: false,
-                    conservative: false,
-                    cull_mode: None,
-                },
-                depth_stencil: None,
-                multisample: wgpu::MultisampleState {
-                    count: texture_info.get_num_samples(),
-                    ..Default::default()
-                },
-                multiview: None,
-                cache: None,
-            });
+        // the shader outputs premultiplied alpha
+        let create_pipeline =
+            |blend_mode: BlendMode, entry_point: &str, buffers: &[wgpu::VertexBufferLayout]| {
+                device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                    label: Some("box render pipeline"),
+                    layout: Some(&shape_render_pipeline_layout),
+                    vertex: wgpu::VertexState {
+                        module: &shape_shader_module,
+                        entry_point: Some(entry_point),
+                        buffers,
+                        compilation_options: Default::default(),
+                    },
+                    fragment: Some(wgpu::FragmentState {
+                        module: &shape_shader_module,
+                        entry_point: "fs_main".into(),
+                        targets: &[Some(wgpu::ColorTargetState {
+                            format: *texture_format,
+                            blend: Some(blend_mode.premultiplied_blend_state()),
+                            write_mask: wgpu::ColorWrites::ALL,
+                        })],
+                        compilation_options: Default::default(),
+                    }),
+                    primitive: wgpu::PrimitiveState {
+                        topology: wgpu::PrimitiveTopology::TriangleList,
+                        strip_index_format: None,
+                        front_face: wgpu::FrontFace::Ccw,
+                        polygon_mode: wgpu::PolygonMode::Fill,
+                        unclipped_depth: false,
+                        conservative: false,
+                        cull_mode: None,
+                    },
+                    depth_stencil: None,
+                    multisample: wgpu::MultisampleState {
+                        count: texture_info.get_num_samples(),
+                        ..Default::default()
+                    },
+                    multiview: None,
+                    cache: None,
+                })
+            };
+
+        let shape_render_pipelines = BlendMode::ALL
+            .into_iter()
+            .map(|blend_mode| {
+                (
+                    blend_mode,
+                    create_pipeline(blend_mode, "vs_main", &[BoxShaderVertex::desc()]),
+                )
+            })
+            .collect();
+
+        let rect_render_pipelines = BlendMode::ALL
+            .into_iter()
+            .map(|blend_mode| {
+                (
+                    blend_mode,
+                    create_pipeline(
+                        blend_mode,
+                        "vs_rect",
+                        &[
+                            DynamicGPUQuadInstanceBuffer::<BoxShaderRectInstance>::quad_desc(),
+                            BoxShaderRectInstance::desc(),
+                        ],
+                    ),
+                )
+            })
+            .collect();
 
         let shader_storage_caps =
             BufferUsages::VERTEX | BufferUsages::STORAGE | BufferUsages::COPY_DST;
@@ -191,6 +275,7 @@ impl ShapeRenderer {
         let clip_rects = DynamicGPUBuffer::new(device, 4, shader_storage_caps);
         let transformations = DynamicGPUBuffer::new(device, 4, shader_storage_caps);
         let transformation_inversions = DynamicGPUBuffer::new(device, 4, shader_storage_caps);
+        let gradients = DynamicGPUBuffer::new(device, 4, shader_storage_caps);
 
         let shape_bind_group = Self::create_bind_group(
             device,
@@ -199,20 +284,36 @@ impl ShapeRenderer {
             &clip_rects.buffer,
             &transformations.buffer,
             &transformation_inversions.buffer,
+            &gradients.buffer,
         );
 
         let shape_buffer = DynamicGPUMeshTriBuffer::new(device);
+        let rect_buffer = DynamicGPUQuadInstanceBuffer::new(device);
 
         Self {
             shape_bind_group,
             shape_bind_group_layout,
-            shape_render_pipeline,
+            shape_render_pipelines,
+            rect_render_pipelines,
 
             clip_rects,
             transformations,
             transformation_inversions,
+            gradients,
 
             shape_buffer,
+            rect_buffer,
+
+            segments: Default::default(),
+            segment_allocators: Default::default(),
+            bound_textures: Default::default(),
+            segments_uploaded: false,
+
+            layer_compositor: LayerCompositor::new(rendering_context),
+
+            gpu_timer: GpuTimer::new(rendering_context),
+            tessellation_time: Default::default(),
+            buffer_upload_time: Default::default(),
         }
     }
 
@@ -223,6 +324,7 @@ impl ShapeRenderer {
         clip_rects_buffer: &wgpu::Buffer,
         transformations_buffer: &wgpu::Buffer,
         transformation_inversions_buffer: &wgpu::Buffer,
+        gradients_buffer: &wgpu::Buffer,
     ) -> wgpu::BindGroup {
         device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("box bind group"),
@@ -244,6 +346,10 @@ impl ShapeRenderer {
                     binding: 3,
                     resource: transformation_inversions_buffer.as_entire_binding(),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: gradients_buffer.as_entire_binding(),
+                },
             ],
         })
     }
@@ -282,6 +388,7 @@ impl ShapeRenderer {
                 &self.clip_rects.buffer,
                 &self.transformations.buffer,
                 &self.transformation_inversions.buffer,
+                &self.gradients.buffer,
             );
         }
     }
@@ -297,15 +404,53 @@ impl ShapeRenderer {
         transformations: &[CoordinateTransform],
         transformation_inversions: &[CoordinateTransform],
     ) {
-        if self
-            .transformations
-            .write(device, queue, transformations, transformations.len() as u64)
-            || self.transformation_inversions.write(
+        // both are written, even if the first one was reallocated
+        let reallocated = self.transformations.write(
+            device,
+            queue,
+            transformations,
+            transformations.len() as u64,
+        );
+
+        let inversions_reallocated = self.transformation_inversions.write(
+            device,
+            queue,
+            transformation_inversions,
+            transformation_inversions.len() as u64,
+        );
+
+        if reallocated || inversions_reallocated {
+            self.shape_bind_group = Self::create_bind_group(
                 device,
-                queue,
-                transformations,
-                transformations.len() as u64,
-            )
+                &self.shape_bind_group_layout,
+                params_buffer,
+                &self.clip_rects.buffer,
+                &self.transformations.buffer,
+                &self.transformation_inversions.buffer,
+                &self.gradients.buffer,
+            );
+        }
+    }
+
+    pub fn write_all_gradients(
+        &mut self,
+        RenderingContext {
+            device,
+            queue,
+            params_buffer,
+            ..
+        }: &RenderingContext,
+        gradients: &[ShaderGradient],
+    ) {
+        // bindings can't be empty, so always upload at least one gradient
+        let gradients = match gradients {
+            [] => &[ShaderGradient::default()][..],
+            gradients => gradients,
+        };
+
+        if self
+            .gradients
+            .write(device, queue, gradients, gradients.len() as u64)
         {
             self.shape_bind_group = Self::create_bind_group(
                 device,
@@ -314,8 +459,589 @@ impl ShapeRenderer {
                 &self.clip_rects.buffer,
                 &self.transformations.buffer,
                 &self.transformation_inversions.buffer,
+                &self.gradients.buffer,
+            );
+        }
+    }
+
+    /// Grows the gradient buffer to fit `num_gradients`, returning whether it was
+    /// reallocated
+    fn reserve_gradients(
+        &mut self,
+        RenderingContext {
+            device,
+            params_buffer,
+            ..
+        }: &RenderingContext,
+        num_gradients: u32,
+    ) -> bool {
+        // bindings can't be empty, so there's always at least one gradient
+        let reallocated = self.gradients.reserve(device, num_gradients.max(1) as u64);
+
+        if reallocated {
+            self.shape_bind_group = Self::create_bind_group(
+                device,
+                &self.shape_bind_group_layout,
+                params_buffer,
+                &self.clip_rects.buffer,
+                &self.transformations.buffer,
+                &self.transformation_inversions.buffer,
+                &self.gradients.buffer,
+            );
+        }
+
+        reallocated
+    }
+
+    /// Uploads the segments which were built this frame, or all of them if the
+    /// buffers don't hold them
+    fn upload_segments(&mut self, render_ctx: &RenderingContext) {
+        let RenderingContext { device, queue, .. } = render_ctx;
+
+        let num_vertices = self.segment_allocators.vertices.end() as u64;
+        let num_indices = self.segment_allocators.indices.end() as u64;
+        let num_instances = self.segment_allocators.instances.end() as u64;
+        let num_gradients = self.segment_allocators.gradients.end();
+
+        // reallocated buffers are empty
+        let shapes_reallocated = self.shape_buffer.reserve(device, num_vertices, num_indices);
+        let rects_reallocated = self.rect_buffer.reserve(device, num_instances);
+        let gradients_reallocated = self.reserve_gradients(render_ctx, num_gradients);
+
+        let upload_all = shapes_reallocated
+            || rects_reallocated
+            || gradients_reallocated
+            || !std::mem::replace(&mut self.segments_uploaded, true);
+
+        for segment in self.segments.values_mut() {
+            if upload_all || segment.needs_upload {
+                segment.upload(
+                    queue,
+                    &self.shape_buffer,
+                    &self.rect_buffer,
+                    &self.gradients,
+                );
+                segment.needs_upload = false;
+            }
+        }
+    }
+
+    /// Builds segments from runs of shapes which changed, reusing the rest, and
+    /// returns the segments in paint order with their layer and blend mode
+    fn build_segments(
+        &mut self,
+        shapes: Vec<PaintShapeWithContext>,
+        element_generations: &HashMap<ElementId, u64>,
+        atlas_manager: &TextureAtlasManagerRef,
+        font_manager: &mut FontManager,
+    ) -> Vec<(SegmentKey, Option<u32>, BlendMode)> {
+        let atlas_generation = atlas_manager.read().unwrap().generation();
+
+        let mut segments = HashMap::with_capacity(self.segments.len());
+        let mut segment_order = Vec::new();
+        // runs and shapes painted by each element so far
+        let mut painted = HashMap::<Option<ElementId>, (u32, u32)>::new();
+
+        for run in split_element_runs(shapes) {
+            let element_id = run[0].element_id;
+            let layer_idx = run[0].layer_idx;
+            let blend_mode = run[0].blend_mode;
+
+            let (num_runs, num_shapes) = painted.entry(element_id).or_default();
+            let key = SegmentKey {
+                element_id,
+                run: *num_runs,
+            };
+            let shape_range = *num_shapes..*num_shapes + run.len() as u32;
+
+            *num_runs += 1;
+            *num_shapes = shape_range.end;
+
+            let generation = element_id.and_then(|id| element_generations.get(&id).copied());
+
+            let clip_rect_idx = run[0].clip_rect_idx.unwrap_or_default();
+            let transformation_idx = run[0].transformation_idx.unwrap_or_default();
+
+            let segment = match self.segments.remove(&key) {
+                Some(mut segment)
+                    if segment.is_current(
+                        generation,
+                        &shape_range,
+                        &atlas_manager.read().unwrap(),
+                    ) =>
+                {
+                    if !segment.glyphs.is_empty() {
+                        atlas_manager
+                            .write()
+                            .unwrap()
+                            .mark_glyphs_used(&segment.glyphs);
+                    }
+
+                    // an earlier element added or removed a clip rect or
+                    // transformation, so the element's moved to another index
+                    if (segment.buffer.clip_rect_idx, segment.buffer.transformation_idx)
+                        != (clip_rect_idx, transformation_idx)
+                    {
+                        segment
+                            .buffer
+                            .set_context_indices(clip_rect_idx, transformation_idx);
+                        segment.needs_upload = true;
+                    }
+
+                    segment
+                }
+                previous => {
+                    let mut segment = RetainedSegment::build(
+                        run,
+                        generation,
+                        shape_range,
+                        atlas_generation,
+                        atlas_manager,
+                        font_manager,
+                    );
+
+                    segment.texture_slots = segment
+                        .buffer
+                        .textures
+                        .iter()
+                        .map(|texture| self.bound_textures.acquire(texture))
+                        .collect();
+
+                    // the previous segment's ranges are kept if they still fit
+                    if let Some(previous) = previous {
+                        segment.ranges = previous.ranges;
+                        self.bound_textures.release(&previous.texture_slots);
+                    }
+
+                    self.segment_allocators
+                        .fit(&mut segment.ranges, &segment.buffer);
+
+                    segment
+                }
+            };
+
+            segments.insert(key, segment);
+            segment_order.push((key, layer_idx, blend_mode));
+        }
+
+        // segments which weren't painted this frame are freed
+        for (_, segment) in std::mem::replace(&mut self.segments, segments) {
+            self.segment_allocators.free(segment.ranges);
+            self.bound_textures.release(&segment.texture_slots);
+        }
+
+        if self.segment_allocators.is_fragmented() {
+            self.segment_allocators.clear();
+
+            for segment in self.segments.values_mut() {
+                segment.ranges = Default::default();
+                self.segment_allocators
+                    .fit(&mut segment.ranges, &segment.buffer);
+            }
+
+            self.segments_uploaded = false;
+        }
+
+        segment_order
+    }
+
+    /// Timings of the last frame's tessellation, buffer upload and GPU phases
+    pub fn frame_timings(&self) -> FrameTimings {
+        FrameTimings {
+            tessellation: self.tessellation_time,
+            buffer_upload: self.buffer_upload_time,
+            gpu: self.gpu_timer.as_ref().and_then(GpuTimer::last_duration),
+            ..Default::default()
+        }
+    }
+
+    /// Begins a pass which clears `view` (or `msaa_view`, resolving into `view`)
+    fn begin_render_pass<'e>(
+        encoder: &'e mut wgpu::CommandEncoder,
+        label: &str,
+        view: &wgpu::TextureView,
+        msaa_view: Option<&wgpu::TextureView>,
+        load_op: wgpu::LoadOp<wgpu::Color>,
+        timestamp_writes: Option<wgpu::RenderPassTimestampWrites>,
+    ) -> wgpu::RenderPass<'e> {
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(label),
+            color_attachments: &[Some(match msaa_view {
+                None => wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: load_op,
+                        store: wgpu::StoreOp::Store,
+                    },
+                },
+                Some(msaa_view) => wgpu::RenderPassColorAttachment {
+                    view: msaa_view,
+                    resolve_target: Some(view),
+                    ops: wgpu::Operations {
+                        load: load_op,
+                        store: wgpu::StoreOp::Discard,
+                    },
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes,
+            occlusion_query_set: Default::default(),
+        })
+    }
+
+    fn bind_shape_pipeline(
+        &self,
+        render_pass: &mut wgpu::RenderPass<'_>,
+        blend_mode: BlendMode,
+        kind: ShapeDrawKind,
+        sampler_bind_group: &wgpu::BindGroup,
+    ) {
+        let pipelines = match kind {
+            ShapeDrawKind::Indexed => &self.shape_render_pipelines,
+            ShapeDrawKind::Rects => &self.rect_render_pipelines,
+        };
+
+        let (_, pipeline) = pipelines
+            .iter()
+            .find(|(mode, _)| *mode == blend_mode)
+            .unwrap();
+
+        render_pass.set_pipeline(pipeline);
+
+        render_pass.set_bind_group(0, &self.shape_bind_group, &[]);
+        render_pass.set_bind_group(2, sampler_bind_group, &[]);
+
+        match kind {
+            ShapeDrawKind::Indexed => self.shape_buffer.bind(render_pass),
+            ShapeDrawKind::Rects => self.rect_buffer.bind(render_pass),
+        }
+    }
+
+    fn draw_layer_commands(
+        &self,
+        render_pass: &mut wgpu::RenderPass<'_>,
+        layers: &[FrameLayer],
+        commands: &[LayerCommand],
+        draws: &[ShapeDraw],
+        texture_bind_groups: &[(DrawPosition, wgpu::BindGroup)],
+        sampler_bind_group: &wgpu::BindGroup,
+    ) {
+        // `None` if the shape pipeline isn't bound
+        let mut bound_pipeline = None;
+        let mut bound_batch = None;
+
+        for command in commands {
+            match command {
+                LayerCommand::Draw(draw_range, blend_mode) => {
+                    for ShapeDraw {
+                        kind,
+                        range,
+                        base_vertex,
+                    } in &draws[draw_range.start as usize..draw_range.end as usize]
+                    {
+                        if bound_pipeline != Some((*blend_mode, *kind)) {
+                            self.bind_shape_pipeline(
+                                render_pass,
+                                *blend_mode,
+                                *kind,
+                                sampler_bind_group,
+                            );
+                            bound_pipeline = Some((*blend_mode, *kind));
+                            bound_batch = None;
+                        }
+
+                        let first_batch = texture_bind_groups
+                            .partition_point(|(start, _)| start.get(*kind) <= range.start)
+                            .saturating_sub(1);
+
+                        // split the draw at the start of each texture batch it overlaps
+                        for (batch_idx, (start, bind_group)) in
+                            texture_bind_groups.iter().enumerate().skip(first_batch)
+                        {
+                            let end = texture_bind_groups
+                                .get(batch_idx + 1)
+                                .map_or(range.end, |(next, _)| next.get(*kind).min(range.end));
+                            let start = start.get(*kind).max(range.start);
+
+                            if start >= range.end {
+                                break;
+                            }
+
+                            if start >= end {
+                                continue;
+                            }
+
+                            if bound_batch != Some(batch_idx) {
+                                render_pass.set_bind_group(1, bind_group, &[]);
+                                bound_batch = Some(batch_idx);
+                            }
+
+                            match kind {
+                                ShapeDrawKind::Indexed => self.shape_buffer.draw(
+                                    render_pass,
+                                    start..end,
+                                    *base_vertex,
+                                    0..1,
+                                ),
+                                ShapeDrawKind::Rects => {
+                                    self.rect_buffer.draw(render_pass, start..end)
+                                }
+                            }
+                        }
+                    }
+                }
+
+                LayerCommand::Composite(layer_idx) => {
+                    let blend_mode = layers[*layer_idx as usize].layer.blend_mode;
+                    self.layer_compositor
+                        .composite(render_pass, *layer_idx, blend_mode);
+
+                    bound_pipeline = None;
+                }
+            }
+        }
+    }
+}
+
+/// Texture views a [`ShapeRenderer`] draws a frame into
+pub struct WgpuRenderTarget<'a> {
+    pub view: &'a wgpu::TextureView,
+    /// Multisampled view, resolved into `view`
+    pub msaa_view: Option<&'a wgpu::TextureView>,
+    pub size: PhysicalSize<u32>,
+    /// Pixels of `view` to redraw, the rest are kept from an earlier frame
+    pub damage: Damage,
+    /// The texture of `view`, and one it's copied to once drawn, e.g. a back
+    /// buffer and the surface texture it's presented with. Not copied if the
+    /// frame didn't change.
+    pub copy_to: Option<(&'a wgpu::Texture, &'a wgpu::Texture)>,
+}
+
+impl Renderer for ShapeRenderer {
+    type Target<'a> = WgpuRenderTarget<'a>;
+
+    fn render_frame(
+        &mut self,
+        RenderResources {
+            rendering_context: render_ctx,
+            font_manager,
+            atlas_manager,
+            texture_manager,
+        }: &mut RenderResources,
+        FramePaint {
+            shapes,
+            clip_rects,
+            transformations,
+            transformation_inverses,
+            layers,
+            custom_renders,
+            element_generations,
+        }: FramePaint,
+        WgpuRenderTarget {
+            view,
+            msaa_view,
+            size,
+            damage,
+            copy_to,
+        }: WgpuRenderTarget,
+    ) {
+        let RenderingContext { device, queue, .. } = *render_ctx;
+
+        if let Some(gpu_timer) = &mut self.gpu_timer {
+            gpu_timer.begin_frame(device);
+        }
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Render Encoder"),
+        });
+
+        for custom_render in &custom_renders {
+            custom_render.render(render_ctx, &mut encoder);
+        }
+
+        let upload_start = Instant::now();
+
+        self.write_all_clip_rects(render_ctx, &clip_rects);
+
+        self.write_all_transformations(render_ctx, &transformations, &transformation_inverses);
+
+        self.buffer_upload_time = upload_start.elapsed();
+
+        let tessellation_start = Instant::now();
+
+        let segment_order =
+            self.build_segments(shapes, &element_generations, atlas_manager, font_manager);
+
+        self.tessellation_time = tessellation_start.elapsed();
+
+        // locked after building segments, since preparing text can create atlases
+        let texture_manager_lock = texture_manager.read().unwrap();
+        let max_textures = texture_manager_lock.get_max_textures();
+
+        // TODO: store this
+        let sampler_bind_group = texture_manager_lock.generate_sampler_bind_group(device);
+
+        let upload_start = Instant::now();
+
+        let mut layer_commands = LayerCommands::new(layers.len());
+        let mut draws = Vec::new();
+
+        let fits_bind_group = self.bound_textures.num_slots() <= max_textures as usize;
+
+        let texture_batches = if fits_bind_group && self.segment_allocators.gradients_indexable() {
+            for (key, layer_idx, blend_mode) in segment_order {
+                let draws_start = draws.len() as u32;
+                draws.extend(self.segments[&key].draws());
+
+                layer_commands.push_draw(
+                    &layers,
+                    layer_idx,
+                    draws_start..draws.len() as u32,
+                    blend_mode,
+                );
+            }
+
+            self.upload_segments(render_ctx);
+
+            vec![TextureBatch {
+                start: Default::default(),
+                textures: self.bound_textures.textures(),
+            }]
+        } else {
+            // too many textures to bind at once, or gradients placed past what
+            // vertices can index, so the segments are copied in paint order and
+            // drawn in batches
+            let mut batched = ShapeBufferWithContext::new();
+
+            for (key, layer_idx, blend_mode) in segment_order {
+                let draws_start = batched.draws.len() as u32;
+                batched.extend(&self.segments[&key].buffer);
+
+                layer_commands.push_draw(
+                    &layers,
+                    layer_idx,
+                    draws_start..batched.draws.len() as u32,
+                    blend_mode,
+                );
+            }
+
+            let texture_batches = batched.split_texture_batches(max_textures);
+
+            self.write_all_gradients(render_ctx, &batched.gradients);
+            self.write_all_shapes(queue, device, batched.vertex_buffers);
+            self.rect_buffer.write_all(queue, device, batched.instances);
+            self.segments_uploaded = false;
+
+            draws = batched.draws;
+
+            texture_batches
+        };
+
+        self.buffer_upload_time += upload_start.elapsed();
+
+        let texture_bind_groups = texture_batches
+            .into_iter()
+            .map(|TextureBatch { start, textures }| {
+                (
+                    start,
+                    texture_manager_lock.create_texture_bind_group(device, &textures),
+                )
+            })
+            .collect_vec();
+
+        self.layer_compositor.prepare(render_ctx, size, &layers);
+
+        let timestamp_writes = |is_first_pass, is_last_pass| {
+            self.gpu_timer
+                .as_ref()
+                .and_then(|gpu_timer| gpu_timer.timestamp_writes(is_first_pass, is_last_pass))
+        };
+
+        let mut is_first_pass = true;
+
+        // children are rendered before they're composited into their parents
+        let layers_to_render = layer_commands
+            .layers_to_render()
+            .filter(|_| damage != Damage::Empty);
+
+        for layer_idx in layers_to_render {
+            let (layer_view, layer_msaa_view) = self.layer_compositor.target_views(layer_idx);
+
+            let mut render_pass = Self::begin_render_pass(
+                &mut encoder,
+                "Layer Render Pass",
+                layer_view,
+                layer_msaa_view,
+                Self::CLEAR_OP,
+                timestamp_writes(std::mem::take(&mut is_first_pass), false),
+            );
+
+            self.draw_layer_commands(
+                &mut render_pass,
+                &layers,
+                layer_commands.get(Some(layer_idx)),
+                &draws,
+                &texture_bind_groups,
+                &sampler_bind_group,
+            );
+        }
+
+        {
+            // multisampled targets are discarded after they're resolved, so
+            // resolving them without drawing would overwrite the frame
+            let msaa_view = msaa_view.filter(|_| damage != Damage::Empty);
+
+            let mut render_pass = Self::begin_render_pass(
+                &mut encoder,
+                "Render Pass",
+                view,
+                msaa_view,
+                match damage {
+                    Damage::Full => Self::CLEAR_OP,
+                    _ => wgpu::LoadOp::Load,
+                },
+                timestamp_writes(is_first_pass, true),
+            );
+
+            if let Damage::Rect(rect) = damage {
+                render_pass.set_scissor_rect(rect.min.x, rect.min.y, rect.width(), rect.height());
+            }
+
+            // the pass still runs when nothing changed, so that timestamps are written
+            if damage != Damage::Empty {
+                self.draw_layer_commands(
+                    &mut render_pass,
+                    &layers,
+                    layer_commands.get(None),
+                    &draws,
+                    &texture_bind_groups,
+                    &sampler_bind_group,
+                );
+            }
+        }
+
+        drop(texture_manager_lock);
+
+        if let Some((source, destination)) = copy_to.filter(|_| damage != Damage::Empty) {
+            encoder.copy_texture_to_texture(
+                source.as_image_copy(),
+                destination.as_image_copy(),
+                source.size(),
             );
         }
+
+        if let Some(gpu_timer) = &self.gpu_timer {
+            gpu_timer.resolve(&mut encoder);
+        }
+
+        // TODO: for multiple render passes, submit multiple encoders as
+        // iterator (??? might work, test performance)
+        queue.submit(std::iter::once(encoder.finish()));
+
+        if let Some(gpu_timer) = &self.gpu_timer {
+            gpu_timer.end_frame();
+        }
     }
 }
 
@@ -325,6 +1051,8 @@ pub enum ShapeType {
     Rectangle = 0,
     #[default]
     Mesh = 1,
+    /// Glyph whose mask is a signed distance field
+    SdfGlyph = 2,
 }
 
 unsafe impl bytemuck::Zeroable for ShapeType {}
@@ -338,6 +1066,8 @@ pub enum FillMode {
     Texture,
     TextureMaskColor,
     TextureMaskTexture,
+    Gradient,
+    TextureMaskGradient,
 }
 
 unsafe impl bytemuck::Zeroable for FillMode {}
@@ -417,6 +1147,68 @@ impl WgpuDescriptor<15> for BoxShaderVertex {
     ];
 }
 
+/// A rectangle drawn as an instance of a shared unit quad, rather than as four
+/// [`BoxShaderVertex`]s
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct BoxShaderRectInstance {
+    fill_mode: FillMode,
+
+    /// min and max corners
+    rect: [f32; 4],
+
+    uv: [f32; 4],
+    uv_alt: [f32; 4],
+    atlas_idx_alt: u16,
+    atlas_idx: u16,
+
+    color: [f32; 4],
+
+    rounding: f32,
+    stroke_width: f32,
+    blur_radius: f32,
+
+    clip_rect_idx: u32,
+
+    transform_idx: u32,
+}
+
+impl WgpuDescriptor<11> for BoxShaderRectInstance {
+    // location 0 is the unit quad's corner
+    const ATTRIBS: [wgpu::VertexAttribute; 11] = wgpu::vertex_attr_array![
+        // fill_mode
+        1 => Uint32,
+
+        // rect
+        2 => Float32x4,
+
+        // uv
+        3 => Float32x4,
+        // uv_alt
+        4 => Float32x4,
+        // atlas_idx
+        5 => Uint32,
+
+        // color
+        6 => Float32x4,
+
+        // rounding
+        7 => Float32,
+        // stroke_width
+        8 => Float32,
+        // blur_radius
+        9 => Float32,
+
+        // clip_rect_idx
+        10 => Uint32,
+
+        // transform_idx
+        11 => Uint32,
+    ];
+
+    const STEP_MODE: wgpu::VertexStepMode = wgpu::VertexStepMode::Instance;
+}
+
 #[derive(Default)]
 struct BoxShaderVertexFill {
     color: [f32; 4],
@@ -431,19 +1223,20 @@ impl BoxShaderVertexFill {
     #[inline]
     fn new(
         atlas_manager: impl ReadLockable<TextureAtlasManager>,
+        output: &mut (impl PushGradients + PushTextures),
         fill: PaintFill,
         main_texture: Option<(&TextureRef, PhysicalRect, AtlasContentType)>,
         uv_mask: Option<Rect>,
     ) -> Self {
         let mut res = Self::default();
 
-        let main_texture = main_texture.map(|(tex, uv, ty)| (tex.get_binding_idx() as u16, uv, ty));
+        let main_texture = main_texture.map(|(tex, uv, ty)| (output.push_texture(tex), uv, ty));
 
         let mut atlas_idx_target = &mut res.atlas_idx;
         let mut uv_target = &mut res.uv;
 
-        if let Some((binding_idx, uv, _)) = main_texture {
-            *atlas_idx_target = binding_idx;
+        if let Some((texture_idx, uv, _)) = main_texture {
+            *atlas_idx_target = texture_idx;
             atlas_idx_target = &mut res.atlas_idx_alt;
 
             *uv_target = uv.into();
@@ -455,43 +1248,42 @@ impl BoxShaderVertexFill {
                 res.color = color.into();
                 res.fill_mode = FillMode::Color;
             }
-            PaintFill::Texture(_) => todo!(),
+            PaintFill::Texture(TextureFill { texture, uv }) => {
+                // the texture color is multiplied by the vertex color
+                res.color = [1., 1., 1., 1.];
+                *atlas_idx_target = output.push_texture(&texture);
+                res.fill_mode = FillMode::Texture;
+                *uv_target = apply_uv_mask(uv, uv_mask).into();
+            }
+            PaintFill::Gradient(gradient) => {
+                // the gradient color is multiplied by the vertex color
+                res.color = [1., 1., 1., 1.];
+                res.atlas_idx_alt = output.push_gradient(&gradient);
+                res.fill_mode = FillMode::Gradient;
+            }
             PaintFill::TextureAtlas(alloc, uv) => {
-                let binding_idx = atlas_manager
-                    .borrow()
-                    .read_lock()
-                    .get_atlas_by_id(&alloc.atlas_id)
-                    .unwrap()
-                    .get_texture_ref()
-                    .get_binding_idx();
+                let texture_idx = output.push_texture(
+                    atlas_manager
+                        .borrow()
+                        .read_lock()
+                        .get_atlas_by_id(&alloc.atlas_id)
+                        .unwrap()
+                        .get_texture_ref(),
+                );
 
                 let alloc_rect = alloc.draw_rect();
 
-                let mut uv_rect = alloc_rect.map(|x| x as f32);
+                let mut uv_rect: PhysicalRect<f32> = alloc_rect.map(|x| x as f32);
 
                 if let Some(uv) = uv {
                     uv_rect =
                         uv_rect.intersection_unchecked(&(uv.translate(uv_rect.min.to_vector())))
                 }
 
-                if let Some(uv_mask) = uv_mask {
-                    let uv_rect_size = uv_rect.size();
-
-                    uv_rect = Rect::new(
-                        Pos::new(
-                            uv_rect.min.x + uv_rect_size.width * uv_mask.min.x,
-                            uv_rect.min.y + uv_rect_size.height * uv_mask.min.y,
-                        ),
-                        Pos::new(
-                            uv_rect.min.x + uv_rect_size.width * uv_mask.max.x,
-                            uv_rect.min.y + uv_rect_size.height * uv_mask.max.y,
-                        ),
-                    );
-                }
-
-                *atlas_idx_target = binding_idx as u16;
+                res.color = [1., 1., 1., 1.];
+                *atlas_idx_target = texture_idx;
                 res.fill_mode = FillMode::Texture;
-                *uv_target = uv_rect.into();
+                *uv_target = apply_uv_mask(uv_rect, uv_mask).into();
             }
         };
 
@@ -500,8 +1292,15 @@ impl BoxShaderVertexFill {
             (Some((_, _, AtlasContentType::Mask)), FillMode::Texture) => {
                 FillMode::TextureMaskTexture
             }
+            (Some((_, _, AtlasContentType::Mask)), FillMode::Gradient) => {
+                FillMode::TextureMaskGradient
+            }
 
-            (Some((_, _, AtlasContentType::Color)), FillMode::Color) => FillMode::Texture,
+            (Some((_, _, AtlasContentType::Color)), FillMode::Color | FillMode::Gradient) => {
+                // color glyphs aren't tinted
+                res.color = [1., 1., 1., 1.];
+                FillMode::Texture
+            }
             (Some((_, _, AtlasContentType::Color)), FillMode::Texture) => {
                 FillMode::TextureMaskTexture
             }
@@ -515,6 +1314,26 @@ impl BoxShaderVertexFill {
     }
 }
 
+/// Restricts `uv_rect` to `uv_mask`, which is relative to its size
+fn apply_uv_mask(uv_rect: PhysicalRect<f32>, uv_mask: Option<Rect>) -> PhysicalRect<f32> {
+    let Some(uv_mask) = uv_mask else {
+        return uv_rect;
+    };
+
+    let uv_rect_size = uv_rect.size();
+
+    PhysicalRect::new(
+        Pos::new(
+            uv_rect.min.x + uv_rect_size.width * uv_mask.min.x,
+            uv_rect.min.y + uv_rect_size.height * uv_mask.min.y,
+        ),
+        Pos::new(
+            uv_rect.min.x + uv_rect_size.width * uv_mask.max.x,
+            uv_rect.min.y + uv_rect_size.height * uv_mask.max.y,
+        ),
+    )
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 enum RectPosition {
     TopLeft,
@@ -542,7 +1361,70 @@ fn rect_vertex<T: Copy, U>(rect: &Rect<T, U>, pos: RectPosition) -> [T; 2] {
     }
 }
 
+impl FillMode {
+    /// Which of `atlas_idx` and `atlas_idx_alt` refer to textures
+    #[inline(always)]
+    fn texture_fields(self) -> (bool, bool) {
+        match self {
+            FillMode::Color | FillMode::Gradient => (false, false),
+            FillMode::Texture | FillMode::TextureMaskColor | FillMode::TextureMaskGradient => {
+                (true, false)
+            }
+            FillMode::TextureMaskTexture => (true, true),
+        }
+    }
+
+    #[inline(always)]
+    fn uses_gradient(self) -> bool {
+        matches!(self, FillMode::Gradient | FillMode::TextureMaskGradient)
+    }
+}
+
 impl BoxShaderVertex {
+    #[inline(always)]
+    fn texture_fields(&self) -> (bool, bool) {
+        self.fill_mode.texture_fields()
+    }
+
+    #[inline]
+    fn texture_indices(&self) -> impl Iterator<Item = u16> {
+        let (main, alt) = self.texture_fields();
+
+        [
+            main.then_some(self.atlas_idx),
+            alt.then_some(self.atlas_idx_alt),
+        ]
+        .into_iter()
+        .flatten()
+    }
+
+    #[inline]
+    fn texture_indices_mut(&mut self) -> impl Iterator<Item = &mut u16> {
+        let (main, alt) = self.texture_fields();
+
+        [
+            main.then_some(&mut self.atlas_idx),
+            alt.then_some(&mut self.atlas_idx_alt),
+        ]
+        .into_iter()
+        .flatten()
+    }
+
+    /// Offsets the gradient index, and maps texture indices through
+    /// `texture_indices`, for when the gradients and textures are stored elsewhere
+    #[inline]
+    fn with_fill_indices(mut self, gradient_offset: u16, texture_indices: &[u16]) -> Self {
+        if self.fill_mode.uses_gradient() {
+            self.atlas_idx_alt = self.atlas_idx_alt.saturating_add(gradient_offset);
+        }
+
+        for idx in self.texture_indices_mut() {
+            *idx = texture_indices[*idx as usize];
+        }
+
+        self
+    }
+
     #[inline]
     fn with_fill(mut self, fill: &BoxShaderVertexFill, idx: RectPosition) -> Self {
         self.color = fill.color;
@@ -563,49 +1445,6 @@ impl BoxShaderVertex {
         self
     }
 
-    pub(crate) fn from_paint_rect(
-        atlas_manager: &TextureAtlasManagerRef,
-        paint_rect: PaintRectangle,
-    ) -> (impl Iterator<Item = [Self; 4]>, u64) {
-        let fill_rect = paint_rect.fill.map(|f| {
-            Self::from_rect_stroked(&atlas_manager, paint_rect.rounded_rect, f, None, None)
-        });
-
-        let stroke_rect =
-            paint_rect
-                .stroke_color
-                .zip(paint_rect.stroke_width)
-                .map(|(color, width)| {
-                    Self::from_rect_stroked(
-                        &atlas_manager,
-                        paint_rect.rounded_rect,
-                        color,
-                        Some(width),
-                        None,
-                    )
-                });
-
-        let blur_rect = paint_rect.blur.map(
-            |PaintBlur {
-                 blur_radius, color, ..
-             }| {
-                Self::from_rect_stroked(
-                    &atlas_manager,
-                    paint_rect.rounded_rect,
-                    color,
-                    None,
-                    Some(blur_radius),
-                )
-            },
-        );
-
-        let rects = [blur_rect, fill_rect, stroke_rect];
-
-        let num_rects = rects.iter().filter(|x| x.is_some()).count();
-
-        (rects.into_iter().flatten(), num_rects as u64)
-    }
-
     pub(crate) fn mesh_tri(pos: Pos, color: ColorRgba) -> Self {
         Self {
             shape_type: ShapeType::Mesh,
@@ -616,14 +1455,42 @@ impl BoxShaderVertex {
         }
     }
 
+    fn filled_mesh_tri(
+        &PaintMeshVertex { pos, color, uv }: &PaintMeshVertex,
+        fill: &BoxShaderVertexFill,
+    ) -> Self {
+        let [r, g, b, a]: [f32; 4] = color.into();
+
+        Self {
+            shape_type: ShapeType::Mesh,
+            fill_mode: fill.fill_mode,
+            pos: [pos.x, pos.y],
+            color: [
+                fill.color[0] * r,
+                fill.color[1] * g,
+                fill.color[2] * b,
+                fill.color[3] * a,
+            ],
+            atlas_idx: fill.atlas_idx,
+            atlas_idx_alt: fill.atlas_idx_alt,
+            uv: [
+                fill.uv.min.x + uv.x * fill.uv.width(),
+                fill.uv.min.y + uv.y * fill.uv.height(),
+            ],
+            ..Default::default()
+        }
+    }
+
     pub(crate) fn glyph_rect(
         atlas_manager: impl ReadLockable<TextureAtlasManager>,
+        output: &mut (impl PushGradients + PushTextures),
         rect: Rect<f32>,
         uv: Rect<u32, PhysicalUnit>,
         glyph_type: AtlasContentType, // TODO: texture id
         fill: impl Into<PaintFill>,
         texture_ref: &TextureRef,
         box_rect: Rect<f32>,
+        sdf_spread: Option<f32>,
     ) -> ([Self; 4], [u16; 6]) {
         let uv_mask = box_rect.intersection(&rect).map(|r| {
             r.map_points(|x| {
@@ -634,6 +1501,7 @@ impl BoxShaderVertex {
 
         let fill = BoxShaderVertexFill::new(
             atlas_manager,
+            output,
             fill.into(),
             Some((texture_ref, uv.map(|x| x as f32), glyph_type)),
             uv_mask,
@@ -642,7 +1510,12 @@ impl BoxShaderVertex {
         return (
             RECT_POSITIONS.map(|idx| {
                 Self {
-                    shape_type: ShapeType::Mesh,
+                    shape_type: match sdf_spread {
+                        Some(_) => ShapeType::SdfGlyph,
+                        None => ShapeType::Mesh,
+                    },
+                    // the shader needs the spread to recover distances from the mask
+                    stroke_width: sdf_spread.unwrap_or_default(),
                     ..Default::default()
                 }
                 .with_fill(&fill, idx)
@@ -651,57 +1524,145 @@ impl BoxShaderVertex {
             RECT_INDICES,
         );
     }
+}
+
+impl BoxShaderRectInstance {
+    #[inline]
+    fn texture_indices(&self) -> impl Iterator<Item = u16> {
+        let (main, alt) = self.fill_mode.texture_fields();
+
+        [
+            main.then_some(self.atlas_idx),
+            alt.then_some(self.atlas_idx_alt),
+        ]
+        .into_iter()
+        .flatten()
+    }
+
+    #[inline]
+    fn texture_indices_mut(&mut self) -> impl Iterator<Item = &mut u16> {
+        let (main, alt) = self.fill_mode.texture_fields();
+
+        [
+            main.then_some(&mut self.atlas_idx),
+            alt.then_some(&mut self.atlas_idx_alt),
+        ]
+        .into_iter()
+        .flatten()
+    }
+
+    /// See [`BoxShaderVertex::with_fill_indices`]
+    #[inline]
+    fn with_fill_indices(mut self, gradient_offset: u16, texture_indices: &[u16]) -> Self {
+        if self.fill_mode.uses_gradient() {
+            self.atlas_idx_alt = self.atlas_idx_alt.saturating_add(gradient_offset);
+        }
+
+        for idx in self.texture_indices_mut() {
+            *idx = texture_indices[*idx as usize];
+        }
+
+        self
+    }
+
+    /// The blur, fill and stroke of `paint_rect`, in that order
+    pub(crate) fn from_paint_rect(
+        atlas_manager: &TextureAtlasManagerRef,
+        output: &mut (impl PushGradients + PushTextures),
+        paint_rect: &PaintRectangle,
+    ) -> impl Iterator<Item = Self> {
+        let blur_rect = paint_rect.blur.as_ref().map(
+            |PaintBlur {
+                 blur_radius, color, ..
+             }| {
+                Self::from_rect_stroked(
+                    atlas_manager,
+                    output,
+                    paint_rect.rounded_rect,
+                    color.clone(),
+                    None,
+                    Some(*blur_radius),
+                )
+            },
+        );
+
+        let fill_rect = paint_rect.fill.clone().map(|f| {
+            Self::from_rect_stroked(
+                atlas_manager,
+                output,
+                paint_rect.rounded_rect,
+                f,
+                None,
+                None,
+            )
+        });
+
+        let stroke_rect = paint_rect
+            .stroke_color
+            .clone()
+            .zip(paint_rect.stroke_width)
+            .map(|(color, width)| {
+                Self::from_rect_stroked(
+                    atlas_manager,
+                    output,
+                    paint_rect.rounded_rect,
+                    color,
+                    Some(width),
+                    None,
+                )
+            });
+
+        [blur_rect, fill_rect, stroke_rect].into_iter().flatten()
+    }
 
     fn from_rect_stroked(
         atlas_manager: &TextureAtlasManagerRef,
+        output: &mut (impl PushGradients + PushTextures),
         rounded_rect: RoundedRect<f32>,
         color: impl Into<PaintFill>,
         stroke_width: Option<f32>,
         blur_radius: Option<f32>,
-    ) -> [Self; 4] {
+    ) -> Self {
         let RoundedRect {
             inner: rect,
             radius,
         } = rounded_rect;
 
-        let rounding = radius.unwrap_or(0.);
+        let fill = BoxShaderVertexFill::new(atlas_manager, output, color.into(), None, None);
 
-        let origin = rect.center();
-
-        let dims = rect.max - origin;
-
-        let stroke_width = stroke_width.unwrap_or(0.);
-        let blur_radius = blur_radius.unwrap_or(0.);
-
-        let origin = origin.into();
-
-        let fill = BoxShaderVertexFill::new(atlas_manager, color.into(), None, None);
-
-        return RECT_POSITIONS.map(|idx| {
-            Self {
-                shape_type: ShapeType::Rectangle,
-                origin,
-                dims: [dims.x, dims.y],
-                rounding,
-                stroke_width,
-                blur_radius,
-                ..Default::default()
-            }
-            .with_fill(&fill, idx)
-            .with_position(&rect, idx)
-        });
+        Self {
+            fill_mode: fill.fill_mode,
+            rect: [rect.min.x, rect.min.y, rect.max.x, rect.max.y],
+            uv: [fill.uv.min.x, fill.uv.min.y, fill.uv.max.x, fill.uv.max.y],
+            uv_alt: [
+                fill.uv_alt.min.x,
+                fill.uv_alt.min.y,
+                fill.uv_alt.max.x,
+                fill.uv_alt.max.y,
+            ],
+            atlas_idx: fill.atlas_idx,
+            atlas_idx_alt: fill.atlas_idx_alt,
+            color: fill.color,
+            rounding: radius.unwrap_or(0.),
+            stroke_width: stroke_width.unwrap_or(0.),
+            blur_radius: blur_radius.unwrap_or(0.),
+            ..Default::default()
+        }
     }
 }
 
 pub type ClipRect<F = f32, U = LogicalUnit> = RoundedRect<F, U>;
 
 #[repr(C)]
-#[derive(Copy, Clone, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct ShaderClipRect {
-    origin: [f32; 2],
-    half_size: [f32; 2],
-    rounding: f32,
-    transformation_idx: u32,
+    pub(crate) origin: [f32; 2],
+    pub(crate) half_size: [f32; 2],
+    pub(crate) rounding: f32,
+    pub(crate) transformation_idx: u32,
+    /// Index of the clip rect this one is nested in, or 0 if there is none
+    pub(crate) parent_idx: u32,
+    _padding: u32,
 }
 
 impl ShaderClipRect {
@@ -714,6 +1675,7 @@ impl ShaderClipRect {
             half_size: half_size.into(),
             rounding,
             transformation_idx,
+            ..Default::default()
         }
     }
 
@@ -721,6 +1683,12 @@ impl ShaderClipRect {
         Self::new(rect.inner, rect.radius.unwrap_or(0.), transformation_idx)
     }
 
+    #[inline(always)]
+    pub fn with_parent_idx(mut self, parent_idx: Option<u32>) -> Self {
+        self.parent_idx = parent_idx.unwrap_or_default();
+        self
+    }
+
     pub fn to_clip_rect_idx(self) -> (ClipRect, Option<usize>) {
         (
             ClipRect::new(
@@ -730,15 +1698,15 @@ impl ShaderClipRect {
                         self.origin[1] - self.half_size[1],
                     ),
                     Pos::new(
-                        self.half_size[0] + self.half_size[0],
-                        self.half_size[1] - self.half_size[1],
+                        self.origin[0] + self.half_size[0],
+                        self.origin[1] + self.half_size[1],
                     ),
                 ),
                 Some(self.rounding),
             ),
             match self.transformation_idx {
-                0 => Some(self.transformation_idx as usize),
-                _ => None,
+                0 => None,
+                idx => Some(idx as usize),
             },
         )
     }
@@ -753,35 +1721,92 @@ impl Into<ClipRect> for ShaderClipRect {
 
 pub struct ClipRectList {
     pub rects: Vec<(ClipRect, Option<usize>)>,
+    /// The clip rect each clip rect is nested in
+    parents: Vec<Option<usize>>,
 }
 
 impl Default for ClipRectList {
     fn default() -> Self {
         Self {
             rects: vec![Default::default()],
+            parents: vec![None],
         }
     }
 }
 
 impl ClipRectList {
     pub fn finish(self) -> impl Iterator<Item = ShaderClipRect> {
-        self.rects.into_iter().map(move |(r, idx)| {
-            ShaderClipRect::from_clip_rect(r, idx.map(|x| x as u32).unwrap_or_default())
-        })
+        self.rects
+            .into_iter()
+            .zip(self.parents)
+            .map(move |((r, idx), parent_idx)| {
+                ShaderClipRect::from_clip_rect(r, idx.map(|x| x as u32).unwrap_or_default())
+                    .with_parent_idx(parent_idx.map(|x| x as u32))
+            })
     }
 
-    pub fn push_clip_rect(&mut self, rect: ClipRect, transformation_idx: Option<usize>) -> usize {
+    /// Pushes a clip rect, which is intersected with `parent_idx` (and its own
+    /// parents) when clipping
+    pub fn push_clip_rect(
+        &mut self,
+        rect: ClipRect,
+        transformation_idx: Option<usize>,
+        parent_idx: Option<usize>,
+    ) -> usize {
         let idx = self.rects.len();
         self.rects.push((rect, transformation_idx));
+        self.parents.push(parent_idx);
         idx
     }
 
     pub fn get(&mut self, idx: usize) -> &(ClipRect, Option<usize>) {
         &self.rects[idx]
     }
+
+    /// The clip rect at `idx`, followed by every clip rect it is nested in
+    pub fn ancestors(
+        &self,
+        idx: Option<usize>,
+    ) -> impl Iterator<Item = (ClipRect, Option<usize>)> + '_ {
+        std::iter::successors(idx, |idx| self.parents[*idx]).map(|idx| self.rects[idx])
+    }
+
+    /// Bounds, in the space of `transformation_idx`, of what can be seen through
+    /// the clip rect at `idx` (and its ancestors) within the `viewport`. `None`
+    /// if nothing can be seen.
+    pub(crate) fn visible_rect(
+        &self,
+        idx: Option<usize>,
+        transformations: &TransformationList,
+        transformation_idx: Option<usize>,
+        viewport: Rect,
+    ) -> Option<Rect> {
+        let visible_rect = self.ancestors(idx).try_fold(
+            viewport,
+            |visible_rect, (clip_rect, clip_transformation_idx)| {
+                let clip_rect = match clip_transformation_idx {
+                    Some(idx) => transformations
+                        .get(idx)
+                        .outer_transformed_rect(&clip_rect.inner),
+                    None => clip_rect.inner,
+                };
+
+                visible_rect.intersection(&clip_rect)
+            },
+        )?;
+
+        match transformation_idx {
+            // degenerate transformations don't cover any pixels
+            Some(idx) if transformations.determinants[idx] == 0. => None,
+            Some(idx) => Some(
+                transformations.transformation_inverses[idx].outer_transformed_rect(&visible_rect),
+            ),
+            None => Some(visible_rect),
+        }
+    }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct PaintBlur<F = f32, U = LogicalUnit> {
     pub blur_radius: F,
     pub color: PaintFill,
@@ -821,17 +1846,20 @@ impl<T: Copy + Mul, U1, U2> Mul<ScaleFactor<U1, U2, T>> for PaintBlur<T, U1> {
     }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct TextureFill {
-    binding_idx: u32,
-    uv: PhysicalRect<f32>,
+    /// Binding indices may change whenever textures are dropped, so they're
+    /// only resolved at render time
+    pub(crate) texture: TextureRef,
+    pub(crate) uv: PhysicalRect<f32>,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum PaintFill {
     Color(ColorRgba),
     Texture(TextureFill),
     TextureAtlas(AtlasAllocationId, Option<PhysicalRect<f32>>),
+    Gradient(PaintGradient),
 }
 
 impl From<ColorRgba> for PaintFill {
@@ -840,6 +1868,12 @@ impl From<ColorRgba> for PaintFill {
     }
 }
 
+impl From<PaintGradient> for PaintFill {
+    fn from(value: PaintGradient) -> Self {
+        Self::Gradient(value)
+    }
+}
+
 impl PaintFill {
     #[inline(always)]
     pub fn from_atlas_allocation(alloc: &AtlasAllocation) -> Self {
@@ -854,7 +1888,7 @@ impl PaintFill {
     #[inline(always)]
     pub fn from_texture(texture: &TextureRef, uv: PhysicalRect<f32>) -> Self {
         Self::Texture(TextureFill {
-            binding_idx: texture.get_binding_idx(),
+            texture: texture.clone(),
             uv,
         })
     }
@@ -862,7 +1896,7 @@ impl PaintFill {
     #[inline(always)]
     pub fn from_entire_texture(texture: &TextureRef) -> Self {
         Self::Texture(TextureFill {
-            binding_idx: texture.get_binding_idx(),
+            texture: texture.clone(),
             uv: PhysicalRect::new(
                 Pos::zero(),
                 Pos::new(texture.texture.width(), texture.texture.height()).map(|x| x as f32),
@@ -879,7 +1913,7 @@ impl From<&AtlasAllocation> for PaintFill {
 }
 
 // TODO: adopt builder pattern (with `impl` args)
-#[derive(Clone, Default, Debug)]
+#[derive(Clone, Default, Debug, PartialEq)]
 pub struct PaintRectangle<F = f32, U = LogicalUnit> {
     pub rounded_rect: RoundedRect<F, U>,
     pub fill: Option<PaintFill>,
@@ -998,15 +2032,89 @@ impl<F, U> PaintRectangle<F, U> {
     }
 }
 
-#[derive(Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct PaintMeshVertex {
     pub pos: Pos,
     pub color: ColorRgba,
+    /// Where a texture fill is sampled, from `(0, 0)` at the top left to `(1, 1)`
+    /// at the bottom right of the fill's uv rect
+    pub uv: Pos,
+}
+
+impl PaintMeshVertex {
+    pub fn new(pos: impl Into<Pos>, color: impl Into<ColorRgba>) -> Self {
+        Self {
+            pos: pos.into(),
+            color: color.into(),
+            uv: Pos::zero(),
+        }
+    }
+
+    #[inline]
+    pub fn with_uv(mut self, uv: impl Into<Pos>) -> Self {
+        self.uv = uv.into();
+        self
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PaintMesh {
+    pub vertices: Vec<PaintMeshVertex>,
+    pub indices: Vec<u16>,
+    /// Fill for the whole mesh, multiplied by each vertex's color. Texture fills
+    /// are sampled at each vertex's uv.
+    pub fill: Option<PaintFill>,
+    /// Whether the outline of the mesh is feathered, so its edges are smooth
+    /// without multisampling. Meshes which are tiled edge to edge may want to
+    /// turn this off, since feathered edges leave faint seams.
+    pub anti_aliased: bool,
+}
+
+impl Default for PaintMesh {
+    fn default() -> Self {
+        Self {
+            vertices: Default::default(),
+            indices: Default::default(),
+            fill: None,
+            anti_aliased: true,
+        }
+    }
 }
 
-pub type PaintMesh = Mesh<PaintMeshVertex>;
+impl From<Mesh<PaintMeshVertex>> for PaintMesh {
+    fn from(Mesh { vertices, indices }: Mesh<PaintMeshVertex>) -> Self {
+        Self {
+            vertices,
+            indices,
+            ..Default::default()
+        }
+    }
+}
 
 impl PaintMesh {
+    #[inline]
+    pub fn with_fill(mut self, fill: impl Into<PaintFill>) -> Self {
+        self.fill = fill.into().into();
+        self
+    }
+
+    #[inline]
+    pub fn without_fill(mut self) -> Self {
+        self.fill = None;
+        self
+    }
+
+    #[inline]
+    pub fn with_anti_aliasing(mut self, anti_aliased: bool) -> Self {
+        self.anti_aliased = anti_aliased;
+        self
+    }
+
+    /// Bounds of the mesh's vertices
+    pub fn get_bounding_box(&self) -> Rect {
+        Rect::from_points(self.vertices.iter().map(|vertex| vertex.pos))
+    }
+
     pub fn from_pos_vertex_buffers(
         vertex_buffers: &PosVertexBuffers,
         color: impl Into<ColorRgba>,
@@ -1018,13 +2126,129 @@ impl PaintMesh {
             vertices: vertex_buffers
                 .vertices
                 .iter()
-                .map(|pos| PaintMeshVertex {
-                    pos: *pos + offset.to_vector(),
-                    color,
-                })
+                .map(|pos| PaintMeshVertex::new(*pos + offset.to_vector(), color))
                 .collect(),
             indices: vertex_buffers.indices.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// Anti-aliases the outline of the mesh with a fringe of `width`, which fades
+    /// out to transparent. The outline is moved in by half of `width`, so the
+    /// mesh covers the same area as before.
+    pub(crate) fn feathered(mut self, width: f32) -> Self {
+        if !self.anti_aliased {
+            return self;
+        }
+
+        let half_width = width / 2.;
+
+        // triangles are connected by position rather than index, since
+        // tessellators may emit the same point more than once
+        let mut vertices_by_pos = HashMap::new();
+
+        let vertex_ids = self
+            .vertices
+            .iter()
+            .enumerate()
+            .map(|(idx, vertex)| {
+                let key = vertex.pos.map(|x| (x + 0.).to_bits()).to_tuple();
+                *vertices_by_pos.entry(key).or_insert(idx as u16)
+            })
+            .collect_vec();
+
+        // the number of triangles using each edge, and the opposite vertex of
+        // the first one
+        let mut edges = HashMap::<(u16, u16), (u32, u16)>::new();
+
+        for tri in self.indices.chunks_exact(3) {
+            let [a, b, c] = [tri[0], tri[1], tri[2]].map(|idx| vertex_ids[idx as usize]);
+
+            for (from, to, opposite) in [(a, b, c), (b, c, a), (c, a, b)] {
+                if from == to {
+                    continue;
+                }
+
+                edges
+                    .entry((from.min(to), from.max(to)))
+                    .or_insert((0, opposite))
+                    .0 += 1;
+            }
+        }
+
+        // outward normals of the edges used by a single triangle, i.e. the outline
+        let outline = edges
+            .into_iter()
+            .filter(|(_, (num_triangles, _))| *num_triangles == 1)
+            .sorted_unstable_by_key(|(edge, _)| *edge)
+            .filter_map(|((from, to), (_, opposite))| {
+                let [from_pos, to_pos, opposite_pos] =
+                    [from, to, opposite].map(|idx| self.vertices[idx as usize].pos);
+
+                let edge = to_pos - from_pos;
+                let normal = Vector::new(edge.y, -edge.x).try_normalize()?;
+
+                let normal = match normal.dot(opposite_pos - from_pos) > 0. {
+                    true => -normal,
+                    false => normal,
+                };
+
+                Some((from, to, normal))
+            })
+            .collect_vec();
+
+        if outline.is_empty() || self.vertices.len() + outline.len() > u16::MAX as usize {
+            return self;
+        }
+
+        let mut normals = vec![(Vector::zero(), 0.); self.vertices.len()];
+
+        for (from, to, normal) in outline.iter() {
+            for idx in [*from, *to] {
+                let (sum, count) = &mut normals[idx as usize];
+                *sum = *sum + *normal;
+                *count += 1.;
+            }
+        }
+
+        let mut outer_vertices = vec![0; self.vertices.len()];
+
+        for (idx, (sum, count)) in normals.into_iter().enumerate() {
+            if count == 0. {
+                continue;
+            }
+
+            // scaled so the fringe has the same width along each adjacent edge,
+            // within reason for sharp corners
+            let normal = sum / count;
+            let normal = normal / normal.square_length().max(0.25);
+
+            let vertex = self.vertices[idx];
+
+            let mut outer_vertex = vertex;
+            outer_vertex.pos = vertex.pos + normal * half_width;
+            outer_vertex.color.alpha = 0.;
+
+            outer_vertices[idx] = self.vertices.len() as u16;
+            self.vertices.push(outer_vertex);
+
+            self.vertices[idx].pos = vertex.pos - normal * half_width;
+        }
+
+        // vertices at the same position as the one which was moved follow it
+        for (idx, id) in vertex_ids.iter().enumerate() {
+            self.vertices[idx].pos = self.vertices[*id as usize].pos;
+        }
+
+        for (from, to, _) in outline {
+            let (outer_from, outer_to) =
+                (outer_vertices[from as usize], outer_vertices[to as usize]);
+
+            self.indices
+                .extend([from, to, outer_to, from, outer_to, outer_from]);
         }
+
+        self
     }
 }
 
@@ -1032,6 +2256,7 @@ pub enum PaintShape<'a> {
     Rectangle(PaintRectangle),
     Text(&'a dyn TextBoxLike),
     Mesh(PaintMesh),
+    Path(PaintPath),
 }
 
 impl<'a> Into<PaintShape<'a>> for &'a dyn TextBoxLike {
@@ -1052,49 +2277,89 @@ impl<'a> Into<PaintShape<'a>> for PaintRectangle {
     }
 }
 
+impl<'a> Into<PaintShape<'a>> for PaintPath {
+    fn into(self) -> PaintShape<'a> {
+        PaintShape::Path(self)
+    }
+}
+
 impl<'a> PaintShape<'a> {
-    pub(crate) fn compute_paint_shape(
+    /// Computes the shapes to render, which may be more than one (e.g. for the
+    /// fill and stroke of a path)
+    ///
+    /// Shapes (and glyphs) which don't intersect `visible_rect` are culled before
+    /// being tessellated or rasterized, and meshes are anti-aliased.
+    pub(crate) fn compute_paint_shapes(
         self,
         clip_rect: impl Into<Option<RoundedRect>>,
+        visible_rect: Rect,
         scale_factor: CompleteScaleFactor,
-    ) -> ComputedPaintShape {
+        mut output: impl FnMut(ComputedPaintShape),
+    ) {
+        // meshes are feathered over a physical pixel
+        let feathering = 1. / scale_factor.as_float().get().max(1e-3);
+
         match self {
-            Self::Rectangle(rect) => ComputedPaintShape::Rectangle(rect),
+            Self::Rectangle(rect) => {
+                if rect.get_bounding_box().intersects(&visible_rect) {
+                    output(ComputedPaintShape::Rectangle(rect))
+                }
+            }
             Self::Text(text) => {
-                ComputedPaintShape::Text(text.calculate_placed_text_box(clip_rect.into(), scale_factor))
+                let text_box = text.calculate_placed_text_box(
+                    clip_rect.into(),
+                    Some(visible_rect),
+                    scale_factor,
+                );
+
+                if !text_box.glyphs.is_empty() {
+                    output(ComputedPaintShape::Text(text_box))
+                }
+            }
+            Self::Mesh(mesh) => {
+                if mesh
+                    .get_bounding_box()
+                    .inflate(feathering, feathering)
+                    .intersects(&visible_rect)
+                {
+                    output(ComputedPaintShape::Mesh(mesh.feathered(feathering)))
+                }
+            }
+            Self::Path(path) => {
+                if path
+                    .get_bounding_box()
+                    .inflate(feathering, feathering)
+                    .intersects(&visible_rect)
+                {
+                    path.tessellate(scale_factor).for_each(|mesh| {
+                        output(ComputedPaintShape::Mesh(mesh.feathered(feathering)))
+                    })
+                }
             }
-            Self::Mesh(mesh) => ComputedPaintShape::Mesh(mesh),
         }
     }
 }
 
-pub(crate) enum ComputedPaintShape {
+#[derive(Clone, PartialEq)]
+pub enum ComputedPaintShape {
     Rectangle(PaintRectangle),
     Text(PlacedTextBox),
     Mesh(PaintMesh),
 }
 
-// impl PaintShape {
-//     pub fn get_bounding_box(&self) -> Rect {
-//         match self {
-//             Self::Rectangle(rect) => rect.get_bounding_box(),
-//             Self::Text(text) => Rect::from_min_size(text.pos, text.bounding_size),
-//             Self::Mesh(mesh) => mesh.get_bounding_box(),
-//         }
-//     }
-// }
-
 impl<F: Num + Copy + Default + Two + MaxNum, U> PaintRectangle<F, U> {
+    /// Bounds of the pixels covered by the rectangle, including its stroke, blur
+    /// and feathering
     pub fn get_bounding_box(&self) -> Rect<F, U> {
+        // matches the padding of rectangle quads in the shader
         let fac = [
-            self.stroke_width.map(|w| w / F::TWO),
+            self.stroke_width,
             self.blur.as_ref().map(|b| b.blur_radius),
-            Some(F::one() / F::TWO), // feathering
+            Some(F::one()), // feathering
         ]
         .into_iter()
         .flatten()
-        .reduce(MaxNum::max_num)
-        .unwrap_or_default();
+        .fold(F::zero(), |acc, x| acc + x);
 
         self.rounded_rect.inner.inflate(fac, fac)
     }
@@ -1115,10 +2380,50 @@ impl<T: Copy + Mul, U1, U2> Mul<ScaleFactor<U1, U2, T>> for PaintRectangle<T, U1
     }
 }
 
-#[derive(Debug, Default)]
-pub(super) struct ShapeBufferWithContext {
-    pub(super) vertex_buffers: VertexBuffers<BoxShaderVertex>,
-    pub(super) clip_rect_idx: u32,
+/// Whether a [`ShapeDraw`] is a range of the index buffer or of rectangle instances
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum ShapeDrawKind {
+    /// Triangles of the vertex buffer, for meshes and glyphs
+    Indexed,
+    /// Rectangles drawn as instances of a unit quad
+    Rects,
+}
+
+/// A range of indices or instances, drawn in the order they were pushed
+#[derive(Clone, Debug, PartialEq)]
+pub(super) struct ShapeDraw {
+    kind: ShapeDrawKind,
+    range: Range<u32>,
+    /// Added to indices, to draw vertices uploaded at a different position
+    base_vertex: i32,
+}
+
+/// A position in both the index and the instance buffer
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(super) struct DrawPosition {
+    index: u32,
+    instance: u32,
+}
+
+impl DrawPosition {
+    #[inline(always)]
+    fn get(&self, kind: ShapeDrawKind) -> u32 {
+        match kind {
+            ShapeDrawKind::Indexed => self.index,
+            ShapeDrawKind::Rects => self.instance,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub(super) struct ShapeBufferWithContext {
+    pub(super) vertex_buffers: VertexBuffers<BoxShaderVertex>,
+    pub(super) instances: Vec<BoxShaderRectInstance>,
+    /// Ranges of `vertex_buffers` and `instances`, in paint order
+    pub(super) draws: Vec<ShapeDraw>,
+    pub(super) gradients: Vec<ShaderGradient>,
+    pub(super) textures: Vec<TextureRef>,
+    pub(super) clip_rect_idx: u32,
     pub(super) transformation_idx: u32,
 }
 
@@ -1126,6 +2431,637 @@ impl ShapeBufferWithContext {
     pub fn new() -> Self {
         Self::default()
     }
+
+    fn push_shape(&mut self, atlas_manager: &TextureAtlasManagerRef, shape: &ComputedPaintShape) {
+        match shape {
+            ComputedPaintShape::Rectangle(paint_rect) => {
+                let instances =
+                    BoxShaderRectInstance::from_paint_rect(atlas_manager, self, paint_rect);
+
+                self.push_rect_instances(instances);
+            }
+
+            ComputedPaintShape::Mesh(PaintMesh {
+                vertices,
+                indices,
+                fill,
+                ..
+            }) => {
+                let fill = fill
+                    .clone()
+                    .map(|fill| BoxShaderVertexFill::new(atlas_manager, self, fill, None, None));
+
+                self.push_vertices(
+                    vertices.iter().map(|vertex| match &fill {
+                        Some(fill) => BoxShaderVertex::filled_mesh_tri(vertex, fill),
+                        None => BoxShaderVertex::mesh_tri(vertex.pos, vertex.color),
+                    }),
+                    indices.iter().copied(),
+                )
+            }
+
+            ComputedPaintShape::Text(_) => {
+                debug_panic!("Text boxes must be prepared by the font manager");
+            }
+        }
+    }
+
+    fn push_rect_instances(&mut self, instances: impl IntoIterator<Item = BoxShaderRectInstance>) {
+        let clip_rect_idx = self.clip_rect_idx;
+        let transformation_idx = self.transformation_idx;
+
+        let start = self.instances.len() as u32;
+
+        self.instances.extend(instances.into_iter().map(|mut x| {
+            x.clip_rect_idx = clip_rect_idx;
+            x.transform_idx = transformation_idx;
+            x
+        }));
+
+        self.push_draw(ShapeDrawKind::Rects, start..self.instances.len() as u32);
+    }
+
+    /// Points every vertex and instance to another clip rect and transformation
+    fn set_context_indices(&mut self, clip_rect_idx: u32, transformation_idx: u32) {
+        self.clip_rect_idx = clip_rect_idx;
+        self.transformation_idx = transformation_idx;
+
+        for vertex in &mut self.vertex_buffers.vertices {
+            vertex.clip_rect_idx = clip_rect_idx;
+            vertex.transform_idx = transformation_idx;
+        }
+
+        for instance in &mut self.instances {
+            instance.clip_rect_idx = clip_rect_idx;
+            instance.transform_idx = transformation_idx;
+        }
+    }
+
+    /// Records a range of newly pushed indices or instances, continuing the last
+    /// draw if it's of the same kind
+    fn push_draw(&mut self, kind: ShapeDrawKind, range: Range<u32>) {
+        if range.is_empty() {
+            return;
+        }
+
+        match self.draws.last_mut() {
+            Some(draw) if draw.kind == kind && draw.range.end == range.start => {
+                draw.range.end = range.end
+            }
+            _ => self.draws.push(ShapeDraw {
+                kind,
+                range,
+                base_vertex: 0,
+            }),
+        }
+    }
+
+    /// Appends a separately built buffer, rebasing its instance, gradient and
+    /// texture indices. Its draws are given their own base vertex, since the
+    /// vertex count may exceed what a u16 index can address.
+    fn extend(&mut self, other: &ShapeBufferWithContext) {
+        let base_vertex = self.vertex_buffers.vertices.len() as i32;
+
+        // vertices index gradients with a u16, so further gradients reuse the last one
+        let gradient_capacity = (u16::MAX as usize + 1).saturating_sub(self.gradients.len());
+        if other.gradients.len() > gradient_capacity {
+            debug_panic!("Frames support at most {} gradients", u16::MAX as usize + 1);
+        }
+
+        let gradient_offset = self.gradients.len().min(u16::MAX as usize) as u16;
+
+        let draw_offset = DrawPosition {
+            index: self.vertex_buffers.indices.len() as u32,
+            instance: self.instances.len() as u32,
+        };
+
+        let texture_indices = other
+            .textures
+            .iter()
+            .map(|texture| self.push_texture(texture))
+            .collect_vec();
+
+        self.vertex_buffers.vertices.extend(
+            other
+                .vertex_buffers
+                .vertices
+                .iter()
+                .map(|v| v.with_fill_indices(gradient_offset, &texture_indices)),
+        );
+
+        self.vertex_buffers
+            .indices
+            .extend_from_slice(&other.vertex_buffers.indices);
+
+        self.instances.extend(
+            other
+                .instances
+                .iter()
+                .map(|x| x.with_fill_indices(gradient_offset, &texture_indices)),
+        );
+
+        // not merged with the last draw, since layer commands refer to draws
+        self.draws
+            .extend(other.draws.iter().map(|ShapeDraw { kind, range, .. }| {
+                let offset = draw_offset.get(*kind);
+
+                ShapeDraw {
+                    kind: *kind,
+                    range: range.start + offset..range.end + offset,
+                    base_vertex,
+                }
+            }));
+
+        self.gradients
+            .extend(other.gradients.iter().take(gradient_capacity).cloned());
+    }
+
+    /// Splits the draws into batches which each sample at most `max_textures`
+    /// textures, and rewrites the texture indices of vertices and instances into
+    /// binding slots of their batch
+    fn split_texture_batches(&mut self, max_textures: u32) -> Vec<TextureBatch> {
+        let max_textures = max_textures as usize;
+
+        // the common case: everything fits into a single bind group
+        if self.textures.len() <= max_textures {
+            return vec![TextureBatch {
+                start: Default::default(),
+                textures: std::mem::take(&mut self.textures),
+            }];
+        }
+
+        let VertexBuffers { vertices, indices } = &mut self.vertex_buffers;
+
+        let mut batcher = TextureBatcher {
+            batches: vec![TextureBatch::default()],
+            slots: Default::default(),
+            max_textures,
+        };
+
+        let mut remapped = vec![false; vertices.len()];
+        let mut position = DrawPosition::default();
+
+        for ShapeDraw {
+            kind,
+            range,
+            base_vertex,
+        } in &self.draws
+        {
+            match kind {
+                // vertices are never shared between shapes, and all vertices of a
+                // shape sample the same textures, so batches are only ever split
+                // between shapes
+                ShapeDrawKind::Indexed => {
+                    for start in range.clone().step_by(3) {
+                        position.index = start;
+
+                        let triangle = indices[start as usize..start as usize + 3]
+                            .iter()
+                            .map(|&v| (v as i32 + base_vertex) as usize)
+                            .collect_vec();
+
+                        batcher.assign(
+                            &self.textures,
+                            triangle
+                                .iter()
+                                .flat_map(|&v| vertices[v].texture_indices())
+                                .collect_vec(),
+                            position,
+                        );
+
+                        for v in triangle {
+                            if std::mem::replace(&mut remapped[v], true) {
+                                continue;
+                            }
+
+                            for idx in vertices[v].texture_indices_mut() {
+                                *idx = batcher.slots[&*idx];
+                            }
+                        }
+                    }
+                }
+
+                ShapeDrawKind::Rects => {
+                    for instance_idx in range.clone() {
+                        position.instance = instance_idx;
+
+                        let instance = &mut self.instances[instance_idx as usize];
+
+                        batcher.assign(
+                            &self.textures,
+                            instance.texture_indices().collect_vec(),
+                            position,
+                        );
+
+                        for idx in instance.texture_indices_mut() {
+                            *idx = batcher.slots[&*idx];
+                        }
+                    }
+                }
+            }
+
+            match kind {
+                ShapeDrawKind::Indexed => position.index = range.end,
+                ShapeDrawKind::Rects => position.instance = range.end,
+            }
+        }
+
+        batcher.batches
+    }
+}
+
+/// Draws which are drawn with the same textures bound
+#[derive(Debug, Default)]
+pub(super) struct TextureBatch {
+    /// The first index and instance of the batch, which lasts until the next batch
+    start: DrawPosition,
+    /// Bound to slots matching their position
+    textures: Vec<TextureRef>,
+}
+
+struct TextureBatcher {
+    batches: Vec<TextureBatch>,
+    /// Binding slots of the last batch's textures
+    slots: HashMap<u16, u16>,
+    max_textures: usize,
+}
+
+impl TextureBatcher {
+    /// Assigns slots to the textures of a shape, starting a new batch at
+    /// `position` if they don't fit into the current one
+    fn assign(
+        &mut self,
+        all_textures: &[TextureRef],
+        mut textures: Vec<u16>,
+        position: DrawPosition,
+    ) {
+        textures.sort_unstable();
+        textures.dedup();
+
+        let num_new = textures
+            .iter()
+            .filter(|idx| !self.slots.contains_key(idx))
+            .count();
+
+        if num_new > 0 && self.slots.len() + num_new > self.max_textures {
+            self.batches.push(TextureBatch {
+                start: position,
+                textures: Default::default(),
+            });
+            self.slots.clear();
+        }
+
+        let batch = self.batches.last_mut().unwrap();
+
+        for idx in textures {
+            self.slots.entry(idx).or_insert_with(|| {
+                batch.textures.push(all_textures[idx as usize].clone());
+                (batch.textures.len() - 1) as u16
+            });
+        }
+    }
+}
+
+/// Identifies the `run`th group of consecutive shapes painted by an element in a
+/// frame (e.g. one for `Element::ui`, and one for `Element::ui_post`), or by no
+/// element
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct SegmentKey {
+    element_id: Option<ElementId>,
+    run: u32,
+}
+
+/// Ranges of the buffers a segment is uploaded to
+#[derive(Clone, Debug, Default)]
+struct SegmentRanges {
+    vertices: Range<u32>,
+    indices: Range<u32>,
+    instances: Range<u32>,
+    gradients: Range<u32>,
+}
+
+#[derive(Debug, Default)]
+struct SegmentAllocators {
+    vertices: RangeAllocator,
+    indices: RangeAllocator,
+    instances: RangeAllocator,
+    gradients: RangeAllocator,
+}
+
+impl SegmentAllocators {
+    /// Resizes `ranges` to fit `buffer`. Ranges which fit and aren't more than
+    /// twice as large as needed are kept in place.
+    fn fit(&mut self, ranges: &mut SegmentRanges, buffer: &ShapeBufferWithContext) {
+        fn fit_range(allocator: &mut RangeAllocator, range: &mut Range<u32>, len: usize) {
+            let len = len as u32;
+            let capacity = range.len() as u32;
+
+            if len <= capacity && len * 2 >= capacity {
+                return;
+            }
+
+            allocator.free(std::mem::take(range));
+            *range = allocator.allocate(len);
+        }
+
+        let VertexBuffers { vertices, indices } = &buffer.vertex_buffers;
+
+        fit_range(&mut self.vertices, &mut ranges.vertices, vertices.len());
+        // indices are uploaded in pairs, see `DynamicGPUMeshTriBuffer::write_at`
+        fit_range(
+            &mut self.indices,
+            &mut ranges.indices,
+            indices.len().next_multiple_of(2),
+        );
+        fit_range(
+            &mut self.instances,
+            &mut ranges.instances,
+            buffer.instances.len(),
+        );
+        fit_range(
+            &mut self.gradients,
+            &mut ranges.gradients,
+            buffer.gradients.len(),
+        );
+    }
+
+    fn free(&mut self, ranges: SegmentRanges) {
+        self.vertices.free(ranges.vertices);
+        self.indices.free(ranges.indices);
+        self.instances.free(ranges.instances);
+        self.gradients.free(ranges.gradients);
+    }
+
+    /// Whether every allocated gradient can be indexed by the u16 of a vertex
+    fn gradients_indexable(&self) -> bool {
+        self.gradients.end() <= u16::MAX as u32 + 1
+    }
+
+    /// Whether more than half of any buffer isn't allocated
+    fn is_fragmented(&self) -> bool {
+        [
+            &self.vertices,
+            &self.indices,
+            &self.instances,
+            &self.gradients,
+        ]
+        .into_iter()
+        .any(|allocator| allocator.num_free() * 2 > allocator.end())
+    }
+
+    fn clear(&mut self) {
+        self.vertices.clear();
+        self.indices.clear();
+        self.instances.clear();
+        self.gradients.clear();
+    }
+}
+
+/// Textures sampled by segments, which keep their binding slot while any
+/// segment samples them
+#[derive(Default)]
+struct BoundTextures {
+    /// Each texture, and the number of segments sampling it
+    slots: Vec<Option<(TextureRef, u32)>>,
+}
+
+impl BoundTextures {
+    fn acquire(&mut self, texture: &TextureRef) -> u16 {
+        let existing = self
+            .slots
+            .iter()
+            .position(|slot| slot.as_ref().is_some_and(|(bound, _)| bound == texture));
+
+        let idx = existing.unwrap_or_else(|| {
+            let idx = self
+                .slots
+                .iter()
+                .position(Option::is_none)
+                .unwrap_or_else(|| {
+                    self.slots.push(None);
+                    self.slots.len() - 1
+                });
+
+            self.slots[idx] = Some((texture.clone(), 0));
+            idx
+        });
+
+        if let Some((_, count)) = &mut self.slots[idx] {
+            *count += 1;
+        }
+
+        idx as u16
+    }
+
+    fn release(&mut self, slots: &[u16]) {
+        for &idx in slots {
+            let slot = &mut self.slots[idx as usize];
+
+            if let Some((_, count)) = slot {
+                *count -= 1;
+
+                if *count == 0 {
+                    *slot = None;
+                }
+            }
+        }
+
+        while self.slots.last().is_some_and(Option::is_none) {
+            self.slots.pop();
+        }
+    }
+
+    fn num_slots(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Textures to bind, in slot order
+    fn textures(&self) -> Vec<TextureRef> {
+        let Some((filler, _)) = self.slots.iter().flatten().next() else {
+            return Vec::new();
+        };
+
+        // free slots aren't sampled, so they're bound to any texture
+        self.slots
+            .iter()
+            .map(|slot| slot.as_ref().map_or(filler, |(texture, _)| texture).clone())
+            .collect()
+    }
+}
+
+/// The vertices of a run of shapes, kept at the same ranges of the buffers
+/// across frames. They're only built again when their element repaints, or
+/// when glyphs of their text change, and only uploaded again after that or when
+/// their element's clip rect or transformation moves to another index.
+struct RetainedSegment {
+    /// Generation of the element's shapes, or `None` if they're painted every
+    /// frame
+    generation: Option<u64>,
+    /// Position of the run among the shapes painted by its element
+    shapes: Range<u32>,
+    /// Generation of the glyph cache it was built with, if it has text
+    atlas_generation: Option<u64>,
+    /// Refers to its own gradients and textures, which are rebased when it's
+    /// uploaded
+    buffer: ShapeBufferWithContext,
+    /// Glyphs of its text, which are kept in the atlases while it's drawn
+    glyphs: Vec<AtlasGlyphKey>,
+    /// Binding slots of `buffer.textures`
+    texture_slots: Vec<u16>,
+    ranges: SegmentRanges,
+    needs_upload: bool,
+}
+
+impl RetainedSegment {
+    fn build(
+        shapes: Vec<PaintShapeWithContext>,
+        generation: Option<u64>,
+        shape_range: Range<u32>,
+        atlas_generation: u64,
+        atlas_manager: &TextureAtlasManagerRef,
+        font_manager: &mut FontManager,
+    ) -> Self {
+        let mut buffer = ShapeBufferWithContext::new();
+        let mut glyphs = Vec::new();
+
+        for PaintShapeWithContext {
+            shape,
+            clip_rect_idx,
+            transformation_idx,
+            ..
+        } in shapes
+        {
+            buffer.clip_rect_idx = clip_rect_idx.unwrap_or_default();
+            buffer.transformation_idx = transformation_idx.unwrap_or_default();
+
+            match shape {
+                ComputedPaintShape::Text(text_box) => {
+                    glyphs.extend(text_box.atlas_glyph_keys());
+                    font_manager.prepare(text_box, &mut buffer)
+                }
+                shape => buffer.push_shape(atlas_manager, &shape),
+            }
+        }
+
+        Self {
+            generation,
+            shapes: shape_range,
+            atlas_generation: (!glyphs.is_empty()).then_some(atlas_generation),
+            buffer,
+            glyphs,
+            texture_slots: Default::default(),
+            ranges: Default::default(),
+            needs_upload: true,
+        }
+    }
+
+    /// Whether it was built from the same shapes, and none of its glyphs were
+    /// added, moved or removed since
+    fn is_current(
+        &self,
+        generation: Option<u64>,
+        shapes: &Range<u32>,
+        atlas_manager: &TextureAtlasManager,
+    ) -> bool {
+        generation.is_some()
+            && self.generation == generation
+            && self.shapes == *shapes
+            && self.atlas_generation.is_none_or(|generation| {
+                atlas_manager.glyphs_unchanged_since(&self.glyphs, generation)
+            })
+    }
+
+    /// Its draws, at its ranges of the buffers
+    fn draws(&self) -> impl Iterator<Item = ShapeDraw> + '_ {
+        self.buffer
+            .draws
+            .iter()
+            .map(|ShapeDraw { kind, range, .. }| {
+                let (offset, base_vertex) = match kind {
+                    ShapeDrawKind::Indexed => {
+                        (self.ranges.indices.start, self.ranges.vertices.start as i32)
+                    }
+                    ShapeDrawKind::Rects => (self.ranges.instances.start, 0),
+                };
+
+                ShapeDraw {
+                    kind: *kind,
+                    range: range.start + offset..range.end + offset,
+                    base_vertex,
+                }
+            })
+    }
+
+    /// Writes the buffer at its ranges, pointing it to its gradients and texture
+    /// slots
+    fn upload(
+        &self,
+        queue: &wgpu::Queue,
+        shape_buffer: &DynamicGPUMeshTriBuffer<BoxShaderVertex>,
+        rect_buffer: &DynamicGPUQuadInstanceBuffer<BoxShaderRectInstance>,
+        gradients: &DynamicGPUBuffer<ShaderGradient>,
+    ) {
+        // segments are only uploaded while all gradients can be indexed, see
+        // `SegmentAllocators::gradients_indexable`
+        let gradient_offset = u16::try_from(self.ranges.gradients.start).unwrap_or_else(|_| {
+            debug_panic!(
+                "Gradients of retained segments must start below {}",
+                u16::MAX as u32 + 1
+            );
+            u16::MAX
+        });
+
+        let vertices = self
+            .buffer
+            .vertex_buffers
+            .vertices
+            .iter()
+            .map(|v| v.with_fill_indices(gradient_offset, &self.texture_slots))
+            .collect_vec();
+
+        let instances = self
+            .buffer
+            .instances
+            .iter()
+            .map(|x| x.with_fill_indices(gradient_offset, &self.texture_slots))
+            .collect_vec();
+
+        shape_buffer.write_at(
+            queue,
+            self.ranges.vertices.start as u64,
+            &vertices,
+            self.ranges.indices.start as u64,
+            &self.buffer.vertex_buffers.indices,
+        );
+
+        rect_buffer.write_at(queue, self.ranges.instances.start as u64, &instances);
+
+        gradients.write_at(
+            queue,
+            self.ranges.gradients.start as u64,
+            &self.buffer.gradients,
+        );
+    }
+}
+
+/// Splits shapes into runs of consecutive shapes painted by the same element
+/// with the same blend mode
+fn split_element_runs(shapes: Vec<PaintShapeWithContext>) -> Vec<Vec<PaintShapeWithContext>> {
+    let mut runs: Vec<Vec<PaintShapeWithContext>> = Vec::new();
+
+    for shape in shapes {
+        match runs.last_mut() {
+            Some(run)
+                if run[0].element_id == shape.element_id
+                    && run[0].blend_mode == shape.blend_mode =>
+            {
+                run.push(shape)
+            }
+            _ => runs.push(vec![shape]),
+        }
+    }
+
+    runs
 }
 
 impl PushVertices<BoxShaderVertex> for ShapeBufferWithContext {
@@ -1137,6 +3073,8 @@ impl PushVertices<BoxShaderVertex> for ShapeBufferWithContext {
         let clip_rect_idx = self.clip_rect_idx;
         let transformation_idx = self.transformation_idx;
 
+        let start = self.vertex_buffers.indices.len() as u32;
+
         self.vertex_buffers.push_vertices(
             vertices.into_iter().map(|mut x| {
                 x.clip_rect_idx = clip_rect_idx;
@@ -1144,6 +3082,274 @@ impl PushVertices<BoxShaderVertex> for ShapeBufferWithContext {
                 x
             }),
             indices,
-        )
+        );
+
+        self.push_draw(
+            ShapeDrawKind::Indexed,
+            start..self.vertex_buffers.indices.len() as u32,
+        );
+    }
+}
+
+impl PushGradients for ShapeBufferWithContext {
+    #[inline(always)]
+    fn push_gradient(&mut self, gradient: &PaintGradient) -> u16 {
+        self.gradients.push_gradient(gradient)
+    }
+}
+
+impl PushTextures for ShapeBufferWithContext {
+    #[inline(always)]
+    fn push_texture(&mut self, texture: &TextureRef) -> u16 {
+        self.textures.push_texture(texture)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn feathering_moves_outline_into_fringe() {
+        let color = ColorRgba::new(1., 1., 1., 1.);
+
+        // two triangles which don't share indices
+        let mesh = PaintMesh {
+            vertices: [(0., 0.), (2., 0.), (2., 2.), (0., 0.), (2., 2.), (0., 2.)]
+                .map(|pos| PaintMeshVertex::new(pos, color))
+                .to_vec(),
+            indices: vec![0, 1, 2, 3, 4, 5],
+            ..Default::default()
+        }
+        .feathered(1.);
+
+        assert_eq!(mesh.vertices.len(), 10);
+        assert_eq!(mesh.indices.len(), 6 + 4 * 6);
+
+        assert_eq!(mesh.vertices[0].pos, Pos::new(0.5, 0.5));
+        assert_eq!(mesh.vertices[3].pos, Pos::new(0.5, 0.5));
+
+        let outer_vertex = mesh
+            .vertices
+            .iter()
+            .find(|vertex| vertex.pos == Pos::new(-0.5, -0.5))
+            .unwrap();
+
+        assert_eq!(outer_vertex.color.alpha, 0.);
+    }
+
+    #[test]
+    fn mesh_uvs_map_into_fill_uv_rect() {
+        let fill = BoxShaderVertexFill {
+            color: [1., 1., 1., 0.5],
+            uv: PhysicalRect::new(Pos::new(10., 20.), Pos::new(30., 60.)),
+            fill_mode: FillMode::Texture,
+            ..Default::default()
+        };
+
+        let vertex = PaintMeshVertex::new((0., 0.), ColorRgba::new(1., 0., 0., 1.))
+            .with_uv((0.5, 0.25));
+
+        let shader_vertex = BoxShaderVertex::filled_mesh_tri(&vertex, &fill);
+
+        // textures are tinted by the vertex color
+        assert_eq!(shader_vertex.color, [1., 0., 0., 0.5]);
+        assert_eq!(shader_vertex.uv, [20., 30.]);
+    }
+
+    #[test]
+    fn visible_rect_is_clipped_and_transformed() {
+        let viewport = Rect::new(Pos::new(0., 0.), Pos::new(100., 100.));
+
+        let mut transformations = TransformationList::default();
+        let scrolled = transformations.push_transform(CoordinateTransform::translation(0., -50.));
+        let scaled = transformations.push_transform(CoordinateTransform::scale(2., 2.));
+
+        let mut clip_rects = ClipRectList::default();
+        let clip_rect = clip_rects.push_clip_rect(
+            ClipRect::from_rect(Rect::new(Pos::new(10., 10.), Pos::new(50., 200.))),
+            None,
+            None,
+        );
+        let nested_clip_rect = clip_rects.push_clip_rect(
+            ClipRect::from_rect(Rect::new(Pos::new(0., 0.), Pos::new(20., 20.))),
+            Some(scaled),
+            Some(clip_rect),
+        );
+
+        assert_eq!(
+            clip_rects.visible_rect(Some(clip_rect), &transformations, Some(scrolled), viewport),
+            Some(Rect::new(Pos::new(10., 60.), Pos::new(50., 150.)))
+        );
+
+        assert_eq!(
+            clip_rects.visible_rect(Some(nested_clip_rect), &transformations, None, viewport),
+            Some(Rect::new(Pos::new(10., 10.), Pos::new(40., 40.)))
+        );
+
+        let offscreen_clip_rect = clip_rects.push_clip_rect(
+            ClipRect::from_rect(Rect::new(Pos::new(200., 0.), Pos::new(300., 100.))),
+            None,
+            None,
+        );
+
+        assert_eq!(
+            clip_rects.visible_rect(Some(offscreen_clip_rect), &transformations, None, viewport),
+            None
+        );
+    }
+
+    #[test]
+    fn extended_draws_keep_paint_order() {
+        let color = ColorRgba::new(1., 1., 1., 1.);
+        let tri = [(0., 0.), (1., 0.), (0., 1.)]
+            .map(|pos| BoxShaderVertex::mesh_tri(Pos::new(pos.0, pos.1), color));
+
+        let mut segment = ShapeBufferWithContext::new();
+        segment.push_rect_instances([BoxShaderRectInstance::default(); 2]);
+        segment.push_vertices(tri, [0, 1, 2]);
+        segment.push_vertices(tri, [0, 1, 2]);
+        segment.push_rect_instances([BoxShaderRectInstance::default()]);
+
+        assert_eq!(segment.draws.len(), 3);
+
+        let mut buffer = ShapeBufferWithContext::new();
+        buffer.extend(&segment);
+        buffer.extend(&segment);
+
+        let draws = buffer
+            .draws
+            .iter()
+            .map(
+                |ShapeDraw {
+                     kind,
+                     range,
+                     base_vertex,
+                 }| (*kind, range.clone(), *base_vertex),
+            )
+            .collect_vec();
+
+        use ShapeDrawKind::*;
+
+        assert_eq!(
+            draws,
+            [
+                (Rects, 0..2, 0),
+                (Indexed, 0..6, 0),
+                (Rects, 2..3, 0),
+                (Rects, 3..5, 6),
+                (Indexed, 6..12, 6),
+                (Rects, 5..6, 6),
+            ]
+        );
+
+        // indices are kept, and offset by the base vertex of their draw instead
+        assert_eq!(buffer.vertex_buffers.indices[6..9], [0, 1, 2]);
+    }
+
+    #[test]
+    fn extended_gradients_reach_the_last_index() {
+        let mut segment = ShapeBufferWithContext::new();
+        segment.gradients.push(Default::default());
+        segment.push_rect_instances([BoxShaderRectInstance {
+            fill_mode: FillMode::Gradient,
+            atlas_idx_alt: 0,
+            ..Default::default()
+        }]);
+
+        let mut buffer = ShapeBufferWithContext::new();
+        buffer.gradients = vec![Default::default(); u16::MAX as usize];
+        buffer.extend(&segment);
+
+        // the offset itself doesn't wrap
+        assert_eq!(buffer.gradients.len(), u16::MAX as usize + 1);
+        assert_eq!(buffer.instances[0].atlas_idx_alt, u16::MAX);
+    }
+
+    #[test]
+    fn segments_past_the_last_gradient_index_are_batched() {
+        let mut segment = ShapeBufferWithContext::new();
+        segment.gradients.push(Default::default());
+
+        let mut allocators = SegmentAllocators::default();
+
+        let first = SegmentRanges {
+            gradients: allocators.gradients.allocate(u16::MAX as u32 + 1),
+            ..Default::default()
+        };
+        assert!(allocators.gradients_indexable());
+
+        // starts above the last index a vertex can hold
+        let mut ranges = SegmentRanges::default();
+        allocators.fit(&mut ranges, &segment);
+
+        assert_eq!(ranges.gradients.start, u16::MAX as u32 + 1);
+        assert!(!allocators.gradients_indexable());
+
+        // indexable again once the gradients past the last index are freed
+        allocators.free(first);
+        allocators.free(ranges);
+        assert!(allocators.gradients_indexable());
+    }
+
+    #[test]
+    fn segments_are_drawn_at_their_ranges() {
+        let color = ColorRgba::new(1., 1., 1., 1.);
+        let tri = [(0., 0.), (1., 0.), (0., 1.)]
+            .map(|pos| BoxShaderVertex::mesh_tri(Pos::new(pos.0, pos.1), color));
+
+        let mut buffer = ShapeBufferWithContext::new();
+        buffer.push_vertices(tri, [0, 1, 2]);
+        buffer.push_rect_instances([BoxShaderRectInstance::default()]);
+
+        let mut allocators = SegmentAllocators::default();
+
+        let mut first = SegmentRanges::default();
+        allocators.fit(&mut first, &buffer);
+
+        let mut segment = RetainedSegment {
+            generation: Some(1),
+            shapes: 0..2,
+            atlas_generation: None,
+            buffer,
+            glyphs: Default::default(),
+            texture_slots: Default::default(),
+            ranges: Default::default(),
+            needs_upload: true,
+        };
+
+        allocators.fit(&mut segment.ranges, &segment.buffer);
+
+        let draws = |segment: &RetainedSegment| {
+            segment
+                .draws()
+                .map(
+                    |ShapeDraw {
+                         kind,
+                         range,
+                         base_vertex,
+                     }| (kind, range, base_vertex),
+                )
+                .collect_vec()
+        };
+
+        use ShapeDrawKind::*;
+
+        // indices are allocated in pairs
+        assert_eq!(draws(&segment), [(Indexed, 4..7, 3), (Rects, 1..2, 0)]);
+
+        // painting as much again keeps the ranges, so nothing else is uploaded
+        allocators.fit(&mut segment.ranges, &segment.buffer);
+        assert_eq!(draws(&segment), [(Indexed, 4..7, 3), (Rects, 1..2, 0)]);
+
+        // once the first segment is freed, a larger paint moves into its ranges
+        allocators.free(first);
+        segment.buffer.push_vertices(tri, [0, 1, 2]);
+        allocators.fit(&mut segment.ranges, &segment.buffer);
+
+        assert_eq!(segment.ranges.vertices, 0..6);
+        assert_eq!(segment.ranges.indices, 0..6);
+        assert_eq!(segment.ranges.instances, 1..2);
+        assert!(!allocators.is_fragmented());
     }
 }
@@ -1,3 +1,4 @@
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::{Arc, RwLock};
 
 use wgpu::rwh::{
@@ -50,9 +51,10 @@ struct ScreenDescriptor {
 
 #[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
 pub enum MultisampleMode {
+    /// Rectangles and meshes are anti-aliased without multisampling
+    #[default]
     None,
     MSAA2x,
-    #[default]
     MSAA4x,
     MSAA8x,
     MSAA16x,
@@ -78,6 +80,28 @@ pub struct RenderSurface<'window> {
 
     multisampled_framebuffer: Option<wgpu::Texture>,
     multisample_mode: MultisampleMode,
+
+    /// Texture frames are drawn into before they're copied to the surface. wgpu
+    /// doesn't expose the age of surface textures, so this keeps the previous
+    /// frame around for partial redraws.
+    back_buffer: Option<wgpu::Texture>,
+    buffer_age: BufferAge,
+}
+
+/// Number of frames since a target's contents were drawn, or 0 if they're
+/// undefined. Targets which persist between frames have an age of 1.
+#[derive(Default)]
+struct BufferAge(AtomicU32);
+
+impl BufferAge {
+    fn reset(&self) {
+        self.0.store(0, Ordering::Relaxed);
+    }
+
+    /// Returns the age of the target's contents, which will be drawn this frame
+    fn next_frame(&self) -> u32 {
+        self.0.swap(1, Ordering::Relaxed)
+    }
 }
 
 struct TextureInfoInner {
@@ -117,10 +141,23 @@ pub struct RenderingContext {
     pub texture_info: TextureInfo,
 }
 
+/// Common interface for anything a [`crate::scene::scene::Scene`] can lay out
+/// and render into.
+pub trait SurfaceLike {
+    fn clone_rendering_context(&self) -> Arc<RenderingContext>;
+    fn rendering_context(&self) -> &RenderingContext;
+    fn device_scale_factor(&self) -> DeviceScaleFactor;
+    fn get_size(&self) -> winit::dpi::PhysicalSize<u32>;
+}
+
 pub struct RenderAttachment {
     pub window_texture: wgpu::SurfaceTexture,
     pub msaa_view: Option<wgpu::TextureView>,
     pub num_samples: u32,
+    /// Drawn into instead of `window_texture`, and copied to it afterwards
+    pub back_buffer: Option<wgpu::Texture>,
+    /// Frames since `back_buffer` was drawn, or 0 if it has to be redrawn fully
+    pub buffer_age: u32,
 }
 
 impl<'window> RenderSurface<'window> {
@@ -161,7 +198,8 @@ impl<'window> RenderSurface<'window> {
         let (device, queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
-                    required_features: wgpu::Features::empty(),
+                    // for gpu timings, if available
+                    required_features: adapter.features() & wgpu::Features::TIMESTAMP_QUERY,
                     // WebGL doesn't support all of wgpu's features, so if
                     // we're building for the web we'll have to disable some.
                     required_limits: wgpu::Limits::default(),
@@ -195,7 +233,11 @@ impl<'window> RenderSurface<'window> {
             .unwrap_or(surface_caps.formats[0]);
 
         let config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            // copying is needed to capture frames and for partial redraws, but
+            // isn't supported everywhere
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                | (surface_caps.usages
+                    & (wgpu::TextureUsages::COPY_SRC | wgpu::TextureUsages::COPY_DST)),
             format: texture_format,
             width: size.width,
             height: size.height,
@@ -244,13 +286,45 @@ impl<'window> RenderSurface<'window> {
 
             multisample_mode,
             multisampled_framebuffer: None,
+
+            back_buffer: None,
+            buffer_age: Default::default(),
         };
 
         render_surface.configure_multisampled_framebuffer();
+        render_surface.configure_back_buffer();
 
         render_surface
     }
 
+    fn configure_back_buffer(&mut self) {
+        self.buffer_age.reset();
+
+        // multisampled frames are resolved fully, so there's nothing to keep
+        let supported = self.config.usage.contains(wgpu::TextureUsages::COPY_DST)
+            && self.multisample_mode.num_samples() == 1;
+
+        self.back_buffer = supported.then(|| {
+            self.rendering_context
+                .device
+                .create_texture(&wgpu::TextureDescriptor {
+                    size: wgpu::Extent3d {
+                        width: self.config.width,
+                        height: self.config.height,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: self.config.format,
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                        | wgpu::TextureUsages::COPY_SRC,
+                    label: Some("back buffer"),
+                    view_formats: &[],
+                })
+        });
+    }
+
     fn configure_multisampled_framebuffer(&mut self) {
         let num_samples = self.multisample_mode.num_samples();
 
@@ -289,6 +363,7 @@ impl<'window> RenderSurface<'window> {
                 .configure(&self.rendering_context.device, &self.config);
 
             self.configure_multisampled_framebuffer();
+            self.configure_back_buffer();
 
             if let Some(scale_factor) = scale_factor {
                 self.screen_descriptor.scale_factor =
@@ -314,6 +389,11 @@ impl<'window> RenderSurface<'window> {
             window_texture,
             msaa_view,
             num_samples: self.multisample_mode.num_samples(),
+            back_buffer: self.back_buffer.clone(),
+            buffer_age: match self.back_buffer {
+                Some(_) => self.buffer_age.next_frame(),
+                None => 0,
+            },
         })
     }
 
@@ -338,6 +418,24 @@ impl<'window> RenderSurface<'window> {
     }
 }
 
+impl<'window> SurfaceLike for RenderSurface<'window> {
+    fn clone_rendering_context(&self) -> Arc<RenderingContext> {
+        RenderSurface::clone_rendering_context(self)
+    }
+
+    fn rendering_context(&self) -> &RenderingContext {
+        RenderSurface::rendering_context(self)
+    }
+
+    fn device_scale_factor(&self) -> DeviceScaleFactor {
+        RenderSurface::device_scale_factor(self)
+    }
+
+    fn get_size(&self) -> winit::dpi::PhysicalSize<u32> {
+        RenderSurface::get_size(self)
+    }
+}
+
 pub struct WindowSurface {
     surface: RenderSurface<'static>,
     window: Window,
@@ -360,4 +458,376 @@ impl WindowSurface {
         // yes mom i know what i'm doing (i don't)
         unsafe { std::mem::transmute( &mut self.surface) }
     }
-}
\ No newline at end of file
+}
+
+/// A headless render target backed by a plain texture, for rendering a scene
+/// without a window (tests, thumbnails, CI).
+pub struct OffscreenSurface {
+    rendering_context: Arc<RenderingContext>,
+    screen_descriptor: ScreenDescriptor,
+
+    texture: wgpu::Texture,
+    buffer_age: BufferAge,
+
+    multisampled_framebuffer: Option<wgpu::Texture>,
+    multisample_mode: MultisampleMode,
+}
+
+impl OffscreenSurface {
+    /// Format of the offscreen texture, and of the pixels returned by [`Self::read_pixels`].
+    pub const TEXTURE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+    /// Creates an offscreen surface on the default adapter, falling back to a
+    /// software adapter if no hardware adapter is available.
+    pub async fn new(size: winit::dpi::PhysicalSize<u32>, scale_factor: f64) -> Self {
+        Self::new_with_options(size, scale_factor, false, Default::default()).await
+    }
+
+    /// Creates an offscreen surface on a software (fallback) adapter.
+    pub async fn new_software(size: winit::dpi::PhysicalSize<u32>, scale_factor: f64) -> Self {
+        Self::new_with_options(size, scale_factor, true, Default::default()).await
+    }
+
+    pub async fn new_with_options(
+        size: winit::dpi::PhysicalSize<u32>,
+        scale_factor: f64,
+        force_fallback_adapter: bool,
+        multisample_mode: MultisampleMode,
+    ) -> Self {
+        Self::try_new_with_options(size, scale_factor, force_fallback_adapter, multisample_mode)
+            .await
+            .expect("No adapter available for offscreen rendering")
+    }
+
+    /// Like [`Self::new_with_options`], but returns `None` if neither the
+    /// requested nor the fallback adapter is available
+    pub async fn try_new_with_options(
+        size: winit::dpi::PhysicalSize<u32>,
+        scale_factor: f64,
+        force_fallback_adapter: bool,
+        multisample_mode: MultisampleMode,
+    ) -> Option<Self> {
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::all(),
+            flags: wgpu::InstanceFlags::empty(),
+            backend_options: Default::default(),
+        });
+
+        let request_adapter = |force_fallback_adapter| {
+            instance.request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::default(),
+                compatible_surface: None,
+                force_fallback_adapter,
+            })
+        };
+
+        let adapter = match request_adapter(force_fallback_adapter).await {
+            Ok(adapter) => adapter,
+            Err(_) if !force_fallback_adapter => request_adapter(true).await.ok()?,
+            Err(err) => {
+                log::warn!("No fallback adapter available: {err:?}");
+                return None;
+            }
+        };
+
+        log::debug!("offscreen adapter: {:?}", adapter.get_info());
+
+        let (device, queue) = adapter
+            .request_device(&wgpu::DeviceDescriptor {
+                required_features: adapter.features() & wgpu::Features::TIMESTAMP_QUERY,
+                required_limits: wgpu::Limits::downlevel_defaults()
+                    .using_resolution(adapter.limits()),
+                label: None,
+                memory_hints: Default::default(),
+                trace: Default::default(),
+            })
+            .await
+            .ok()?;
+
+        let params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("params buffer"),
+            size: std::mem::size_of::<ParamsBuffer>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let rendering_context = RenderingContext {
+            device,
+            params_buffer,
+            queue,
+            texture_format: Self::TEXTURE_FORMAT,
+            texture_info: TextureInfo::new(multisample_mode.num_samples()),
+            adapter,
+        }
+        .into();
+
+        Some(Self::from_rendering_context(
+            rendering_context,
+            size,
+            scale_factor,
+            multisample_mode,
+        ))
+    }
+
+    /// A surface for tests which render, or `None` if the machine has no
+    /// adapter, not even a software one
+    #[cfg(test)]
+    pub(crate) fn for_tests(width: u32, height: u32) -> Option<Self> {
+        pollster::block_on(Self::try_new_with_options(
+            winit::dpi::PhysicalSize::new(width, height),
+            1.,
+            false,
+            Default::default(),
+        ))
+    }
+
+    /// Creates an offscreen surface sharing an existing rendering context.
+    ///
+    /// The context's texture format and sample count must match `multisample_mode`
+    /// and [`Self::TEXTURE_FORMAT`], since the shape pipeline is built against them.
+    pub fn from_rendering_context(
+        rendering_context: Arc<RenderingContext>,
+        size: winit::dpi::PhysicalSize<u32>,
+        scale_factor: f64,
+        multisample_mode: MultisampleMode,
+    ) -> Self {
+        let size = winit::dpi::PhysicalSize::new(size.width.max(1), size.height.max(1));
+
+        let texture = Self::create_texture(&rendering_context, size);
+
+        let mut offscreen_surface = Self {
+            rendering_context,
+            screen_descriptor: ScreenDescriptor {
+                size,
+                scale_factor: DeviceScaleFactor::from_float(scale_factor as f32),
+            },
+            texture,
+            buffer_age: Default::default(),
+            multisampled_framebuffer: None,
+            multisample_mode,
+        };
+
+        offscreen_surface.configure_multisampled_framebuffer();
+        offscreen_surface.write_params();
+
+        offscreen_surface
+    }
+
+    fn create_texture(
+        rendering_context: &RenderingContext,
+        size: winit::dpi::PhysicalSize<u32>,
+    ) -> wgpu::Texture {
+        rendering_context
+            .device
+            .create_texture(&wgpu::TextureDescriptor {
+                label: Some("offscreen surface texture"),
+                size: wgpu::Extent3d {
+                    width: size.width,
+                    height: size.height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: rendering_context.texture_format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::COPY_SRC
+                    | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            })
+    }
+
+    fn configure_multisampled_framebuffer(&mut self) {
+        let num_samples = self.multisample_mode.num_samples();
+        let size = self.screen_descriptor.size;
+
+        self.multisampled_framebuffer = (num_samples > 1).then(|| {
+            self.rendering_context
+                .device
+                .create_texture(&wgpu::TextureDescriptor {
+                    size: wgpu::Extent3d {
+                        width: size.width,
+                        height: size.height,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: num_samples,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: self.rendering_context.texture_format,
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                    label: Some("offscreen multisampled framebuffer"),
+                    view_formats: &[],
+                })
+        });
+    }
+
+    fn write_params(&self) {
+        let params = ParamsBuffer {
+            screen_resolution: self.screen_descriptor.size.into(),
+            scale_fac: self.screen_descriptor.scale_factor.as_float().get(),
+            padding: 0,
+        };
+
+        self.rendering_context.queue.write_buffer(
+            &self.rendering_context.params_buffer,
+            0,
+            bytemuck::bytes_of(&params),
+        );
+    }
+
+    pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>, scale_factor: Option<f64>) {
+        if new_size.width == 0 || new_size.height == 0 {
+            return;
+        }
+
+        if new_size != self.screen_descriptor.size {
+            self.screen_descriptor.size = new_size;
+            self.texture = Self::create_texture(&self.rendering_context, new_size);
+            self.buffer_age.reset();
+            self.configure_multisampled_framebuffer();
+        }
+
+        if let Some(scale_factor) = scale_factor {
+            self.screen_descriptor.scale_factor =
+                DeviceScaleFactor::from_float(scale_factor as f32);
+        }
+
+        self.write_params();
+    }
+
+    pub(crate) fn get_views(&self) -> (wgpu::TextureView, Option<wgpu::TextureView>) {
+        (
+            self.texture
+                .create_view(&wgpu::TextureViewDescriptor::default()),
+            self.multisampled_framebuffer
+                .as_ref()
+                .map(|tex| tex.create_view(&wgpu::TextureViewDescriptor::default())),
+        )
+    }
+
+    pub fn texture(&self) -> &wgpu::Texture {
+        &self.texture
+    }
+
+    /// Frames since the texture was drawn, or 0 if it has to be redrawn fully
+    pub(crate) fn next_buffer_age(&self) -> u32 {
+        match self.multisampled_framebuffer {
+            // multisampled frames are resolved fully, so there's nothing to keep
+            Some(_) => 0,
+            None => self.buffer_age.next_frame(),
+        }
+    }
+
+    pub fn multisample_mode(&self) -> MultisampleMode {
+        self.multisample_mode
+    }
+
+    /// Copies the contents of the offscreen texture back to the CPU as tightly
+    /// packed, row-major RGBA8 (sRGB-encoded) pixels.
+    pub fn read_pixels(&self) -> Vec<u8> {
+        let size = self.screen_descriptor.size;
+
+        read_texture_rgba8(
+            &self.rendering_context,
+            &self.texture,
+            wgpu::Origin3d::ZERO,
+            size.width,
+            size.height,
+        )
+    }
+}
+
+impl SurfaceLike for OffscreenSurface {
+    fn clone_rendering_context(&self) -> Arc<RenderingContext> {
+        self.rendering_context.clone()
+    }
+
+    fn rendering_context(&self) -> &RenderingContext {
+        &self.rendering_context
+    }
+
+    fn device_scale_factor(&self) -> DeviceScaleFactor {
+        self.screen_descriptor.scale_factor
+    }
+
+    fn get_size(&self) -> winit::dpi::PhysicalSize<u32> {
+        self.screen_descriptor.size
+    }
+}
+
+/// Reads back a region of a 4 byte-per-texel texture, stripping the row padding
+/// required by `copy_texture_to_buffer`.
+pub(crate) fn read_texture_rgba8(
+    RenderingContext { device, queue, .. }: &RenderingContext,
+    texture: &wgpu::Texture,
+    origin: wgpu::Origin3d,
+    width: u32,
+    height: u32,
+) -> Vec<u8> {
+    let unpadded_bytes_per_row = width * 4;
+    let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+        * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("texture readback buffer"),
+        size: (padded_bytes_per_row * height) as u64,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("texture readback encoder"),
+    });
+
+    encoder.copy_texture_to_buffer(
+        wgpu::TexelCopyTextureInfo {
+            texture,
+            mip_level: 0,
+            origin,
+            aspect: wgpu::TextureAspect::All,
+        },
+        wgpu::TexelCopyBufferInfo {
+            buffer: &buffer,
+            layout: wgpu::TexelCopyBufferLayout {
+                offset: 0,
+                bytes_per_row: Some(padded_bytes_per_row),
+                rows_per_image: Some(height),
+            },
+        },
+        wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+    );
+
+    queue.submit(std::iter::once(encoder.finish()));
+
+    let buffer_slice = buffer.slice(..);
+    let (sender, receiver) = std::sync::mpsc::channel();
+
+    buffer_slice.map_async(wgpu::MapMode::Read, move |result| {
+        let _ = sender.send(result);
+    });
+
+    device.poll(wgpu::PollType::Wait).unwrap();
+
+    receiver
+        .recv()
+        .unwrap()
+        .expect("Failed to map texture readback buffer");
+
+    let mut pixels = Vec::with_capacity((unpadded_bytes_per_row * height) as usize);
+
+    {
+        let padded_data = buffer_slice.get_mapped_range();
+
+        for row in padded_data.chunks(padded_bytes_per_row as usize) {
+            pixels.extend_from_slice(&row[..unpadded_bytes_per_row as usize]);
+        }
+    }
+
+    buffer.unmap();
+
+    pixels
+}
@@ -0,0 +1,787 @@
+use std::{collections::HashMap, path::Path as FilePath, str::FromStr};
+
+use lyon::path::{builder::WithSvg, path::BuilderImpl, traits::SvgPathBuilder, ArcFlags, Path};
+use palette::WithAlpha;
+
+use crate::{
+    color::{ColorRgba, ColorSrgb},
+    math::{Angle, CoordinateTransform, Pos, Rect, Size, Vector},
+    path::{lyon_transform, FillRule, LineCap, LineJoin, PaintPath, StrokeStyle},
+    util::svg::{build_lyon_from_svg_path_data, SVGAttributes, SVGParser, SVGPathData},
+};
+
+type Transform = CoordinateTransform;
+
+/// The size of documents without `width`, `height` or `viewBox` attributes
+const DEFAULT_SIZE: Size = Size::new(300., 150.);
+
+#[derive(Debug)]
+pub enum SvgError {
+    Io(std::io::Error),
+    Parse(::svg::parser::Error),
+    /// The document has no root `<svg>` element
+    MissingRoot,
+}
+
+impl From<std::io::Error> for SvgError {
+    fn from(value: std::io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+
+impl From<::svg::parser::Error> for SvgError {
+    fn from(value: ::svg::parser::Error) -> Self {
+        Self::Parse(value)
+    }
+}
+
+pub type SvgResult<T> = Result<T, SvgError>;
+
+/// A color in a document, which may be left to the element painting it
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum SvgPaint {
+    Color(ColorRgba),
+    CurrentColor { opacity: f32 },
+}
+
+impl SvgPaint {
+    #[inline(always)]
+    fn resolve(self, current_color: ColorRgba) -> ColorRgba {
+        match self {
+            Self::Color(color) => color,
+            Self::CurrentColor { opacity } => {
+                current_color.with_alpha(current_color.alpha * opacity)
+            }
+        }
+    }
+
+    #[inline(always)]
+    fn with_opacity(self, opacity: f32) -> Self {
+        match self {
+            Self::Color(color) => Self::Color(color.with_alpha(color.alpha * opacity)),
+            Self::CurrentColor { opacity: x } => Self::CurrentColor {
+                opacity: x * opacity,
+            },
+        }
+    }
+}
+
+/// A shape in the coordinates of the document's `viewBox`
+#[derive(Clone, Debug)]
+struct SvgShape {
+    path: Path,
+    fill: Option<SvgPaint>,
+    fill_rule: FillRule,
+    stroke: Option<(SvgPaint, StrokeStyle)>,
+}
+
+/// A parsed SVG document, which is painted as [`PaintPath`]s.
+///
+/// Groups, transforms, fills, strokes, opacity and the basic shapes are
+/// supported. Gradients, patterns, clip paths, masks, `<use>` and text are
+/// skipped. Group opacity is applied to each shape individually.
+#[derive(Clone, Debug)]
+pub struct SvgDocument {
+    size: Size,
+    view_box: Rect,
+    shapes: Vec<SvgShape>,
+}
+
+impl FromStr for SvgDocument {
+    type Err = SvgError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl SvgDocument {
+    pub fn parse(source: &str) -> SvgResult<Self> {
+        DocumentParser::default().parse(source)
+    }
+
+    pub fn open(path: impl AsRef<FilePath>) -> SvgResult<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// The size given by the `width` and `height` attributes, falling back to
+    /// the size of the `viewBox`
+    #[inline(always)]
+    pub fn size(&self) -> Size {
+        self.size
+    }
+
+    #[inline(always)]
+    pub fn view_box(&self) -> Rect {
+        self.view_box
+    }
+
+    /// Maps the `viewBox` into `rect`, preserving its aspect ratio and centering
+    /// it (like `preserveAspectRatio="xMidYMid meet"`)
+    pub fn view_box_transform(&self, rect: Rect) -> CoordinateTransform {
+        let view_box_size = self.view_box.size();
+
+        let scale = f32::min(
+            rect.width() / view_box_size.width,
+            rect.height() / view_box_size.height,
+        );
+
+        let scale = if scale.is_finite() { scale } else { 0. };
+
+        let offset = rect.min.to_vector() + (rect.size() - view_box_size * scale).to_vector() / 2.;
+
+        Transform::translation(-self.view_box.min.x, -self.view_box.min.y)
+            .then(&Transform::scale(scale, scale))
+            .then(&Transform::translation(offset.x, offset.y))
+    }
+
+    /// The shapes of the document fitted into `rect`, where `current_color`
+    /// is used for `currentColor` fills and strokes
+    pub fn paths(
+        &self,
+        rect: Rect,
+        current_color: ColorRgba,
+    ) -> impl Iterator<Item = PaintPath> + '_ {
+        let transform = self.view_box_transform(rect);
+
+        self.shapes.iter().map(move |shape| {
+            let mut path = PaintPath::new(shape.path.clone()).with_fill_rule(shape.fill_rule);
+
+            if let Some(fill) = shape.fill {
+                path = path.with_fill(fill.resolve(current_color));
+            }
+
+            if let Some((stroke, style)) = &shape.stroke {
+                path = path.with_stroke(stroke.resolve(current_color), style.clone());
+            }
+
+            path.transformed(&transform)
+        })
+    }
+}
+
+/// Inherited presentation attributes of an element
+#[derive(Clone, Debug)]
+struct SvgStyle {
+    transform: CoordinateTransform,
+    color: SvgPaint,
+    fill: Option<SvgPaint>,
+    fill_rule: FillRule,
+    fill_opacity: f32,
+    stroke: Option<SvgPaint>,
+    stroke_opacity: f32,
+    stroke_style: StrokeStyle,
+    /// The product of the opacities of the element and its ancestors
+    opacity: f32,
+}
+
+impl Default for SvgStyle {
+    fn default() -> Self {
+        let black = SvgPaint::Color(ColorRgba::new(0., 0., 0., 1.));
+
+        Self {
+            transform: Transform::identity(),
+            // left to the element, unless the document sets `color`
+            color: SvgPaint::CurrentColor { opacity: 1. },
+            fill: Some(black),
+            fill_rule: FillRule::NonZero,
+            fill_opacity: 1.,
+            stroke: None,
+            stroke_opacity: 1.,
+            stroke_style: StrokeStyle::new(1.),
+            opacity: 1.,
+        }
+    }
+}
+
+impl SvgStyle {
+    /// The style of a child element with `attributes`
+    fn inherit(&self, attributes: &SVGAttributes) -> Self {
+        let mut style = self.clone();
+
+        let declarations = attributes
+            .get("style")
+            .map(|s| parse_style_declarations(s))
+            .unwrap_or_default();
+
+        // declarations in `style` take precedence over presentation attributes
+        let property = |name: &str| {
+            declarations
+                .get(name)
+                .map(String::as_str)
+                .or_else(|| attributes.get(name).map(|v| v.trim()))
+        };
+
+        if let Some(transform) = attributes.get("transform") {
+            style.transform = parse_transform(transform).then(&self.transform);
+        }
+
+        // resolved first, since `currentColor` refers to it
+        if let Some(color) = property("color").and_then(|c| parse_paint(c, self.color)) {
+            style.color = color;
+        }
+
+        if let Some(fill) = property("fill") {
+            style.fill = parse_paint(fill, style.color);
+        }
+
+        if let Some(stroke) = property("stroke") {
+            style.stroke = parse_paint(stroke, style.color);
+        }
+
+        if let Some(fill_rule) = property("fill-rule") {
+            style.fill_rule = match fill_rule {
+                "evenodd" => FillRule::EvenOdd,
+                _ => FillRule::NonZero,
+            };
+        }
+
+        let parse_opacity = |name| {
+            property(name)
+                .and_then(parse_number)
+                .map(|x| x.clamp(0., 1.))
+        };
+
+        if let Some(opacity) = parse_opacity("fill-opacity") {
+            style.fill_opacity = opacity;
+        }
+
+        if let Some(opacity) = parse_opacity("stroke-opacity") {
+            style.stroke_opacity = opacity;
+        }
+
+        if let Some(opacity) = parse_opacity("opacity") {
+            style.opacity *= opacity;
+        }
+
+        if let Some(width) = property("stroke-width").and_then(parse_length) {
+            style.stroke_style.width = width;
+        }
+
+        if let Some(join) = property("stroke-linejoin") {
+            style.stroke_style.join = match join {
+                "round" => LineJoin::Round,
+                "bevel" => LineJoin::Bevel,
+                "miter-clip" => LineJoin::MiterClip,
+                _ => LineJoin::Miter,
+            };
+        }
+
+        if let Some(cap) = property("stroke-linecap") {
+            style.stroke_style.cap = match cap {
+                "round" => LineCap::Round,
+                "square" => LineCap::Square,
+                _ => LineCap::Butt,
+            };
+        }
+
+        if let Some(miter_limit) = property("stroke-miterlimit").and_then(parse_number) {
+            style.stroke_style.miter_limit = miter_limit;
+        }
+
+        if let Some(dashes) = property("stroke-dasharray") {
+            style.stroke_style.dashes = match dashes {
+                "none" => Default::default(),
+                dashes => parse_numbers(dashes),
+            };
+        }
+
+        if let Some(dash_offset) = property("stroke-dashoffset").and_then(parse_length) {
+            style.stroke_style.dash_offset = dash_offset;
+        }
+
+        style
+    }
+
+    fn is_hidden(attributes: &SVGAttributes) -> bool {
+        let in_style = attributes.get("style").is_some_and(|s| {
+            parse_style_declarations(s)
+                .get("display")
+                .is_some_and(|d| d == "none")
+        });
+
+        in_style
+            || attributes
+                .get("display")
+                .is_some_and(|d| d.trim() == "none")
+    }
+
+    /// Builds a shape out of `path`, which is in the coordinates of the element
+    fn shape(&self, path: Path) -> SvgShape {
+        let fill = self
+            .fill
+            .map(|fill| fill.with_opacity(self.fill_opacity * self.opacity));
+
+        let stroke = self
+            .stroke
+            .filter(|_| self.stroke_style.width > 0.)
+            .map(|stroke| {
+                (
+                    stroke.with_opacity(self.stroke_opacity * self.opacity),
+                    self.stroke_style.clone(),
+                )
+            });
+
+        let scale = self.transform.determinant().abs().sqrt();
+
+        SvgShape {
+            path: path.transformed(&lyon_transform(&self.transform)),
+            fill,
+            fill_rule: self.fill_rule,
+            stroke: stroke.map(|(stroke, style)| (stroke, style.scaled(scale))),
+        }
+    }
+}
+
+/// Elements whose children are never rendered directly
+const SKIPPED_ELEMENTS: &[&str] = &[
+    "defs",
+    "clipPath",
+    "mask",
+    "marker",
+    "pattern",
+    "symbol",
+    "linearGradient",
+    "radialGradient",
+    "filter",
+    "style",
+    "script",
+    "text",
+    "title",
+    "desc",
+    "metadata",
+    "foreignObject",
+];
+
+const GROUP_ELEMENTS: &[&str] = &["svg", "g", "a", "switch"];
+
+#[derive(Default)]
+struct DocumentParser {
+    root: Option<(Size, Rect)>,
+    styles: Vec<SvgStyle>,
+    shapes: Vec<SvgShape>,
+    /// Depth into an element which is skipped along with its children
+    skip_depth: usize,
+}
+
+impl DocumentParser {
+    fn parse(mut self, source: &str) -> SvgResult<SvgDocument> {
+        use ::svg::{node::element::tag::Type, parser::Event};
+
+        for event in SVGParser::new(source) {
+            let (name, ty, attributes) = match event {
+                Event::Error(err) => return Err(err.into()),
+                Event::Tag(name, ty, attributes) => (name, ty, attributes),
+                _ => continue,
+            };
+
+            if self.skip_depth > 0 {
+                match ty {
+                    Type::Start => self.skip_depth += 1,
+                    Type::End => self.skip_depth -= 1,
+                    Type::Empty => {}
+                }
+
+                continue;
+            }
+
+            if ty == Type::End {
+                if GROUP_ELEMENTS.contains(&name) {
+                    self.styles.pop();
+                }
+
+                continue;
+            }
+
+            if SKIPPED_ELEMENTS.contains(&name) || SvgStyle::is_hidden(&attributes) {
+                if ty == Type::Start {
+                    self.skip_depth = 1;
+                }
+
+                continue;
+            }
+
+            if name == "svg" && self.root.is_none() {
+                self.root = Some(parse_root(&attributes));
+            } else if self.root.is_none() {
+                continue;
+            }
+
+            let style = self
+                .styles
+                .last()
+                .cloned()
+                .unwrap_or_default()
+                .inherit(&attributes);
+
+            if GROUP_ELEMENTS.contains(&name) {
+                if ty == Type::Start {
+                    self.styles.push(style);
+                }
+
+                continue;
+            }
+
+            if name == "use" {
+                log::debug!("Skipping unsupported svg element <use>");
+                continue;
+            }
+
+            if let Some(path) = shape_path(name, &attributes) {
+                self.shapes.push(style.shape(path));
+            }
+        }
+
+        let (size, view_box) = self.root.ok_or(SvgError::MissingRoot)?;
+
+        Ok(SvgDocument {
+            size,
+            view_box,
+            shapes: self.shapes,
+        })
+    }
+}
+
+/// The intrinsic size and `viewBox` of the root element
+fn parse_root(attributes: &SVGAttributes) -> (Size, Rect) {
+    let view_box = attributes
+        .get("viewBox")
+        .map(|v| parse_numbers(v))
+        .filter(|v| v.len() == 4 && v[2] > 0. && v[3] > 0.)
+        .map(|v| Rect::from_min_size(Pos::new(v[0], v[1]), Size::new(v[2], v[3])));
+
+    let width = attributes.get("width").and_then(|w| parse_length(w));
+    let height = attributes.get("height").and_then(|h| parse_length(h));
+
+    let size = match (width, height, view_box) {
+        (Some(width), Some(height), _) => Size::new(width, height),
+        // the missing dimension follows the aspect ratio of the view box
+        (Some(width), None, Some(v)) => Size::new(width, width * v.height() / v.width()),
+        (None, Some(height), Some(v)) => Size::new(height * v.width() / v.height(), height),
+        (None, None, Some(v)) => v.size(),
+        (width, height, None) => Size::new(
+            width.unwrap_or(DEFAULT_SIZE.width),
+            height.unwrap_or(DEFAULT_SIZE.height),
+        ),
+    };
+
+    let view_box = view_box.unwrap_or_else(|| Rect::from_min_size(Pos::zero(), size));
+
+    (size, view_box)
+}
+
+/// The outline of a basic shape or `<path>`, in the coordinates of the element
+fn shape_path(name: &str, attributes: &SVGAttributes) -> Option<Path> {
+    let attr = |name: &str| attributes.get(name).and_then(|v| parse_length(v));
+    let attr_or_zero = |name: &str| attr(name).unwrap_or(0.);
+
+    let mut builder = Path::svg_builder();
+
+    match name {
+        "path" => {
+            let data = attributes.get("d")?;
+
+            match SVGPathData::parse(data) {
+                Ok(data) => build_lyon_from_svg_path_data(&data, &mut builder),
+                Err(err) => {
+                    log::warn!("Failed to parse svg path data: {err}");
+                    return None;
+                }
+            }
+        }
+
+        "rect" => {
+            let (x, y) = (attr_or_zero("x"), attr_or_zero("y"));
+            let (width, height) = (attr("width")?, attr("height")?);
+
+            if width <= 0. || height <= 0. {
+                return None;
+            }
+
+            // a missing radius defaults to the other one
+            let (rx, ry) = match (attr("rx"), attr("ry")) {
+                (Some(rx), Some(ry)) => (rx, ry),
+                (Some(r), None) | (None, Some(r)) => (r, r),
+                (None, None) => (0., 0.),
+            };
+
+            let (rx, ry) = (rx.clamp(0., width / 2.), ry.clamp(0., height / 2.));
+
+            let corner = |builder: &mut WithSvg<BuilderImpl>, to: Pos| {
+                if rx > 0. && ry > 0. {
+                    builder.arc_to(
+                        lyon::math::vector(rx, ry),
+                        lyon::math::Angle::zero(),
+                        ArcFlags {
+                            large_arc: false,
+                            sweep: true,
+                        },
+                        lyon::math::point(to.x, to.y),
+                    );
+                }
+            };
+
+            builder.move_to(lyon::math::point(x + rx, y));
+            builder.line_to(lyon::math::point(x + width - rx, y));
+            corner(&mut builder, Pos::new(x + width, y + ry));
+            builder.line_to(lyon::math::point(x + width, y + height - ry));
+            corner(&mut builder, Pos::new(x + width - rx, y + height));
+            builder.line_to(lyon::math::point(x + rx, y + height));
+            corner(&mut builder, Pos::new(x, y + height - ry));
+            builder.line_to(lyon::math::point(x, y + ry));
+            corner(&mut builder, Pos::new(x + rx, y));
+            builder.close();
+        }
+
+        "circle" | "ellipse" => {
+            let center: Pos = Pos::new(attr_or_zero("cx"), attr_or_zero("cy"));
+
+            let radii: Vector = match name {
+                "circle" => Vector::splat(attr("r")?),
+                _ => Vector::new(attr("rx")?, attr("ry")?),
+            };
+
+            if radii.x <= 0. || radii.y <= 0. {
+                return None;
+            }
+
+            let flags = ArcFlags {
+                large_arc: false,
+                sweep: true,
+            };
+            let radii = lyon::math::vector(radii.x, radii.y);
+
+            builder.move_to(lyon::math::point(center.x + radii.x, center.y));
+            builder.arc_to(
+                radii,
+                lyon::math::Angle::zero(),
+                flags,
+                lyon::math::point(center.x - radii.x, center.y),
+            );
+            builder.arc_to(
+                radii,
+                lyon::math::Angle::zero(),
+                flags,
+                lyon::math::point(center.x + radii.x, center.y),
+            );
+            builder.close();
+        }
+
+        "line" => {
+            builder.move_to(lyon::math::point(attr_or_zero("x1"), attr_or_zero("y1")));
+            builder.line_to(lyon::math::point(attr_or_zero("x2"), attr_or_zero("y2")));
+        }
+
+        "polyline" | "polygon" => {
+            let points = parse_numbers(attributes.get("points")?);
+            let mut points = points.chunks_exact(2);
+
+            let first = points.next()?;
+            builder.move_to(lyon::math::point(first[0], first[1]));
+
+            for point in points {
+                builder.line_to(lyon::math::point(point[0], point[1]));
+            }
+
+            if name == "polygon" {
+                builder.close();
+            }
+        }
+
+        _ => return None,
+    }
+
+    Some(builder.build())
+}
+
+/// Parses the declarations of a `style` attribute, e.g. `fill: red; opacity: 0.5`
+fn parse_style_declarations(style: &str) -> HashMap<String, String> {
+    style
+        .split(';')
+        .filter_map(|declaration| declaration.split_once(':'))
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .collect()
+}
+
+/// Parses a `fill` or `stroke` value, where `None` means no paint
+fn parse_paint(paint: &str, current_color: SvgPaint) -> Option<SvgPaint> {
+    match paint {
+        "none" | "transparent" => None,
+        "currentColor" => Some(current_color),
+        paint => {
+            let color = parse_color(paint);
+
+            if color.is_none() {
+                log::debug!("Skipping unsupported svg paint {paint:?}");
+            }
+
+            color.map(SvgPaint::Color)
+        }
+    }
+}
+
+fn parse_color(color: &str) -> Option<ColorRgba> {
+    let srgb = if color.starts_with('#') {
+        ColorSrgb::<u8>::from_str(color).ok()?
+    } else if let Some(args) = color
+        .strip_prefix("rgb(")
+        .or_else(|| color.strip_prefix("rgba("))
+        .and_then(|c| c.strip_suffix(')'))
+    {
+        let args = args
+            .split(|c: char| c == ',' || c == '/' || c.is_whitespace())
+            .filter(|arg| !arg.is_empty())
+            .collect::<Vec<_>>();
+
+        let channel = |arg: &str| match arg.strip_suffix('%') {
+            Some(percent) => percent.parse::<f32>().ok().map(|p| p / 100.),
+            None => arg.parse::<f32>().ok().map(|c| c / 255.),
+        };
+
+        let [r, g, b] = [args.first()?, args.get(1)?, args.get(2)?].map(|arg| channel(arg));
+
+        let alpha = match args.get(3) {
+            Some(alpha) => match alpha.strip_suffix('%') {
+                Some(percent) => percent.parse::<f32>().ok()? / 100.,
+                None => alpha.parse::<f32>().ok()?,
+            },
+            None => 1.,
+        };
+
+        return Some(
+            ColorSrgb::new(r?, g?, b?)
+                .into_linear()
+                .with_alpha(alpha.clamp(0., 1.)),
+        );
+    } else {
+        palette::named::from_str(&color.to_ascii_lowercase())?
+    };
+
+    Some(srgb.into_format::<f32>().into_linear().with_alpha(1.))
+}
+
+/// Parses a transform list, e.g. `translate(10 20) rotate(45)`
+fn parse_transform(transform: &str) -> CoordinateTransform {
+    let mut result = Transform::identity();
+
+    for function in transform.split_inclusive(')') {
+        let Some((name, args)) = function.split_once('(') else {
+            continue;
+        };
+
+        let args = parse_numbers(args.trim_end_matches(')'));
+        let arg = |i: usize| args.get(i).copied();
+
+        let transform = match (
+            name.trim_matches(|c: char| c == ',' || c.is_whitespace()),
+            args.len(),
+        ) {
+            ("matrix", 6) => Transform::new(args[0], args[1], args[2], args[3], args[4], args[5]),
+            ("translate", 1 | 2) => Transform::translation(args[0], arg(1).unwrap_or(0.)),
+            ("scale", 1 | 2) => Transform::scale(args[0], arg(1).unwrap_or(args[0])),
+            ("rotate", 1) => Transform::rotation(Angle::degrees(args[0])),
+            ("rotate", 3) => Transform::translation(-args[1], -args[2])
+                .then(&Transform::rotation(Angle::degrees(args[0])))
+                .then(&Transform::translation(args[1], args[2])),
+            ("skewX", 1) => Transform::new(1., 0., Angle::degrees(args[0]).get().tan(), 1., 0., 0.),
+            ("skewY", 1) => Transform::new(1., Angle::degrees(args[0]).get().tan(), 0., 1., 0., 0.),
+            (name, _) => {
+                log::debug!("Skipping invalid svg transform {name}({args:?})");
+                continue;
+            }
+        };
+
+        // later functions in the list are applied first
+        result = transform.then(&result);
+    }
+
+    result
+}
+
+/// Parses a list of numbers separated by whitespace and/or commas
+fn parse_numbers(numbers: &str) -> Vec<f32> {
+    numbers
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|n| !n.is_empty())
+        .filter_map(parse_number)
+        .collect()
+}
+
+#[inline(always)]
+fn parse_number(number: &str) -> Option<f32> {
+    number.trim().parse().ok()
+}
+
+/// Parses a length in user units. Percentages and font-relative units aren't
+/// supported.
+fn parse_length(length: &str) -> Option<f32> {
+    let length = length.trim();
+
+    let (number, scale) = [
+        ("px", 1.),
+        ("pt", 4. / 3.),
+        ("pc", 16.),
+        ("mm", 96. / 25.4),
+        ("cm", 96. / 2.54),
+        ("in", 96.),
+    ]
+    .into_iter()
+    .find_map(|(unit, scale)| length.strip_suffix(unit).map(|n| (n, scale)))
+    .unwrap_or((length, 1.));
+
+    parse_number(number).map(|n| n * scale)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transform_lists_apply_right_to_left() {
+        let transform = parse_transform("translate(10, 0) scale(2)");
+
+        assert_eq!(
+            transform.transform_point(Pos::new(1., 1.)),
+            Pos::new(12., 2.)
+        );
+    }
+
+    #[test]
+    fn root_size_follows_view_box() {
+        let document = SvgDocument::parse(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="48" viewBox="0 0 24 12">
+                <g fill="red" opacity="0.5">
+                    <rect x="2" y="2" width="10" height="4" />
+                    <circle cx="18" cy="6" r="4" fill="none" stroke="currentColor" />
+                </g>
+                <defs><rect width="1" height="1" /></defs>
+            </svg>"#,
+        )
+        .unwrap();
+
+        assert_eq!(document.size(), Size::new(48., 24.));
+        assert_eq!(document.shapes.len(), 2);
+        assert_eq!(
+            document.shapes[0]
+                .fill
+                .map(|f| f.resolve(ColorRgba::default()).alpha),
+            Some(0.5)
+        );
+        assert!(document.shapes[1].fill.is_none());
+        assert_eq!(
+            document.shapes[1].stroke.as_ref().map(|(s, _)| *s),
+            Some(SvgPaint::CurrentColor { opacity: 0.5 })
+        );
+    }
+
+    #[test]
+    fn colors() {
+        let red = ColorRgba::new(1., 0., 0., 1.);
+
+        assert_eq!(parse_color("#f00"), Some(red));
+        assert_eq!(parse_color("#ff0000"), Some(red));
+        assert_eq!(parse_color("rgb(255, 0, 0)"), Some(red));
+        assert_eq!(parse_color("Red"), Some(red));
+        assert_eq!(parse_color("url(#gradient)"), None);
+    }
+}
@@ -1,26 +1,14 @@
-use std::sync::{
-    atomic::{AtomicU32, Ordering},
-    Arc, RwLock, Weak,
-};
+use std::sync::{Arc, RwLock};
 
 use itertools::Itertools;
 use shrinkwraprs::Shrinkwrap;
 
 use crate::{surface::RenderingContext, text};
 
-use drain_filter_polyfill::VecExt;
-
 #[derive(Debug)]
 pub struct TextureRefInner {
     pub texture: wgpu::Texture,
     pub texture_view: wgpu::TextureView,
-    binding_idx: AtomicU32,
-}
-
-impl TextureRefInner {
-    pub(crate) fn get_binding_idx(&self) -> u32 {
-        self.binding_idx.load(Ordering::Relaxed)
-    }
 }
 
 #[derive(Clone, Debug, Shrinkwrap)]
@@ -35,46 +23,18 @@ impl From<Arc<TextureRefInner>> for TextureRef {
     }
 }
 
-#[derive(Clone, Debug, Shrinkwrap)]
-pub(crate) struct TextureWeakRef {
-    inner: Weak<TextureRefInner>,
-}
-
-impl From<Weak<TextureRefInner>> for TextureWeakRef {
-    #[inline(always)]
-    fn from(value: Weak<TextureRefInner>) -> Self {
-        Self { inner: value }
-    }
-}
-
-impl TextureWeakRef {
-    #[inline]
-    pub(crate) fn upgrade(&self) -> Option<TextureRef> {
-        self.inner.upgrade().map(Into::into)
-    }
-}
-
 impl TextureRef {
-    fn new(
-        texture: wgpu::Texture,
-        texture_view: Option<wgpu::TextureView>,
-        binding_idx: u32,
-    ) -> Self {
+    fn new(texture: wgpu::Texture, texture_view: Option<wgpu::TextureView>) -> Self {
         Self {
             inner: Arc::new(TextureRefInner {
                 texture_view: texture_view.unwrap_or_else(|| {
                     texture.create_view(&wgpu::TextureViewDescriptor::default())
                 }),
                 texture,
-                binding_idx: AtomicU32::new(binding_idx),
             }),
         }
     }
 
-    pub(crate) fn get_weak_ref(&self) -> TextureWeakRef {
-        Arc::downgrade(&self.inner).into()
-    }
-
     pub fn texture(&self) -> &wgpu::Texture {
         &self.inner.texture
     }
@@ -88,14 +48,24 @@ impl PartialEq for TextureRef {
 
 impl Eq for TextureRef {}
 
-#[derive(Debug)]
-pub enum TextureManagerError {
-    /// The texture manager has run out of texture slots.
-    /// The maximum number of textures is provided in the error.
-    OutOfTextureSlots(u32),
+/// A sink for the textures referenced by shader vertices
+pub trait PushTextures {
+    /// Stores `texture`, returning its index into the list of textures drawn
+    /// this frame
+    fn push_texture(&mut self, texture: &TextureRef) -> u16;
 }
 
-pub type TextureManagerResult<T> = Result<T, TextureManagerError>;
+impl PushTextures for Vec<TextureRef> {
+    fn push_texture(&mut self, texture: &TextureRef) -> u16 {
+        // most recently pushed textures (e.g. glyph atlases) are the most likely to repeat
+        let idx = self.iter().rposition(|t| t == texture).unwrap_or_else(|| {
+            self.push(texture.clone());
+            self.len() - 1
+        });
+
+        idx as u16
+    }
+}
 
 #[derive(Shrinkwrap, Debug, Clone)]
 pub struct TextureManagerRef(Arc<RwLock<TextureManager>>);
@@ -104,6 +74,22 @@ impl TextureManagerRef {
     pub(crate) fn new(max_textures: u32, ctx: &RenderingContext) -> Self {
         Self::from(TextureManager::new(max_textures, ctx))
     }
+
+    /// Creates a texture manager binding as many textures per draw call as the
+    /// device allows. Any number of textures can be registered, since draw calls
+    /// are split whenever they would sample more.
+    pub fn from_device_limits(ctx: &RenderingContext) -> Self {
+        let wgpu::Limits {
+            max_sampled_textures_per_shader_stage,
+            max_bindings_per_bind_group,
+            ..
+        } = ctx.device.limits();
+
+        Self::new(
+            max_sampled_textures_per_shader_stage.min(max_bindings_per_bind_group),
+            ctx,
+        )
+    }
 }
 
 impl From<TextureManager> for TextureManagerRef {
@@ -115,7 +101,6 @@ impl From<TextureManager> for TextureManagerRef {
 
 #[derive(Debug)]
 pub struct TextureManager {
-    textures: Vec<TextureWeakRef>,
     sampler: wgpu::Sampler,
 
     max_textures: u32,
@@ -155,6 +140,7 @@ impl TextureManager {
             address_mode_u: wgpu::AddressMode::ClampToEdge,
             address_mode_v: wgpu::AddressMode::ClampToEdge,
             mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
             ..Default::default()
         });
 
@@ -187,7 +173,6 @@ impl TextureManager {
             });
 
         Self {
-            textures: Default::default(),
             max_textures,
 
             _dummy_texture: dummy_texture,
@@ -203,39 +188,22 @@ impl TextureManager {
         &mut self,
         texture: wgpu::Texture,
         texture_view: impl Into<Option<wgpu::TextureView>>,
-    ) -> TextureManagerResult<TextureRef> {
-        let new_idx = self.textures.len() as u32;
-
-        if new_idx >= self.max_textures {
-            return Err(TextureManagerError::OutOfTextureSlots(self.max_textures));
-        }
-
-        let texture_ref = TextureRef::new(texture, texture_view.into(), new_idx);
-        self.textures.push(texture_ref.get_weak_ref());
-
-        Ok(texture_ref)
+    ) -> TextureRef {
+        TextureRef::new(texture, texture_view.into())
     }
 
-    pub(crate) fn register_texture(
-        &mut self,
-        texture: wgpu::Texture,
-    ) -> TextureManagerResult<TextureRef> {
+    pub(crate) fn register_texture(&mut self, texture: wgpu::Texture) -> TextureRef {
         self.register_texture_with_view(texture, None)
     }
 
-    pub(crate) fn generate_texture_bind_group(&mut self, device: &wgpu::Device) -> wgpu::BindGroup {
-        let _changed = self
-            .textures
-            .drain_filter(|t| t.upgrade().is_none())
-            .next()
-            .is_some();
-
-        let textures_to_allocate = self
-            .textures
-            .iter()
-            .map(TextureWeakRef::upgrade)
-            .flatten()
-            .collect_vec();
+    /// Binds `textures` to the slots matching their indices, where there can be
+    /// at most [`Self::get_max_textures`]
+    pub(crate) fn create_texture_bind_group(
+        &self,
+        device: &wgpu::Device,
+        textures: &[TextureRef],
+    ) -> wgpu::BindGroup {
+        debug_assert!(textures.len() <= self.max_textures as usize);
 
         device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("texture atlas bind group"),
@@ -244,15 +212,10 @@ impl TextureManager {
                 .map(|i| wgpu::BindGroupEntry {
                     binding: i,
 
-                    resource: wgpu::BindingResource::TextureView(
-                        match textures_to_allocate.get(i as usize) {
-                            Some(tex) => {
-                                tex.binding_idx.store(i, Ordering::Relaxed);
-                                &tex.texture_view
-                            }
-                            None => &self.dummy_texture_view,
-                        },
-                    ),
+                    resource: wgpu::BindingResource::TextureView(match textures.get(i as usize) {
+                        Some(tex) => &tex.texture_view,
+                        None => &self.dummy_texture_view,
+                    }),
                 })
                 .collect_vec(),
         })
@@ -480,6 +480,9 @@ impl Into<taffy::style::Style> for CSSLayoutBuilder {
 #[derive(From)]
 pub enum TaffyNodeContext {
     Text(Arc<Mutex<dyn TextCacheBufferLike>>),
+    /// A leaf with a natural size (e.g. an image), which keeps its aspect ratio
+    /// when only one dimension is known
+    IntrinsicSize(Size),
 }
 
 pub type TaffyEngine = taffy::TaffyTree<TaffyNodeContext>;
@@ -624,6 +627,19 @@ impl LayoutEngine {
 
                         result.into()
                     }
+
+                    Some(TaffyNodeContext::IntrinsicSize(size)) => {
+                        let aspect_ratio = size.width / size.height;
+
+                        let (width, height) = match (known_dimensions.width, known_dimensions.height) {
+                            (Some(width), Some(height)) => (width, height),
+                            (Some(width), None) if aspect_ratio.is_normal() => (width, width / aspect_ratio),
+                            (None, Some(height)) if aspect_ratio.is_normal() => (height * aspect_ratio, height),
+                            (width, height) => (width.unwrap_or(size.width), height.unwrap_or(size.height)),
+                        };
+
+                        taffy::Size { width, height }
+                    }
                 }
             })
     }
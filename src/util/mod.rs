@@ -7,6 +7,7 @@ pub use traits::*;
 pub mod guard;
 pub mod layout;
 pub mod math;
+pub mod sdf;
 pub mod svg;
 pub mod taffy;
 pub mod template;
@@ -0,0 +1,155 @@
+//! Signed distance fields from coverage masks, using the exact euclidean
+//! distance transform of Felzenszwalb & Huttenlocher (as in Mapbox's TinySDF)
+
+const INF: f64 = 1e20;
+
+/// Converts an 8-bit coverage mask into a signed distance field of the same
+/// size. 128 is the outline, and the distance to it is mapped linearly to
+/// `[0, 255]` over `spread` pixels on either side, higher being inside.
+///
+/// Partially covered pixels are treated as being that far from the outline,
+/// which keeps anti-aliased edges smooth.
+pub fn signed_distance_field(coverage: &[u8], width: usize, height: usize, spread: u32) -> Vec<u8> {
+    debug_assert_eq!(coverage.len(), width * height);
+
+    // squared distances to the outline, from pixels outside and inside of it
+    let mut outer = vec![0.; coverage.len()];
+    let mut inner = vec![0.; coverage.len()];
+
+    for (i, &value) in coverage.iter().enumerate() {
+        let a = value as f64 / 255.;
+
+        (outer[i], inner[i]) = match value {
+            255 => (0., INF),
+            0 => (INF, 0.),
+            _ => (f64::max(0., 0.5 - a).powi(2), f64::max(0., a - 0.5).powi(2)),
+        };
+    }
+
+    let mut scratch = Scratch::new(width.max(height));
+
+    edt(&mut outer, width, height, &mut scratch);
+    edt(&mut inner, width, height, &mut scratch);
+
+    let spread = spread.max(1) as f64;
+
+    outer
+        .iter()
+        .zip(inner.iter())
+        .map(|(outer, inner)| {
+            let dist = inner.sqrt() - outer.sqrt();
+            ((0.5 + dist / (2. * spread)).clamp(0., 1.) * 255.).round() as u8
+        })
+        .collect()
+}
+
+struct Scratch {
+    f: Vec<f64>,
+    v: Vec<usize>,
+    z: Vec<f64>,
+}
+
+impl Scratch {
+    fn new(len: usize) -> Self {
+        Self {
+            f: vec![0.; len],
+            v: vec![0; len],
+            z: vec![0.; len + 1],
+        }
+    }
+}
+
+/// 2D squared distance transform, in place
+fn edt(grid: &mut [f64], width: usize, height: usize, scratch: &mut Scratch) {
+    for x in 0..width {
+        edt_1d(grid, x, width, height, scratch);
+    }
+
+    for y in 0..height {
+        edt_1d(grid, y * width, 1, width, scratch);
+    }
+}
+
+/// 1D squared distance transform of `len` values of `grid`, `stride` apart
+fn edt_1d(grid: &mut [f64], offset: usize, stride: usize, len: usize, scratch: &mut Scratch) {
+    let Scratch { f, v, z } = scratch;
+
+    for q in 0..len {
+        f[q] = grid[offset + q * stride];
+    }
+
+    // lower envelope of the parabolas rooted at each value
+    let mut k = 0;
+    v[0] = 0;
+    z[0] = -INF;
+    z[1] = INF;
+
+    for q in 1..len {
+        let qf = q as f64;
+
+        // z[0] is -INF, so this stops at the first parabola
+        let s = loop {
+            let r = v[k];
+            let rf = r as f64;
+            let s = (f[q] - f[r] + qf * qf - rf * rf) / (2. * (qf - rf));
+
+            if s > z[k] {
+                break s;
+            }
+
+            k -= 1;
+        };
+
+        k += 1;
+        v[k] = q;
+        z[k] = s;
+        z[k + 1] = INF;
+    }
+
+    k = 0;
+
+    for q in 0..len {
+        let qf = q as f64;
+
+        while z[k + 1] < qf {
+            k += 1;
+        }
+
+        let r = v[k];
+        let rf = r as f64;
+        grid[offset + q * stride] = (qf - rf) * (qf - rf) + f[r];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn square_distance_field() {
+        let (width, height) = (32, 32);
+
+        let coverage: Vec<u8> = (0..width * height)
+            .map(|i| {
+                let (x, y) = (i % width, i / width);
+                if (8..24).contains(&x) && (8..24).contains(&y) {
+                    255
+                } else {
+                    0
+                }
+            })
+            .collect();
+
+        let sdf = signed_distance_field(&coverage, width, height, 4);
+        let at = |x: usize, y: usize| sdf[y * width + x];
+
+        assert_eq!(at(16, 16), 255);
+        assert_eq!(at(0, 0), 0);
+
+        // the outline lies halfway between 7 and 8
+        assert!(at(8, 16) > 128 && at(7, 16) < 128);
+        assert!((at(8, 16) as i32 + at(7, 16) as i32 - 255).abs() <= 1);
+        assert!(at(10, 16) > at(8, 16));
+        assert!(at(5, 16) < at(7, 16));
+    }
+}
@@ -23,20 +23,29 @@ impl<'a> SVGParsable<'a> for &'a str {
     }
 }
 
+pub type SVGPathData = svg::node::element::path::Data;
+
 pub fn build_lyon_from_svg_path_attributes(
     attributes: SVGAttributes,
     path: &mut WithSvg<impl lyon::path::builder::PathBuilder>,
 ) {
-    use lyon::path::traits::SvgPathBuilder;
-    use svg::node::element::path::Data;
-
     let data = attributes.get("d").unwrap();
-    let data = Data::parse(data).unwrap();
+    let data = SVGPathData::parse(data).unwrap();
+
+    build_lyon_from_svg_path_data(&data, path);
+}
+
+/// Appends the commands of a parsed SVG path `d` attribute to `path`
+pub fn build_lyon_from_svg_path_data(
+    data: &SVGPathData,
+    path: &mut WithSvg<impl lyon::path::builder::PathBuilder>,
+) {
+    use lyon::path::traits::SvgPathBuilder;
 
     use lyon::math::{point, vector};
     use svg::node::element::path::{Command, Position};
 
-    for command in data.into_iter() {
+    for command in data.iter() {
         match command {
             Command::Move(pos, args) => {
                 for pt in args.chunks_exact(2) {
@@ -1,10 +1,10 @@
 use std::{
     marker::PhantomData,
-    ops::{Deref, DerefMut, Mul},
+    ops::{Deref, DerefMut, Mul, Range},
     sync::{Arc, Mutex},
 };
 
-use cosmic_text::{Edit, PhysicalGlyph, Scroll, Shaping};
+use cosmic_text::{Edit, PhysicalGlyph, Scroll, Shaping, SubpixelBin};
 use ordered_float::{NotNan, OrderedFloat};
 use rustc_hash::FxHashMap;
 use shrinkwraprs::Shrinkwrap;
@@ -48,6 +48,52 @@ impl AtlasContentType {
     }
 }
 
+/// How the glyphs of a text box are rasterized
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum GlyphRenderMode {
+    /// Coverage masks at the exact size of each glyph, which are the sharpest,
+    /// but are rasterized again whenever the size changes
+    #[default]
+    Bitmap,
+    /// Signed distance fields, which are rasterized once per glyph and stay
+    /// crisp when the text is scaled or rotated
+    Sdf,
+}
+
+impl GlyphRenderMode {
+    /// Font size, in pixels, that distance fields are rasterized at
+    pub const SDF_FONT_SIZE: f32 = 48.;
+    /// Distance, in pixels at [`Self::SDF_FONT_SIZE`], that distance fields
+    /// extend past the outline of a glyph
+    pub const SDF_SPREAD: u32 = 6;
+}
+
+/// A glyph in the atlas. Distance fields don't depend on the size or subpixel
+/// offset of the glyph, so they share a key.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct AtlasGlyphKey {
+    pub cache_key: GlyphCacheKey,
+    pub mode: GlyphRenderMode,
+}
+
+impl AtlasGlyphKey {
+    pub fn new(mut cache_key: GlyphCacheKey, mode: GlyphRenderMode) -> Self {
+        if mode == GlyphRenderMode::Sdf {
+            cache_key.font_size_bits = GlyphRenderMode::SDF_FONT_SIZE.to_bits();
+            cache_key.x_bin = SubpixelBin::Zero;
+            cache_key.y_bin = SubpixelBin::Zero;
+        }
+
+        Self { cache_key, mode }
+    }
+
+    /// Size the glyph is rasterized at
+    #[inline(always)]
+    pub fn font_size(&self) -> f32 {
+        f32::from_bits(self.cache_key.font_size_bits)
+    }
+}
+
 #[derive(Shrinkwrap, Clone)]
 pub struct FontSystemRef(Arc<Mutex<FontSystem>>);
 
@@ -80,6 +126,7 @@ impl FontSystem {
     }
 }
 
+#[derive(Clone)]
 pub struct PlacedGlyph<U = PhysicalUnit> {
     pub glyph: PhysicalGlyph,
     pub depth: f32,
@@ -87,6 +134,16 @@ pub struct PlacedGlyph<U = PhysicalUnit> {
     _unit: PhantomData<U>,
 }
 
+impl<U> PartialEq for PlacedGlyph<U> {
+    fn eq(&self, other: &Self) -> bool {
+        self.glyph.cache_key == other.glyph.cache_key
+            && self.glyph.x == other.glyph.x
+            && self.glyph.y == other.glyph.y
+            && self.depth == other.depth
+            && self.color == other.color
+    }
+}
+
 impl<U: std::fmt::Debug> PlacedGlyph<U> {
     fn from_layout_glyph<UnitFrom>(
         glyph: &cosmic_text::LayoutGlyph,
@@ -117,22 +174,23 @@ impl<U: std::fmt::Debug> PlacedGlyph<U> {
     pub fn to_draw_glyph<TargetUnit>(
         &self,
         pos: Pos<f32, TargetUnit>,
-        size: PhysicalSize<u32>,
-        mut placement: PhysicalPos<i32>,
+        size: PhysicalSize<f32>,
+        mut placement: PhysicalPos<f32>,
         scale_fac: ScaleFactor<PhysicalUnit, TargetUnit>,
     ) -> Rect<f32, TargetUnit> {
-        placement.y *= -1;
+        placement.y *= -1.;
 
         let scale_fac = scale_fac.as_float();
 
         Rect::from_min_size(
-            pos + (placement.map(|x| x as f32) * scale_fac).to_vector()
+            pos + (placement * scale_fac).to_vector()
                 + (PhysicalVector::new(self.glyph.x as f32, self.glyph.y as f32) * scale_fac),
-            size.map(|x| x as f32) * scale_fac,
+            size * scale_fac,
         )
     }
 }
 
+#[derive(Clone, PartialEq)]
 pub struct PlacedTextBox<U = LogicalUnit> {
     pub glyphs: Vec<PlacedGlyph>,
     pub clip_rect: Option<RoundedRect<f32, U>>,
@@ -140,9 +198,13 @@ pub struct PlacedTextBox<U = LogicalUnit> {
     pub color: PaintFill,
     pub scale_fac: ScaleFactor<U, PhysicalUnit>,
     pub bounding_size: Size<f32, U>,
+    pub glyph_mode: GlyphRenderMode,
 }
 
 impl<U> PlacedTextBox<U> {
+    /// How far glyphs may extend from their origin, in ems
+    pub const MAX_GLYPH_EXTENT: f32 = 2.;
+
     pub fn new(
         glyphs: Vec<PlacedGlyph>,
         pos: Pos<f32, U>,
@@ -158,13 +220,49 @@ impl<U> PlacedTextBox<U> {
             color: color.into(),
             scale_fac,
             bounding_size,
+            glyph_mode: Default::default(),
         }
     }
 
+    /// Bounds of the glyphs, assuming that none extends further than
+    /// [`Self::MAX_GLYPH_EXTENT`] from its origin. `None` if there are no glyphs.
+    pub fn glyph_bounds(&self) -> Option<Rect<f32, U>>
+    where
+        U: Copy,
+    {
+        let scale_fac = self.scale_fac.as_float().get();
+
+        self.glyphs
+            .iter()
+            .map(|glyph| {
+                let origin = Pos::<f32, U>::new(glyph.glyph.x as f32, glyph.glyph.y as f32);
+                let extent = f32::from_bits(glyph.glyph.cache_key.font_size_bits) * Self::MAX_GLYPH_EXTENT;
+
+                Rect::new(origin, origin).inflate(extent, extent)
+            })
+            .reduce(|a, b| a.union(&b))
+            .map(|rect| {
+                rect.map_points(|p| Pos::new(p.x / scale_fac, p.y / scale_fac))
+                    .translate(self.pos.to_vector())
+            })
+    }
+
     pub fn glyph_cache_keys(&self) -> impl Iterator<Item = GlyphCacheKey> + '_ {
         self.glyphs.iter().map(|glyph| glyph.glyph.cache_key)
     }
 
+    /// Keys of the glyphs in the atlas, according to [`Self::glyph_mode`]
+    pub fn atlas_glyph_keys(&self) -> impl Iterator<Item = AtlasGlyphKey> + '_ {
+        self.glyphs
+            .iter()
+            .map(|glyph| AtlasGlyphKey::new(glyph.glyph.cache_key, self.glyph_mode))
+    }
+
+    #[inline]
+    pub fn with_glyph_mode(self, glyph_mode: GlyphRenderMode) -> Self {
+        Self { glyph_mode, ..self }
+    }
+
     #[inline]
     pub fn with_clip_rect(self, clip_rect: impl Into<Option<RoundedRect<f32, U>>>) -> Self {
         Self {
@@ -245,6 +343,7 @@ pub struct TextBox<Buffer: HasBuffer = cosmic_text::Buffer, U = LogicalUnit> {
     pub buffer: Buffer,
     pub color: PaintFill,
     pub pos: Pos<f32, U>,
+    pub glyph_mode: GlyphRenderMode,
     _unit: PhantomData<U>,
 }
 
@@ -263,9 +362,12 @@ impl<U, Buffer: HasBuffer> DerefMut for TextBox<Buffer, U> {
 }
 
 pub(crate) trait TextBoxLike<U: std::fmt::Debug = LogicalUnit> {
+    /// Places the glyphs of the text box. If `visible_rect` is given, glyphs
+    /// which can't intersect it are left out.
     fn calculate_placed_text_box(
         &self,
         clip_rect: Option<RoundedRect<f32, U>>,
+        visible_rect: Option<Rect<f32, U>>,
         scale_factor: ScaleFactor<U, PhysicalUnit>,
     ) -> PlacedTextBox<U>;
 }
@@ -274,18 +376,49 @@ impl<Buffer: HasBuffer, U: std::fmt::Debug> TextBoxLike<U> for TextBox<Buffer, U
     fn calculate_placed_text_box(
         &self,
         clip_rect: Option<RoundedRect<f32, U>>,
+        visible_rect: Option<Rect<f32, U>>,
         scale_factor: ScaleFactor<U, PhysicalUnit>,
     ) -> PlacedTextBox<U> {
         let bounding_size = self.computed_size();
 
+        // glyphs can overhang their line and advance (e.g. diacritics and
+        // italics), so their extents are padded by `margin`
+        let overlaps = |range: Range<f32>, margin: f32, visible: Range<f32>| {
+            range.start - margin < visible.end && range.end + margin > visible.start
+        };
+
         let glyphs = self
             .buffer
             .buffer()
             .layout_runs()
+            .filter(|r| {
+                visible_rect
+                    .map(|visible_rect| {
+                        overlaps(
+                            self.pos.y + r.line_top..self.pos.y + r.line_top + r.line_height,
+                            r.line_height,
+                            visible_rect.y_range(),
+                        )
+                    })
+                    .unwrap_or(true)
+            })
             .flat_map(|r| {
-                r.glyphs.iter().map(move |g| {
-                    PlacedGlyph::from_layout_glyph(g, scale_factor, self.pos, self.color, r.line_y)
-                })
+                r.glyphs
+                    .iter()
+                    .filter(move |g| {
+                        visible_rect
+                            .map(|visible_rect| {
+                                overlaps(
+                                    self.pos.x + g.x..self.pos.x + g.x + g.w,
+                                    g.font_size,
+                                    visible_rect.x_range(),
+                                )
+                            })
+                            .unwrap_or(true)
+                    })
+                    .map(move |g| {
+                        PlacedGlyph::from_layout_glyph(g, scale_factor, self.pos, self.color.clone(), r.line_y)
+                    })
             })
             .collect();
 
@@ -293,9 +426,10 @@ impl<Buffer: HasBuffer, U: std::fmt::Debug> TextBoxLike<U> for TextBox<Buffer, U
             glyphs: glyphs,
             clip_rect: clip_rect.into(),
             pos: self.pos,
-            color: self.color,
+            color: self.color.clone(),
             scale_fac: scale_factor,
             bounding_size,
+            glyph_mode: self.glyph_mode,
         }
     }
 }
@@ -320,10 +454,19 @@ impl<Buffer: HasBuffer, U: std::fmt::Debug> TextBox<Buffer, U> {
             buffer,
             color: color.into(),
             pos,
+            glyph_mode: Default::default(),
             _unit: PhantomData,
         }
     }
 
+    /// Renders the glyphs as signed distance fields, e.g. for text which is
+    /// zoomed or rotated
+    #[inline]
+    pub fn with_glyph_mode(mut self, glyph_mode: GlyphRenderMode) -> Self {
+        self.glyph_mode = glyph_mode;
+        self
+    }
+
     #[inline(always)]
     pub fn lines(&self) -> &Vec<BufferLine> {
         &self.buffer.buffer().lines
@@ -11,13 +11,14 @@ pub trait MultiplyNumericFields<F> {
 
 pub trait WgpuDescriptor<const N: usize>: Sized {
     const ATTRIBS: [wgpu::VertexAttribute; N];
+    const STEP_MODE: wgpu::VertexStepMode = wgpu::VertexStepMode::Vertex;
 
     fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
         use std::mem;
 
         wgpu::VertexBufferLayout {
             array_stride: mem::size_of::<Self>() as wgpu::BufferAddress,
-            step_mode: wgpu::VertexStepMode::Vertex,
+            step_mode: Self::STEP_MODE,
             attributes: &Self::ATTRIBS,
         }
     }